@@ -0,0 +1,102 @@
+//! Compares the stack and register backends on the example modules.
+//!
+//! Run with `cargo bench`, or `cargo bench --features jit` to include the
+//! JIT; each case calls an export repeatedly on a fresh instance and reports
+//! the time per call of each backend and its speedup over the stack machine.
+
+#![allow(clippy::needless_return)]
+
+extern crate wasm_interpreter;
+
+use wasm_interpreter::interpreter::{Backend, Config, Imports, Instance, Value};
+use wasm_interpreter::parser::Parser;
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+struct Case {
+    file: &'static str,
+    export: &'static str,
+    args: &'static [Value],
+    iterations: u32,
+}
+
+const CASES: &[Case] = &[
+    Case {
+        file: "examples/factorial.wasm",
+        export: "fac",
+        args: &[Value::F64(100.0)],
+        iterations: 20_000,
+    },
+    Case {
+        file: "examples/add.wasm",
+        export: "fac",
+        args: &[Value::I32(1000)],
+        iterations: 20_000,
+    },
+    Case {
+        file: "examples/add.wasm",
+        export: "add_one",
+        args: &[Value::I32(41)],
+        iterations: 1_000_000,
+    },
+    Case {
+        file: "examples/xor.wasm",
+        export: "XOR",
+        args: &[Value::I32(12), Value::I32(10)],
+        iterations: 1_000_000,
+    },
+    Case {
+        file: "examples/simple.wasm",
+        export: "addTwo",
+        args: &[Value::I32(12), Value::I32(10)],
+        iterations: 1_000_000,
+    },
+];
+
+fn measure(data: &[u8], case: &Case, backend: Backend) -> (Duration, Option<Value>) {
+    let module = Parser::new(data).parse().expect("example module");
+    let config = Config {
+        backend,
+        ..Config::default()
+    };
+    let mut instance =
+        Instance::with_config(&module, &Imports::new(), config).expect("instantiation");
+    let mut result = None;
+    let start = Instant::now();
+    for _ in 0..case.iterations {
+        result = instance.invoke(case.export, case.args).expect("call");
+    }
+    return (start.elapsed() / case.iterations, result);
+}
+
+fn main() {
+    println!(
+        "{:<28} {:>12} {:>12} {:>8}",
+        "benchmark", "stack", "register", "speedup"
+    );
+    for case in CASES {
+        let data = fs::read(case.file).expect("example file");
+        let (stack, expected) = measure(&data, case, Backend::Stack);
+        let (register, result) = measure(&data, case, Backend::Register);
+        assert_eq!(expected, result, "backends disagree on {}", case.export);
+        println!(
+            "{:<28} {:>12?} {:>12?} {:>7.2}x",
+            format!(
+                "{}:{}",
+                case.file.trim_start_matches("examples/"),
+                case.export
+            ),
+            stack,
+            register,
+            stack.as_secs_f64() / register.as_secs_f64()
+        );
+        #[cfg(feature = "jit")]
+        {
+            let (jit, result) = measure(&data, case, Backend::Jit);
+            assert_eq!(expected, result, "the JIT disagrees on {}", case.export);
+            let speedup = stack.as_secs_f64() / jit.as_secs_f64();
+            println!("{:<28} {:>12?} {:>12} {:>7.2}x", "  jit", jit, "", speedup);
+        }
+    }
+}
@@ -0,0 +1,1458 @@
+//! Translates validated modules into a self-contained C file, similar to
+//! wasm2c. The state of an instance (imported functions, memory, table and
+//! globals) lives in a `<prefix>_instance` struct, every function becomes a
+//! static C function and every exported function gets a
+//! `<prefix>_export_<name>` wrapper that returns 0 or the trap code.
+//!
+//! The generated file is compiled on its own; hosts include it with
+//! `<PREFIX>_INTERFACE_ONLY` defined to get the declarations.
+
+use interpreter::ir::{skip_unreachable, vu32};
+use interpreter::memory::{MAX_PAGES, PAGE_SIZE};
+use parser::{ParseError, ParseResult, ResizableLimits, Type};
+use parser::code_section::FnBody;
+use parser::import_export_section::{ExternalKind, ExternalKindType};
+use parser::opcode::{InitExpr, Op, Opcode, Payload};
+use parser::type_section::FuncType;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The interface up to the instance struct. `$p` and `$P` stand for the
+/// prefix in lower and upper case.
+const HEADER: &str = "\
+/* Generated by wasm-interpreter. Compile this file to link the module, or
+ * include it with $P_INTERFACE_ONLY defined for its declarations. */
+
+#ifndef $P_H
+#define $P_H
+
+#include <setjmp.h>
+#include <stdint.h>
+
+/* Results of $p_init and the exported functions. */
+enum {
+  $P_OK,
+  $P_TRAP_UNREACHABLE,
+  $P_TRAP_MEMORY_OUT_OF_BOUNDS,
+  $P_TRAP_UNDEFINED_ELEMENT,
+  $P_TRAP_UNINITIALIZED_ELEMENT,
+  $P_TRAP_INDIRECT_CALL_TYPE_MISMATCH,
+  $P_TRAP_INTEGER_DIVIDE_BY_ZERO,
+  $P_TRAP_INTEGER_OVERFLOW,
+  $P_TRAP_INVALID_CONVERSION_TO_INTEGER,
+  $P_TRAP_CALL_STACK_EXHAUSTED,
+  /* An imported function is NULL. */
+  $P_UNKNOWN_IMPORT,
+  /* The imported memory or table does not match its limits. */
+  $P_INCOMPATIBLE_IMPORT,
+  /* An element or data segment does not fit into the table or memory. */
+  $P_SEGMENT_OUT_OF_BOUNDS,
+  $P_OUT_OF_MEMORY
+};
+
+typedef struct $p_instance $p_instance;
+
+/* Zero-initialize an instance and set its imports, then call $p_init.
+ * Call $p_free afterwards, even if $p_init failed. */
+";
+
+/// The helpers used by the translated functions.
+const RUNTIME: &str = "\
+#include <math.h>
+#include <stdlib.h>
+#include <string.h>
+
+#ifndef $P_MAX_CALL_DEPTH
+#define $P_MAX_CALL_DEPTH 10000
+#endif
+
+#define TRAP(code) $p_trap(m, $P_TRAP_##code)
+
+void $p_trap($p_instance *m, uint32_t code) {
+  if (m->trap == NULL)
+    abort();
+  m->trap_code = code;
+  longjmp(*m->trap, 1);
+}
+
+static inline float f32_from_bits(uint32_t bits) {
+  float value;
+  memcpy(&value, &bits, sizeof(value));
+  return value;
+}
+
+static inline double f64_from_bits(uint64_t bits) {
+  double value;
+  memcpy(&value, &bits, sizeof(value));
+  return value;
+}
+
+static inline uint32_t f32_bits(float value) {
+  uint32_t bits;
+  memcpy(&bits, &value, sizeof(bits));
+  return bits;
+}
+
+static inline uint64_t f64_bits(double value) {
+  uint64_t bits;
+  memcpy(&bits, &value, sizeof(bits));
+  return bits;
+}
+
+/* Memory is accessed in host byte order, which must be little-endian. */
+#define LOAD(name, t, s)                                     \\
+  static inline t name($p_instance *m, uint64_t address) {   \\
+    s value;                                                 \\
+    if (address + sizeof(value) > m->memory_size)            \\
+      TRAP(MEMORY_OUT_OF_BOUNDS);                            \\
+    memcpy(&value, m->memory + address, sizeof(value));      \\
+    return (t)value;                                         \\
+  }
+
+LOAD(i32_load, uint32_t, uint32_t)
+LOAD(i64_load, uint64_t, uint64_t)
+LOAD(f32_load, float, float)
+LOAD(f64_load, double, double)
+LOAD(i32_load8_s, uint32_t, int8_t)
+LOAD(i32_load8_u, uint32_t, uint8_t)
+LOAD(i32_load16_s, uint32_t, int16_t)
+LOAD(i32_load16_u, uint32_t, uint16_t)
+LOAD(i64_load8_s, uint64_t, int8_t)
+LOAD(i64_load8_u, uint64_t, uint8_t)
+LOAD(i64_load16_s, uint64_t, int16_t)
+LOAD(i64_load16_u, uint64_t, uint16_t)
+LOAD(i64_load32_s, uint64_t, int32_t)
+LOAD(i64_load32_u, uint64_t, uint32_t)
+
+#define STORE(name, t, s)                                             \\
+  static inline void name($p_instance *m, uint64_t address, t value) { \\
+    s stored = (s)value;                                              \\
+    if (address + sizeof(stored) > m->memory_size)                    \\
+      TRAP(MEMORY_OUT_OF_BOUNDS);                                     \\
+    memcpy(m->memory + address, &stored, sizeof(stored));             \\
+  }
+
+STORE(i32_store, uint32_t, uint32_t)
+STORE(i64_store, uint64_t, uint64_t)
+STORE(f32_store, float, float)
+STORE(f64_store, double, double)
+STORE(i32_store8, uint32_t, uint8_t)
+STORE(i32_store16, uint32_t, uint16_t)
+STORE(i64_store8, uint64_t, uint8_t)
+STORE(i64_store16, uint64_t, uint16_t)
+STORE(i64_store32, uint64_t, uint32_t)
+
+static inline uint32_t memory_grow($p_instance *m, uint32_t delta) {
+  uint64_t pages = m->memory_size / 65536;
+  uint8_t *memory;
+  if (delta == 0)
+    return (uint32_t)pages;
+  if (pages + delta > m->memory_max_pages)
+    return 0xffffffffu;
+  memory = realloc(m->memory, (size_t)((pages + delta) * 65536));
+  if (memory == NULL)
+    return 0xffffffffu;
+  memset(memory + m->memory_size, 0, (size_t)delta * 65536);
+  m->memory = memory;
+  m->memory_size = (pages + delta) * 65536;
+  return (uint32_t)pages;
+}
+
+static inline uint32_t i32_div_s($p_instance *m, uint32_t a, uint32_t b) {
+  if (b == 0)
+    TRAP(INTEGER_DIVIDE_BY_ZERO);
+  if (a == 0x80000000u && b == 0xffffffffu)
+    TRAP(INTEGER_OVERFLOW);
+  return (uint32_t)((int32_t)a / (int32_t)b);
+}
+
+static inline uint32_t i32_div_u($p_instance *m, uint32_t a, uint32_t b) {
+  if (b == 0)
+    TRAP(INTEGER_DIVIDE_BY_ZERO);
+  return a / b;
+}
+
+static inline uint32_t i32_rem_s($p_instance *m, uint32_t a, uint32_t b) {
+  if (b == 0)
+    TRAP(INTEGER_DIVIDE_BY_ZERO);
+  if (b == 0xffffffffu)
+    return 0;
+  return (uint32_t)((int32_t)a % (int32_t)b);
+}
+
+static inline uint32_t i32_rem_u($p_instance *m, uint32_t a, uint32_t b) {
+  if (b == 0)
+    TRAP(INTEGER_DIVIDE_BY_ZERO);
+  return a % b;
+}
+
+static inline uint64_t i64_div_s($p_instance *m, uint64_t a, uint64_t b) {
+  if (b == 0)
+    TRAP(INTEGER_DIVIDE_BY_ZERO);
+  if (a == UINT64_C(0x8000000000000000) && b == UINT64_MAX)
+    TRAP(INTEGER_OVERFLOW);
+  return (uint64_t)((int64_t)a / (int64_t)b);
+}
+
+static inline uint64_t i64_div_u($p_instance *m, uint64_t a, uint64_t b) {
+  if (b == 0)
+    TRAP(INTEGER_DIVIDE_BY_ZERO);
+  return a / b;
+}
+
+static inline uint64_t i64_rem_s($p_instance *m, uint64_t a, uint64_t b) {
+  if (b == 0)
+    TRAP(INTEGER_DIVIDE_BY_ZERO);
+  if (b == UINT64_MAX)
+    return 0;
+  return (uint64_t)((int64_t)a % (int64_t)b);
+}
+
+static inline uint64_t i64_rem_u($p_instance *m, uint64_t a, uint64_t b) {
+  if (b == 0)
+    TRAP(INTEGER_DIVIDE_BY_ZERO);
+  return a % b;
+}
+
+static inline uint32_t i32_rotl(uint32_t a, uint32_t b) {
+  return (a << (b & 31)) | (a >> ((32 - b) & 31));
+}
+
+static inline uint32_t i32_rotr(uint32_t a, uint32_t b) {
+  return (a >> (b & 31)) | (a << ((32 - b) & 31));
+}
+
+static inline uint64_t i64_rotl(uint64_t a, uint64_t b) {
+  return (a << (b & 63)) | (a >> ((64 - b) & 63));
+}
+
+static inline uint64_t i64_rotr(uint64_t a, uint64_t b) {
+  return (a >> (b & 63)) | (a << ((64 - b) & 63));
+}
+
+static inline uint32_t i32_clz(uint32_t x) {
+  uint32_t n = 0;
+  if (x == 0)
+    return 32;
+  for (; !(x & 0x80000000u); x <<= 1)
+    n++;
+  return n;
+}
+
+static inline uint32_t i32_ctz(uint32_t x) {
+  uint32_t n = 0;
+  if (x == 0)
+    return 32;
+  for (; !(x & 1); x >>= 1)
+    n++;
+  return n;
+}
+
+static inline uint32_t i32_popcnt(uint32_t x) {
+  uint32_t n = 0;
+  for (; x != 0; x &= x - 1)
+    n++;
+  return n;
+}
+
+static inline uint64_t i64_clz(uint64_t x) {
+  uint64_t n = 0;
+  if (x == 0)
+    return 64;
+  for (; !(x & UINT64_C(0x8000000000000000)); x <<= 1)
+    n++;
+  return n;
+}
+
+static inline uint64_t i64_ctz(uint64_t x) {
+  uint64_t n = 0;
+  if (x == 0)
+    return 64;
+  for (; !(x & 1); x >>= 1)
+    n++;
+  return n;
+}
+
+static inline uint64_t i64_popcnt(uint64_t x) {
+  uint64_t n = 0;
+  for (; x != 0; x &= x - 1)
+    n++;
+  return n;
+}
+
+/* min and max propagate NaN and order -0 below +0. */
+static inline float f32_min(float a, float b) {
+  if (a != a || b != b)
+    return NAN;
+  if (a == b)
+    return f32_from_bits(f32_bits(a) | f32_bits(b));
+  return a < b ? a : b;
+}
+
+static inline float f32_max(float a, float b) {
+  if (a != a || b != b)
+    return NAN;
+  if (a == b)
+    return f32_from_bits(f32_bits(a) & f32_bits(b));
+  return a > b ? a : b;
+}
+
+static inline double f64_min(double a, double b) {
+  if (a != a || b != b)
+    return NAN;
+  if (a == b)
+    return f64_from_bits(f64_bits(a) | f64_bits(b));
+  return a < b ? a : b;
+}
+
+static inline double f64_max(double a, double b) {
+  if (a != a || b != b)
+    return NAN;
+  if (a == b)
+    return f64_from_bits(f64_bits(a) & f64_bits(b));
+  return a > b ? a : b;
+}
+
+/* Truncation to integers; f32 operands are widened to double exactly. */
+static inline uint32_t i32_trunc_s($p_instance *m, double x) {
+  if (x != x)
+    TRAP(INVALID_CONVERSION_TO_INTEGER);
+  if (x <= -2147483649.0 || x >= 2147483648.0)
+    TRAP(INTEGER_OVERFLOW);
+  return (uint32_t)(int32_t)x;
+}
+
+static inline uint32_t i32_trunc_u($p_instance *m, double x) {
+  if (x != x)
+    TRAP(INVALID_CONVERSION_TO_INTEGER);
+  if (x <= -1.0 || x >= 4294967296.0)
+    TRAP(INTEGER_OVERFLOW);
+  return (uint32_t)x;
+}
+
+static inline uint64_t i64_trunc_s($p_instance *m, double x) {
+  if (x != x)
+    TRAP(INVALID_CONVERSION_TO_INTEGER);
+  if (x < -9223372036854775808.0 || x >= 9223372036854775808.0)
+    TRAP(INTEGER_OVERFLOW);
+  return (uint64_t)(int64_t)x;
+}
+
+static inline uint64_t i64_trunc_u($p_instance *m, double x) {
+  if (x != x)
+    TRAP(INVALID_CONVERSION_TO_INTEGER);
+  if (x <= -1.0 || x >= 18446744073709551616.0)
+    TRAP(INTEGER_OVERFLOW);
+  return (uint64_t)x;
+}
+";
+
+/// Names of the instance fields that imports must not use.
+const FIELDS: [&str; 9] = [
+    "host",
+    "memory",
+    "memory_size",
+    "memory_max_pages",
+    "table",
+    "table_size",
+    "trap",
+    "trap_code",
+    "depth",
+];
+
+/// `name` with every character that may not appear in a C identifier
+/// replaced by an underscore.
+fn identifier(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        id.insert(0, '_');
+    }
+    return id;
+}
+
+/// `name`, or `name` with a number appended if it is already taken.
+fn unique(taken: &mut Vec<String>, name: String) -> String {
+    let mut unique = name.clone();
+    let mut n = 1;
+    while taken.contains(&unique) {
+        unique = format!("{}_{}", name, n);
+        n += 1;
+    }
+    taken.push(unique.clone());
+    return unique;
+}
+
+fn c_type(typ: Type) -> &'static str {
+    match typ {
+        Type::I32 => "uint32_t",
+        Type::I64 => "uint64_t",
+        Type::F32 => "float",
+        Type::F64 => "double",
+        _ => panic!("not a value type: {:?}", typ),
+    }
+}
+
+fn return_type(typ: &FuncType) -> &'static str {
+    typ.return_type.map_or("void", c_type)
+}
+
+/// The variable holding the operand of type `typ` at stack height `height`.
+fn slot(typ: Type, height: usize) -> String {
+    let prefix = match typ {
+        Type::I32 => 'i',
+        Type::I64 => 'j',
+        Type::F32 => 'f',
+        _ => 'd',
+    };
+    format!("{}{}", prefix, height)
+}
+
+/// The type loaded, stored or pushed by a memory or constant instruction.
+fn operand_type(opcode: Opcode) -> Type {
+    match &opcode.name()[..3] {
+        "i32" => Type::I32,
+        "i64" => Type::I64,
+        "f32" => Type::F32,
+        _ => Type::F64,
+    }
+}
+
+fn constant(payload: &Payload) -> String {
+    match *payload {
+        Payload::VI32(v) => format!("{}u", v as u32),
+        Payload::VI64(v) => format!("UINT64_C({})", v as u64),
+        Payload::F32(v) => format!("f32_from_bits({:#x}u)", v.to_bits()),
+        Payload::F64(v) => format!("f64_from_bits(UINT64_C({:#x}))", v.to_bits()),
+        _ => panic!("expected a constant immediate"),
+    }
+}
+
+/// The number of operands, the result type and the C expression of a
+/// numeric instruction, with `$0` and `$1` standing for the operands.
+fn operator(opcode: Opcode) -> (usize, Type, &'static str) {
+    use parser::Type::{F32, F64, I32, I64};
+    match opcode {
+        Opcode::i32_eqz => (1, I32, "$0 == 0"),
+        Opcode::i32_eq => (2, I32, "$0 == $1"),
+        Opcode::i32_ne => (2, I32, "$0 != $1"),
+        Opcode::i32_lt_s => (2, I32, "(int32_t)$0 < (int32_t)$1"),
+        Opcode::i32_lt_u => (2, I32, "$0 < $1"),
+        Opcode::i32_gt_s => (2, I32, "(int32_t)$0 > (int32_t)$1"),
+        Opcode::i32_gt_u => (2, I32, "$0 > $1"),
+        Opcode::i32_le_s => (2, I32, "(int32_t)$0 <= (int32_t)$1"),
+        Opcode::i32_le_u => (2, I32, "$0 <= $1"),
+        Opcode::i32_ge_s => (2, I32, "(int32_t)$0 >= (int32_t)$1"),
+        Opcode::i32_ge_u => (2, I32, "$0 >= $1"),
+        Opcode::i64_eqz => (1, I32, "$0 == 0"),
+        Opcode::i64_eq => (2, I32, "$0 == $1"),
+        Opcode::i64_ne => (2, I32, "$0 != $1"),
+        Opcode::i64_lt_s => (2, I32, "(int64_t)$0 < (int64_t)$1"),
+        Opcode::i64_lt_u => (2, I32, "$0 < $1"),
+        Opcode::i64_gt_s => (2, I32, "(int64_t)$0 > (int64_t)$1"),
+        Opcode::i64_gt_u => (2, I32, "$0 > $1"),
+        Opcode::i64_le_s => (2, I32, "(int64_t)$0 <= (int64_t)$1"),
+        Opcode::i64_le_u => (2, I32, "$0 <= $1"),
+        Opcode::i64_ge_s => (2, I32, "(int64_t)$0 >= (int64_t)$1"),
+        Opcode::i64_ge_u => (2, I32, "$0 >= $1"),
+        Opcode::f32_eq | Opcode::f64_eq => (2, I32, "$0 == $1"),
+        Opcode::f32_ne | Opcode::f64_ne => (2, I32, "$0 != $1"),
+        Opcode::f32_lt | Opcode::f64_lt => (2, I32, "$0 < $1"),
+        Opcode::f32_gt | Opcode::f64_gt => (2, I32, "$0 > $1"),
+        Opcode::f32_le | Opcode::f64_le => (2, I32, "$0 <= $1"),
+        Opcode::f32_ge | Opcode::f64_ge => (2, I32, "$0 >= $1"),
+        Opcode::i32_clz => (1, I32, "i32_clz($0)"),
+        Opcode::i32_ctz => (1, I32, "i32_ctz($0)"),
+        Opcode::i32_popcnt => (1, I32, "i32_popcnt($0)"),
+        Opcode::i32_add => (2, I32, "$0 + $1"),
+        Opcode::i32_sub => (2, I32, "$0 - $1"),
+        Opcode::i32_mul => (2, I32, "$0 * $1"),
+        Opcode::i32_div_s => (2, I32, "i32_div_s(m, $0, $1)"),
+        Opcode::i32_div_u => (2, I32, "i32_div_u(m, $0, $1)"),
+        Opcode::i32_rem_s => (2, I32, "i32_rem_s(m, $0, $1)"),
+        Opcode::i32_rem_u => (2, I32, "i32_rem_u(m, $0, $1)"),
+        Opcode::i32_and => (2, I32, "$0 & $1"),
+        Opcode::i32_or => (2, I32, "$0 | $1"),
+        Opcode::i32_xor => (2, I32, "$0 ^ $1"),
+        Opcode::i32_shl => (2, I32, "$0 << ($1 & 31)"),
+        Opcode::i32_shr_s => (2, I32, "(uint32_t)((int32_t)$0 >> ($1 & 31))"),
+        Opcode::i32_shr_u => (2, I32, "$0 >> ($1 & 31)"),
+        Opcode::i32_rotl => (2, I32, "i32_rotl($0, $1)"),
+        Opcode::i32_rotr => (2, I32, "i32_rotr($0, $1)"),
+        Opcode::i64_clz => (1, I64, "i64_clz($0)"),
+        Opcode::i64_ctz => (1, I64, "i64_ctz($0)"),
+        Opcode::i64_popcnt => (1, I64, "i64_popcnt($0)"),
+        Opcode::i64_add => (2, I64, "$0 + $1"),
+        Opcode::i64_sub => (2, I64, "$0 - $1"),
+        Opcode::i64_mul => (2, I64, "$0 * $1"),
+        Opcode::i64_div_s => (2, I64, "i64_div_s(m, $0, $1)"),
+        Opcode::i64_div_u => (2, I64, "i64_div_u(m, $0, $1)"),
+        Opcode::i64_rem_s => (2, I64, "i64_rem_s(m, $0, $1)"),
+        Opcode::i64_rem_u => (2, I64, "i64_rem_u(m, $0, $1)"),
+        Opcode::i64_and => (2, I64, "$0 & $1"),
+        Opcode::i64_or => (2, I64, "$0 | $1"),
+        Opcode::i64_xor => (2, I64, "$0 ^ $1"),
+        Opcode::i64_shl => (2, I64, "$0 << ($1 & 63)"),
+        Opcode::i64_shr_s => (2, I64, "(uint64_t)((int64_t)$0 >> ($1 & 63))"),
+        Opcode::i64_shr_u => (2, I64, "$0 >> ($1 & 63)"),
+        Opcode::i64_rotl => (2, I64, "i64_rotl($0, $1)"),
+        Opcode::i64_rotr => (2, I64, "i64_rotr($0, $1)"),
+        Opcode::f32_abs => (1, F32, "fabsf($0)"),
+        Opcode::f32_neg => (1, F32, "-$0"),
+        Opcode::f32_ceil => (1, F32, "ceilf($0)"),
+        Opcode::f32_floor => (1, F32, "floorf($0)"),
+        Opcode::f32_trunc => (1, F32, "truncf($0)"),
+        Opcode::f32_nearest => (1, F32, "nearbyintf($0)"),
+        Opcode::f32_sqrt => (1, F32, "sqrtf($0)"),
+        Opcode::f32_add => (2, F32, "$0 + $1"),
+        Opcode::f32_sub => (2, F32, "$0 - $1"),
+        Opcode::f32_mul => (2, F32, "$0 * $1"),
+        Opcode::f32_div => (2, F32, "$0 / $1"),
+        Opcode::f32_min => (2, F32, "f32_min($0, $1)"),
+        Opcode::f32_max => (2, F32, "f32_max($0, $1)"),
+        Opcode::f32_copysign => (2, F32, "copysignf($0, $1)"),
+        Opcode::f64_abs => (1, F64, "fabs($0)"),
+        Opcode::f64_neg => (1, F64, "-$0"),
+        Opcode::f64_ceil => (1, F64, "ceil($0)"),
+        Opcode::f64_floor => (1, F64, "floor($0)"),
+        Opcode::f64_trunc => (1, F64, "trunc($0)"),
+        Opcode::f64_nearest => (1, F64, "nearbyint($0)"),
+        Opcode::f64_sqrt => (1, F64, "sqrt($0)"),
+        Opcode::f64_add => (2, F64, "$0 + $1"),
+        Opcode::f64_sub => (2, F64, "$0 - $1"),
+        Opcode::f64_mul => (2, F64, "$0 * $1"),
+        Opcode::f64_div => (2, F64, "$0 / $1"),
+        Opcode::f64_min => (2, F64, "f64_min($0, $1)"),
+        Opcode::f64_max => (2, F64, "f64_max($0, $1)"),
+        Opcode::f64_copysign => (2, F64, "copysign($0, $1)"),
+        Opcode::i32_wrap_i64 => (1, I32, "(uint32_t)$0"),
+        Opcode::i32_trunc_s_f32 | Opcode::i32_trunc_s_f64 => (1, I32, "i32_trunc_s(m, $0)"),
+        Opcode::i32_trunc_u_f32 | Opcode::i32_trunc_u_f64 => (1, I32, "i32_trunc_u(m, $0)"),
+        Opcode::i64_extend_s_i32 => (1, I64, "(uint64_t)(int32_t)$0"),
+        Opcode::i64_extend_u_i32 => (1, I64, "(uint64_t)$0"),
+        Opcode::i64_trunc_s_f32 | Opcode::i64_trunc_s_f64 => (1, I64, "i64_trunc_s(m, $0)"),
+        Opcode::i64_trunc_u_f32 | Opcode::i64_trunc_u_f64 => (1, I64, "i64_trunc_u(m, $0)"),
+        Opcode::f32_convert_s_i32 => (1, F32, "(float)(int32_t)$0"),
+        Opcode::f32_convert_s_i64 => (1, F32, "(float)(int64_t)$0"),
+        Opcode::f32_convert_u_i32 | Opcode::f32_convert_u_i64 | Opcode::f32_demote_f64 => {
+            (1, F32, "(float)$0")
+        }
+        Opcode::f64_convert_s_i32 => (1, F64, "(double)(int32_t)$0"),
+        Opcode::f64_convert_s_i64 => (1, F64, "(double)(int64_t)$0"),
+        Opcode::f64_convert_u_i32 | Opcode::f64_convert_u_i64 | Opcode::f64_promote_f32 => {
+            (1, F64, "(double)$0")
+        }
+        Opcode::i32_reinterpret_f32 => (1, I32, "f32_bits($0)"),
+        Opcode::i64_reinterpret_f64 => (1, I64, "f64_bits($0)"),
+        Opcode::f32_reinterpret_i32 => (1, F32, "f32_from_bits($0)"),
+        Opcode::f64_reinterpret_i64 => (1, F64, "f64_from_bits($0)"),
+        _ => panic!("not a numeric instruction: {:?}", opcode),
+    }
+}
+
+struct Module<'a> {
+    prefix: String,
+    types: &'a [FuncType],
+    /// The type index of every function, imports first.
+    functions: Vec<u32>,
+    /// The instance field and type of every global, imports first.
+    globals: Vec<(String, Type)>,
+}
+
+impl<'a> Module<'a> {
+    fn function_type(&self, index: u32) -> &'a FuncType {
+        &self.types[self.functions[index as usize] as usize]
+    }
+
+    /// The first type index with the signature of type `index`, which
+    /// `call_indirect` compares.
+    fn canonical_type(&self, index: u32) -> u32 {
+        let typ = &self.types[index as usize];
+        let same = |other: &FuncType| {
+            other.param_types == typ.param_types && other.return_type == typ.return_type
+        };
+        return self.types.iter().position(same).unwrap_or(index as usize) as u32;
+    }
+
+    /// The C declaration of a function `name` of type `typ`, with parameters
+    /// named `m` and `<param>0`, `<param>1` etc. unless `param` is `None`.
+    fn declaration(&self, typ: &FuncType, name: &str, param: Option<&str>) -> String {
+        let mut out = format!("{} {}({}_instance *", return_type(typ), name, self.prefix);
+        if param.is_some() {
+            out.push('m');
+        }
+        for (i, &t) in typ.param_types.iter().enumerate() {
+            write!(out, ", {}", c_type(t)).unwrap();
+            if let Some(param) = param {
+                write!(out, " {}{}", param, i).unwrap();
+            }
+        }
+        out.push(')');
+        return out;
+    }
+
+    fn init_expr(&self, expr: &InitExpr) -> String {
+        match expr.0.opcode {
+            Opcode::get_global => format!("m->{}", self.globals[vu32(&expr.0.payload) as usize].0),
+            _ => constant(&expr.0.payload),
+        }
+    }
+}
+
+struct Block {
+    opcode: Opcode,
+    label: usize,
+    /// Operand stack height at the start of the block.
+    height: usize,
+    result: Option<Type>,
+    /// Whether a branch targets the block.
+    used: bool,
+    /// The line of the label of a loop.
+    line: usize,
+}
+
+/// Translates a function body one instruction at a time. Every operand
+/// stack slot becomes a variable per type and height, and branches become
+/// `goto`s after moving the block result into the slot of the target.
+struct Function<'a> {
+    module: &'a Module<'a>,
+    lines: Vec<String>,
+    indent: usize,
+    locals: Vec<Type>,
+    stack: Vec<Type>,
+    blocks: Vec<Block>,
+    labels: usize,
+    /// The slot variables used, by type and height.
+    slots: BTreeMap<(u8, usize), Type>,
+}
+
+impl<'a> Function<'a> {
+    fn line(&mut self, line: String) {
+        self.lines
+            .push(format!("{}{}", "  ".repeat(self.indent), line));
+    }
+
+    fn variable(&mut self, typ: Type, height: usize) -> String {
+        self.slots.insert((typ as u8, height), typ);
+        return slot(typ, height);
+    }
+
+    fn push(&mut self, typ: Type) -> String {
+        let height = self.stack.len();
+        self.stack.push(typ);
+        return self.variable(typ, height);
+    }
+
+    fn pop(&mut self) -> String {
+        let typ = self.stack.pop().expect("an operand");
+        return slot(typ, self.stack.len());
+    }
+
+    fn top(&self) -> String {
+        let typ = *self.stack.last().expect("an operand");
+        return slot(typ, self.stack.len() - 1);
+    }
+
+    /// Pops `count` operands and lists them as the arguments after `m`.
+    fn pop_args(&mut self, count: usize) -> String {
+        let mut args: Vec<String> = (0..count).map(|_| self.pop()).collect();
+        args.reverse();
+        return args.iter().map(|arg| format!(", {}", arg)).collect();
+    }
+
+    fn call(&mut self, result: Option<Type>, call: String) {
+        match result {
+            Some(typ) => {
+                let value = self.push(typ);
+                self.line(format!("{} = {};", value, call));
+            }
+            None => self.line(format!("{};", call)),
+        }
+    }
+
+    fn branch(&mut self, depth: u32) {
+        let index = self.blocks.len() - 1 - depth as usize;
+        self.blocks[index].used = true;
+        let (opcode, label, height, result) = {
+            let block = &self.blocks[index];
+            (block.opcode, block.label, block.height, block.result)
+        };
+        if let (Some(typ), false) = (result, opcode == Opcode::loop_) {
+            let target = self.variable(typ, height);
+            let value = self.top();
+            if target != value {
+                self.line(format!("{} = {};", target, value));
+            }
+        }
+        self.line(format!("goto L{};", label));
+    }
+
+    fn end(&mut self) {
+        let block = self.blocks.pop().expect("an open block");
+        self.stack.truncate(block.height);
+        if block.opcode == Opcode::loop_ {
+            if !block.used {
+                self.lines[block.line].clear();
+            }
+        } else {
+            if block.opcode == Opcode::if_ {
+                self.indent -= 1;
+                self.line("}".to_string());
+            }
+            if block.used {
+                self.line(format!("L{}:;", block.label));
+            }
+        }
+        if let Some(typ) = block.result {
+            self.push(typ);
+        }
+    }
+
+    /// Translates `op` and returns whether the code after it is unreachable.
+    fn op(&mut self, op: &Op) -> bool {
+        let module = self.module;
+        match op.opcode {
+            Opcode::unreachable => {
+                self.line("TRAP(UNREACHABLE);".to_string());
+                return true;
+            }
+            Opcode::nop => {}
+            Opcode::block | Opcode::loop_ | Opcode::if_ => {
+                let result = match op.payload {
+                    Payload::BlockType(Type::empty_block) => None,
+                    Payload::BlockType(typ) => Some(typ),
+                    _ => None,
+                };
+                if op.opcode == Opcode::if_ {
+                    let condition = self.pop();
+                    self.line(format!("if ({}) {{", condition));
+                    self.indent += 1;
+                }
+                let label = self.labels;
+                self.labels += 1;
+                let line = self.lines.len();
+                if op.opcode == Opcode::loop_ {
+                    self.line(format!("L{}:;", label));
+                }
+                self.blocks.push(Block {
+                    opcode: op.opcode,
+                    label,
+                    height: self.stack.len(),
+                    result,
+                    used: false,
+                    line,
+                });
+            }
+            Opcode::else_ => {
+                let height = self.blocks.last().expect("an open block").height;
+                self.stack.truncate(height);
+                self.indent -= 1;
+                self.line("} else {".to_string());
+                self.indent += 1;
+            }
+            Opcode::end => self.end(),
+            Opcode::br => {
+                self.branch(vu32(&op.payload));
+                return true;
+            }
+            Opcode::br_if => {
+                let condition = self.pop();
+                self.line(format!("if ({}) {{", condition));
+                self.indent += 1;
+                self.branch(vu32(&op.payload));
+                self.indent -= 1;
+                self.line("}".to_string());
+            }
+            Opcode::br_table => {
+                if let Payload::BrTable {
+                    ref target_table,
+                    default_target,
+                } = op.payload
+                {
+                    let index = self.pop();
+                    self.line(format!("switch ({}) {{", index));
+                    for (i, &depth) in target_table.iter().enumerate() {
+                        self.line(format!("case {}u:", i));
+                        self.indent += 1;
+                        self.branch(depth);
+                        self.indent -= 1;
+                    }
+                    self.line("default:".to_string());
+                    self.indent += 1;
+                    self.branch(default_target);
+                    self.indent -= 1;
+                    self.line("}".to_string());
+                }
+                return true;
+            }
+            Opcode::return_ => {
+                let depth = self.blocks.len() as u32 - 1;
+                self.branch(depth);
+                return true;
+            }
+            Opcode::call_ => {
+                let index = vu32(&op.payload);
+                let typ = module.function_type(index);
+                let args = self.pop_args(typ.param_types.len());
+                self.call(typ.return_type, format!("f{}(m{})", index, args));
+            }
+            Opcode::call_indirect => {
+                let index = vu32(&op.payload);
+                let typ = &module.types[index as usize];
+                let element = self.pop();
+                let args = self.pop_args(typ.param_types.len());
+                let call = format!(
+                    "(({})indirect(m, {}, {}u))(m{})",
+                    module.declaration(typ, "(*)", None),
+                    element,
+                    module.canonical_type(index),
+                    args
+                );
+                self.call(typ.return_type, call);
+            }
+            Opcode::drop => {
+                self.pop();
+            }
+            Opcode::select => {
+                let condition = self.pop();
+                let b = self.pop();
+                let a = self.top();
+                self.line(format!("if (!{}) {} = {};", condition, a, b));
+            }
+            Opcode::get_local => {
+                let index = vu32(&op.payload);
+                let value = self.push(self.locals[index as usize]);
+                self.line(format!("{} = l{};", value, index));
+            }
+            Opcode::set_local => {
+                let value = self.pop();
+                self.line(format!("l{} = {};", vu32(&op.payload), value));
+            }
+            Opcode::tee_local => {
+                let value = self.top();
+                self.line(format!("l{} = {};", vu32(&op.payload), value));
+            }
+            Opcode::get_global => {
+                let (ref name, typ) = module.globals[vu32(&op.payload) as usize];
+                let value = self.push(typ);
+                self.line(format!("{} = m->{};", value, name));
+            }
+            Opcode::set_global => {
+                let name = &module.globals[vu32(&op.payload) as usize].0;
+                let value = self.pop();
+                self.line(format!("m->{} = {};", name, value));
+            }
+            Opcode::current_memory => {
+                let value = self.push(Type::I32);
+                self.line(format!(
+                    "{} = (uint32_t)(m->memory_size / {});",
+                    value, PAGE_SIZE
+                ));
+            }
+            Opcode::grow_memory => {
+                let delta = self.top();
+                self.line(format!("{0} = memory_grow(m, {0});", delta));
+            }
+            Opcode::i32_const | Opcode::i64_const | Opcode::f32_const | Opcode::f64_const => {
+                let value = self.push(operand_type(op.opcode));
+                self.line(format!("{} = {};", value, constant(&op.payload)));
+            }
+            opcode => match op.payload {
+                Payload::MemoryImmediate { offset, .. } => self.memory(opcode, offset),
+                _ => {
+                    let (arity, result, expr) = operator(opcode);
+                    let mut args: Vec<String> = (0..arity).map(|_| self.pop()).collect();
+                    args.reverse();
+                    let mut expr = expr.to_string();
+                    for (i, arg) in args.iter().enumerate() {
+                        expr = expr.replace(&format!("${}", i), arg);
+                    }
+                    let value = self.push(result);
+                    self.line(format!("{} = {};", value, expr));
+                }
+            },
+        }
+        return false;
+    }
+
+    fn memory(&mut self, opcode: Opcode, offset: u32) {
+        let name = opcode.name().replace('.', "_");
+        let is_load = (opcode as u8) <= Opcode::i64_load32_u as u8;
+        let value = if is_load { None } else { Some(self.pop()) };
+        let address = match offset {
+            0 => format!("(uint64_t){}", self.pop()),
+            _ => format!("(uint64_t){} + {}u", self.pop(), offset),
+        };
+        match value {
+            Some(value) => self.line(format!("{}(m, {}, {});", name, address, value)),
+            None => {
+                let value = self.push(operand_type(opcode));
+                self.line(format!("{} = {}(m, {});", value, name, address));
+            }
+        }
+    }
+}
+
+/// The definition of function `index`.
+fn function(module: &Module, index: u32, body: &FnBody) -> String {
+    let typ = module.function_type(index);
+    let mut locals = typ.param_types.clone();
+    for local in &body.locals {
+        locals.extend((0..local.count).map(|_| local.typ));
+    }
+    let mut function = Function {
+        module,
+        lines: Vec::new(),
+        indent: 1,
+        locals,
+        stack: Vec::new(),
+        blocks: Vec::new(),
+        labels: 1,
+        slots: BTreeMap::new(),
+    };
+    function.blocks.push(Block {
+        opcode: Opcode::block,
+        label: 0,
+        height: 0,
+        result: typ.return_type,
+        used: false,
+        line: 0,
+    });
+    let mut pc = 0;
+    while pc < body.code.len() {
+        pc = match function.op(&body.code[pc]) {
+            true => skip_unreachable(&body.code, pc + 1),
+            false => pc + 1,
+        };
+    }
+    function.end();
+    function.line("m->depth--;".to_string());
+    if let Some(typ) = typ.return_type {
+        function.line(format!("return {};", slot(typ, 0)));
+    }
+
+    let name = format!("f{}", index);
+    let mut out = format!("static {} {{\n", module.declaration(typ, &name, Some("l")));
+    for (i, &typ) in function
+        .locals
+        .iter()
+        .enumerate()
+        .skip(typ.param_types.len())
+    {
+        writeln!(out, "  {} l{} = 0;", c_type(typ), i).unwrap();
+    }
+    for &typ in &[Type::I32, Type::I64, Type::F32, Type::F64] {
+        let slots: Vec<String> = function
+            .slots
+            .iter()
+            .filter(|&(_, &t)| t == typ)
+            .map(|(&(_, height), _)| format!("{} = 0", slot(typ, height)))
+            .collect();
+        if !slots.is_empty() {
+            writeln!(out, "  {} {};", c_type(typ), slots.join(", ")).unwrap();
+        }
+    }
+    writeln!(
+        out,
+        "  if (++m->depth > {}_MAX_CALL_DEPTH)\n    TRAP(CALL_STACK_EXHAUSTED);",
+        module.prefix.to_uppercase()
+    )
+    .unwrap();
+    for line in function.lines.iter().filter(|line| !line.is_empty()) {
+        writeln!(out, "{}", line).unwrap();
+    }
+    out.push_str("}\n\n");
+    return out;
+}
+
+/// Statements that run `call` with traps caught, returning the trap code.
+fn guarded(call: &str, ok: &str) -> String {
+    format!(
+        "  jmp_buf trap, *outer = m->trap;
+  uint32_t depth = m->depth;
+  m->trap = &trap;
+  if (setjmp(trap)) {{
+    m->trap = outer;
+    m->depth = depth;
+    return m->trap_code;
+  }}
+  {};
+  m->trap = outer;
+  return {};
+",
+        call, ok
+    )
+}
+
+fn byte_array(out: &mut String, name: &str, data: &[u8]) {
+    writeln!(out, "static const uint8_t {}[] = {{", name).unwrap();
+    for chunk in data.chunks(16) {
+        let bytes: Vec<String> = chunk.iter().map(|b| format!("0x{:02x}", b)).collect();
+        writeln!(out, "  {},", bytes.join(", ")).unwrap();
+    }
+    out.push_str("};\n\n");
+}
+
+/// Translates the module into C. All functions, types and macros of the
+/// interface start with `prefix`, made a valid identifier.
+pub fn translate_module(module: &ParseResult, prefix: &str) -> Result<String, ParseError> {
+    let prefix = identifier(prefix);
+    let upper = prefix.to_uppercase();
+    let types = module.function_types.as_ref().map_or(&[][..], |t| &t[..]);
+    let fn_ids = module.function_ids.as_ref().map_or(&[][..], |f| &f[..]);
+    let bodies = module.function_bodies.as_ref().map_or(&[][..], |b| &b[..]);
+    let defined_globals = module.global_variables.as_ref().map_or(&[][..], |g| &g[..]);
+    let exports = module.export_entires.as_ref().map_or(&[][..], |e| &e[..]);
+    let mut cx = Module {
+        prefix: prefix.clone(),
+        types,
+        functions: Vec::new(),
+        globals: Vec::new(),
+    };
+
+    let mut taken: Vec<String> = FIELDS.iter().map(|field| field.to_string()).collect();
+    let mut imported_functions = Vec::new();
+    // The limits of the memory and table, and whether they are imported.
+    let mut memory: Option<(&ResizableLimits, bool)> = None;
+    let mut table: Option<(&ResizableLimits, bool)> = None;
+    for import in module.import_entires.iter().flat_map(|i| i.iter()) {
+        let name = || identifier(&format!("{}_{}", import.module, import.field));
+        match import.typ {
+            ExternalKindType::Func(typ) => {
+                imported_functions.push(unique(&mut taken, name()));
+                cx.functions.push(typ);
+            }
+            ExternalKindType::Table(_, ref limits) => table = Some((limits, true)),
+            ExternalKindType::Memory(ref limits) => memory = Some((limits, true)),
+            ExternalKindType::Global(typ, _) => cx.globals.push((unique(&mut taken, name()), typ)),
+        }
+    }
+    cx.functions.extend(fn_ids.iter().map(|id| id.0));
+    for entry in module.table_entries.iter().flat_map(|t| t.iter()) {
+        table = Some((&entry.limits, false));
+    }
+    for entry in module.memory_types.iter().flat_map(|m| m.iter()) {
+        memory = Some((&entry.limits, false));
+    }
+    for global in defined_globals {
+        let index = cx.globals.len();
+        cx.globals
+            .push((format!("g{}", index), global.typ.content_type));
+    }
+
+    let mut out = HEADER.replace("$p", &prefix).replace("$P", &upper);
+    writeln!(out, "struct {}_instance {{", prefix).unwrap();
+    if !imported_functions.is_empty() {
+        out.push_str("  /* Imported functions. */\n");
+    }
+    for (index, name) in imported_functions.iter().enumerate() {
+        let typ = cx.function_type(index as u32);
+        writeln!(
+            out,
+            "  {};",
+            cx.declaration(typ, &format!("(*{})", name), None)
+        )
+        .unwrap();
+    }
+    out.push_str("  /* Free for use by the imported functions. */\n  void *host;\n");
+    out.push_str("  /* The linear memory; an imported one is allocated with malloc. */\n");
+    out.push_str("  uint8_t *memory;\n  uint64_t memory_size;\n  uint32_t memory_max_pages;\n");
+    out.push_str("  /* Function indices of the elements, UINT32_MAX if uninitialized. */\n");
+    out.push_str("  uint32_t *table;\n  uint32_t table_size;\n");
+    if !cx.globals.is_empty() {
+        out.push_str("  /* Globals. */\n");
+    }
+    for (index, &(ref name, typ)) in cx.globals.iter().enumerate() {
+        write!(out, "  {} {};", c_type(typ), name).unwrap();
+        for export in exports {
+            if export.kind == ExternalKind::Global && export.index == index as u32 {
+                let field = export
+                    .field
+                    .escape_default()
+                    .to_string()
+                    .replace("*/", "*\\/");
+                write!(out, " /* exported as \"{}\" */", field).unwrap();
+            }
+        }
+        out.push('\n');
+    }
+    out.push_str("  /* Used while running the module. */\n");
+    out.push_str("  jmp_buf *trap;\n  uint32_t trap_code;\n  uint32_t depth;\n};\n\n");
+
+    writeln!(out, "uint32_t {}_init({}_instance *m);", prefix, prefix).unwrap();
+    writeln!(out, "void {}_free({}_instance *m);", prefix, prefix).unwrap();
+    out.push_str("/* Aborts the running exported function, for the imported functions. */\n");
+    writeln!(
+        out,
+        "void {}_trap({}_instance *m, uint32_t code);",
+        prefix, prefix
+    )
+    .unwrap();
+    let mut wrappers = Vec::new();
+    let mut exported = Vec::new();
+    for export in exports.iter().filter(|e| e.kind == ExternalKind::Func) {
+        let typ = cx.function_type(export.index);
+        let name = format!("{}_export_{}", prefix, identifier(export.field));
+        let mut declaration = format!(
+            "uint32_t {}({}_instance *m",
+            unique(&mut exported, name),
+            prefix
+        );
+        for (i, &t) in typ.param_types.iter().enumerate() {
+            write!(declaration, ", {} a{}", c_type(t), i).unwrap();
+        }
+        if let Some(t) = typ.return_type {
+            write!(declaration, ", {} *result", c_type(t)).unwrap();
+        }
+        declaration.push(')');
+        writeln!(out, "{};", declaration).unwrap();
+        wrappers.push((declaration, export.index));
+    }
+    writeln!(out, "\n#endif\n\n#ifndef {}_INTERFACE_ONLY\n", upper).unwrap();
+    out.push_str(&RUNTIME.replace("$p", &prefix).replace("$P", &upper));
+    out.push('\n');
+
+    for index in 0..cx.functions.len() as u32 {
+        let name = format!("f{}", index);
+        writeln!(
+            out,
+            "static {};",
+            cx.declaration(cx.function_type(index), &name, Some("l"))
+        )
+        .unwrap();
+    }
+    out.push('\n');
+    if table.is_some() && !cx.functions.is_empty() {
+        let count = cx.functions.len();
+        let canonical: Vec<String> = cx
+            .functions
+            .iter()
+            .map(|&t| format!("{}u", cx.canonical_type(t)))
+            .collect();
+        let pointers: Vec<String> = (0..count).map(|i| format!("(function)f{}", i)).collect();
+        out.push_str("typedef void (*function)(void);\n\n");
+        writeln!(
+            out,
+            "static const uint32_t function_types[] = {{{}}};\n",
+            canonical.join(", ")
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "static const function functions[] = {{{}}};\n",
+            pointers.join(", ")
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "static inline function indirect({}_instance *m, uint32_t element, uint32_t type) {{
+  uint32_t index;
+  if (element >= m->table_size)
+    TRAP(UNDEFINED_ELEMENT);
+  index = m->table[element];
+  if (index >= {}u)
+    TRAP(UNINITIALIZED_ELEMENT);
+  if (function_types[index] != type)
+    TRAP(INDIRECT_CALL_TYPE_MISMATCH);
+  return functions[index];
+}}
+",
+            prefix, count
+        )
+        .unwrap();
+    }
+
+    for (index, name) in imported_functions.iter().enumerate() {
+        let typ = cx.function_type(index as u32);
+        let args: String = (0..typ.param_types.len())
+            .map(|i| format!(", l{}", i))
+            .collect();
+        let call = format!("m->{}(m{})", name, args);
+        let name = format!("f{}", index);
+        writeln!(out, "static {} {{", cx.declaration(typ, &name, Some("l"))).unwrap();
+        match typ.return_type {
+            Some(_) => writeln!(out, "  return {};\n}}\n", call).unwrap(),
+            None => writeln!(out, "  {};\n}}\n", call).unwrap(),
+        }
+    }
+    for (i, body) in bodies.iter().enumerate() {
+        let index = (imported_functions.len() + i) as u32;
+        out.push_str(&function(&cx, index, body.body()?));
+    }
+
+    let segments = module.element_segments.as_ref().map_or(&[][..], |s| &s[..]);
+    let datas = module.data_entries.as_ref().map_or(&[][..], |d| &d[..]);
+    for (i, segment) in segments
+        .iter()
+        .enumerate()
+        .filter(|s| !s.1.elems.is_empty())
+    {
+        let elems: Vec<String> = segment
+            .elems
+            .iter()
+            .map(|id| format!("{}u", id.0))
+            .collect();
+        writeln!(
+            out,
+            "static const uint32_t elem_{}[] = {{{}}};\n",
+            i,
+            elems.join(", ")
+        )
+        .unwrap();
+    }
+    for (i, data) in datas.iter().enumerate().filter(|d| !d.1.data.is_empty()) {
+        byte_array(&mut out, &format!("data_{}", i), data.data);
+    }
+
+    writeln!(out, "uint32_t {}_init({}_instance *m) {{", prefix, prefix).unwrap();
+    for name in &imported_functions {
+        writeln!(
+            out,
+            "  if (m->{} == NULL)\n    return {}_UNKNOWN_IMPORT;",
+            name, upper
+        )
+        .unwrap();
+    }
+    match memory {
+        Some((limits, true)) => {
+            let mut check = format!(
+                "m->memory_size < UINT64_C({})",
+                limits.initial as usize * PAGE_SIZE
+            );
+            if let Some(max) = limits.maximum {
+                write!(check, " || m->memory_max_pages > {}u", max).unwrap();
+            }
+            writeln!(
+                out,
+                "  if ({})\n    return {}_INCOMPATIBLE_IMPORT;",
+                check, upper
+            )
+            .unwrap();
+        }
+        Some((limits, false)) => {
+            let size = limits.initial as usize * PAGE_SIZE;
+            writeln!(
+                out,
+                "  m->memory = calloc({}, 1);
+  if (m->memory == NULL)
+    return {}_OUT_OF_MEMORY;
+  m->memory_size = UINT64_C({});
+  m->memory_max_pages = {}u;",
+                size.max(1),
+                upper,
+                size,
+                limits.maximum.unwrap_or(MAX_PAGES)
+            )
+            .unwrap();
+        }
+        None => {}
+    }
+    match table {
+        Some((limits, true)) => {
+            writeln!(
+                out,
+                "  if (m->table_size < {}u)\n    return {}_INCOMPATIBLE_IMPORT;",
+                limits.initial, upper
+            )
+            .unwrap();
+        }
+        Some((limits, false)) => {
+            writeln!(
+                out,
+                "  m->table = malloc(sizeof(uint32_t) * {});
+  if (m->table == NULL)
+    return {}_OUT_OF_MEMORY;
+  memset(m->table, 0xff, sizeof(uint32_t) * {});
+  m->table_size = {}u;",
+                limits.initial.max(1),
+                upper,
+                limits.initial,
+                limits.initial
+            )
+            .unwrap();
+        }
+        None => {}
+    }
+    let imported_globals = cx.globals.len() - defined_globals.len();
+    for (i, global) in defined_globals.iter().enumerate() {
+        let name = &cx.globals[imported_globals + i].0;
+        writeln!(out, "  m->{} = {};", name, cx.init_expr(&global.init)).unwrap();
+    }
+    // All segments are checked before any is copied, as in the interpreter.
+    for segment in segments {
+        writeln!(
+            out,
+            "  if ((uint64_t){} + {} > m->table_size)\n    return {}_SEGMENT_OUT_OF_BOUNDS;",
+            cx.init_expr(&segment.offset),
+            segment.elems.len(),
+            upper
+        )
+        .unwrap();
+    }
+    for data in datas {
+        writeln!(
+            out,
+            "  if ((uint64_t){} + {} > m->memory_size)\n    return {}_SEGMENT_OUT_OF_BOUNDS;",
+            cx.init_expr(&data.offset),
+            data.data.len(),
+            upper
+        )
+        .unwrap();
+    }
+    for (i, segment) in segments
+        .iter()
+        .enumerate()
+        .filter(|s| !s.1.elems.is_empty())
+    {
+        let offset = cx.init_expr(&segment.offset);
+        writeln!(
+            out,
+            "  memcpy(m->table + {}, elem_{}, sizeof(elem_{}));",
+            offset, i, i
+        )
+        .unwrap();
+    }
+    for (i, data) in datas.iter().enumerate().filter(|d| !d.1.data.is_empty()) {
+        let offset = cx.init_expr(&data.offset);
+        writeln!(
+            out,
+            "  memcpy(m->memory + {}, data_{}, sizeof(data_{}));",
+            offset, i, i
+        )
+        .unwrap();
+    }
+    match module.start_function {
+        Some(ref start) => {
+            let ok = format!("{}_OK", upper);
+            out.push_str(&guarded(&format!("f{}(m)", start.0), &ok));
+        }
+        None => writeln!(out, "  return {}_OK;", upper).unwrap(),
+    }
+    out.push_str("}\n\n");
+
+    writeln!(out, "void {}_free({}_instance *m) {{", prefix, prefix).unwrap();
+    let mut owned = false;
+    if let Some((_, false)) = memory {
+        out.push_str("  free(m->memory);\n  m->memory = NULL;\n  m->memory_size = 0;\n");
+        owned = true;
+    }
+    if let Some((_, false)) = table {
+        out.push_str("  free(m->table);\n  m->table = NULL;\n  m->table_size = 0;\n");
+        owned = true;
+    }
+    if !owned {
+        out.push_str("  (void)m;\n");
+    }
+    out.push_str("}\n\n");
+
+    for (declaration, index) in wrappers {
+        let typ = cx.function_type(index);
+        let args: String = (0..typ.param_types.len())
+            .map(|i| format!(", a{}", i))
+            .collect();
+        let mut call = format!("f{}(m{})", index, args);
+        if typ.return_type.is_some() {
+            call = format!("*result = {}", call);
+        }
+        writeln!(out, "{} {{", declaration).unwrap();
+        out.push_str(&guarded(&call, &format!("{}_OK", upper)));
+        out.push_str("}\n\n");
+    }
+    writeln!(out, "#endif /* {}_INTERFACE_ONLY */", upper).unwrap();
+    return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+
+    use std::env;
+    use std::fs;
+    use std::process::{self, Command};
+
+    /// Compiles the translation of `wasm` together with `main`, a C file
+    /// including it, and returns the output of the program, or `None` if there
+    /// is no C compiler.
+    fn run(wasm: &[u8], prefix: &str, main: &str) -> Option<String> {
+        if Command::new("cc").arg("--version").output().is_err() {
+            return None;
+        }
+        let module = Parser::new(wasm).parse().expect("valid module");
+        let source = translate_module(&module, prefix).expect("translatable module");
+        let dir = env::temp_dir().join(format!("wasm-interpreter-c-{}-{}", prefix, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(format!("{}.c", prefix)), source).unwrap();
+        fs::write(dir.join("main.c"), main).unwrap();
+        let compiled = Command::new("cc")
+            .current_dir(&dir)
+            .args(["-o", "main", "main.c", &format!("{}.c", prefix), "-lm"])
+            .output()
+            .unwrap();
+        let output = Command::new(dir.join("main")).output();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(compiled.status.success(), "{}", String::from_utf8_lossy(&compiled.stderr));
+        let output = output.unwrap();
+        assert!(output.status.success());
+        return Some(String::from_utf8(output.stdout).unwrap());
+    }
+
+    #[test]
+    fn factorial() {
+        let wasm = fs::read("examples/factorial.wasm").unwrap();
+        let main = "\
+#define FAC_INTERFACE_ONLY
+#include \"fac.c\"
+#include <stdio.h>
+
+int main(void) {
+  fac_instance m = {0};
+  double result = 0;
+  uint32_t init = fac_init(&m);
+  uint32_t status = fac_export_fac(&m, 5, &result);
+  printf(\"%u %u %g\\n\", init, status, result);
+  fac_free(&m);
+  return 0;
+}
+";
+        if let Some(output) = run(&wasm, "fac", main) {
+            assert_eq!(output, "0 0 120\n");
+        }
+    }
+
+    #[test]
+    fn traps() {
+        // (func (param i32 i32) (result i32) get_local 0 get_local 1 i32.div_s)
+        // (func (export "f") (param i32) (result i32) get_local 0 i32.const 0 call 0)
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x01, 0x0c, 0x02, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x01, 0x7f, 0x01,
+            0x7f, // types
+            0x03, 0x03, 0x02, 0x00, 0x01, // functions
+            0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x01, // exports
+            0x0a, 0x12, 0x02, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6d, 0x0b, 0x08, 0x00,
+            0x20, 0x00, 0x41, 0x00, 0x10, 0x00, 0x0b, // code
+        ];
+        let main = "\
+#define DIV_INTERFACE_ONLY
+#include \"div.c\"
+#include <stdio.h>
+
+int main(void) {
+  div_instance m = {0};
+  int32_t result = 0;
+  div_init(&m);
+  uint32_t status = div_export_f(&m, 7, &result);
+  printf(\"%d\\n\", status == DIV_TRAP_INTEGER_DIVIDE_BY_ZERO);
+  div_free(&m);
+  return 0;
+}
+";
+        if let Some(output) = run(&wasm, "div", main) {
+            assert_eq!(output, "1\n");
+        }
+    }
+}
@@ -0,0 +1,517 @@
+use interpreter::fusion;
+use interpreter::imports::HostContext;
+use interpreter::instance::{Backend, Function, Instance};
+use interpreter::ir::{self, Code, Instr, Target};
+#[cfg(feature = "jit")]
+use interpreter::jit;
+use interpreter::memory::Memory;
+use interpreter::numeric;
+use interpreter::register_exec;
+use interpreter::trap::Trap;
+use interpreter::value::Value;
+use parser::code_section::FnBody;
+use parser::type_section::FuncType;
+use validator::function_body::check_function_body;
+
+use std::rc::Rc;
+
+/// A function activation.
+struct Frame {
+    func: u32,
+    code: Rc<Code>,
+    /// Next instruction to execute, after a call the one following it.
+    pc: usize,
+    /// Index of the first local on the value stack. The operands of the
+    /// function follow its locals.
+    locals: usize,
+    returns: bool,
+}
+
+/// The state of a call in progress. All functions share the value stack, so
+/// an execution that runs out of fuel can be kept as it is and resumed later.
+pub struct Execution {
+    frames: Vec<Frame>,
+    stack: Vec<Value>,
+}
+
+/// A call that ran out of fuel, in the state of the backend running it.
+pub enum Suspended {
+    Stack(Execution),
+    Register(register_exec::Execution),
+}
+
+/// Unwinds the operand stack for a branch and returns where it continues.
+fn jump(stack: &mut Vec<Value>, target: &Target) -> usize {
+    if target.drop > 0 {
+        let len = stack.len();
+        let keep_from = len - target.arity as usize;
+        stack.copy_within(keep_from.., keep_from - target.drop as usize);
+        stack.truncate(len - target.drop as usize);
+    }
+    return target.pc as usize;
+}
+
+pub(super) fn same_type(a: &FuncType, b: &FuncType) -> bool {
+    a.param_types == b.param_types && a.return_type == b.return_type
+}
+
+pub(super) fn memory(memory: &mut Option<Memory>) -> Result<&mut Memory, Trap> {
+    memory.as_mut().ok_or(Trap::MemoryOutOfBounds)
+}
+
+macro_rules! pop {
+    ($stack:ident, $t:ident) => {
+        match $stack.pop() {
+            Some(Value::$t(v)) => v,
+            other => panic!("expected {} operand, found {:?}", stringify!($t), other),
+        }
+    };
+}
+
+impl<'m> Instance<'m> {
+    /// Calls the function with the given index in the function index space.
+    ///
+    /// The arguments must match the function's parameter types.
+    pub fn call(&mut self, index: u32, args: &[Value]) -> Result<Option<Value>, Trap> {
+        self.backtrace.clear();
+        if self.backend == Backend::Register {
+            return self.call_registers(index, args);
+        }
+        let mut execution = Execution {
+            frames: Vec::new(),
+            stack: args.to_vec(),
+        };
+        self.enter(&mut execution, index)?;
+        return self.finish(execution);
+    }
+
+    /// Continues a call that ran out of fuel.
+    pub(super) fn resume_suspended(&mut self, suspended: Suspended) -> Result<Option<Value>, Trap> {
+        self.backtrace.clear();
+        match suspended {
+            Suspended::Stack(execution) => self.finish(execution),
+            Suspended::Register(execution) => self.finish_registers(execution),
+        }
+    }
+
+    /// Runs an execution to its end, or keeps it in the instance if it runs
+    /// out of fuel.
+    fn finish(&mut self, mut execution: Execution) -> Result<Option<Value>, Trap> {
+        match self.run(&mut execution) {
+            Ok(()) => Ok(execution.stack.pop()),
+            Err(Trap::OutOfFuel) => {
+                self.suspended = Some(Suspended::Stack(execution));
+                Err(Trap::OutOfFuel)
+            }
+            Err(trap) => {
+                // every frame continues after the instruction it stopped at
+                let frames = execution.frames.iter().rev();
+                self.record_backtrace(frames.map(|f| (f.func, f.code.ops[f.pc.max(1) - 1])));
+                Err(trap)
+            }
+        }
+    }
+
+    /// Calls a function with the arguments on top of the value stack. A host
+    /// function runs right away, a wasm function gets a new frame.
+    fn enter(&mut self, execution: &mut Execution, index: u32) -> Result<(), Trap> {
+        let typ = self.func_type(index);
+        let stack = &mut execution.stack;
+        let params = stack.len() - typ.param_types.len();
+        #[cfg(feature = "jit")]
+        {
+            if self.backend == Backend::Jit && self.fuel.is_none() {
+                if let Some(native) = self.compile(index)? {
+                    let args = stack.split_off(params);
+                    let memory = self.memory.as_mut().map(Memory::data_mut);
+                    stack.extend(native.call(&args, memory)?);
+                    return Ok(());
+                }
+            }
+        }
+        let code = match self.functions[index as usize] {
+            Function::Host { ref func, .. } => {
+                let func = func.clone();
+                let args = stack.split_off(params);
+                let mut ctx = HostContext {
+                    memory: self.memory.as_mut(),
+                };
+                let result = func.call(&mut ctx, &args)?;
+                if result.map(|v| v.typ()) != typ.return_type {
+                    return Err(Trap::Host(
+                        "host function returned a wrong type".to_string(),
+                    ));
+                }
+                stack.extend(result);
+                return Ok(());
+            }
+            Function::Wasm {
+                code: Some(ref code),
+                ..
+            } => code.clone(),
+            Function::Wasm { .. } => self.lower(index)?,
+        };
+        if execution.frames.len() >= self.max_call_depth
+            || stack.len() + code.locals.len() + code.max_height as usize > self.max_value_stack
+        {
+            return Err(Trap::CallStackExhausted);
+        }
+        stack.extend_from_slice(&code.locals);
+        execution.frames.push(Frame {
+            func: index,
+            code,
+            pc: 0,
+            locals: params,
+            returns: typ.return_type.is_some(),
+        });
+        Ok(())
+    }
+
+    /// Compiles a wasm function to machine code and caches the result, `None`
+    /// if the JIT does not support it or the function is a host function.
+    #[cfg(feature = "jit")]
+    fn compile(&mut self, index: u32) -> Result<Option<Rc<jit::Code>>, Trap> {
+        match self.functions[index as usize] {
+            Function::Wasm {
+                native: Some(ref native),
+                ..
+            } => return Ok(native.clone()),
+            Function::Host { .. } => return Ok(None),
+            Function::Wasm { .. } => {}
+        }
+        let body = self.body(index)?;
+        let native = jit::compile(self.func_type(index), body).map(Rc::new);
+        if let Function::Wasm {
+            native: ref mut cached,
+            ..
+        } = self.functions[index as usize]
+        {
+            *cached = Some(native.clone());
+        }
+        return Ok(native);
+    }
+
+    /// The decoded body of a wasm function. It is validated the first time,
+    /// since modules validated lazily may contain invalid bodies.
+    pub(super) fn body(&mut self, index: u32) -> Result<&'m FnBody, Trap> {
+        let type_index = self.functions[index as usize].type_index();
+        let types = self.types();
+        let context = &self.context;
+        let (body, checked) = match self.functions[index as usize] {
+            Function::Wasm {
+                body,
+                ref mut checked,
+                ..
+            } => (body, checked),
+            Function::Host { .. } => panic!("expected a wasm function"),
+        };
+        let result = checked.get_or_insert_with(|| {
+            let decoded = body.body().map_err(|err| format!("malformed body: {}", err))?;
+            check_function_body(types, context, type_index, decoded)
+        });
+        match *result {
+            Ok(()) => Ok(body.body().expect("decoded above")),
+            Err(ref msg) => Err(Trap::InvalidFunction(index, msg.clone())),
+        }
+    }
+
+    /// Lowers the body of a wasm function and caches the result.
+    fn lower(&mut self, index: u32) -> Result<Rc<Code>, Trap> {
+        let body = self.body(index)?;
+        let mut code = ir::lower(
+            self.func_type(index),
+            body,
+            self.types(),
+            &|callee| self.func_type(callee),
+        );
+        fusion::fuse(&mut code.instrs, &mut code.ops);
+        let code = Rc::new(code);
+        if let Function::Wasm {
+            code: ref mut cached,
+            ..
+        } = self.functions[index as usize]
+        {
+            *cached = Some(code.clone());
+        }
+        return Ok(code);
+    }
+
+    fn run(&mut self, execution: &mut Execution) -> Result<(), Trap> {
+        'frames: while let Some(frame) = execution.frames.last() {
+            let code = frame.code.clone();
+            let instrs = &code.instrs[..];
+            let mut pc = frame.pc;
+            let locals = frame.locals;
+            let returns = frame.returns;
+            let stack = &mut execution.stack;
+            let frames = &mut execution.frames;
+            // Records where to continue the frame, after a call or when
+            // suspended.
+            macro_rules! save_pc {
+                () => {
+                    frames.last_mut().expect("a frame").pc = pc;
+                };
+            }
+            // Raises a trap, recording the instruction that raised it as where
+            // the frame continues.
+            macro_rules! trap {
+                ($trap:expr) => {{
+                    save_pc!();
+                    return Err($trap);
+                }};
+            }
+            macro_rules! check {
+                ($result:expr) => {
+                    match $result {
+                        Ok(value) => value,
+                        Err(err) => trap!(err),
+                    }
+                };
+            }
+            while pc < instrs.len() {
+                let instr = &instrs[pc];
+                if let Some(ref mut fuel) = self.fuel {
+                    let cost = instr.cost(&self.fuel_costs);
+                    if *fuel < cost {
+                        save_pc!();
+                        return Err(Trap::OutOfFuel);
+                    }
+                    *fuel -= cost;
+                }
+                pc += 1;
+                match *instr {
+                    Instr::Unreachable => trap!(Trap::Unreachable),
+                    Instr::Br(ref target) => pc = jump(stack, target),
+                    Instr::BrIf(ref target) => {
+                        if pop!(stack, I32) != 0 {
+                            pc = jump(stack, target);
+                        }
+                    }
+                    Instr::BrTable(ref targets) => {
+                        let index = pop!(stack, I32) as u32 as usize;
+                        pc = jump(stack, &targets[index.min(targets.len() - 1)]);
+                    }
+                    Instr::BrUnless(target) => {
+                        if pop!(stack, I32) == 0 {
+                            pc = target as usize;
+                        }
+                    }
+                    Instr::Jump(target) => pc = target as usize,
+                    Instr::Return => break,
+                    Instr::Call(index) => {
+                        save_pc!();
+                        self.enter(execution, index)?;
+                        continue 'frames;
+                    }
+                    Instr::CallIndirect(type_index) => {
+                        let expected = &self.types()[type_index as usize];
+                        let element = pop!(stack, I32) as u32;
+                        let func = match self.table {
+                            Some(ref table) => check!(table.get(element)),
+                            None => trap!(Trap::UndefinedElement),
+                        };
+                        let func = check!(func.ok_or(Trap::UninitializedElement));
+                        if !same_type(expected, self.func_type(func)) {
+                            trap!(Trap::IndirectCallTypeMismatch);
+                        }
+                        save_pc!();
+                        self.enter(execution, func)?;
+                        continue 'frames;
+                    }
+
+                    Instr::Drop => {
+                        stack.pop();
+                    }
+                    Instr::Select => {
+                        let cond = pop!(stack, I32);
+                        let b = stack.pop().expect("operand");
+                        let a = stack.pop().expect("operand");
+                        stack.push(if cond != 0 { a } else { b });
+                    }
+
+                    Instr::GetLocal(index) => {
+                        let value = stack[locals + index as usize];
+                        stack.push(value);
+                    }
+                    Instr::SetLocal(index) => {
+                        let value = stack.pop().expect("operand");
+                        stack[locals + index as usize] = value;
+                    }
+                    Instr::TeeLocal(index) => {
+                        let value = *stack.last().expect("operand");
+                        stack[locals + index as usize] = value;
+                    }
+                    Instr::GetGlobal(index) => stack.push(self.globals[index as usize]),
+                    Instr::SetGlobal(index) => {
+                        self.globals[index as usize] = stack.pop().expect("operand");
+                    }
+
+                    Instr::Load(opcode, offset) => {
+                        let addr = pop!(stack, I32) as u32;
+                        let memory = check!(memory(&mut self.memory));
+                        stack.push(check!(memory.load(opcode, addr, offset)));
+                    }
+                    Instr::Store(opcode, offset) => {
+                        let value = stack.pop().expect("operand");
+                        let addr = pop!(stack, I32) as u32;
+                        let memory = check!(memory(&mut self.memory));
+                        check!(memory.store(opcode, addr, offset, value));
+                    }
+                    Instr::CurrentMemory => {
+                        let size = check!(memory(&mut self.memory)).size();
+                        stack.push(Value::I32(size as i32));
+                    }
+                    Instr::GrowMemory => {
+                        let delta = pop!(stack, I32) as u32;
+                        let old = check!(self.grow_memory(delta));
+                        stack.push(Value::I32(old.map_or(-1, |old| old as i32)));
+                    }
+
+                    Instr::Const(value) => stack.push(value),
+                    Instr::Unary(opcode) => {
+                        let a = stack.pop().expect("operand");
+                        stack.push(check!(numeric::unary(opcode, a)));
+                    }
+                    Instr::Binary(opcode) => {
+                        let b = stack.pop().expect("operand");
+                        let a = stack.pop().expect("operand");
+                        stack.push(check!(numeric::binary(opcode, a, b)));
+                    }
+
+                    Instr::GetLocals(a, b) => {
+                        let a = stack[locals + a as usize];
+                        let b = stack[locals + b as usize];
+                        stack.push(a);
+                        stack.push(b);
+                    }
+                    Instr::SetGetLocal(set, get) => {
+                        let value = stack.pop().expect("operand");
+                        stack[locals + set as usize] = value;
+                        let value = stack[locals + get as usize];
+                        stack.push(value);
+                    }
+                    Instr::BinaryLocals(opcode, a, b) => {
+                        let a = stack[locals + a as usize];
+                        let b = stack[locals + b as usize];
+                        stack.push(check!(numeric::binary(opcode, a, b)));
+                    }
+                    Instr::BinaryConst(opcode, b) => {
+                        let a = stack.pop().expect("operand");
+                        stack.push(check!(numeric::binary(opcode, a, b)));
+                    }
+                    Instr::BinaryLocalConst(opcode, a, b) => {
+                        let a = stack[locals + a as usize];
+                        stack.push(check!(numeric::binary(opcode, a, b)));
+                    }
+                    Instr::BrIfLocalConst(opcode, a, b, ref target) => {
+                        let a = stack[locals + a as usize];
+                        if check!(numeric::binary(opcode, a, Value::I32(b))) != Value::I32(0) {
+                            pc = jump(stack, target);
+                        }
+                    }
+                    Instr::BinarySetLocal(opcode, local) => {
+                        let b = stack.pop().expect("operand");
+                        let a = stack.pop().expect("operand");
+                        stack[locals + local as usize] = check!(numeric::binary(opcode, a, b));
+                    }
+                    Instr::LoadLocal(opcode, local, offset) => {
+                        let addr = match stack[locals + local as usize] {
+                            Value::I32(addr) => addr as u32,
+                            other => panic!("expected I32 operand, found {:?}", other),
+                        };
+                        let memory = check!(memory(&mut self.memory));
+                        stack.push(check!(memory.load(opcode, addr, offset)));
+                    }
+                    Instr::StoreConst(opcode, offset, value) => {
+                        let addr = pop!(stack, I32) as u32;
+                        let memory = check!(memory(&mut self.memory));
+                        check!(memory.store(opcode, addr, offset, value));
+                    }
+                }
+            }
+            let result = if returns { stack.pop() } else { None };
+            stack.truncate(locals);
+            frames.pop();
+            stack.extend(result);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use interpreter::imports::Imports;
+    use interpreter::instance::{Backend, Config, Instance, InvokeError};
+    use interpreter::trap::Trap;
+    use interpreter::value::Value;
+    use parser::Parser;
+
+    /// (func (export "f") (param i32) (result i32)
+    ///  (if (result i32) (get_local 0)
+    ///   (then (i32.add (call 0 (i32.sub (get_local 0) (i32.const 1))) (i32.const 1)))
+    ///   (else (i32.const 0))))
+    /// which is `n + 1` calls deep for the argument `n`.
+    const RECURSIVE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // types
+        0x03, 0x02, 0x01, 0x00, // functions
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // exports
+        0x0a, 0x16, 0x01, 0x14, 0x00, 0x20, 0x00, 0x04, 0x7f, 0x20, 0x00, 0x41, 0x01, 0x6b,
+        0x10, 0x00, 0x41, 0x01, 0x6a, 0x05, 0x41, 0x00, 0x0b, 0x0b, // code
+    ];
+
+    const BACKENDS: [Backend; 2] = [Backend::Stack, Backend::Register];
+
+    fn call(config: Config, n: i32) -> Result<Option<Value>, InvokeError> {
+        let module = Parser::new(RECURSIVE).parse().expect("valid module");
+        let mut instance =
+            Instance::with_config(&module, &Imports::new(), config).expect("instance");
+        instance.invoke("f", &[Value::I32(n)])
+    }
+
+    fn exhausted(result: Result<Option<Value>, InvokeError>) -> bool {
+        matches!(result, Err(InvokeError::Trap(Trap::CallStackExhausted)))
+    }
+
+    #[test]
+    fn deep_recursion() {
+        for &backend in &BACKENDS {
+            let config = Config {
+                backend,
+                ..Config::default()
+            };
+            assert!(exhausted(call(config, 1_000_000)), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn exact_call_depth() {
+        for &backend in &BACKENDS {
+            for &depth in &[1, 2, 100] {
+                let config = || Config {
+                    backend,
+                    max_call_depth: depth,
+                    ..Config::default()
+                };
+                let n = depth as i32 - 1;
+                let result = call(config(), n).expect("within the limit");
+                assert_eq!(result, Some(Value::I32(n)));
+                assert!(exhausted(call(config(), n + 1)), "{:?} {}", backend, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn value_stack_limit() {
+        for &backend in &BACKENDS {
+            let config = |max_value_stack| Config {
+                backend,
+                max_value_stack,
+                ..Config::default()
+            };
+            assert!(exhausted(call(config(1_000), 1_000)), "{:?}", backend);
+            let result = call(config(100_000), 1_000).expect("within the limit");
+            assert_eq!(result, Some(Value::I32(1_000)));
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use parser::opcode::Opcode;
+
+use std::fmt;
+
+/// The fuel each instruction consumes. `nop`, `block`, `loop` and `end` are
+/// gone once a body is lowered and always cost nothing.
+#[derive(Clone)]
+pub struct FuelCosts {
+    costs: [u64; 256],
+}
+
+impl Default for FuelCosts {
+    /// Every instruction costs one unit.
+    fn default() -> FuelCosts {
+        FuelCosts::uniform(1)
+    }
+}
+
+/// The instructions that only structure the code and are never executed.
+const FREE: [Opcode; 4] = [Opcode::nop, Opcode::block, Opcode::loop_, Opcode::end];
+
+impl FuelCosts {
+    /// Every instruction except the free ones costs `cost`.
+    pub fn uniform(cost: u64) -> FuelCosts {
+        let mut costs = [cost; 256];
+        for &opcode in &FREE {
+            costs[opcode as usize] = 0;
+        }
+        FuelCosts { costs }
+    }
+
+    /// # Panics
+    ///
+    /// If `opcode` is `nop`, `block`, `loop` or `end`, which cannot cost fuel.
+    pub fn set(&mut self, opcode: Opcode, cost: u64) -> &mut FuelCosts {
+        assert!(!FREE.contains(&opcode), "{:?} cannot cost fuel", opcode);
+        self.costs[opcode as usize] = cost;
+        self
+    }
+
+    pub fn get(&self, opcode: Opcode) -> u64 {
+        self.costs[opcode as usize]
+    }
+}
+
+impl fmt::Debug for FuelCosts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FuelCosts(..)")
+    }
+}
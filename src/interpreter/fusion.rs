@@ -0,0 +1,157 @@
+//! A peephole pass that fuses frequent instruction sequences of lowered code
+//! into superinstructions. The sequences follow the opcode pairs reported by
+//! `wasm-interpreter stats` for the example modules, where hello.wasm and
+//! wasm_test.wasm are led by `set_local get_local`, `get_local i32.const`,
+//! `get_local get_local`, `i32.const i32.add`, `i32.add set_local`,
+//! `get_local i32.load` and `i32.const i32.store`.
+
+use interpreter::ir::{Instr, Target};
+use interpreter::value::Value;
+use parser::opcode::Opcode;
+
+fn is_i32_comparison(opcode: Opcode) -> bool {
+    (opcode as u8) >= Opcode::i32_eq as u8 && (opcode as u8) <= Opcode::i32_ge_u as u8
+}
+
+/// The superinstruction for the sequence starting at `instrs[0]` and the
+/// number of instructions it replaces, longest sequences first.
+fn superinstruction(instrs: &[Instr]) -> Option<(Instr, usize)> {
+    use self::Instr::*;
+    let fused = match *instrs {
+        [GetLocal(local), Const(Value::I32(value)), Binary(opcode), BrIf(target), ..]
+            if is_i32_comparison(opcode) =>
+        {
+            (BrIfLocalConst(opcode, local, value, target), 4)
+        }
+        [GetLocal(a), GetLocal(b), Binary(opcode), ..] => (BinaryLocals(opcode, a, b), 3),
+        [GetLocal(local), Const(value), Binary(opcode), ..] => {
+            (BinaryLocalConst(opcode, local, value), 3)
+        }
+        [GetLocal(a), GetLocal(b), ..] => (GetLocals(a, b), 2),
+        [SetLocal(a), GetLocal(b), ..] => (SetGetLocal(a, b), 2),
+        [Const(value), Binary(opcode), ..] => (BinaryConst(opcode, value), 2),
+        [Binary(opcode), SetLocal(local), ..] => (BinarySetLocal(opcode, local), 2),
+        [GetLocal(local), Load(opcode, offset), ..] => (LoadLocal(opcode, local, offset), 2),
+        [Const(value), Store(opcode, offset), ..] => (StoreConst(opcode, offset, value), 2),
+        _ => return None,
+    };
+    return Some(fused);
+}
+
+fn targets_mut(instr: &mut Instr) -> &mut [Target] {
+    match *instr {
+        Instr::Br(ref mut target)
+        | Instr::BrIf(ref mut target)
+        | Instr::BrIfLocalConst(_, _, _, ref mut target) => ::std::slice::from_mut(target),
+        Instr::BrTable(ref mut targets) => targets,
+        _ => &mut [],
+    }
+}
+
+/// The indices branches continue at, where no sequence may be fused across.
+fn branch_targets(instrs: &[Instr]) -> Vec<bool> {
+    let mut targeted = vec![false; instrs.len() + 1];
+    for instr in instrs {
+        match *instr {
+            Instr::Br(target) | Instr::BrIf(target) => targeted[target.pc as usize] = true,
+            Instr::BrTable(ref targets) => {
+                for target in targets.iter() {
+                    targeted[target.pc as usize] = true;
+                }
+            }
+            Instr::BrUnless(pc) | Instr::Jump(pc) => targeted[pc as usize] = true,
+            _ => {}
+        }
+    }
+    return targeted;
+}
+
+/// Replaces the sequences of `instrs` that have a superinstruction, unless a
+/// branch continues inside them, and points the branches at the new indices.
+/// A superinstruction keeps the entry of `ops` of the part it is named after.
+pub fn fuse(instrs: &mut Vec<Instr>, ops: &mut Vec<u32>) {
+    let targeted = branch_targets(instrs);
+    let mut fused = Vec::with_capacity(instrs.len());
+    let mut fused_ops = Vec::with_capacity(instrs.len());
+    // The new index of every old one, and of the end.
+    let mut index = Vec::with_capacity(instrs.len() + 1);
+    let mut pc = 0;
+    while pc < instrs.len() {
+        let next = match superinstruction(&instrs[pc..]) {
+            Some((instr, len)) if !targeted[pc + 1..pc + len].contains(&true) => {
+                let part = instrs[pc..pc + len]
+                    .iter()
+                    .position(|part| part.opcode() == instr.opcode())
+                    .unwrap_or(len - 1);
+                fused_ops.push(ops[pc + part]);
+                fused.push(instr);
+                len
+            }
+            _ => {
+                fused_ops.push(ops[pc]);
+                fused.push(instrs[pc].clone());
+                1
+            }
+        };
+        for _ in 0..next {
+            index.push(fused.len() as u32 - 1);
+        }
+        pc += next;
+    }
+    index.push(fused.len() as u32);
+    for instr in &mut fused {
+        for target in targets_mut(instr) {
+            target.pc = index[target.pc as usize];
+        }
+        match *instr {
+            Instr::BrUnless(ref mut pc) | Instr::Jump(ref mut pc) => *pc = index[*pc as usize],
+            _ => {}
+        }
+    }
+    *instrs = fused;
+    *ops = fused_ops;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branches_into_sequences() {
+        let target = |pc| Target {
+            pc,
+            drop: 0,
+            arity: 0,
+        };
+        // A loop entered at its `get_local`, which must not be fused with
+        // the `set_local` before it.
+        let mut instrs = vec![
+            Instr::Jump(2),
+            Instr::SetLocal(0),
+            Instr::GetLocal(0),
+            Instr::Const(Value::I32(0)),
+            Instr::Binary(Opcode::i32_gt_s),
+            Instr::BrIf(target(6)),
+            Instr::GetLocal(0),
+            Instr::Const(Value::I32(1)),
+            Instr::Binary(Opcode::i32_sub),
+            Instr::Br(target(1)),
+        ];
+        let mut ops = (0..instrs.len() as u32).collect();
+        fuse(&mut instrs, &mut ops);
+        assert_eq!(instrs.len(), 5);
+        assert_eq!(ops, [0, 1, 5, 8, 9]);
+        match instrs[2] {
+            Instr::BrIfLocalConst(Opcode::i32_gt_s, 0, 0, exit) => assert_eq!(exit.pc, 3),
+            ref other => panic!("expected a fused br_if, found {:?}", other),
+        }
+        match instrs[3] {
+            Instr::BinaryLocalConst(Opcode::i32_sub, 0, Value::I32(1)) => {}
+            ref other => panic!("expected a fused i32.sub, found {:?}", other),
+        }
+        match instrs[4] {
+            Instr::Br(back) => assert_eq!(back.pc, 1),
+            ref other => panic!("expected br, found {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use interpreter::memory::{Memory, Table};
+use interpreter::trap::Trap;
+use interpreter::value::Value;
+use parser::Type;
+use parser::type_section::FuncType;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// What a host function can access while it runs.
+pub struct HostContext<'c> {
+    /// Memory of the calling instance, if it has one.
+    pub memory: Option<&'c mut Memory>,
+}
+
+pub type HostResult = Result<Option<Value>, Trap>;
+
+type HostFn = dyn Fn(&mut HostContext, &[Value]) -> HostResult;
+
+/// A function implemented by the embedder.
+#[derive(Clone)]
+pub struct HostFunc {
+    pub params: Vec<Type>,
+    pub result: Option<Type>,
+    func: Rc<HostFn>,
+}
+
+impl HostFunc {
+    pub fn new<F>(params: Vec<Type>, result: Option<Type>, func: F) -> HostFunc
+    where
+        F: Fn(&mut HostContext, &[Value]) -> HostResult + 'static,
+    {
+        HostFunc {
+            params,
+            result,
+            func: Rc::new(func),
+        }
+    }
+
+    pub fn call(&self, ctx: &mut HostContext, args: &[Value]) -> HostResult {
+        (self.func)(ctx, args)
+    }
+
+    pub fn matches(&self, typ: &FuncType) -> bool {
+        self.params == typ.param_types && self.result == typ.return_type
+    }
+}
+
+impl fmt::Debug for HostFunc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HostFunc({:?} -> {:?})", self.params, self.result)
+    }
+}
+
+/// A value provided for an import.
+#[derive(Clone, Debug)]
+pub enum Extern {
+    Func(HostFunc),
+    /// Imported memories and tables are copied into the instance.
+    Memory(Memory),
+    Table(Table),
+    Global(Value),
+}
+
+/// The values available to a module's imports, by module and field name.
+#[derive(Default)]
+pub struct Imports {
+    entries: HashMap<(String, String), Extern>,
+}
+
+impl Imports {
+    pub fn new() -> Imports {
+        Imports::default()
+    }
+
+    pub fn define(&mut self, module: &str, field: &str, value: Extern) -> &mut Imports {
+        self.entries
+            .insert((module.to_string(), field.to_string()), value);
+        self
+    }
+
+    pub fn func(&mut self, module: &str, field: &str, func: HostFunc) -> &mut Imports {
+        self.define(module, field, Extern::Func(func))
+    }
+
+    pub fn get(&self, module: &str, field: &str) -> Option<&Extern> {
+        self.entries.get(&(module.to_string(), field.to_string()))
+    }
+}
@@ -0,0 +1,746 @@
+use interpreter::exec::Suspended;
+use interpreter::fuel::FuelCosts;
+use interpreter::imports::{Extern, HostFunc, Imports};
+use interpreter::ir::Code;
+#[cfg(feature = "jit")]
+use interpreter::jit;
+use interpreter::limits::SharedLimiter;
+use interpreter::memory::{Memory, Table, MAX_PAGES, PAGE_SIZE};
+use interpreter::register;
+use interpreter::trap::{TraceFrame, Trap};
+use interpreter::value::Value;
+use parser::{ParseResult, ResizableLimits};
+use parser::code_section::LazyFnBody;
+use parser::import_export_section::{ExternalKind, ExternalKindType, ImportEntry};
+use parser::opcode::{InitExpr, Opcode, Payload};
+use parser::type_section::FuncType;
+use validator::function_body::ModuleContext;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+/// An entry of the function index space of an instance.
+pub enum Function<'m> {
+    Host { type_index: u32, func: HostFunc },
+    Wasm {
+        type_index: u32,
+        body: &'m LazyFnBody<'m>,
+        /// Whether the body decoded and validated, once it was first needed.
+        checked: Option<Result<(), String>>,
+        /// The body lowered for execution, once the function was called.
+        code: Option<Rc<Code>>,
+        /// The body translated for the register backend.
+        registers: Option<Rc<register::Code>>,
+        /// The body compiled to machine code, `Some(None)` if the JIT does not
+        /// support it.
+        #[cfg(feature = "jit")]
+        native: Option<Option<Rc<jit::Code>>>,
+    },
+}
+
+impl<'m> Function<'m> {
+    pub fn type_index(&self) -> u32 {
+        match *self {
+            Function::Host { type_index, .. } | Function::Wasm { type_index, .. } => type_index,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InstantiationError {
+    UnknownImport { module: String, field: String },
+    /// The provided value does not match the type of the import.
+    IncompatibleImport { module: String, field: String },
+    InvalidInitExpr,
+    /// An element or data segment does not fit into its table or memory.
+    SegmentOutOfBounds,
+    /// The resource limiter refused the instance, its memory or its table.
+    ResourceLimitExceeded,
+    /// The start function trapped.
+    Trap(Trap),
+}
+
+impl fmt::Display for InstantiationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InstantiationError::UnknownImport { ref module, ref field } => {
+                write!(f, "unknown import {}.{}", module, field)
+            }
+            InstantiationError::IncompatibleImport { ref module, ref field } => {
+                write!(f, "incompatible import type for {}.{}", module, field)
+            }
+            InstantiationError::InvalidInitExpr => write!(f, "invalid constant expression"),
+            InstantiationError::SegmentOutOfBounds => write!(f, "segment does not fit"),
+            InstantiationError::ResourceLimitExceeded => write!(f, "resource limit exceeded"),
+            InstantiationError::Trap(ref trap) => write!(f, "start function trapped: {}", trap),
+        }
+    }
+}
+
+impl Error for InstantiationError {}
+
+#[derive(Debug)]
+pub enum InvokeError {
+    UnknownExport(String),
+    /// The arguments do not match the parameters of the function.
+    ArgumentMismatch,
+    /// `resume` was called without a call suspended by `OutOfFuel`.
+    NotSuspended,
+    Trap(Trap),
+}
+
+impl fmt::Display for InvokeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvokeError::UnknownExport(ref name) => write!(f, "no exported function '{}'", name),
+            InvokeError::ArgumentMismatch => write!(f, "arguments do not match the parameters"),
+            InvokeError::NotSuspended => write!(f, "no suspended call to resume"),
+            InvokeError::Trap(ref trap) => write!(f, "trap: {}", trap),
+        }
+    }
+}
+
+impl Error for InvokeError {}
+
+/// How an instance executes function bodies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// A stack machine, close to the wasm instructions.
+    Stack,
+    /// Register bytecode with fewer, larger instructions. Each one charges the
+    /// fuel of the wasm instructions it covers, as on the stack machine.
+    Register,
+    /// Machine code from the baseline JIT for the functions it can compile,
+    /// the stack machine for the others and whenever fuel is metered.
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+/// Settings of an instance that apply from its instantiation on.
+#[derive(Clone)]
+pub struct Config {
+    /// Fuel available to the instance, or `None` to run without metering.
+    pub fuel: Option<u64>,
+    pub fuel_costs: FuelCosts,
+    pub limiter: Option<SharedLimiter>,
+    /// Number of nested wasm calls at which `CallStackExhausted` is raised.
+    pub max_call_depth: usize,
+    /// Number of values (locals and operands of all active calls) at which
+    /// `CallStackExhausted` is raised.
+    pub max_value_stack: usize,
+    /// The interpreter that runs the calls of the instance.
+    pub backend: Backend,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            fuel: None,
+            fuel_costs: FuelCosts::default(),
+            limiter: None,
+            max_call_depth: 10_000,
+            max_value_stack: 1 << 20,
+            backend: Backend::Stack,
+        }
+    }
+}
+
+/// A module instantiated with its imports, ready to run.
+pub struct Instance<'m> {
+    pub(super) module: &'m ParseResult<'m>,
+    pub(super) functions: Vec<Function<'m>>,
+    /// The index spaces the bodies are validated against.
+    pub(super) context: ModuleContext,
+    pub(super) memory: Option<Memory>,
+    pub(super) table: Option<Table>,
+    pub(super) globals: Vec<Value>,
+    pub(super) max_call_depth: usize,
+    pub(super) max_value_stack: usize,
+    pub(super) backend: Backend,
+    pub(super) fuel: Option<u64>,
+    pub(super) fuel_costs: FuelCosts,
+    /// A call that ran out of fuel.
+    pub(super) suspended: Option<Suspended>,
+    pub(super) backtrace: Vec<TraceFrame>,
+    limiter: Option<SharedLimiter>,
+}
+
+fn limits_match(actual_initial: u32, actual_max: Option<u32>, limits: &ResizableLimits) -> bool {
+    if actual_initial < limits.initial {
+        return false;
+    }
+    match (limits.maximum, actual_max) {
+        (None, _) => true,
+        (Some(max), Some(actual_max)) => actual_max <= max,
+        (Some(_), None) => false,
+    }
+}
+
+impl<'m> Instance<'m> {
+    /// Instantiates a validated module and runs its start function.
+    pub fn new(
+        module: &'m ParseResult<'m>,
+        imports: &Imports,
+    ) -> Result<Instance<'m>, InstantiationError> {
+        Instance::with_config(module, imports, Config::default())
+    }
+
+    pub fn with_config(
+        module: &'m ParseResult<'m>,
+        imports: &Imports,
+        config: Config,
+    ) -> Result<Instance<'m>, InstantiationError> {
+        if let Some(ref limiter) = config.limiter {
+            if !limiter.borrow_mut().instance_creating() {
+                return Err(InstantiationError::ResourceLimitExceeded);
+            }
+        }
+        let mut instance = Instance {
+            module,
+            functions: Vec::new(),
+            context: ModuleContext::new(module),
+            memory: None,
+            table: None,
+            globals: Vec::new(),
+            max_call_depth: config.max_call_depth,
+            max_value_stack: config.max_value_stack,
+            backend: config.backend,
+            fuel: config.fuel,
+            fuel_costs: config.fuel_costs,
+            suspended: None,
+            backtrace: Vec::new(),
+            limiter: config.limiter,
+        };
+        if let Some(ref entries) = module.import_entires {
+            for entry in entries {
+                instance.resolve_import(entry, imports)?;
+            }
+        }
+        if let (Some(ids), Some(bodies)) = (&module.function_ids, &module.function_bodies) {
+            for (id, body) in ids.iter().zip(bodies) {
+                instance.functions.push(Function::Wasm {
+                    type_index: id.0,
+                    body,
+                    checked: None,
+                    code: None,
+                    registers: None,
+                    #[cfg(feature = "jit")]
+                    native: None,
+                });
+            }
+        }
+        if let Some(memory) = module.memory_types.as_ref().and_then(|m| m.first()) {
+            instance.set_memory(Memory::new(memory.limits.initial, memory.limits.maximum))?;
+        }
+        if let Some(table) = module.table_entries.as_ref().and_then(|t| t.first()) {
+            instance.set_table(Table::new(table.limits.initial, table.limits.maximum))?;
+        }
+        for global in module.global_variables.iter().flatten() {
+            let value = instance.eval_init_expr(&global.init)?;
+            instance.globals.push(value);
+        }
+        instance.init_segments()?;
+        if let Some(ref start) = module.start_function {
+            instance
+                .call(start.0, &[])
+                .map_err(InstantiationError::Trap)?;
+        }
+        return Ok(instance);
+    }
+
+    fn resolve_import(
+        &mut self,
+        entry: &ImportEntry,
+        imports: &Imports,
+    ) -> Result<(), InstantiationError> {
+        let incompatible = || InstantiationError::IncompatibleImport {
+            module: entry.module.to_string(),
+            field: entry.field.to_string(),
+        };
+        let value = match imports.get(entry.module, entry.field) {
+            Some(value) => value,
+            None => {
+                return Err(InstantiationError::UnknownImport {
+                    module: entry.module.to_string(),
+                    field: entry.field.to_string(),
+                })
+            }
+        };
+        match (&entry.typ, value) {
+            (&ExternalKindType::Func(type_index), Extern::Func(func)) => {
+                match self.types().get(type_index as usize) {
+                    Some(typ) if func.matches(typ) => {}
+                    _ => return Err(incompatible()),
+                }
+                self.functions.push(Function::Host {
+                    type_index,
+                    func: func.clone(),
+                });
+            }
+            (ExternalKindType::Memory(limits), Extern::Memory(memory)) => {
+                if !limits_match(memory.size(), memory.maximum(), limits) {
+                    return Err(incompatible());
+                }
+                self.set_memory(memory.clone())?;
+            }
+            (ExternalKindType::Table(_, limits), Extern::Table(table)) => {
+                if !limits_match(table.size(), table.maximum(), limits) {
+                    return Err(incompatible());
+                }
+                self.set_table(table.clone())?;
+            }
+            (&ExternalKindType::Global(typ, _), &Extern::Global(value)) => {
+                if value.typ() != typ {
+                    return Err(incompatible());
+                }
+                self.globals.push(value);
+            }
+            _ => return Err(incompatible()),
+        }
+        Ok(())
+    }
+
+    /// Sets the memory of the instance if the limiter allows it.
+    fn set_memory(&mut self, memory: Memory) -> Result<(), InstantiationError> {
+        if let Some(ref limiter) = self.limiter {
+            let mut limiter = limiter.borrow_mut();
+            let maximum = memory.maximum().map(|max| max as usize * PAGE_SIZE);
+            if !limiter.memory_creating() {
+                return Err(InstantiationError::ResourceLimitExceeded);
+            }
+            if !limiter.memory_growing(0, memory.data().len(), maximum) {
+                limiter.memory_dropped();
+                return Err(InstantiationError::ResourceLimitExceeded);
+            }
+        }
+        self.memory = Some(memory);
+        Ok(())
+    }
+
+    fn set_table(&mut self, table: Table) -> Result<(), InstantiationError> {
+        if let Some(ref limiter) = self.limiter {
+            let mut limiter = limiter.borrow_mut();
+            if !limiter.table_creating() {
+                return Err(InstantiationError::ResourceLimitExceeded);
+            }
+            if !limiter.table_growing(0, table.size(), table.maximum()) {
+                limiter.table_dropped();
+                return Err(InstantiationError::ResourceLimitExceeded);
+            }
+        }
+        self.table = Some(table);
+        Ok(())
+    }
+
+    /// Grows the memory by `delta` pages as `grow_memory` does, returning the
+    /// previous size or `None` if the memory can not grow.
+    pub(super) fn grow_memory(&mut self, delta: u32) -> Result<Option<u32>, Trap> {
+        let memory = self.memory.as_mut().ok_or(Trap::MemoryOutOfBounds)?;
+        let pages = memory.size() as u64 + delta as u64;
+        let maximum = memory.maximum().unwrap_or(MAX_PAGES).min(MAX_PAGES);
+        let limiter = match self.limiter {
+            Some(ref limiter) => limiter,
+            None => return Ok(memory.grow(delta)),
+        };
+        let mut limiter = limiter.borrow_mut();
+        let allowed = pages <= maximum as u64
+            && limiter.memory_growing(
+                memory.data().len(),
+                pages as usize * PAGE_SIZE,
+                memory.maximum().map(|max| max as usize * PAGE_SIZE),
+            );
+        if allowed {
+            return Ok(memory.grow(delta));
+        }
+        if limiter.trap_on_grow_failure() {
+            return Err(Trap::ResourceLimitExceeded);
+        }
+        Ok(None)
+    }
+
+    fn eval_init_expr(&self, expr: &InitExpr) -> Result<Value, InstantiationError> {
+        let value = match (expr.0.opcode, &expr.0.payload) {
+            (Opcode::i32_const, &Payload::VI32(v)) => Value::I32(v),
+            (Opcode::i64_const, &Payload::VI64(v)) => Value::I64(v),
+            (Opcode::f32_const, &Payload::F32(v)) => Value::F32(v),
+            (Opcode::f64_const, &Payload::F64(v)) => Value::F64(v),
+            (Opcode::get_global, &Payload::VU32(index)) => match self.globals.get(index as usize) {
+                Some(&value) => value,
+                None => return Err(InstantiationError::InvalidInitExpr),
+            },
+            _ => return Err(InstantiationError::InvalidInitExpr),
+        };
+        Ok(value)
+    }
+
+    fn eval_offset(&self, expr: &InitExpr) -> Result<u32, InstantiationError> {
+        match self.eval_init_expr(expr)? {
+            Value::I32(offset) => Ok(offset as u32),
+            _ => Err(InstantiationError::InvalidInitExpr),
+        }
+    }
+
+    /// Copies the element and data segments after checking that all of them
+    /// fit, so that a failed instantiation has no side effects.
+    fn init_segments(&mut self) -> Result<(), InstantiationError> {
+        let mut elems = Vec::new();
+        for segment in self.module.element_segments.iter().flatten() {
+            let offset = self.eval_offset(&segment.offset)?;
+            let size = self.table.as_ref().map_or(0, |t| t.size());
+            if offset as u64 + segment.elems.len() as u64 > size as u64 {
+                return Err(InstantiationError::SegmentOutOfBounds);
+            }
+            elems.push((offset, segment));
+        }
+        let mut datas = Vec::new();
+        for segment in self.module.data_entries.iter().flatten() {
+            let offset = self.eval_offset(&segment.offset)?;
+            let size = self.memory.as_ref().map_or(0, |m| m.data().len());
+            if offset as u64 + segment.data.len() as u64 > size as u64 {
+                return Err(InstantiationError::SegmentOutOfBounds);
+            }
+            datas.push((offset, segment.data));
+        }
+        for (offset, segment) in elems {
+            let table = self.table.as_mut().expect("checked above");
+            for (i, id) in segment.elems.iter().enumerate() {
+                table.set(offset + i as u32, Some(id.0)).expect("checked above");
+            }
+        }
+        for (offset, data) in datas {
+            let memory = self.memory.as_mut().expect("checked above");
+            memory.write(offset, 0, data).expect("checked above");
+        }
+        Ok(())
+    }
+
+    pub fn module(&self) -> &'m ParseResult<'m> {
+        self.module
+    }
+
+    pub(super) fn types(&self) -> &'m [FuncType] {
+        match self.module.function_types {
+            Some(ref types) => types,
+            None => &[],
+        }
+    }
+
+    pub fn func_type(&self, index: u32) -> &'m FuncType {
+        &self.types()[self.functions[index as usize].type_index() as usize]
+    }
+
+    /// Function index of the export `name`, if it is a function.
+    pub fn exported_function(&self, name: &str) -> Option<u32> {
+        self.module
+            .export_entires
+            .as_ref()?
+            .iter()
+            .find(|e| e.field == name && e.kind == ExternalKind::Func)
+            .map(|e| e.index)
+    }
+
+    /// Calls the exported function `name`. A call suspended by `OutOfFuel` is
+    /// abandoned.
+    pub fn invoke(&mut self, name: &str, args: &[Value]) -> Result<Option<Value>, InvokeError> {
+        let index = match self.exported_function(name) {
+            Some(index) => index,
+            None => return Err(InvokeError::UnknownExport(name.to_string())),
+        };
+        let params = &self.func_type(index).param_types;
+        if args.len() != params.len() || args.iter().zip(params).any(|(a, &t)| a.typ() != t) {
+            return Err(InvokeError::ArgumentMismatch);
+        }
+        self.suspended = None;
+        self.call(index, args).map_err(InvokeError::Trap)
+    }
+
+    /// The wasm functions that were running when the last call trapped,
+    /// innermost first. Calls compiled by the JIT are not included.
+    pub fn backtrace(&self) -> &[TraceFrame] {
+        &self.backtrace
+    }
+
+    /// Replaces the backtrace with the given frames, innermost first, as the
+    /// function index and the index of the instruction in its body.
+    pub(super) fn record_backtrace<I>(&mut self, frames: I)
+    where
+        I: Iterator<Item = (u32, u32)>,
+    {
+        let mut offsets: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut backtrace = Vec::new();
+        for (func, op) in frames {
+            let body = match self.functions[func as usize] {
+                Function::Wasm { body, .. } => body,
+                Function::Host { .. } => continue,
+            };
+            // the body was decoded to run it, so decoding it again succeeds
+            let offsets = offsets.entry(func).or_insert_with(|| {
+                let instructions = body.instructions().unwrap_or_default();
+                instructions.iter().map(|ins| ins.offset).collect()
+            });
+            if let Some(&offset) = offsets.get(op as usize) {
+                backtrace.push(TraceFrame { func, offset });
+            }
+        }
+        self.backtrace = backtrace;
+    }
+
+    /// Continues the call that ran out of fuel where it stopped.
+    pub fn resume(&mut self) -> Result<Option<Value>, InvokeError> {
+        match self.suspended.take() {
+            Some(suspended) => self.resume_suspended(suspended).map_err(InvokeError::Trap),
+            None => Err(InvokeError::NotSuspended),
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    /// Remaining fuel, or `None` if execution is not metered.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Sets the remaining fuel, `None` turns metering off.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Adds fuel if execution is metered.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(ref mut remaining) = self.fuel {
+            *remaining = remaining.saturating_add(fuel);
+        }
+    }
+
+    pub fn memory(&self) -> Option<&Memory> {
+        self.memory.as_ref()
+    }
+
+    pub fn memory_mut(&mut self) -> Option<&mut Memory> {
+        self.memory.as_mut()
+    }
+
+    pub fn table(&self) -> Option<&Table> {
+        self.table.as_ref()
+    }
+
+    pub fn global(&self, index: u32) -> Option<Value> {
+        self.globals.get(index as usize).cloned()
+    }
+}
+
+impl<'m> Drop for Instance<'m> {
+    fn drop(&mut self) {
+        if let Some(ref limiter) = self.limiter {
+            let mut limiter = limiter.borrow_mut();
+            if self.memory.is_some() {
+                limiter.memory_dropped();
+            }
+            if self.table.is_some() {
+                limiter.table_dropped();
+            }
+            limiter.instance_dropped();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+    use validator::BodyValidation::Lazy;
+    use validator::Validator;
+
+    #[test]
+    fn backtraces() {
+        // (func (param i32 i32) (result i32) get_local 0 get_local 1 i32.div_s)
+        // (func (export "f") (param i32) (result i32) get_local 0 i32.const 0 call 0)
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x01, 0x0c, 0x02, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x01, 0x7f, 0x01,
+            0x7f, // types
+            0x03, 0x03, 0x02, 0x00, 0x01, // functions
+            0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x01, // exports
+            0x0a, 0x12, 0x02, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6d, 0x0b, 0x08, 0x00,
+            0x20, 0x00, 0x41, 0x00, 0x10, 0x00, 0x0b, // code
+        ];
+        let module = Parser::new(&wasm).parse().expect("valid module");
+        for &backend in &[Backend::Stack, Backend::Register] {
+            let config = Config {
+                backend,
+                ..Config::default()
+            };
+            let mut instance =
+                Instance::with_config(&module, &Imports::new(), config).expect("instance");
+            match instance.invoke("f", &[Value::I32(1)]) {
+                Err(InvokeError::Trap(Trap::IntegerDivideByZero)) => {}
+                other => panic!("expected a division trap, found {:?}", other),
+            }
+            let frames: Vec<_> = instance.backtrace().iter().map(|f| (f.func, f.offset)).collect();
+            // the i32.div_s in the callee and the call in the export
+            assert_eq!(frames, [(0, 0x2b), (1, 0x33)]);
+        }
+    }
+
+    #[test]
+    fn invalid_bodies() {
+        // (func (export "f")) with bodies that fail to decode (an unknown
+        // opcode, a truncated immediate, a missing end, truncated locals) and
+        // bodies that decode but do not validate (an i32.add without operands,
+        // a value left on the stack, an undefined local)
+        let bodies: [&[u8]; 7] = [
+            &[0x00, 0xff, 0x0b],
+            &[0x00, 0x41, 0x80],
+            &[0x00],
+            &[0x01],
+            &[0x00, 0x6a, 0x0b],
+            &[0x00, 0x41, 0x00, 0x0b],
+            &[0x00, 0x20, 0x05, 0x0b],
+        ];
+        for body in &bodies {
+            let mut wasm = vec![
+                0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+                0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types
+                0x03, 0x02, 0x01, 0x00, // functions
+                0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // exports
+            ];
+            wasm.extend_from_slice(&[0x0a, body.len() as u8 + 2, 0x01, body.len() as u8]);
+            wasm.extend_from_slice(body);
+            let module = Parser::new(&wasm).parse().expect("bodies are decoded lazily");
+            assert!(!Validator::new(Parser::new(&wasm).parse().unwrap()).validate());
+            let lazy = Validator::with_mode(Parser::new(&wasm).parse().unwrap(), Lazy);
+            assert!(lazy.validate());
+            let backends = [
+                Backend::Stack,
+                Backend::Register,
+                #[cfg(feature = "jit")]
+                Backend::Jit,
+            ];
+            for backend in backends {
+                let config = Config {
+                    backend,
+                    ..Config::default()
+                };
+                let mut instance =
+                    Instance::with_config(&module, &Imports::new(), config).expect("instance");
+                // the second call fails from the cached result
+                for _ in 0..2 {
+                    match instance.invoke("f", &[]) {
+                        Err(InvokeError::Trap(Trap::InvalidFunction(0, _))) => {}
+                        other => panic!("expected an invalid function trap, found {:?}", other),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn backends_consume_the_same_fuel() {
+        // Counts a local down to zero in a loop, adding 10 through a br_table
+        // when it is a multiple of 3 and calling an adding function from an if
+        // when it is odd, with drops and nops in between, and returns the sum
+        // from a block with a result.
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x01, 0x0c, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f, 0x60, 0x02, 0x7f, 0x7f, 0x01,
+            0x7f, // types
+            0x03, 0x03, 0x02, 0x00, 0x01, // functions
+            0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // exports
+            0x0a, 0x5f, 0x02, 0x55, 0x01, 0x01, 0x7f, 0x41, 0x00, 0x21, 0x01, 0x02, 0x40, 0x03,
+            0x40, 0x20, 0x00, 0x45, 0x0d, 0x01, 0x02, 0x40, 0x02, 0x40, 0x20, 0x00, 0x41, 0x03,
+            0x70, 0x0e, 0x02, 0x00, 0x01, 0x01, 0x0b, 0x20, 0x01, 0x41, 0x0a, 0x6a, 0x21, 0x01,
+            0x01, 0x0b, 0x20, 0x00, 0x41, 0x01, 0x71, 0x04, 0x40, 0x20, 0x01, 0x20, 0x00, 0x10,
+            0x01, 0x21, 0x01, 0x05, 0x20, 0x01, 0x1a, 0x01, 0x0b, 0x20, 0x00, 0x41, 0x01, 0x6b,
+            0x21, 0x00, 0x0c, 0x00, 0x0b, 0x0b, 0x02, 0x7f, 0x20, 0x01, 0x20, 0x00, 0x0d, 0x00,
+            0x1a, 0x20, 0x01, 0x0b, 0x0b, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b, // code
+        ];
+        let module = Parser::new(&wasm).parse().expect("valid module");
+        let mut costs = FuelCosts::uniform(1);
+        let opcodes = [
+            Opcode::if_,
+            Opcode::else_,
+            Opcode::br,
+            Opcode::br_if,
+            Opcode::br_table,
+            Opcode::call_,
+            Opcode::drop,
+            Opcode::get_local,
+            Opcode::set_local,
+            Opcode::i32_const,
+            Opcode::i32_add,
+        ];
+        for (i, &opcode) in opcodes.iter().enumerate() {
+            costs.set(opcode, 1 << i);
+        }
+        for n in 0..12 {
+            let consumed: Vec<_> = [Backend::Stack, Backend::Register]
+                .iter()
+                .map(|&backend| {
+                    let config = Config {
+                        fuel: Some(1 << 40),
+                        fuel_costs: costs.clone(),
+                        backend,
+                        ..Config::default()
+                    };
+                    let mut instance =
+                        Instance::with_config(&module, &Imports::new(), config).expect("instance");
+                    let result = instance.invoke("f", &[Value::I32(n)]).ok();
+                    (result, (1 << 40) - instance.fuel().unwrap())
+                })
+                .collect();
+            assert_eq!(consumed[0], consumed[1], "f({})", n);
+        }
+    }
+
+    #[test]
+    fn refuel_and_resume() {
+        // (func (export "f") (param i32) (result i32)
+        //  (block (loop (br_if 1 (i32.eqz (get_local 0)))
+        //   (set_local 0 (i32.add (get_local 0) (i32.const -1))) (nop) (br 0)))
+        //  (i32.const 42))
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // types
+            0x03, 0x02, 0x01, 0x00, // functions
+            0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // exports
+            0x0a, 0x1b, 0x01, 0x19, 0x00, 0x02, 0x40, 0x03, 0x40, 0x20, 0x00, 0x45, 0x0d, 0x01,
+            0x20, 0x00, 0x41, 0x7f, 0x6a, 0x21, 0x00, 0x01, 0x0c, 0x00, 0x0b, 0x0b, 0x41, 0x2a,
+            0x0b, // code
+        ];
+        let module = Parser::new(&wasm).parse().expect("valid module");
+        let mut costs = FuelCosts::default();
+        costs.set(Opcode::br, 3);
+        for &backend in &[Backend::Stack, Backend::Register] {
+            let config = Config {
+                fuel: Some(7),
+                fuel_costs: costs.clone(),
+                backend,
+                ..Config::default()
+            };
+            let mut instance =
+                Instance::with_config(&module, &Imports::new(), config).expect("instance");
+            let mut result = instance.invoke("f", &[Value::I32(5)]);
+            let mut added = 7;
+            while let Err(InvokeError::Trap(Trap::OutOfFuel)) = result {
+                assert!(instance.is_suspended());
+                instance.add_fuel(5);
+                added += 5;
+                result = instance.resume();
+            }
+            assert_eq!(result.unwrap(), Some(Value::I32(42)));
+            // 10 per iteration (the br costs 3), 3 to leave the loop and 1 for
+            // the result; nop, block, loop and end cost nothing
+            assert_eq!(added - instance.fuel().unwrap(), 5 * 10 + 3 + 1);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot cost fuel")]
+    fn structure_costs_nothing() {
+        FuelCosts::default().set(Opcode::loop_, 1);
+    }
+}
@@ -0,0 +1,508 @@
+//! The instructions the interpreter executes, lowered from the `Op`s of a
+//! function body. Blocks are gone: branches carry the index they continue at
+//! and how many operands they remove, so nothing is searched at run time.
+
+use interpreter::fuel::FuelCosts;
+use interpreter::numeric;
+use interpreter::value::Value;
+use parser::Type;
+use parser::code_section::FnBody;
+use parser::opcode::{Op, Opcode, Payload};
+use parser::type_section::FuncType;
+
+/// Where a branch continues and how it unwinds the operand stack.
+#[derive(Clone, Copy, Debug)]
+pub struct Target {
+    /// Index of the instruction to continue at.
+    pub pc: u32,
+    /// Number of operands below the carried values to remove.
+    pub drop: u32,
+    /// Number of values the branch carries.
+    pub arity: u32,
+}
+
+#[derive(Clone, Debug)]
+pub enum Instr {
+    Unreachable,
+    Br(Target),
+    BrIf(Target),
+    /// The targets of a `br_table`, the default last.
+    BrTable(Box<[Target]>),
+    /// Pops the condition of an `if` and continues at the index if it is zero.
+    BrUnless(u32),
+    /// Skips the else branch of an `if` when the then branch is done.
+    Jump(u32),
+    Return,
+    Call(u32),
+    CallIndirect(u32),
+    Drop,
+    Select,
+    GetLocal(u32),
+    SetLocal(u32),
+    TeeLocal(u32),
+    GetGlobal(u32),
+    SetGlobal(u32),
+    /// A load with its offset.
+    Load(Opcode, u32),
+    /// A store with its offset.
+    Store(Opcode, u32),
+    CurrentMemory,
+    GrowMemory,
+    Const(Value),
+    /// A numeric instruction with one operand.
+    Unary(Opcode),
+    /// A numeric instruction with two operands.
+    Binary(Opcode),
+
+    // Superinstructions, fused from the instructions they are named after by
+    // `fusion::fuse`.
+    GetLocals(u32, u32),
+    SetGetLocal(u32, u32),
+    /// A binary instruction on two locals.
+    BinaryLocals(Opcode, u32, u32),
+    /// A binary instruction with a constant right operand.
+    BinaryConst(Opcode, Value),
+    BinaryLocalConst(Opcode, u32, Value),
+    /// Compares a local with an i32 constant and branches if the comparison
+    /// holds.
+    BrIfLocalConst(Opcode, u32, i32, Target),
+    BinarySetLocal(Opcode, u32),
+    /// A load from the address in a local, with its offset.
+    LoadLocal(Opcode, u32, u32),
+    /// A store of a constant, with its offset.
+    StoreConst(Opcode, u32, Value),
+}
+
+fn const_opcode(value: Value) -> Opcode {
+    match value {
+        Value::I32(..) => Opcode::i32_const,
+        Value::I64(..) => Opcode::i64_const,
+        Value::F32(..) => Opcode::f32_const,
+        Value::F64(..) => Opcode::f64_const,
+    }
+}
+
+impl Instr {
+    /// The instruction this was lowered from, the last one for a
+    /// superinstruction.
+    pub fn opcode(&self) -> Opcode {
+        match *self {
+            Instr::Unreachable => Opcode::unreachable,
+            Instr::Br(..) => Opcode::br,
+            Instr::BrIf(..) => Opcode::br_if,
+            Instr::BrTable(..) => Opcode::br_table,
+            Instr::BrUnless(..) => Opcode::if_,
+            Instr::Jump(..) => Opcode::else_,
+            Instr::Return => Opcode::return_,
+            Instr::Call(..) => Opcode::call_,
+            Instr::CallIndirect(..) => Opcode::call_indirect,
+            Instr::Drop => Opcode::drop,
+            Instr::Select => Opcode::select,
+            Instr::GetLocal(..) => Opcode::get_local,
+            Instr::SetLocal(..) => Opcode::set_local,
+            Instr::TeeLocal(..) => Opcode::tee_local,
+            Instr::GetGlobal(..) => Opcode::get_global,
+            Instr::SetGlobal(..) => Opcode::set_global,
+            Instr::Load(opcode, _)
+            | Instr::Store(opcode, _)
+            | Instr::Unary(opcode)
+            | Instr::Binary(opcode) => opcode,
+            Instr::CurrentMemory => Opcode::current_memory,
+            Instr::GrowMemory => Opcode::grow_memory,
+            Instr::Const(value) => const_opcode(value),
+            Instr::GetLocals(..) | Instr::SetGetLocal(..) => Opcode::get_local,
+            Instr::BinaryLocals(opcode, ..)
+            | Instr::BinaryConst(opcode, _)
+            | Instr::BinaryLocalConst(opcode, ..)
+            | Instr::LoadLocal(opcode, ..)
+            | Instr::StoreConst(opcode, ..) => opcode,
+            Instr::BrIfLocalConst(..) => Opcode::br_if,
+            Instr::BinarySetLocal(..) => Opcode::set_local,
+        }
+    }
+
+    /// The fuel the instruction consumes. A superinstruction costs as much as
+    /// the instructions it was fused from.
+    pub fn cost(&self, costs: &FuelCosts) -> u64 {
+        let fused = match *self {
+            Instr::GetLocals(..) | Instr::LoadLocal(..) => costs.get(Opcode::get_local),
+            Instr::SetGetLocal(..) => costs.get(Opcode::set_local),
+            Instr::BinaryLocals(..) => 2 * costs.get(Opcode::get_local),
+            Instr::BinaryConst(_, value) | Instr::StoreConst(_, _, value) => {
+                costs.get(const_opcode(value))
+            }
+            Instr::BinaryLocalConst(_, _, value) => {
+                costs.get(Opcode::get_local) + costs.get(const_opcode(value))
+            }
+            Instr::BrIfLocalConst(opcode, ..) => {
+                costs.get(Opcode::get_local) + costs.get(Opcode::i32_const) + costs.get(opcode)
+            }
+            Instr::BinarySetLocal(opcode, _) => costs.get(opcode),
+            _ => 0,
+        };
+        return fused + costs.get(self.opcode());
+    }
+}
+
+/// A lowered function body.
+#[derive(Debug)]
+pub struct Code {
+    pub instrs: Vec<Instr>,
+    /// For every instruction, the index in the body of the wasm instruction
+    /// it was lowered from.
+    pub ops: Vec<u32>,
+    /// Initial values of the locals following the parameters.
+    pub locals: Vec<Value>,
+    /// Largest number of operands the body keeps on the stack.
+    pub max_height: u32,
+}
+
+/// A block (or the function body) being lowered.
+struct Block {
+    /// Operand stack height when the block was entered.
+    height: u32,
+    /// Number of values the block leaves on the stack.
+    results: u32,
+    /// Index a branch to a loop continues at. Branches to other blocks
+    /// continue at the end.
+    start: Option<u32>,
+    /// The `BrUnless` of an `if` that has not reached its `else` yet.
+    condition: Option<usize>,
+    /// Branches to the end of the block, as the instruction and the index in
+    /// a branch table.
+    fixups: Vec<(usize, usize)>,
+}
+
+struct Lowering<'a, 'm: 'a> {
+    types: &'m [FuncType],
+    func_type: &'a dyn Fn(u32) -> &'m FuncType,
+    instrs: Vec<Instr>,
+    blocks: Vec<Block>,
+    height: u32,
+    max_height: u32,
+}
+
+pub(super) fn block_results(payload: &Payload) -> u32 {
+    match *payload {
+        Payload::BlockType(Type::empty_block) => 0,
+        _ => 1,
+    }
+}
+
+pub(crate) fn vu32(payload: &Payload) -> u32 {
+    match *payload {
+        Payload::VU32(v) => v,
+        _ => panic!("expected an index immediate"),
+    }
+}
+
+/// Index of the `end` or `else` closing the block whose unreachable rest
+/// starts at `pc`, or the end of the body.
+pub(crate) fn skip_unreachable(code: &[Op], mut pc: usize) -> usize {
+    let mut depth = 0;
+    while pc < code.len() {
+        match code[pc].opcode {
+            Opcode::block | Opcode::loop_ | Opcode::if_ => depth += 1,
+            Opcode::else_ if depth == 0 => return pc,
+            Opcode::end => {
+                if depth == 0 {
+                    return pc;
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+        pc += 1;
+    }
+    return pc;
+}
+
+impl<'a, 'm> Lowering<'a, 'm> {
+    fn push(&mut self, count: u32) {
+        self.height += count;
+        self.max_height = self.max_height.max(self.height);
+    }
+
+    fn pop(&mut self, count: u32) {
+        self.height -= count;
+    }
+
+    fn block(&mut self, results: u32, start: Option<u32>, condition: Option<usize>) {
+        self.blocks.push(Block {
+            height: self.height,
+            results,
+            start,
+            condition,
+            fixups: Vec::new(),
+        });
+    }
+
+    /// The target of a branch to the label `depth` levels up, taken by the
+    /// instruction emitted next.
+    fn target(&mut self, depth: u32, slot: usize) -> Target {
+        let index = self.blocks.len() - 1 - depth as usize;
+        let next = self.instrs.len();
+        let block = &mut self.blocks[index];
+        let (pc, arity) = match block.start {
+            Some(start) => (start, 0),
+            None => {
+                block.fixups.push((next, slot));
+                (0, block.results)
+            }
+        };
+        Target {
+            pc,
+            drop: self.height - block.height - arity,
+            arity,
+        }
+    }
+
+    /// Points the branches to the end of `block` at the next instruction.
+    fn patch(&mut self, block: &Block) {
+        let pc = self.instrs.len() as u32;
+        for &(index, slot) in &block.fixups {
+            match self.instrs[index] {
+                Instr::Br(ref mut target) | Instr::BrIf(ref mut target) => target.pc = pc,
+                Instr::BrTable(ref mut targets) => targets[slot].pc = pc,
+                Instr::Jump(ref mut target) => *target = pc,
+                _ => unreachable!(),
+            }
+        }
+        if let Some(index) = block.condition {
+            self.instrs[index] = Instr::BrUnless(pc);
+        }
+    }
+
+    /// Lowers `op`, returning whether the code after it is reachable.
+    fn lower(&mut self, op: &Op) -> bool {
+        let instr = match op.opcode {
+            Opcode::unreachable => {
+                self.instrs.push(Instr::Unreachable);
+                return false;
+            }
+            Opcode::nop => return true,
+            Opcode::block => {
+                self.block(block_results(&op.payload), None, None);
+                return true;
+            }
+            Opcode::loop_ => {
+                let start = self.instrs.len() as u32;
+                self.block(block_results(&op.payload), Some(start), None);
+                return true;
+            }
+            Opcode::if_ => {
+                self.pop(1);
+                let condition = self.instrs.len();
+                self.block(block_results(&op.payload), None, Some(condition));
+                Instr::BrUnless(0)
+            }
+            Opcode::else_ => {
+                let jump = self.instrs.len();
+                self.instrs.push(Instr::Jump(0));
+                let block = self.blocks.last_mut().expect("an if block");
+                block.fixups.push((jump, 0));
+                let condition = block.condition.take().expect("an if block");
+                self.height = block.height;
+                self.instrs[condition] = Instr::BrUnless(jump as u32 + 1);
+                return true;
+            }
+            Opcode::end => {
+                let block = self.blocks.pop().expect("a block");
+                self.patch(&block);
+                self.height = block.height;
+                self.push(block.results);
+                return true;
+            }
+            Opcode::br => {
+                let target = self.target(vu32(&op.payload), 0);
+                self.instrs.push(Instr::Br(target));
+                return false;
+            }
+            Opcode::br_if => {
+                self.pop(1);
+                Instr::BrIf(self.target(vu32(&op.payload), 0))
+            }
+            Opcode::br_table => {
+                self.pop(1);
+                let depths = match op.payload {
+                    Payload::BrTable {
+                        ref target_table,
+                        ref default_target,
+                    } => target_table.iter().chain(Some(default_target)),
+                    _ => panic!("expected a branch table"),
+                };
+                let targets: Vec<Target> = depths
+                    .enumerate()
+                    .map(|(slot, &depth)| self.target(depth, slot))
+                    .collect();
+                self.instrs.push(Instr::BrTable(targets.into_boxed_slice()));
+                return false;
+            }
+            Opcode::return_ => {
+                self.instrs.push(Instr::Return);
+                return false;
+            }
+            Opcode::call_ | Opcode::call_indirect => {
+                let index = vu32(&op.payload);
+                let typ = if op.opcode == Opcode::call_ {
+                    (self.func_type)(index)
+                } else {
+                    self.pop(1);
+                    &self.types[index as usize]
+                };
+                self.pop(typ.param_types.len() as u32);
+                self.push(typ.return_type.is_some() as u32);
+                if op.opcode == Opcode::call_ {
+                    Instr::Call(index)
+                } else {
+                    Instr::CallIndirect(index)
+                }
+            }
+            Opcode::drop => {
+                self.pop(1);
+                Instr::Drop
+            }
+            Opcode::select => {
+                self.pop(2);
+                Instr::Select
+            }
+            Opcode::get_local => {
+                self.push(1);
+                Instr::GetLocal(vu32(&op.payload))
+            }
+            Opcode::set_local => {
+                self.pop(1);
+                Instr::SetLocal(vu32(&op.payload))
+            }
+            Opcode::tee_local => Instr::TeeLocal(vu32(&op.payload)),
+            Opcode::get_global => {
+                self.push(1);
+                Instr::GetGlobal(vu32(&op.payload))
+            }
+            Opcode::set_global => {
+                self.pop(1);
+                Instr::SetGlobal(vu32(&op.payload))
+            }
+            Opcode::current_memory => {
+                self.push(1);
+                Instr::CurrentMemory
+            }
+            Opcode::grow_memory => Instr::GrowMemory,
+            Opcode::i32_const | Opcode::i64_const | Opcode::f32_const | Opcode::f64_const => {
+                self.push(1);
+                Instr::Const(match op.payload {
+                    Payload::VI32(v) => Value::I32(v),
+                    Payload::VI64(v) => Value::I64(v),
+                    Payload::F32(v) => Value::F32(v),
+                    Payload::F64(v) => Value::F64(v),
+                    _ => panic!("expected a constant"),
+                })
+            }
+            opcode => match op.payload {
+                Payload::MemoryImmediate { offset, .. } => {
+                    if (opcode as u8) < Opcode::i32_store as u8 {
+                        Instr::Load(opcode, offset)
+                    } else {
+                        self.pop(2);
+                        Instr::Store(opcode, offset)
+                    }
+                }
+                _ if numeric::is_binary(opcode) => {
+                    self.pop(1);
+                    Instr::Binary(opcode)
+                }
+                _ => Instr::Unary(opcode),
+            },
+        };
+        self.instrs.push(instr);
+        return true;
+    }
+}
+
+/// Lowers a validated function body. `func_type` gives the type of the
+/// functions it calls, `types` are the types of the module.
+pub fn lower<'m>(
+    typ: &FuncType,
+    body: &FnBody,
+    types: &'m [FuncType],
+    func_type: &dyn Fn(u32) -> &'m FuncType,
+) -> Code {
+    let mut lowering = Lowering {
+        types,
+        func_type,
+        instrs: Vec::with_capacity(body.code.len()),
+        blocks: Vec::new(),
+        height: 0,
+        max_height: 0,
+    };
+    lowering.block(typ.return_type.is_some() as u32, None, None);
+    let mut ops = Vec::with_capacity(body.code.len());
+    let mut pc = 0;
+    while pc < body.code.len() {
+        let reachable = lowering.lower(&body.code[pc]);
+        ops.resize(lowering.instrs.len(), pc as u32);
+        pc += 1;
+        if !reachable {
+            pc = skip_unreachable(&body.code, pc);
+        }
+    }
+    let function = lowering.blocks.pop().expect("the function block");
+    lowering.patch(&function);
+    let mut locals = Vec::new();
+    for local in &body.locals {
+        for _ in 0..local.count {
+            locals.push(Value::default_for(local.typ));
+        }
+    }
+    return Code {
+        instrs: lowering.instrs,
+        ops,
+        locals,
+        max_height: lowering.max_height,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::opcode::Op;
+
+    fn op(opcode: Opcode, payload: Payload) -> Op {
+        Op { opcode, payload }
+    }
+
+    #[test]
+    fn branch_targets() {
+        // (block (result i32) (i32.const 1) (i32.const 2) (get_local 0) (br_if 0)
+        //  (drop)) (return) (unreachable)
+        let code = vec![
+            op(Opcode::block, Payload::BlockType(Type::I32)),
+            op(Opcode::i32_const, Payload::VI32(1)),
+            op(Opcode::i32_const, Payload::VI32(2)),
+            op(Opcode::get_local, Payload::VU32(0)),
+            op(Opcode::br_if, Payload::VU32(0)),
+            op(Opcode::drop, Payload::None),
+            op(Opcode::end, Payload::None),
+            op(Opcode::return_, Payload::None),
+            op(Opcode::unreachable, Payload::None),
+        ];
+        let typ = FuncType {
+            form: Type::func,
+            param_types: vec![Type::I32],
+            return_type: Some(Type::I32),
+        };
+        let body = FnBody {
+            locals: Vec::new(),
+            code,
+        };
+        let code = lower(&typ, &body, &[], &|_| unreachable!());
+        assert_eq!(code.instrs.len(), 6);
+        match code.instrs[3] {
+            Instr::BrIf(target) => {
+                assert_eq!((target.pc, target.drop, target.arity), (5, 1, 1));
+            }
+            ref other => panic!("expected br_if, found {:?}", other),
+        }
+        assert_eq!(code.max_height, 3);
+    }
+}
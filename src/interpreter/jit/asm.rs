@@ -0,0 +1,298 @@
+//! Encodes the few x86-64 instructions the JIT emits. Operands live in
+//! 8-byte slots addressed from `rdi`, linear memory starts at `rsi` and its
+//! length is kept in `r8`.
+
+pub type Reg = u8;
+
+pub const RAX: Reg = 0;
+pub const RCX: Reg = 1;
+pub const RDX: Reg = 2;
+pub const RDI: Reg = 7;
+pub const R8: Reg = 8;
+
+/// Condition codes of `jcc` and `setcc`.
+#[derive(Clone, Copy)]
+pub enum Cond {
+    Below = 0x2,
+    AboveEqual = 0x3,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    BelowEqual = 0x6,
+    Above = 0x7,
+    Less = 0xc,
+    GreaterEqual = 0xd,
+    LessEqual = 0xe,
+    Greater = 0xf,
+}
+
+/// Two-operand arithmetic, as the opcode of its `r/m, reg` form.
+#[derive(Clone, Copy)]
+pub enum Alu {
+    Add = 0x01,
+    Or = 0x09,
+    And = 0x21,
+    Sub = 0x29,
+    Xor = 0x31,
+    Cmp = 0x39,
+    Test = 0x85,
+}
+
+/// Shifts and rotates by `cl`, as the extension of opcode `d3`.
+#[derive(Clone, Copy)]
+pub enum Shift {
+    Rol = 0,
+    Ror = 1,
+    Shl = 4,
+    Shr = 5,
+    Sar = 7,
+}
+
+/// Bit counts, as the second opcode byte after `f3 0f`.
+#[derive(Clone, Copy)]
+pub enum Count {
+    Popcnt = 0xb8,
+    Tzcnt = 0xbc,
+    Lzcnt = 0xbd,
+}
+
+/// How a load reads memory at `rsi + rax` into `rax`.
+#[derive(Clone, Copy)]
+pub enum Load {
+    U8,
+    S8,
+    U16,
+    S16,
+    U32,
+    S32To64,
+    S8To64,
+    S16To64,
+    U64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Label(usize);
+
+pub struct Assembler {
+    pub code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    /// Positions of rel32 displacements and the label they jump to.
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler {
+            code: Vec::new(),
+            labels: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// Emits a REX prefix if one is needed for a 64-bit operation or the
+    /// registers `r8` to `r15`.
+    fn rex(&mut self, wide: bool, reg: Reg, rm: Reg) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | rm >> 3;
+        if rex != 0x40 {
+            self.emit(&[rex]);
+        }
+    }
+
+    fn modrm(&mut self, mode: u8, reg: Reg, rm: Reg) {
+        self.emit(&[mode << 6 | (reg & 7) << 3 | (rm & 7)]);
+    }
+
+    /// `[rdi + slot * 8]`
+    fn slot(&mut self, reg: Reg, slot: u32) {
+        self.modrm(0b10, reg, RDI);
+        self.emit(&(slot as i32 * 8).to_le_bytes());
+    }
+
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    /// Fills in the jumps to the labels, which must all be bound.
+    pub fn finish(mut self) -> Vec<u8> {
+        for &(at, label) in &self.fixups {
+            let target = self.labels[label.0].expect("a bound label");
+            let rel = target as i32 - (at as i32 + 4);
+            self.code[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+        }
+        return self.code;
+    }
+
+    fn rel32(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.emit(&[0; 4]);
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.emit(&[0xe9]);
+        self.rel32(label);
+    }
+
+    pub fn jcc(&mut self, cond: Cond, label: Label) {
+        self.emit(&[0x0f, 0x80 | cond as u8]);
+        self.rel32(label);
+    }
+
+    pub fn ret(&mut self) {
+        self.emit(&[0xc3]);
+    }
+
+    /// `mov reg, [slot]`
+    pub fn load_slot(&mut self, reg: Reg, slot: u32) {
+        self.rex(true, reg, RDI);
+        self.emit(&[0x8b]);
+        self.slot(reg, slot);
+    }
+
+    /// `mov [slot], reg`
+    pub fn store_slot(&mut self, slot: u32, reg: Reg) {
+        self.rex(true, reg, RDI);
+        self.emit(&[0x89]);
+        self.slot(reg, slot);
+    }
+
+    /// `movsxd rax, dword [slot]`
+    pub fn load_slot_s32(&mut self, slot: u32) {
+        self.rex(true, RAX, RDI);
+        self.emit(&[0x63]);
+        self.slot(RAX, slot);
+    }
+
+    /// `mov dst, src`
+    pub fn mov(&mut self, dst: Reg, src: Reg) {
+        self.rex(true, src, dst);
+        self.emit(&[0x89]);
+        self.modrm(0b11, src, dst);
+    }
+
+    /// Clears the upper half of `reg` (`mov r32, r32`).
+    pub fn clear_upper(&mut self, reg: Reg) {
+        self.rex(false, reg, reg);
+        self.emit(&[0x89]);
+        self.modrm(0b11, reg, reg);
+    }
+
+    /// Loads a constant, zero extended if it has 32 bits.
+    pub fn mov_imm(&mut self, reg: Reg, value: u64) {
+        if value <= u32::MAX as u64 {
+            self.rex(false, 0, reg);
+            self.emit(&[0xb8 | (reg & 7)]);
+            self.emit(&(value as u32).to_le_bytes());
+        } else {
+            self.rex(true, 0, reg);
+            self.emit(&[0xb8 | (reg & 7)]);
+            self.emit(&value.to_le_bytes());
+        }
+    }
+
+    /// `op dst, src`. A 32-bit operation clears the upper half of `dst`.
+    pub fn alu(&mut self, op: Alu, wide: bool, dst: Reg, src: Reg) {
+        self.rex(wide, src, dst);
+        self.emit(&[op as u8]);
+        self.modrm(0b11, src, dst);
+    }
+
+    /// `cmp reg, imm32`, sign extended for 64 bits.
+    pub fn cmp_imm(&mut self, wide: bool, reg: Reg, value: i32) {
+        self.rex(wide, 0, reg);
+        self.emit(&[0x81]);
+        self.modrm(0b11, 7, reg);
+        self.emit(&value.to_le_bytes());
+    }
+
+    pub fn imul(&mut self, wide: bool, dst: Reg, src: Reg) {
+        self.rex(wide, dst, src);
+        self.emit(&[0x0f, 0xaf]);
+        self.modrm(0b11, dst, src);
+    }
+
+    /// Divides `rdx:rax` by `src`, signed or unsigned.
+    pub fn div(&mut self, wide: bool, signed: bool, src: Reg) {
+        self.rex(wide, 0, src);
+        self.emit(&[0xf7]);
+        self.modrm(0b11, if signed { 7 } else { 6 }, src);
+    }
+
+    /// Sign extends `rax` into `rdx` (`cdq` or `cqo`).
+    pub fn sign_extend_rax(&mut self, wide: bool) {
+        self.rex(wide, 0, 0);
+        self.emit(&[0x99]);
+    }
+
+    /// Shifts or rotates `reg` by `cl`.
+    pub fn shift(&mut self, op: Shift, wide: bool, reg: Reg) {
+        self.rex(wide, 0, reg);
+        self.emit(&[0xd3]);
+        self.modrm(0b11, op as u8, reg);
+    }
+
+    pub fn shr_imm(&mut self, wide: bool, reg: Reg, count: u8) {
+        self.rex(wide, 0, reg);
+        self.emit(&[0xc1]);
+        self.modrm(0b11, Shift::Shr as u8, reg);
+        self.emit(&[count]);
+    }
+
+    /// `rax = count(rax)`
+    pub fn count(&mut self, op: Count, wide: bool) {
+        self.emit(&[0xf3]);
+        self.rex(wide, RAX, RAX);
+        self.emit(&[0x0f, op as u8]);
+        self.modrm(0b11, RAX, RAX);
+    }
+
+    /// Sets `rax` to 1 if `cond` holds, else to 0.
+    pub fn set(&mut self, cond: Cond) {
+        // setcc al; movzx eax, al
+        self.emit(&[0x0f, 0x90 | cond as u8, 0xc0, 0x0f, 0xb6, 0xc0]);
+    }
+
+    /// `lea dst, [src + disp8]`
+    pub fn lea(&mut self, dst: Reg, src: Reg, disp: i8) {
+        self.rex(true, dst, src);
+        self.emit(&[0x8d]);
+        self.modrm(0b01, dst, src);
+        self.emit(&[disp as u8]);
+    }
+
+    /// Reads memory at `rsi + rax` into `rax`.
+    pub fn load_memory(&mut self, load: Load) {
+        // The operand is always [rsi + rax]: modrm 04, sib 06.
+        let bytes: &[u8] = match load {
+            Load::U8 => &[0x0f, 0xb6],
+            Load::S8 => &[0x0f, 0xbe],
+            Load::U16 => &[0x0f, 0xb7],
+            Load::S16 => &[0x0f, 0xbf],
+            Load::U32 => &[0x8b],
+            Load::S32To64 => &[0x48, 0x63],
+            Load::S8To64 => &[0x48, 0x0f, 0xbe],
+            Load::S16To64 => &[0x48, 0x0f, 0xbf],
+            Load::U64 => &[0x48, 0x8b],
+        };
+        self.emit(bytes);
+        self.emit(&[0x04, 0x06]);
+    }
+
+    /// Writes the low `bytes` bytes of `rcx` to memory at `rsi + rax`.
+    pub fn store_memory(&mut self, bytes: u32) {
+        match bytes {
+            1 => self.emit(&[0x88]),
+            2 => self.emit(&[0x66, 0x89]),
+            4 => self.emit(&[0x89]),
+            _ => self.emit(&[0x48, 0x89]),
+        }
+        self.emit(&[0x0c, 0x06]);
+    }
+}
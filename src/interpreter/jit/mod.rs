@@ -0,0 +1,654 @@
+//! A single-pass baseline JIT compiler for x86-64 Linux, built with the `jit`
+//! feature. Every local and operand of a function gets a fixed 8-byte slot in
+//! a frame, so the machine code needs no register allocation and a branch
+//! only copies the value it carries. Bodies with calls, globals, floats or
+//! `grow_memory` are not compiled and run on the interpreter instead.
+
+mod asm;
+
+use self::asm::{Alu, Assembler, Cond, Count, Label, Load, Shift, R8, RAX, RCX, RDX};
+use interpreter::ir::{block_results, skip_unreachable, vu32};
+use interpreter::trap::Trap;
+use interpreter::value::Value;
+use parser::Type;
+use parser::code_section::FnBody;
+use parser::opcode::{Op, Opcode, Payload};
+use parser::type_section::FuncType;
+
+use std::{io, mem, ptr};
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("the jit feature needs x86-64 Linux");
+
+/// The status compiled code returns, 0 if it did not trap.
+const TRAPS: [Trap; 4] = [
+    Trap::Unreachable,
+    Trap::MemoryOutOfBounds,
+    Trap::IntegerDivideByZero,
+    Trap::IntegerOverflow,
+];
+
+const UNREACHABLE: usize = 0;
+const OUT_OF_BOUNDS: usize = 1;
+const DIVIDE_BY_ZERO: usize = 2;
+const OVERFLOW: usize = 3;
+
+/// The signature of compiled code: the frame of slots, the linear memory and
+/// its length in bytes.
+type Entry = unsafe extern "sysv64" fn(*mut u64, *mut u8, u64) -> u32;
+
+/// Pages mapped executable, holding the code of one function.
+struct Executable {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Executable {
+    fn new(code: &[u8]) -> io::Result<Executable> {
+        // The pages are writable until the code is copied, then executable.
+        unsafe {
+            let ptr = libc::mmap(
+                ptr::null_mut(),
+                code.len(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            ptr::copy_nonoverlapping(code.as_ptr(), ptr as *mut u8, code.len());
+            if libc::mprotect(ptr, code.len(), libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                let err = io::Error::last_os_error();
+                libc::munmap(ptr, code.len());
+                return Err(err);
+            }
+            return Ok(Executable {
+                ptr: ptr as *mut u8,
+                len: code.len(),
+            });
+        }
+    }
+}
+
+impl Drop for Executable {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// The machine code of a function.
+pub struct Code {
+    executable: Executable,
+    /// Number of slots of a frame: the locals, then the operands. The result
+    /// is returned in the first.
+    slots: usize,
+    result: Option<Type>,
+}
+
+impl Code {
+    /// Runs the function on `memory`, which must not change size in between.
+    pub fn call(&self, args: &[Value], memory: Option<&mut [u8]>) -> Result<Option<Value>, Trap> {
+        let mut slots = vec![0u64; self.slots];
+        for (slot, arg) in slots.iter_mut().zip(args) {
+            *slot = match *arg {
+                Value::I32(v) => v as u32 as u64,
+                Value::I64(v) => v as u64,
+                other => panic!("expected an integer argument, found {:?}", other),
+            };
+        }
+        let (base, len) = match memory {
+            Some(memory) => (memory.as_mut_ptr(), memory.len()),
+            None => (ptr::null_mut(), 0),
+        };
+        // The code only accesses the slots and, after a bounds check, the
+        // memory.
+        let status = unsafe {
+            let entry: Entry = mem::transmute(self.executable.ptr);
+            entry(slots.as_mut_ptr(), base, len as u64)
+        };
+        if status != 0 {
+            return Err(TRAPS[status as usize - 1].clone());
+        }
+        return Ok(match self.result {
+            Some(Type::I32) => Some(Value::I32(slots[0] as u32 as i32)),
+            Some(Type::I64) => Some(Value::I64(slots[0] as i64)),
+            _ => None,
+        });
+    }
+}
+
+/// A block (or the function body) being compiled.
+struct Block {
+    /// Operand stack height when the block was entered.
+    height: u32,
+    /// Number of values the block leaves on the stack.
+    results: u32,
+    is_loop: bool,
+    /// Where a branch to the block continues: the start of a loop, else the
+    /// end.
+    branch: Label,
+    end: Label,
+    /// The else branch of an `if` that has not reached its `else` yet.
+    otherwise: Option<Label>,
+}
+
+struct Compiler {
+    asm: Assembler,
+    locals: u32,
+    height: u32,
+    max_height: u32,
+    blocks: Vec<Block>,
+    traps: [Label; 4],
+    /// Whether the CPU has `lzcnt`, `tzcnt` and `popcnt`.
+    bit_counts: bool,
+}
+
+fn is_integer(typ: Type) -> bool {
+    typ == Type::I32 || typ == Type::I64
+}
+
+impl Compiler {
+    fn slot(&self, height: u32) -> u32 {
+        self.locals + height
+    }
+
+    fn push(&mut self) -> u32 {
+        self.height += 1;
+        self.max_height = self.max_height.max(self.height);
+        self.slot(self.height - 1)
+    }
+
+    fn pop(&mut self) -> u32 {
+        self.height -= 1;
+        self.slot(self.height)
+    }
+
+    fn top(&self) -> u32 {
+        self.slot(self.height - 1)
+    }
+
+    fn copy(&mut self, dst: u32, src: u32) {
+        if dst != src {
+            self.asm.load_slot(RAX, src);
+            self.asm.store_slot(dst, RAX);
+        }
+    }
+
+    fn block(&mut self, results: u32, is_loop: bool, otherwise: Option<Label>) {
+        let end = self.asm.label();
+        let branch = if is_loop {
+            let start = self.asm.label();
+            self.asm.bind(start);
+            start
+        } else {
+            end
+        };
+        self.blocks.push(Block {
+            height: self.height,
+            results,
+            is_loop,
+            branch,
+            end,
+            otherwise,
+        });
+    }
+
+    /// Jumps to the label `depth` levels up with the value it carries.
+    fn branch(&mut self, depth: u32) {
+        let (dst, carries, label) = {
+            let block = &self.blocks[self.blocks.len() - 1 - depth as usize];
+            (
+                block.height,
+                !block.is_loop && block.results > 0,
+                block.branch,
+            )
+        };
+        if carries {
+            let (dst, src) = (self.slot(dst), self.top());
+            self.copy(dst, src);
+        }
+        self.asm.jmp(label);
+    }
+
+    fn needs_copy(&self, depth: u32) -> bool {
+        let block = &self.blocks[self.blocks.len() - 1 - depth as usize];
+        !block.is_loop && block.results > 0 && self.slot(block.height) != self.top()
+    }
+
+    /// Leaves the effective address of an access of `size` bytes in `rax`,
+    /// or traps if it is out of bounds.
+    fn address(&mut self, addr: u32, offset: u32, size: i8) {
+        self.asm.load_slot(RAX, addr);
+        self.asm.clear_upper(RAX);
+        if offset != 0 {
+            self.asm.mov_imm(RCX, offset as u64);
+            self.asm.alu(Alu::Add, true, RAX, RCX);
+        }
+        self.asm.lea(RCX, RAX, size);
+        self.asm.alu(Alu::Cmp, true, RCX, R8);
+        self.asm.jcc(Cond::Above, self.traps[OUT_OF_BOUNDS]);
+    }
+
+    /// Divides `rax` by `rcx`, leaving the quotient or remainder in `rax`.
+    fn divide(&mut self, wide: bool, signed: bool, remainder: bool) {
+        self.asm.alu(Alu::Test, wide, RCX, RCX);
+        self.asm.jcc(Cond::Equal, self.traps[DIVIDE_BY_ZERO]);
+        let done = self.asm.label();
+        if signed {
+            // The minimum divided by -1 overflows, its remainder is 0.
+            let divide = self.asm.label();
+            self.asm.cmp_imm(wide, RCX, -1);
+            self.asm.jcc(Cond::NotEqual, divide);
+            if remainder {
+                self.asm.alu(Alu::Xor, false, RAX, RAX);
+                self.asm.jmp(done);
+            } else {
+                let min = if wide {
+                    i64::MIN as u64
+                } else {
+                    i32::MIN as u32 as u64
+                };
+                self.asm.mov_imm(RDX, min);
+                self.asm.alu(Alu::Cmp, wide, RAX, RDX);
+                self.asm.jcc(Cond::Equal, self.traps[OVERFLOW]);
+            }
+            self.asm.bind(divide);
+            self.asm.sign_extend_rax(wide);
+        } else {
+            self.asm.alu(Alu::Xor, false, RDX, RDX);
+        }
+        self.asm.div(wide, signed, RCX);
+        if remainder {
+            self.asm.mov(RAX, RDX);
+        }
+        self.asm.bind(done);
+    }
+
+    /// Compiles `op`, returning whether the code after it is reachable, or
+    /// `None` if the JIT does not support it.
+    fn compile(&mut self, op: &Op) -> Option<bool> {
+        let byte = op.opcode as u8;
+        match op.opcode {
+            Opcode::unreachable => {
+                self.asm.jmp(self.traps[UNREACHABLE]);
+                return Some(false);
+            }
+            Opcode::nop => {}
+            Opcode::block | Opcode::loop_ => {
+                let is_loop = op.opcode == Opcode::loop_;
+                self.block(block_results(&op.payload), is_loop, None);
+            }
+            Opcode::if_ => {
+                let cond = self.pop();
+                self.asm.load_slot(RAX, cond);
+                self.asm.alu(Alu::Test, false, RAX, RAX);
+                let otherwise = self.asm.label();
+                self.asm.jcc(Cond::Equal, otherwise);
+                self.block(block_results(&op.payload), false, Some(otherwise));
+            }
+            Opcode::else_ => {
+                let block = self.blocks.last_mut().expect("an if block");
+                self.asm.jmp(block.end);
+                self.asm.bind(block.otherwise.take().expect("an if block"));
+                self.height = block.height;
+            }
+            Opcode::end => {
+                let block = self.blocks.pop().expect("a block");
+                if let Some(otherwise) = block.otherwise {
+                    self.asm.bind(otherwise);
+                }
+                self.asm.bind(block.end);
+                self.height = block.height + block.results;
+            }
+            Opcode::br => {
+                self.branch(vu32(&op.payload));
+                return Some(false);
+            }
+            Opcode::br_if => {
+                let depth = vu32(&op.payload);
+                let cond = self.pop();
+                self.asm.load_slot(RAX, cond);
+                self.asm.alu(Alu::Test, false, RAX, RAX);
+                if self.needs_copy(depth) {
+                    let skip = self.asm.label();
+                    self.asm.jcc(Cond::Equal, skip);
+                    self.branch(depth);
+                    self.asm.bind(skip);
+                } else {
+                    let block = &self.blocks[self.blocks.len() - 1 - depth as usize];
+                    let label = block.branch;
+                    self.asm.jcc(Cond::NotEqual, label);
+                }
+            }
+            Opcode::br_table => {
+                let (table, default) = match op.payload {
+                    Payload::BrTable {
+                        ref target_table,
+                        default_target,
+                    } => (target_table, default_target),
+                    _ => panic!("expected a branch table"),
+                };
+                let index = self.pop();
+                self.asm.load_slot(RCX, index);
+                for (i, &depth) in table.iter().enumerate() {
+                    let next = self.asm.label();
+                    self.asm.cmp_imm(false, RCX, i as i32);
+                    self.asm.jcc(Cond::NotEqual, next);
+                    self.branch(depth);
+                    self.asm.bind(next);
+                }
+                self.branch(default);
+                return Some(false);
+            }
+            Opcode::return_ => {
+                self.branch(self.blocks.len() as u32 - 1);
+                return Some(false);
+            }
+            Opcode::drop => {
+                self.pop();
+            }
+            Opcode::select => {
+                let cond = self.pop();
+                let b = self.pop();
+                let a = self.top();
+                let keep = self.asm.label();
+                self.asm.load_slot(RAX, cond);
+                self.asm.alu(Alu::Test, false, RAX, RAX);
+                self.asm.jcc(Cond::NotEqual, keep);
+                self.copy(a, b);
+                self.asm.bind(keep);
+            }
+            Opcode::get_local => {
+                let dst = self.push();
+                self.copy(dst, vu32(&op.payload));
+            }
+            Opcode::set_local => {
+                let src = self.pop();
+                self.copy(vu32(&op.payload), src);
+            }
+            Opcode::tee_local => {
+                let src = self.top();
+                self.copy(vu32(&op.payload), src);
+            }
+            Opcode::i32_const | Opcode::i64_const => {
+                let value = match op.payload {
+                    Payload::VI32(v) => v as u32 as u64,
+                    Payload::VI64(v) => v as u64,
+                    _ => panic!("expected an integer constant"),
+                };
+                let dst = self.push();
+                self.asm.mov_imm(RAX, value);
+                self.asm.store_slot(dst, RAX);
+            }
+            Opcode::current_memory => {
+                let dst = self.push();
+                self.asm.mov(RAX, R8);
+                self.asm.shr_imm(true, RAX, 16);
+                self.asm.store_slot(dst, RAX);
+            }
+            Opcode::i32_load
+            | Opcode::i64_load
+            | Opcode::i32_load8_s
+            | Opcode::i32_load8_u
+            | Opcode::i32_load16_s
+            | Opcode::i32_load16_u
+            | Opcode::i64_load8_s
+            | Opcode::i64_load8_u
+            | Opcode::i64_load16_s
+            | Opcode::i64_load16_u
+            | Opcode::i64_load32_s
+            | Opcode::i64_load32_u => {
+                let (load, size) = match op.opcode {
+                    Opcode::i32_load | Opcode::i64_load32_u => (Load::U32, 4),
+                    Opcode::i64_load => (Load::U64, 8),
+                    Opcode::i32_load8_s => (Load::S8, 1),
+                    Opcode::i32_load8_u | Opcode::i64_load8_u => (Load::U8, 1),
+                    Opcode::i32_load16_s => (Load::S16, 2),
+                    Opcode::i32_load16_u | Opcode::i64_load16_u => (Load::U16, 2),
+                    Opcode::i64_load8_s => (Load::S8To64, 1),
+                    Opcode::i64_load16_s => (Load::S16To64, 2),
+                    _ => (Load::S32To64, 4),
+                };
+                let addr = self.top();
+                self.address(addr, offset(&op.payload), size);
+                self.asm.load_memory(load);
+                self.asm.store_slot(addr, RAX);
+            }
+            Opcode::i32_store
+            | Opcode::i64_store
+            | Opcode::i32_store8
+            | Opcode::i32_store16
+            | Opcode::i64_store8
+            | Opcode::i64_store16
+            | Opcode::i64_store32 => {
+                let size = match op.opcode {
+                    Opcode::i32_store8 | Opcode::i64_store8 => 1,
+                    Opcode::i32_store16 | Opcode::i64_store16 => 2,
+                    Opcode::i64_store => 8,
+                    _ => 4,
+                };
+                let value = self.pop();
+                let addr = self.pop();
+                self.address(addr, offset(&op.payload), size);
+                self.asm.load_slot(RCX, value);
+                self.asm.store_memory(size as u32);
+            }
+            Opcode::i32_eqz | Opcode::i64_eqz => {
+                let a = self.top();
+                self.asm.load_slot(RAX, a);
+                self.asm
+                    .alu(Alu::Test, op.opcode == Opcode::i64_eqz, RAX, RAX);
+                self.asm.set(Cond::Equal);
+                self.asm.store_slot(a, RAX);
+            }
+            Opcode::i32_wrap_i64 => {
+                let a = self.top();
+                self.asm.load_slot(RAX, a);
+                self.asm.clear_upper(RAX);
+                self.asm.store_slot(a, RAX);
+            }
+            // i32 slots are kept zero extended.
+            Opcode::i64_extend_u_i32 => {}
+            Opcode::i64_extend_s_i32 => {
+                let a = self.top();
+                self.asm.load_slot_s32(a);
+                self.asm.store_slot(a, RAX);
+            }
+            _ if (0x46..=0x4f).contains(&byte) || (0x51..=0x5a).contains(&byte) => {
+                let wide = byte >= 0x51;
+                let cond = [
+                    Cond::Equal,
+                    Cond::NotEqual,
+                    Cond::Less,
+                    Cond::Below,
+                    Cond::Greater,
+                    Cond::Above,
+                    Cond::LessEqual,
+                    Cond::BelowEqual,
+                    Cond::GreaterEqual,
+                    Cond::AboveEqual,
+                ][(byte - if wide { 0x51 } else { 0x46 }) as usize];
+                let b = self.pop();
+                let a = self.top();
+                self.asm.load_slot(RAX, a);
+                self.asm.load_slot(RCX, b);
+                self.asm.alu(Alu::Cmp, wide, RAX, RCX);
+                self.asm.set(cond);
+                self.asm.store_slot(a, RAX);
+            }
+            _ if (0x67..=0x69).contains(&byte) || (0x79..=0x7b).contains(&byte) => {
+                if !self.bit_counts {
+                    return None;
+                }
+                let wide = byte >= 0x79;
+                let count = [Count::Lzcnt, Count::Tzcnt, Count::Popcnt]
+                    [(byte - if wide { 0x79 } else { 0x67 }) as usize];
+                let a = self.top();
+                self.asm.load_slot(RAX, a);
+                self.asm.count(count, wide);
+                self.asm.store_slot(a, RAX);
+            }
+            _ if (0x6a..=0x78).contains(&byte) || (0x7c..=0x8a).contains(&byte) => {
+                let wide = byte >= 0x7c;
+                // The i64 operators are in the same order as the i32 ones.
+                let opcode = if wide { byte - 0x7c + 0x6a } else { byte };
+                let b = self.pop();
+                let a = self.top();
+                self.asm.load_slot(RAX, a);
+                self.asm.load_slot(RCX, b);
+                match opcode {
+                    0x6a => self.asm.alu(Alu::Add, wide, RAX, RCX),
+                    0x6b => self.asm.alu(Alu::Sub, wide, RAX, RCX),
+                    0x6c => self.asm.imul(wide, RAX, RCX),
+                    0x6d => self.divide(wide, true, false),
+                    0x6e => self.divide(wide, false, false),
+                    0x6f => self.divide(wide, true, true),
+                    0x70 => self.divide(wide, false, true),
+                    0x71 => self.asm.alu(Alu::And, wide, RAX, RCX),
+                    0x72 => self.asm.alu(Alu::Or, wide, RAX, RCX),
+                    0x73 => self.asm.alu(Alu::Xor, wide, RAX, RCX),
+                    0x74 => self.asm.shift(Shift::Shl, wide, RAX),
+                    0x75 => self.asm.shift(Shift::Sar, wide, RAX),
+                    0x76 => self.asm.shift(Shift::Shr, wide, RAX),
+                    0x77 => self.asm.shift(Shift::Rol, wide, RAX),
+                    _ => self.asm.shift(Shift::Ror, wide, RAX),
+                }
+                self.asm.store_slot(a, RAX);
+            }
+            _ => return None,
+        }
+        return Some(true);
+    }
+}
+
+fn offset(payload: &Payload) -> u32 {
+    match *payload {
+        Payload::MemoryImmediate { offset, .. } => offset,
+        _ => panic!("expected a memory immediate"),
+    }
+}
+
+/// Compiles a validated function body, or returns `None` if it uses
+/// something the JIT does not support.
+pub fn compile(typ: &FuncType, body: &FnBody) -> Option<Code> {
+    let types = typ.param_types.iter().chain(&typ.return_type);
+    if !types
+        .chain(body.locals.iter().map(|local| &local.typ))
+        .all(|&t| is_integer(t))
+    {
+        return None;
+    }
+    let declared: u32 = body.locals.iter().map(|local| local.count).sum();
+    let mut asm = Assembler::new();
+    let traps = [asm.label(), asm.label(), asm.label(), asm.label()];
+    let mut compiler = Compiler {
+        asm,
+        locals: typ.param_types.len() as u32 + declared,
+        height: 0,
+        max_height: 0,
+        blocks: Vec::new(),
+        traps,
+        bit_counts: is_x86_feature_detected!("lzcnt")
+            && is_x86_feature_detected!("bmi1")
+            && is_x86_feature_detected!("popcnt"),
+    };
+    // The length of the memory stays in r8, as rdx is taken by division.
+    compiler.asm.mov(R8, RDX);
+    compiler.block(typ.return_type.is_some() as u32, false, None);
+    let mut pc = 0;
+    while pc < body.code.len() {
+        let reachable = compiler.compile(&body.code[pc])?;
+        pc += 1;
+        if !reachable {
+            pc = skip_unreachable(&body.code, pc);
+        }
+    }
+    let function = compiler.blocks.pop().expect("the function block");
+    compiler.asm.bind(function.end);
+    if typ.return_type.is_some() {
+        let result = compiler.slot(0);
+        compiler.copy(0, result);
+    }
+    compiler.asm.alu(Alu::Xor, false, RAX, RAX);
+    compiler.asm.ret();
+    for (status, &trap) in compiler.traps.iter().enumerate() {
+        compiler.asm.bind(trap);
+        compiler.asm.mov_imm(RAX, status as u64 + 1);
+        compiler.asm.ret();
+    }
+    let slots = (compiler.locals + compiler.max_height).max(1) as usize;
+    let code = compiler.asm.finish();
+    return Some(Code {
+        executable: Executable::new(&code).ok()?,
+        slots,
+        result: typ.return_type,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interpreter::{Backend, Config, Extern, HostFunc, Imports, Instance};
+    use parser::Parser;
+
+    fn op(opcode: Opcode, payload: Payload) -> Op {
+        Op { opcode, payload }
+    }
+
+    #[test]
+    fn division_traps() {
+        let typ = FuncType {
+            form: Type::func,
+            param_types: vec![Type::I32, Type::I32],
+            return_type: Some(Type::I32),
+        };
+        let body = FnBody {
+            locals: Vec::new(),
+            code: vec![
+                op(Opcode::get_local, Payload::VU32(0)),
+                op(Opcode::get_local, Payload::VU32(1)),
+                op(Opcode::i32_div_s, Payload::None),
+            ],
+        };
+        let code = compile(&typ, &body).expect("compiled code");
+        let div = |a, b| code.call(&[Value::I32(a), Value::I32(b)], None);
+        assert_eq!(div(-7, 2), Ok(Some(Value::I32(-3))));
+        assert_eq!(div(7, 0), Err(Trap::IntegerDivideByZero));
+        assert_eq!(div(i32::MIN, -1), Err(Trap::IntegerOverflow));
+    }
+
+    #[test]
+    fn calls_imports() {
+        // (import "env" "double" (func (param i32) (result i32)))
+        // (func (export "f") (param i32) (result i32) get_local 0 call 0)
+        // (export "double" (func 0))
+        let wasm = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // types
+            0x02, 0x0e, 0x01, 0x03, b'e', b'n', b'v', 0x06, b'd', b'o', b'u', b'b', b'l', b'e',
+            0x00, 0x00, // imports
+            0x03, 0x02, 0x01, 0x00, // functions
+            0x07, 0x0e, 0x02, 0x01, b'f', 0x00, 0x01, 0x06, b'd', b'o', b'u', b'b', b'l', b'e',
+            0x00, 0x00, // exports
+            0x0a, 0x08, 0x01, 0x06, 0x00, 0x20, 0x00, 0x10, 0x00, 0x0b, // code
+        ];
+        let module = Parser::new(&wasm).parse().expect("valid module");
+        let double = HostFunc::new(vec![Type::I32], Some(Type::I32), |_, args| match args[0] {
+            Value::I32(v) => Ok(Some(Value::I32(v * 2))),
+            _ => unreachable!(),
+        });
+        let mut imports = Imports::new();
+        imports.define("env", "double", Extern::Func(double));
+        let config = Config {
+            backend: Backend::Jit,
+            ..Config::default()
+        };
+        let mut instance = Instance::with_config(&module, &imports, config).expect("instance");
+        assert_eq!(instance.invoke("f", &[Value::I32(21)]).ok(), Some(Some(Value::I32(42))));
+        assert_eq!(instance.invoke("double", &[Value::I32(4)]).ok(), Some(Some(Value::I32(8))));
+    }
+}
@@ -0,0 +1,248 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Embedder policy for the memories and tables of instances, consulted when
+/// they are created and whenever they grow.
+pub trait ResourceLimiter {
+    /// Whether a memory may grow from `current` to `desired` bytes. `maximum`
+    /// is the maximum of the memory type in bytes; growing beyond it fails
+    /// without asking the limiter.
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> bool;
+
+    /// Whether a table may grow from `current` to `desired` elements.
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool;
+
+    /// Whether another instance may be created.
+    fn instance_creating(&mut self) -> bool {
+        true
+    }
+
+    fn memory_creating(&mut self) -> bool {
+        true
+    }
+
+    fn table_creating(&mut self) -> bool {
+        true
+    }
+
+    fn instance_dropped(&mut self) {}
+
+    fn memory_dropped(&mut self) {}
+
+    fn table_dropped(&mut self) {}
+
+    /// Whether a failed `grow_memory` traps instead of returning -1.
+    fn trap_on_grow_failure(&self) -> bool {
+        false
+    }
+}
+
+/// A limiter shared by the instances it governs.
+pub type SharedLimiter = Rc<RefCell<dyn ResourceLimiter>>;
+
+/// A limiter with fixed caps. Counts of instances, memories and tables are
+/// over all instances using it.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    memory_size: Option<usize>,
+    table_elements: Option<u32>,
+    instances: Option<usize>,
+    memories: Option<usize>,
+    tables: Option<usize>,
+    trap_on_grow_failure: bool,
+    live_instances: usize,
+    live_memories: usize,
+    live_tables: usize,
+}
+
+fn below(limit: Option<usize>, count: usize) -> bool {
+    limit.is_none_or(|limit| count < limit)
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+
+    /// Bytes of linear memory per instance.
+    pub fn memory_size(&mut self, bytes: usize) -> &mut Limits {
+        self.memory_size = Some(bytes);
+        self
+    }
+
+    /// Elements per table.
+    pub fn table_elements(&mut self, elements: u32) -> &mut Limits {
+        self.table_elements = Some(elements);
+        self
+    }
+
+    pub fn instances(&mut self, count: usize) -> &mut Limits {
+        self.instances = Some(count);
+        self
+    }
+
+    pub fn memories(&mut self, count: usize) -> &mut Limits {
+        self.memories = Some(count);
+        self
+    }
+
+    pub fn tables(&mut self, count: usize) -> &mut Limits {
+        self.tables = Some(count);
+        self
+    }
+
+    pub fn trap_on_grow_failure(&mut self, trap: bool) -> &mut Limits {
+        self.trap_on_grow_failure = trap;
+        self
+    }
+
+    pub fn shared(&self) -> SharedLimiter {
+        Rc::new(RefCell::new(self.clone()))
+    }
+}
+
+impl ResourceLimiter for Limits {
+    fn memory_growing(&mut self, _: usize, desired: usize, _: Option<usize>) -> bool {
+        self.memory_size.is_none_or(|limit| desired <= limit)
+    }
+
+    fn table_growing(&mut self, _: u32, desired: u32, _: Option<u32>) -> bool {
+        self.table_elements.is_none_or(|limit| desired <= limit)
+    }
+
+    fn instance_creating(&mut self) -> bool {
+        let allowed = below(self.instances, self.live_instances);
+        self.live_instances += allowed as usize;
+        return allowed;
+    }
+
+    fn memory_creating(&mut self) -> bool {
+        let allowed = below(self.memories, self.live_memories);
+        self.live_memories += allowed as usize;
+        return allowed;
+    }
+
+    fn table_creating(&mut self) -> bool {
+        let allowed = below(self.tables, self.live_tables);
+        self.live_tables += allowed as usize;
+        return allowed;
+    }
+
+    fn instance_dropped(&mut self) {
+        self.live_instances -= 1;
+    }
+
+    fn memory_dropped(&mut self) {
+        self.live_memories -= 1;
+    }
+
+    fn table_dropped(&mut self) {
+        self.live_tables -= 1;
+    }
+
+    fn trap_on_grow_failure(&self) -> bool {
+        self.trap_on_grow_failure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interpreter::instance::{Backend, Config, Instance, InstantiationError, InvokeError};
+    use interpreter::imports::Imports;
+    use interpreter::memory::PAGE_SIZE;
+    use interpreter::trap::Trap;
+    use interpreter::value::Value;
+    use parser::{ParseResult, Parser};
+
+    /// A table of two elements, a memory of one page and
+    /// (func (export "grow") (param i32) (result i32) (grow_memory (get_local 0)))
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // types
+        0x03, 0x02, 0x01, 0x00, // functions
+        0x04, 0x04, 0x01, 0x70, 0x00, 0x02, // table
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory
+        0x07, 0x08, 0x01, 0x04, b'g', b'r', b'o', b'w', 0x00, 0x00, // exports
+        0x0a, 0x08, 0x01, 0x06, 0x00, 0x20, 0x00, 0x40, 0x00, 0x0b, // code
+    ];
+
+    fn instantiate<'m>(
+        module: &'m ParseResult,
+        limits: &Limits,
+        backend: Backend,
+    ) -> Result<Instance<'m>, InstantiationError> {
+        let config = Config {
+            backend,
+            limiter: Some(limits.shared()),
+            ..Config::default()
+        };
+        Instance::with_config(module, &Imports::new(), config)
+    }
+
+    #[test]
+    fn initial_sizes() {
+        let refused = [
+            Limits::new().memory_size(PAGE_SIZE - 1).clone(),
+            Limits::new().table_elements(1).clone(),
+            Limits::new().memories(0).clone(),
+            Limits::new().tables(0).clone(),
+            Limits::new().instances(0).clone(),
+        ];
+        let module = Parser::new(MODULE).parse().expect("valid module");
+        for limits in &refused {
+            match instantiate(&module, limits, Backend::Stack) {
+                Err(InstantiationError::ResourceLimitExceeded) => {}
+                Err(e) => panic!("{:?}: expected a refusal, found {:?}", limits, e),
+                Ok(_) => panic!("{:?}: expected a refusal", limits),
+            }
+        }
+        let exact = Limits::new().memory_size(PAGE_SIZE).table_elements(2).clone();
+        assert!(instantiate(&module, &exact, Backend::Stack).is_ok());
+    }
+
+    #[test]
+    fn grow_failure_returns_minus_one() {
+        let module = Parser::new(MODULE).parse().expect("valid module");
+        let backends = [
+            Backend::Stack,
+            Backend::Register,
+            #[cfg(feature = "jit")]
+            Backend::Jit,
+        ];
+        for &backend in &backends {
+            let limits = Limits::new().memory_size(2 * PAGE_SIZE).clone();
+            let mut instance = instantiate(&module, &limits, backend).expect("instance");
+            let result = instance.invoke("grow", &[Value::I32(1)]).expect("result");
+            assert_eq!(result, Some(Value::I32(1)));
+            let result = instance.invoke("grow", &[Value::I32(1)]).expect("result");
+            assert_eq!(result, Some(Value::I32(-1)));
+            let result = instance.invoke("grow", &[Value::I32(0)]).expect("result");
+            assert_eq!(result, Some(Value::I32(2)));
+        }
+    }
+
+    #[test]
+    fn grow_failure_traps() {
+        let module = Parser::new(MODULE).parse().expect("valid module");
+        let backends = [
+            Backend::Stack,
+            Backend::Register,
+            #[cfg(feature = "jit")]
+            Backend::Jit,
+        ];
+        for &backend in &backends {
+            let limits = Limits::new()
+                .memory_size(2 * PAGE_SIZE)
+                .trap_on_grow_failure(true)
+                .clone();
+            let mut instance = instantiate(&module, &limits, backend).expect("instance");
+            let result = instance.invoke("grow", &[Value::I32(1)]).expect("result");
+            assert_eq!(result, Some(Value::I32(1)));
+            match instance.invoke("grow", &[Value::I32(1)]) {
+                Err(InvokeError::Trap(Trap::ResourceLimitExceeded)) => {}
+                other => panic!("expected a limit trap, found {:?}", other),
+            }
+        }
+    }
+}
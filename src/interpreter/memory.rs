@@ -0,0 +1,270 @@
+use interpreter::trap::Trap;
+use interpreter::value::Value;
+use interpreter::byteorder::{ByteOrder, LittleEndian};
+use parser::opcode::Opcode;
+
+pub const PAGE_SIZE: usize = 65536;
+/// The 32 bit address space allows at most this many pages.
+pub const MAX_PAGES: u32 = 65536;
+
+/// A linear memory.
+#[derive(Clone, Debug)]
+pub struct Memory {
+    data: Vec<u8>,
+    maximum: Option<u32>,
+}
+
+macro_rules! load {
+    ($mem:expr, $addr:ident, $offset:ident, $load:ident => $r:ident, |$v:ident| $e:expr) => {{
+        let $v = $mem.$load($addr, $offset)?;
+        Value::$r($e)
+    }};
+}
+
+macro_rules! store {
+    (
+        $mem:expr,
+        $addr:ident,
+        $offset:ident,
+        $value:ident,
+        $t:ident,
+        $store:ident,
+        |$v:ident| $e:expr
+    ) => {{
+        let $v = match $value {
+            Value::$t(v) => v,
+            other => panic!("expected {} operand, found {:?}", stringify!($t), other),
+        };
+        $mem.$store($addr, $offset, $e)?;
+    }};
+}
+
+impl Memory {
+    pub fn new(initial: u32, maximum: Option<u32>) -> Memory {
+        Memory {
+            data: vec![0; initial as usize * PAGE_SIZE],
+            maximum,
+        }
+    }
+
+    /// Current size in pages.
+    pub fn size(&self) -> u32 {
+        (self.data.len() / PAGE_SIZE) as u32
+    }
+
+    pub fn maximum(&self) -> Option<u32> {
+        self.maximum
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Grows the memory by `delta` pages and returns the previous size, or
+    /// `None` if the maximum would be exceeded.
+    pub fn grow(&mut self, delta: u32) -> Option<u32> {
+        let old = self.size();
+        let new = old.checked_add(delta)?;
+        if new > self.maximum.unwrap_or(MAX_PAGES).min(MAX_PAGES) {
+            return None;
+        }
+        self.data.resize(new as usize * PAGE_SIZE, 0);
+        return Some(old);
+    }
+
+    fn range(&self, addr: u32, offset: u32, len: usize) -> Result<(usize, usize), Trap> {
+        let start = addr as u64 + offset as u64;
+        let end = start + len as u64;
+        if end > self.data.len() as u64 {
+            return Err(Trap::MemoryOutOfBounds);
+        }
+        Ok((start as usize, end as usize))
+    }
+
+    /// The bytes at `addr + offset`.
+    pub fn read(&self, addr: u32, offset: u32, len: usize) -> Result<&[u8], Trap> {
+        let (start, end) = self.range(addr, offset, len)?;
+        Ok(&self.data[start..end])
+    }
+
+    pub fn write(&mut self, addr: u32, offset: u32, bytes: &[u8]) -> Result<(), Trap> {
+        let (start, end) = self.range(addr, offset, bytes.len())?;
+        self.data[start..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn load_u8(&self, addr: u32, offset: u32) -> Result<u8, Trap> {
+        Ok(self.read(addr, offset, 1)?[0])
+    }
+
+    pub fn load_u16(&self, addr: u32, offset: u32) -> Result<u16, Trap> {
+        Ok(LittleEndian::read_u16(self.read(addr, offset, 2)?))
+    }
+
+    pub fn load_u32(&self, addr: u32, offset: u32) -> Result<u32, Trap> {
+        Ok(LittleEndian::read_u32(self.read(addr, offset, 4)?))
+    }
+
+    pub fn load_u64(&self, addr: u32, offset: u32) -> Result<u64, Trap> {
+        Ok(LittleEndian::read_u64(self.read(addr, offset, 8)?))
+    }
+
+    pub fn store_u8(&mut self, addr: u32, offset: u32, value: u8) -> Result<(), Trap> {
+        self.write(addr, offset, &[value])
+    }
+
+    pub fn store_u16(&mut self, addr: u32, offset: u32, value: u16) -> Result<(), Trap> {
+        let mut buf = [0; 2];
+        LittleEndian::write_u16(&mut buf, value);
+        self.write(addr, offset, &buf)
+    }
+
+    pub fn store_u32(&mut self, addr: u32, offset: u32, value: u32) -> Result<(), Trap> {
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, value);
+        self.write(addr, offset, &buf)
+    }
+
+    pub fn store_u64(&mut self, addr: u32, offset: u32, value: u64) -> Result<(), Trap> {
+        let mut buf = [0; 8];
+        LittleEndian::write_u64(&mut buf, value);
+        self.write(addr, offset, &buf)
+    }
+
+    /// Executes the load instruction `opcode`.
+    pub fn load(&self, opcode: Opcode, addr: u32, offset: u32) -> Result<Value, Trap> {
+        let value = match opcode {
+            Opcode::i32_load => {
+                load!(self, addr, offset, load_u32 => I32, |v| v as i32)
+            }
+            Opcode::i64_load => {
+                load!(self, addr, offset, load_u64 => I64, |v| v as i64)
+            }
+            Opcode::f32_load => {
+                load!(self, addr, offset, load_u32 => F32, |v| f32::from_bits(v))
+            }
+            Opcode::f64_load => {
+                load!(self, addr, offset, load_u64 => F64, |v| f64::from_bits(v))
+            }
+            Opcode::i32_load8_s => {
+                load!(self, addr, offset, load_u8 => I32, |v| v as i8 as i32)
+            }
+            Opcode::i32_load8_u => {
+                load!(self, addr, offset, load_u8 => I32, |v| v as i32)
+            }
+            Opcode::i32_load16_s => {
+                load!(self, addr, offset, load_u16 => I32, |v| v as i16 as i32)
+            }
+            Opcode::i32_load16_u => {
+                load!(self, addr, offset, load_u16 => I32, |v| v as i32)
+            }
+            Opcode::i64_load8_s => {
+                load!(self, addr, offset, load_u8 => I64, |v| v as i8 as i64)
+            }
+            Opcode::i64_load8_u => {
+                load!(self, addr, offset, load_u8 => I64, |v| v as i64)
+            }
+            Opcode::i64_load16_s => {
+                load!(self, addr, offset, load_u16 => I64, |v| v as i16 as i64)
+            }
+            Opcode::i64_load16_u => {
+                load!(self, addr, offset, load_u16 => I64, |v| v as i64)
+            }
+            Opcode::i64_load32_s => {
+                load!(self, addr, offset, load_u32 => I64, |v| v as i32 as i64)
+            }
+            Opcode::i64_load32_u => {
+                load!(self, addr, offset, load_u32 => I64, |v| v as i64)
+            }
+            _ => panic!("expected a load"),
+        };
+        return Ok(value);
+    }
+
+    /// Executes the store instruction `opcode`.
+    pub fn store(
+        &mut self,
+        opcode: Opcode,
+        addr: u32,
+        offset: u32,
+        value: Value,
+    ) -> Result<(), Trap> {
+        match opcode {
+            Opcode::i32_store => {
+                store!(self, addr, offset, value, I32, store_u32, |v| v as u32)
+            }
+            Opcode::i64_store => {
+                store!(self, addr, offset, value, I64, store_u64, |v| v as u64)
+            }
+            Opcode::f32_store => {
+                store!(self, addr, offset, value, F32, store_u32, |v| v.to_bits())
+            }
+            Opcode::f64_store => {
+                store!(self, addr, offset, value, F64, store_u64, |v| v.to_bits())
+            }
+            Opcode::i32_store8 => {
+                store!(self, addr, offset, value, I32, store_u8, |v| v as u8)
+            }
+            Opcode::i32_store16 => {
+                store!(self, addr, offset, value, I32, store_u16, |v| v as u16)
+            }
+            Opcode::i64_store8 => {
+                store!(self, addr, offset, value, I64, store_u8, |v| v as u8)
+            }
+            Opcode::i64_store16 => {
+                store!(self, addr, offset, value, I64, store_u16, |v| v as u16)
+            }
+            Opcode::i64_store32 => {
+                store!(self, addr, offset, value, I64, store_u32, |v| v as u32)
+            }
+            _ => panic!("expected a store"),
+        }
+        return Ok(());
+    }
+}
+
+/// A table of function references, holding indices into the function index
+/// space of the instance.
+#[derive(Clone, Debug)]
+pub struct Table {
+    elements: Vec<Option<u32>>,
+    maximum: Option<u32>,
+}
+
+impl Table {
+    pub fn new(initial: u32, maximum: Option<u32>) -> Table {
+        Table {
+            elements: vec![None; initial as usize],
+            maximum,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.elements.len() as u32
+    }
+
+    pub fn maximum(&self) -> Option<u32> {
+        self.maximum
+    }
+
+    pub fn get(&self, index: u32) -> Result<Option<u32>, Trap> {
+        match self.elements.get(index as usize) {
+            Some(&element) => Ok(element),
+            None => Err(Trap::UndefinedElement),
+        }
+    }
+
+    pub fn set(&mut self, index: u32, element: Option<u32>) -> Result<(), Trap> {
+        match self.elements.get_mut(index as usize) {
+            Some(slot) => {
+                *slot = element;
+                Ok(())
+            }
+            None => Err(Trap::UndefinedElement),
+        }
+    }
+}
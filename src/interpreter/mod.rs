@@ -0,0 +1,26 @@
+//! Executes validated modules.
+
+extern crate byteorder;
+
+pub mod value;
+pub mod trap;
+pub mod memory;
+pub mod fuel;
+pub mod limits;
+pub mod imports;
+pub mod instance;
+mod exec;
+pub(crate) mod ir;
+mod fusion;
+#[cfg(feature = "jit")]
+mod jit;
+mod register;
+mod register_exec;
+mod numeric;
+
+pub use self::imports::{Extern, HostContext, HostFunc, Imports};
+pub use self::fuel::FuelCosts;
+pub use self::limits::{Limits, ResourceLimiter};
+pub use self::instance::{Backend, Config, Instance, InstantiationError, InvokeError};
+pub use self::trap::{TraceFrame, Trap};
+pub use self::value::Value;
@@ -0,0 +1,325 @@
+//! The numeric instructions, and the operations whose wasm semantics differ
+//! from Rust's operators.
+
+use interpreter::trap::Trap;
+use interpreter::value::Value;
+use parser::opcode::Opcode;
+
+pub fn div_s32(a: i32, b: i32) -> Result<i32, Trap> {
+    match (a, b) {
+        (_, 0) => Err(Trap::IntegerDivideByZero),
+        (i32::MIN, -1) => Err(Trap::IntegerOverflow),
+        _ => Ok(a / b),
+    }
+}
+
+pub fn div_u32(a: i32, b: i32) -> Result<i32, Trap> {
+    match b {
+        0 => Err(Trap::IntegerDivideByZero),
+        _ => Ok((a as u32 / b as u32) as i32),
+    }
+}
+
+pub fn rem_s32(a: i32, b: i32) -> Result<i32, Trap> {
+    match b {
+        0 => Err(Trap::IntegerDivideByZero),
+        _ => Ok(a.wrapping_rem(b)),
+    }
+}
+
+pub fn rem_u32(a: i32, b: i32) -> Result<i32, Trap> {
+    match b {
+        0 => Err(Trap::IntegerDivideByZero),
+        _ => Ok((a as u32 % b as u32) as i32),
+    }
+}
+
+pub fn div_s64(a: i64, b: i64) -> Result<i64, Trap> {
+    match (a, b) {
+        (_, 0) => Err(Trap::IntegerDivideByZero),
+        (i64::MIN, -1) => Err(Trap::IntegerOverflow),
+        _ => Ok(a / b),
+    }
+}
+
+pub fn div_u64(a: i64, b: i64) -> Result<i64, Trap> {
+    match b {
+        0 => Err(Trap::IntegerDivideByZero),
+        _ => Ok((a as u64 / b as u64) as i64),
+    }
+}
+
+pub fn rem_s64(a: i64, b: i64) -> Result<i64, Trap> {
+    match b {
+        0 => Err(Trap::IntegerDivideByZero),
+        _ => Ok(a.wrapping_rem(b)),
+    }
+}
+
+pub fn rem_u64(a: i64, b: i64) -> Result<i64, Trap> {
+    match b {
+        0 => Err(Trap::IntegerDivideByZero),
+        _ => Ok((a as u64 % b as u64) as i64),
+    }
+}
+
+// min and max propagate NaN and order -0 below +0, unlike f32::min/max
+
+pub fn min32(a: f32, b: f32) -> f32 {
+    if a.is_nan() || b.is_nan() {
+        f32::NAN
+    } else if a == b {
+        f32::from_bits(a.to_bits() | b.to_bits())
+    } else {
+        a.min(b)
+    }
+}
+
+pub fn max32(a: f32, b: f32) -> f32 {
+    if a.is_nan() || b.is_nan() {
+        f32::NAN
+    } else if a == b {
+        f32::from_bits(a.to_bits() & b.to_bits())
+    } else {
+        a.max(b)
+    }
+}
+
+pub fn min64(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        f64::from_bits(a.to_bits() | b.to_bits())
+    } else {
+        a.min(b)
+    }
+}
+
+pub fn max64(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        f64::from_bits(a.to_bits() & b.to_bits())
+    } else {
+        a.max(b)
+    }
+}
+
+// float to integer truncation; f32 operands are widened to f64 exactly
+
+fn check_trunc(x: f64, lower: f64, upper: f64) -> Result<(), Trap> {
+    if x.is_nan() {
+        return Err(Trap::InvalidConversionToInteger);
+    }
+    if x <= lower || x >= upper {
+        return Err(Trap::IntegerOverflow);
+    }
+    Ok(())
+}
+
+pub fn trunc_s32(x: f64) -> Result<i32, Trap> {
+    check_trunc(x, -2147483649.0, 2147483648.0)?;
+    Ok(x as i32)
+}
+
+pub fn trunc_u32(x: f64) -> Result<i32, Trap> {
+    check_trunc(x, -1.0, 4294967296.0)?;
+    Ok(x as u32 as i32)
+}
+
+pub fn trunc_s64(x: f64) -> Result<i64, Trap> {
+    // -2^63 itself is representable
+    if x == -9223372036854775808.0 {
+        return Ok(i64::MIN);
+    }
+    check_trunc(x, -9223372036854775808.0, 9223372036854775808.0)?;
+    Ok(x as i64)
+}
+
+pub fn trunc_u64(x: f64) -> Result<i64, Trap> {
+    check_trunc(x, -1.0, 18446744073709551616.0)?;
+    Ok(x as u64 as i64)
+}
+
+macro_rules! operand {
+    ($value:ident, $t:ident) => {
+        match $value {
+            Value::$t(v) => v,
+            other => panic!("expected {} operand, found {:?}", stringify!($t), other),
+        }
+    };
+}
+
+macro_rules! unop {
+    ($value:ident, $t:ident => $r:ident, |$a:ident| $e:expr) => {{
+        let $a = operand!($value, $t);
+        Value::$r($e)
+    }};
+}
+
+macro_rules! binop {
+    ($lhs:ident, $rhs:ident, $t:ident => $r:ident, |$a:ident, $b:ident| $e:expr) => {{
+        let $a = operand!($lhs, $t);
+        let $b = operand!($rhs, $t);
+        Value::$r($e)
+    }};
+}
+
+/// Whether the numeric instruction `opcode` takes two operands.
+pub fn is_binary(opcode: Opcode) -> bool {
+    matches!(
+        opcode as u8,
+        0x46..=0x4f | 0x51..=0x66 | 0x6a..=0x78 | 0x7c..=0x8a | 0x92..=0x98 | 0xa0..=0xa6
+    )
+}
+
+/// Executes a numeric instruction with one operand: a test, a unary operator
+/// or a conversion.
+#[inline(always)]
+pub fn unary(opcode: Opcode, a: Value) -> Result<Value, Trap> {
+    let result = match opcode {
+        Opcode::i32_eqz => unop!(a, I32 => I32, |a| (a == 0) as i32),
+        Opcode::i64_eqz => unop!(a, I64 => I32, |a| (a == 0) as i32),
+        Opcode::i32_clz => unop!(a, I32 => I32, |a| a.leading_zeros() as i32),
+        Opcode::i32_ctz => unop!(a, I32 => I32, |a| a.trailing_zeros() as i32),
+        Opcode::i32_popcnt => unop!(a, I32 => I32, |a| a.count_ones() as i32),
+        Opcode::i64_clz => unop!(a, I64 => I64, |a| a.leading_zeros() as i64),
+        Opcode::i64_ctz => unop!(a, I64 => I64, |a| a.trailing_zeros() as i64),
+        Opcode::i64_popcnt => unop!(a, I64 => I64, |a| a.count_ones() as i64),
+        Opcode::f32_abs => unop!(a, F32 => F32, |a| a.abs()),
+        Opcode::f32_neg => unop!(a, F32 => F32, |a| -a),
+        Opcode::f32_ceil => unop!(a, F32 => F32, |a| a.ceil()),
+        Opcode::f32_floor => unop!(a, F32 => F32, |a| a.floor()),
+        Opcode::f32_trunc => unop!(a, F32 => F32, |a| a.trunc()),
+        Opcode::f32_nearest => unop!(a, F32 => F32, |a| a.round_ties_even()),
+        Opcode::f32_sqrt => unop!(a, F32 => F32, |a| a.sqrt()),
+        Opcode::f64_abs => unop!(a, F64 => F64, |a| a.abs()),
+        Opcode::f64_neg => unop!(a, F64 => F64, |a| -a),
+        Opcode::f64_ceil => unop!(a, F64 => F64, |a| a.ceil()),
+        Opcode::f64_floor => unop!(a, F64 => F64, |a| a.floor()),
+        Opcode::f64_trunc => unop!(a, F64 => F64, |a| a.trunc()),
+        Opcode::f64_nearest => unop!(a, F64 => F64, |a| a.round_ties_even()),
+        Opcode::f64_sqrt => unop!(a, F64 => F64, |a| a.sqrt()),
+        Opcode::i32_wrap_i64 => unop!(a, I64 => I32, |a| a as i32),
+        Opcode::i32_trunc_s_f32 => unop!(a, F32 => I32, |a| trunc_s32(a as f64)?),
+        Opcode::i32_trunc_u_f32 => unop!(a, F32 => I32, |a| trunc_u32(a as f64)?),
+        Opcode::i32_trunc_s_f64 => unop!(a, F64 => I32, |a| trunc_s32(a)?),
+        Opcode::i32_trunc_u_f64 => unop!(a, F64 => I32, |a| trunc_u32(a)?),
+        Opcode::i64_extend_s_i32 => unop!(a, I32 => I64, |a| a as i64),
+        Opcode::i64_extend_u_i32 => unop!(a, I32 => I64, |a| a as u32 as i64),
+        Opcode::i64_trunc_s_f32 => unop!(a, F32 => I64, |a| trunc_s64(a as f64)?),
+        Opcode::i64_trunc_u_f32 => unop!(a, F32 => I64, |a| trunc_u64(a as f64)?),
+        Opcode::i64_trunc_s_f64 => unop!(a, F64 => I64, |a| trunc_s64(a)?),
+        Opcode::i64_trunc_u_f64 => unop!(a, F64 => I64, |a| trunc_u64(a)?),
+        Opcode::f32_convert_s_i32 => unop!(a, I32 => F32, |a| a as f32),
+        Opcode::f32_convert_u_i32 => unop!(a, I32 => F32, |a| a as u32 as f32),
+        Opcode::f32_convert_s_i64 => unop!(a, I64 => F32, |a| a as f32),
+        Opcode::f32_convert_u_i64 => unop!(a, I64 => F32, |a| a as u64 as f32),
+        Opcode::f32_demote_f64 => unop!(a, F64 => F32, |a| a as f32),
+        Opcode::f64_convert_s_i32 => unop!(a, I32 => F64, |a| a as f64),
+        Opcode::f64_convert_u_i32 => unop!(a, I32 => F64, |a| a as u32 as f64),
+        Opcode::f64_convert_s_i64 => unop!(a, I64 => F64, |a| a as f64),
+        Opcode::f64_convert_u_i64 => unop!(a, I64 => F64, |a| a as u64 as f64),
+        Opcode::f64_promote_f32 => unop!(a, F32 => F64, |a| a as f64),
+        Opcode::i32_reinterpret_f32 => unop!(a, F32 => I32, |a| a.to_bits() as i32),
+        Opcode::i64_reinterpret_f64 => unop!(a, F64 => I64, |a| a.to_bits() as i64),
+        Opcode::f32_reinterpret_i32 => unop!(a, I32 => F32, |a| f32::from_bits(a as u32)),
+        Opcode::f64_reinterpret_i64 => unop!(a, I64 => F64, |a| f64::from_bits(a as u64)),
+        _ => unreachable!("{:?} is not a unary instruction", opcode),
+    };
+    return Ok(result);
+}
+
+/// Executes a numeric instruction with two operands: a comparison or a
+/// binary operator.
+#[inline(always)]
+pub fn binary(opcode: Opcode, a: Value, b: Value) -> Result<Value, Trap> {
+    let result = match opcode {
+        Opcode::i32_eq => binop!(a, b, I32 => I32, |a, b| (a == b) as i32),
+        Opcode::i32_ne => binop!(a, b, I32 => I32, |a, b| (a != b) as i32),
+        Opcode::i32_lt_s => binop!(a, b, I32 => I32, |a, b| (a < b) as i32),
+        Opcode::i32_lt_u => binop!(a, b, I32 => I32, |a, b| ((a as u32) < b as u32) as i32),
+        Opcode::i32_gt_s => binop!(a, b, I32 => I32, |a, b| (a > b) as i32),
+        Opcode::i32_gt_u => binop!(a, b, I32 => I32, |a, b| (a as u32 > b as u32) as i32),
+        Opcode::i32_le_s => binop!(a, b, I32 => I32, |a, b| (a <= b) as i32),
+        Opcode::i32_le_u => binop!(a, b, I32 => I32, |a, b| (a as u32 <= b as u32) as i32),
+        Opcode::i32_ge_s => binop!(a, b, I32 => I32, |a, b| (a >= b) as i32),
+        Opcode::i32_ge_u => binop!(a, b, I32 => I32, |a, b| (a as u32 >= b as u32) as i32),
+        Opcode::i64_eq => binop!(a, b, I64 => I32, |a, b| (a == b) as i32),
+        Opcode::i64_ne => binop!(a, b, I64 => I32, |a, b| (a != b) as i32),
+        Opcode::i64_lt_s => binop!(a, b, I64 => I32, |a, b| (a < b) as i32),
+        Opcode::i64_lt_u => binop!(a, b, I64 => I32, |a, b| ((a as u64) < b as u64) as i32),
+        Opcode::i64_gt_s => binop!(a, b, I64 => I32, |a, b| (a > b) as i32),
+        Opcode::i64_gt_u => binop!(a, b, I64 => I32, |a, b| (a as u64 > b as u64) as i32),
+        Opcode::i64_le_s => binop!(a, b, I64 => I32, |a, b| (a <= b) as i32),
+        Opcode::i64_le_u => binop!(a, b, I64 => I32, |a, b| (a as u64 <= b as u64) as i32),
+        Opcode::i64_ge_s => binop!(a, b, I64 => I32, |a, b| (a >= b) as i32),
+        Opcode::i64_ge_u => binop!(a, b, I64 => I32, |a, b| (a as u64 >= b as u64) as i32),
+        Opcode::f32_eq => binop!(a, b, F32 => I32, |a, b| (a == b) as i32),
+        Opcode::f32_ne => binop!(a, b, F32 => I32, |a, b| (a != b) as i32),
+        Opcode::f32_lt => binop!(a, b, F32 => I32, |a, b| (a < b) as i32),
+        Opcode::f32_gt => binop!(a, b, F32 => I32, |a, b| (a > b) as i32),
+        Opcode::f32_le => binop!(a, b, F32 => I32, |a, b| (a <= b) as i32),
+        Opcode::f32_ge => binop!(a, b, F32 => I32, |a, b| (a >= b) as i32),
+        Opcode::f64_eq => binop!(a, b, F64 => I32, |a, b| (a == b) as i32),
+        Opcode::f64_ne => binop!(a, b, F64 => I32, |a, b| (a != b) as i32),
+        Opcode::f64_lt => binop!(a, b, F64 => I32, |a, b| (a < b) as i32),
+        Opcode::f64_gt => binop!(a, b, F64 => I32, |a, b| (a > b) as i32),
+        Opcode::f64_le => binop!(a, b, F64 => I32, |a, b| (a <= b) as i32),
+        Opcode::f64_ge => binop!(a, b, F64 => I32, |a, b| (a >= b) as i32),
+        Opcode::i32_add => binop!(a, b, I32 => I32, |a, b| a.wrapping_add(b)),
+        Opcode::i32_sub => binop!(a, b, I32 => I32, |a, b| a.wrapping_sub(b)),
+        Opcode::i32_mul => binop!(a, b, I32 => I32, |a, b| a.wrapping_mul(b)),
+        Opcode::i32_div_s => binop!(a, b, I32 => I32, |a, b| div_s32(a, b)?),
+        Opcode::i32_div_u => binop!(a, b, I32 => I32, |a, b| div_u32(a, b)?),
+        Opcode::i32_rem_s => binop!(a, b, I32 => I32, |a, b| rem_s32(a, b)?),
+        Opcode::i32_rem_u => binop!(a, b, I32 => I32, |a, b| rem_u32(a, b)?),
+        Opcode::i32_and => binop!(a, b, I32 => I32, |a, b| a & b),
+        Opcode::i32_or => binop!(a, b, I32 => I32, |a, b| a | b),
+        Opcode::i32_xor => binop!(a, b, I32 => I32, |a, b| a ^ b),
+        Opcode::i32_shl => binop!(a, b, I32 => I32, |a, b| a.wrapping_shl(b as u32)),
+        Opcode::i32_shr_s => binop!(a, b, I32 => I32, |a, b| a.wrapping_shr(b as u32)),
+        Opcode::i32_shr_u => {
+            binop!(a, b, I32 => I32, |a, b| (a as u32).wrapping_shr(b as u32) as i32)
+        }
+        Opcode::i32_rotl => binop!(a, b, I32 => I32, |a, b| a.rotate_left(b as u32 % 32)),
+        Opcode::i32_rotr => binop!(a, b, I32 => I32, |a, b| a.rotate_right(b as u32 % 32)),
+        Opcode::i64_add => binop!(a, b, I64 => I64, |a, b| a.wrapping_add(b)),
+        Opcode::i64_sub => binop!(a, b, I64 => I64, |a, b| a.wrapping_sub(b)),
+        Opcode::i64_mul => binop!(a, b, I64 => I64, |a, b| a.wrapping_mul(b)),
+        Opcode::i64_div_s => binop!(a, b, I64 => I64, |a, b| div_s64(a, b)?),
+        Opcode::i64_div_u => binop!(a, b, I64 => I64, |a, b| div_u64(a, b)?),
+        Opcode::i64_rem_s => binop!(a, b, I64 => I64, |a, b| rem_s64(a, b)?),
+        Opcode::i64_rem_u => binop!(a, b, I64 => I64, |a, b| rem_u64(a, b)?),
+        Opcode::i64_and => binop!(a, b, I64 => I64, |a, b| a & b),
+        Opcode::i64_or => binop!(a, b, I64 => I64, |a, b| a | b),
+        Opcode::i64_xor => binop!(a, b, I64 => I64, |a, b| a ^ b),
+        Opcode::i64_shl => binop!(a, b, I64 => I64, |a, b| a.wrapping_shl(b as u32)),
+        Opcode::i64_shr_s => binop!(a, b, I64 => I64, |a, b| a.wrapping_shr(b as u32)),
+        Opcode::i64_shr_u => {
+            binop!(a, b, I64 => I64, |a, b| (a as u64).wrapping_shr(b as u32) as i64)
+        }
+        Opcode::i64_rotl => {
+            binop!(a, b, I64 => I64, |a, b| a.rotate_left((b % 64) as u32))
+        }
+        Opcode::i64_rotr => {
+            binop!(a, b, I64 => I64, |a, b| a.rotate_right((b % 64) as u32))
+        }
+        Opcode::f32_add => binop!(a, b, F32 => F32, |a, b| a + b),
+        Opcode::f32_sub => binop!(a, b, F32 => F32, |a, b| a - b),
+        Opcode::f32_mul => binop!(a, b, F32 => F32, |a, b| a * b),
+        Opcode::f32_div => binop!(a, b, F32 => F32, |a, b| a / b),
+        Opcode::f32_min => binop!(a, b, F32 => F32, |a, b| min32(a, b)),
+        Opcode::f32_max => binop!(a, b, F32 => F32, |a, b| max32(a, b)),
+        Opcode::f32_copysign => binop!(a, b, F32 => F32, |a, b| a.copysign(b)),
+        Opcode::f64_add => binop!(a, b, F64 => F64, |a, b| a + b),
+        Opcode::f64_sub => binop!(a, b, F64 => F64, |a, b| a - b),
+        Opcode::f64_mul => binop!(a, b, F64 => F64, |a, b| a * b),
+        Opcode::f64_div => binop!(a, b, F64 => F64, |a, b| a / b),
+        Opcode::f64_min => binop!(a, b, F64 => F64, |a, b| min64(a, b)),
+        Opcode::f64_max => binop!(a, b, F64 => F64, |a, b| max64(a, b)),
+        Opcode::f64_copysign => binop!(a, b, F64 => F64, |a, b| a.copysign(b)),
+        _ => unreachable!("{:?} is not a binary instruction", opcode),
+    };
+    return Ok(result);
+}
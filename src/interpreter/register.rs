@@ -0,0 +1,584 @@
+//! A register-based bytecode for the register backend. Every frame has a
+//! window of registers on the value stack: the locals, then the constants of
+//! the body, then one slot for each operand stack height. Instructions name
+//! their operands and destination, so `get_local` and constants need no
+//! instruction of their own and most values are never copied.
+
+use interpreter::fuel::FuelCosts;
+use interpreter::ir::{block_results, skip_unreachable, vu32};
+use interpreter::numeric;
+use interpreter::value::Value;
+use parser::code_section::FnBody;
+use parser::opcode::{Op, Opcode, Payload};
+use parser::type_section::FuncType;
+
+/// Index of a register in the window of a frame.
+pub type Reg = u32;
+
+/// Where a branch continues and which register receives the value it
+/// carries. Branches without a value copy the first slot onto itself.
+#[derive(Clone, Copy, Debug)]
+pub struct Target {
+    pub pc: u32,
+    pub src: Reg,
+    pub dst: Reg,
+}
+
+#[derive(Clone, Debug)]
+pub enum Instr {
+    Unreachable,
+    Br(Target),
+    BrIf {
+        cond: Reg,
+        target: Target,
+    },
+    /// The targets of a `br_table`, the default last.
+    BrTable {
+        index: Reg,
+        targets: Box<[Target]>,
+    },
+    /// Continues at `pc` if `cond` is zero, lowered from `if`.
+    BrUnless {
+        cond: Reg,
+        pc: u32,
+    },
+    /// Skips the else branch of an `if` when the then branch is done.
+    Jump(u32),
+    /// Returns the value in the register, if the function has a result.
+    Return(Reg),
+    /// Calls a function with the arguments in the registers from `args` on.
+    /// The callee's window starts there, and the result replaces the first
+    /// argument.
+    Call {
+        index: u32,
+        args: Reg,
+    },
+    CallIndirect {
+        type_index: u32,
+        element: Reg,
+        args: Reg,
+    },
+    Copy {
+        dst: Reg,
+        src: Reg,
+    },
+    Select {
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+        cond: Reg,
+    },
+    GetGlobal {
+        dst: Reg,
+        index: u32,
+    },
+    SetGlobal {
+        index: u32,
+        src: Reg,
+    },
+    Load {
+        opcode: Opcode,
+        dst: Reg,
+        addr: Reg,
+        offset: u32,
+    },
+    Store {
+        opcode: Opcode,
+        addr: Reg,
+        value: Reg,
+        offset: u32,
+    },
+    CurrentMemory(Reg),
+    GrowMemory {
+        dst: Reg,
+        delta: Reg,
+    },
+    Unary {
+        opcode: Opcode,
+        dst: Reg,
+        a: Reg,
+    },
+    Binary {
+        opcode: Opcode,
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    /// Consumes the fuel of the wasm instructions before a loop or the end of
+    /// a block that were translated to no instruction of their own.
+    Nop,
+}
+
+impl Instr {
+    /// The register written by an instruction whose result could go to a
+    /// local directly.
+    fn dst_mut(&mut self) -> Option<&mut Reg> {
+        match *self {
+            Instr::Select { ref mut dst, .. }
+            | Instr::GetGlobal { ref mut dst, .. }
+            | Instr::Load { ref mut dst, .. }
+            | Instr::CurrentMemory(ref mut dst)
+            | Instr::GrowMemory { ref mut dst, .. }
+            | Instr::Unary { ref mut dst, .. }
+            | Instr::Binary { ref mut dst, .. } => Some(dst),
+            _ => None,
+        }
+    }
+}
+
+/// A translated function body.
+#[derive(Debug)]
+pub struct Code {
+    pub instrs: Vec<Instr>,
+    /// For every instruction, the index in the body of the wasm instruction
+    /// it was translated from.
+    pub ops: Vec<u32>,
+    /// For every instruction, the fuel of the wasm instructions it covers.
+    pub fuel: Vec<u64>,
+    /// Initial values of the registers following the parameters.
+    pub init: Vec<Value>,
+    /// Number of registers in the window.
+    pub frame_size: usize,
+    /// The register holding the result when the body falls off its end.
+    pub result: Reg,
+}
+
+/// A block (or the function body) being translated.
+struct Block {
+    /// Operand stack height when the block was entered.
+    height: u32,
+    /// Number of values the block leaves on the stack.
+    results: u32,
+    /// Index a branch to a loop continues at. Branches to other blocks
+    /// continue at the end.
+    start: Option<u32>,
+    /// The `BrUnless` of an `if` that has not reached its `else` yet.
+    condition: Option<usize>,
+    /// Branches to the end of the block, as the instruction and the index in
+    /// a branch table.
+    fixups: Vec<(usize, usize)>,
+}
+
+struct Translation<'a, 'm: 'a> {
+    types: &'m [FuncType],
+    func_type: &'a dyn Fn(u32) -> &'m FuncType,
+    costs: &'a FuelCosts,
+    instrs: Vec<Instr>,
+    fuel: Vec<u64>,
+    /// Fuel of the wasm instructions since the last emitted instruction.
+    pending: u64,
+    blocks: Vec<Block>,
+    /// The register holding each operand on the stack: its slot, a local or
+    /// a constant.
+    operands: Vec<Reg>,
+    /// Number of locals, parameters included.
+    locals: Reg,
+    consts: Vec<Value>,
+    /// The first slot register.
+    slots: Reg,
+    max_height: u32,
+    /// The last instruction wrote the top operand to its slot, so a following
+    /// `set_local` or `tee_local` can have it write the local instead.
+    producer: bool,
+    reachable: bool,
+}
+
+impl<'a, 'm> Translation<'a, 'm> {
+    fn emit(&mut self, instr: Instr) {
+        self.instrs.push(instr);
+        self.fuel.push(self.pending);
+        self.pending = 0;
+        self.producer = false;
+    }
+
+    /// Charges the pending fuel before the next instruction, which branches
+    /// may continue at.
+    fn flush(&mut self) {
+        if self.pending > 0 {
+            self.emit(Instr::Nop);
+        }
+    }
+
+    fn slot(&self, height: usize) -> Reg {
+        self.slots + height as Reg
+    }
+
+    fn push(&mut self, reg: Reg) {
+        self.operands.push(reg);
+        self.max_height = self.max_height.max(self.operands.len() as u32);
+    }
+
+    fn pop(&mut self) -> Reg {
+        self.operands.pop().expect("operand")
+    }
+
+    /// Emits `instr`, which writes the next slot, and pushes that slot.
+    fn produce<F: FnOnce(Reg) -> Instr>(&mut self, instr: F) {
+        let dst = self.slot(self.operands.len());
+        self.emit(instr(dst));
+        self.push(dst);
+        self.producer = true;
+    }
+
+    /// Copies the operand at `height` to its slot if it is still a local or a
+    /// constant.
+    fn materialize(&mut self, height: usize) {
+        let slot = self.slot(height);
+        let src = self.operands[height];
+        if src != slot {
+            self.emit(Instr::Copy { dst: slot, src });
+            self.operands[height] = slot;
+        }
+    }
+
+    fn materialize_from(&mut self, height: usize) {
+        for height in height..self.operands.len() {
+            self.materialize(height);
+        }
+    }
+
+    /// Writes the top operand to `local`, keeping it on the stack if `keep`.
+    fn set_local(&mut self, local: Reg, keep: bool) {
+        let src = self.pop();
+        let pending = self.operands.contains(&local);
+        let produced = match self.instrs.last_mut().and_then(Instr::dst_mut) {
+            Some(dst) if self.producer && *dst == src && !pending => Some(dst),
+            _ => None,
+        };
+        if let Some(dst) = produced {
+            *dst = local;
+            self.producer = false;
+            if keep {
+                self.push(local);
+            }
+            return;
+        }
+        for height in 0..self.operands.len() {
+            if self.operands[height] == local {
+                self.materialize(height);
+            }
+        }
+        self.emit(Instr::Copy { dst: local, src });
+        if keep {
+            self.push(src);
+        }
+    }
+
+    fn block(&mut self, results: u32, start: Option<u32>, condition: Option<usize>) {
+        self.blocks.push(Block {
+            height: self.operands.len() as u32,
+            results,
+            start,
+            condition,
+            fixups: Vec::new(),
+        });
+    }
+
+    /// The target of a branch to the label `depth` levels up, taken by the
+    /// instruction emitted next.
+    fn target(&mut self, depth: u32, slot: usize) -> Target {
+        let index = self.blocks.len() - 1 - depth as usize;
+        let next = self.instrs.len();
+        let top = self.operands.last().cloned();
+        let block = &mut self.blocks[index];
+        let (pc, carries) = match block.start {
+            Some(start) => (start, false),
+            None => {
+                block.fixups.push((next, slot));
+                (0, block.results > 0)
+            }
+        };
+        if !carries {
+            return Target {
+                pc,
+                src: self.slots,
+                dst: self.slots,
+            };
+        }
+        Target {
+            pc,
+            src: top.expect("operand"),
+            dst: self.slots + block.height,
+        }
+    }
+
+    /// Points the branches to the end of `block` at the next instruction.
+    fn patch(&mut self, block: &Block) {
+        let pc = self.instrs.len() as u32;
+        for &(index, slot) in &block.fixups {
+            match self.instrs[index] {
+                Instr::Br(ref mut target) | Instr::BrIf { ref mut target, .. } => target.pc = pc,
+                Instr::BrTable { ref mut targets, .. } => targets[slot].pc = pc,
+                Instr::Jump(ref mut target) => *target = pc,
+                _ => unreachable!(),
+            }
+        }
+        if let Some(index) = block.condition {
+            if let Instr::BrUnless { pc: ref mut target, .. } = self.instrs[index] {
+                *target = pc;
+            }
+        }
+    }
+
+    /// Translates `op`, returning whether the code after it is reachable.
+    fn translate(&mut self, op: &Op) -> bool {
+        match op.opcode {
+            Opcode::unreachable => {
+                self.emit(Instr::Unreachable);
+                return false;
+            }
+            Opcode::nop => {}
+            Opcode::block | Opcode::loop_ => {
+                // Joins need every operand in its slot.
+                self.materialize_from(0);
+                self.producer = false;
+                let start = if op.opcode == Opcode::loop_ {
+                    self.flush();
+                    Some(self.instrs.len() as u32)
+                } else {
+                    None
+                };
+                self.block(block_results(&op.payload), start, None);
+            }
+            Opcode::if_ => {
+                let cond = self.pop();
+                self.materialize_from(0);
+                let condition = self.instrs.len();
+                self.emit(Instr::BrUnless { cond, pc: 0 });
+                self.block(block_results(&op.payload), None, Some(condition));
+            }
+            Opcode::else_ => {
+                if self.reachable {
+                    self.materialize_from(0);
+                }
+                let jump = self.instrs.len();
+                self.emit(Instr::Jump(0));
+                let block = self.blocks.last_mut().expect("an if block");
+                block.fixups.push((jump, 0));
+                let condition = block.condition.take().expect("an if block");
+                let height = block.height as usize;
+                if let Instr::BrUnless { ref mut pc, .. } = self.instrs[condition] {
+                    *pc = jump as u32 + 1;
+                }
+                self.operands.truncate(height);
+            }
+            Opcode::end => {
+                if self.reachable {
+                    self.materialize_from(0);
+                    self.flush();
+                }
+                let block = self.blocks.pop().expect("a block");
+                self.patch(&block);
+                self.operands.truncate(block.height as usize);
+                if block.results > 0 {
+                    let slot = self.slot(block.height as usize);
+                    self.push(slot);
+                }
+                self.producer = false;
+            }
+            Opcode::br => {
+                let target = self.target(vu32(&op.payload), 0);
+                self.emit(Instr::Br(target));
+                return false;
+            }
+            Opcode::br_if => {
+                let cond = self.pop();
+                let target = self.target(vu32(&op.payload), 0);
+                self.emit(Instr::BrIf { cond, target });
+            }
+            Opcode::br_table => {
+                let index = self.pop();
+                let depths = match op.payload {
+                    Payload::BrTable {
+                        ref target_table,
+                        ref default_target,
+                    } => target_table.iter().chain(Some(default_target)),
+                    _ => panic!("expected a branch table"),
+                };
+                let targets: Vec<Target> = depths
+                    .enumerate()
+                    .map(|(slot, &depth)| self.target(depth, slot))
+                    .collect();
+                self.emit(Instr::BrTable {
+                    index,
+                    targets: targets.into_boxed_slice(),
+                });
+                return false;
+            }
+            Opcode::return_ => {
+                let src = self.operands.last().cloned().unwrap_or(self.slots);
+                self.emit(Instr::Return(src));
+                return false;
+            }
+            Opcode::call_ | Opcode::call_indirect => {
+                let index = vu32(&op.payload);
+                let (typ, element) = if op.opcode == Opcode::call_ {
+                    ((self.func_type)(index), None)
+                } else {
+                    (&self.types[index as usize], Some(self.pop()))
+                };
+                let at = self.operands.len() - typ.param_types.len();
+                self.materialize_from(at);
+                self.operands.truncate(at);
+                let args = self.slot(at);
+                self.emit(match element {
+                    None => Instr::Call { index, args },
+                    Some(element) => Instr::CallIndirect {
+                        type_index: index,
+                        element,
+                        args,
+                    },
+                });
+                if typ.return_type.is_some() {
+                    self.push(args);
+                }
+            }
+            Opcode::drop => {
+                self.pop();
+            }
+            Opcode::select => {
+                let cond = self.pop();
+                let b = self.pop();
+                let a = self.pop();
+                self.produce(|dst| Instr::Select { dst, a, b, cond });
+            }
+            Opcode::get_local => self.push(vu32(&op.payload)),
+            Opcode::set_local => self.set_local(vu32(&op.payload), false),
+            Opcode::tee_local => self.set_local(vu32(&op.payload), true),
+            Opcode::get_global => {
+                let index = vu32(&op.payload);
+                self.produce(|dst| Instr::GetGlobal { dst, index });
+            }
+            Opcode::set_global => {
+                let src = self.pop();
+                self.emit(Instr::SetGlobal {
+                    index: vu32(&op.payload),
+                    src,
+                });
+            }
+            Opcode::current_memory => self.produce(Instr::CurrentMemory),
+            Opcode::grow_memory => {
+                let delta = self.pop();
+                self.produce(|dst| Instr::GrowMemory { dst, delta });
+            }
+            Opcode::i32_const | Opcode::i64_const | Opcode::f32_const | Opcode::f64_const => {
+                let reg = self.locals + self.consts.len() as Reg;
+                self.consts.push(match op.payload {
+                    Payload::VI32(v) => Value::I32(v),
+                    Payload::VI64(v) => Value::I64(v),
+                    Payload::F32(v) => Value::F32(v),
+                    Payload::F64(v) => Value::F64(v),
+                    _ => panic!("expected a constant"),
+                });
+                self.push(reg);
+            }
+            opcode => match op.payload {
+                Payload::MemoryImmediate { offset, .. } => {
+                    if (opcode as u8) < Opcode::i32_store as u8 {
+                        let addr = self.pop();
+                        self.produce(|dst| Instr::Load {
+                            opcode,
+                            dst,
+                            addr,
+                            offset,
+                        });
+                    } else {
+                        let value = self.pop();
+                        let addr = self.pop();
+                        self.emit(Instr::Store {
+                            opcode,
+                            addr,
+                            value,
+                            offset,
+                        });
+                    }
+                }
+                _ if numeric::is_binary(opcode) => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.produce(|dst| Instr::Binary { opcode, dst, a, b });
+                }
+                _ => {
+                    let a = self.pop();
+                    self.produce(|dst| Instr::Unary { opcode, dst, a });
+                }
+            },
+        }
+        return true;
+    }
+}
+
+fn is_const(op: &Op) -> bool {
+    matches!(
+        op.opcode,
+        Opcode::i32_const | Opcode::i64_const | Opcode::f32_const | Opcode::f64_const
+    )
+}
+
+/// Translates a validated function body. `func_type` gives the type of the
+/// functions it calls, `types` are the types of the module.
+pub fn translate<'m>(
+    typ: &FuncType,
+    body: &FnBody,
+    types: &'m [FuncType],
+    func_type: &dyn Fn(u32) -> &'m FuncType,
+    costs: &FuelCosts,
+) -> Code {
+    let mut init = Vec::new();
+    for local in &body.locals {
+        for _ in 0..local.count {
+            init.push(Value::default_for(local.typ));
+        }
+    }
+    let locals = (typ.param_types.len() + init.len()) as Reg;
+    let consts = body.code.iter().filter(|op| is_const(op)).count() as Reg;
+    let mut translation = Translation {
+        types,
+        func_type,
+        costs,
+        instrs: Vec::with_capacity(body.code.len()),
+        fuel: Vec::with_capacity(body.code.len()),
+        pending: 0,
+        blocks: Vec::new(),
+        operands: Vec::new(),
+        locals,
+        consts: Vec::with_capacity(consts as usize),
+        slots: locals + consts,
+        max_height: 0,
+        producer: false,
+        reachable: true,
+    };
+    translation.block(typ.return_type.is_some() as u32, None, None);
+    let mut ops = Vec::with_capacity(body.code.len());
+    let mut pc = 0;
+    while pc < body.code.len() {
+        translation.pending += translation.costs.get(body.code[pc].opcode);
+        translation.reachable = translation.translate(&body.code[pc]);
+        ops.resize(translation.instrs.len(), pc as u32);
+        pc += 1;
+        if !translation.reachable {
+            pc = skip_unreachable(&body.code, pc);
+        }
+    }
+    if translation.reachable {
+        // the copies of the results belong to the final `end`
+        translation.materialize_from(0);
+        translation.flush();
+        ops.resize(translation.instrs.len(), body.code.len() as u32);
+    }
+    let function = translation.blocks.pop().expect("the function block");
+    translation.patch(&function);
+    init.extend(translation.consts);
+    // Branches without a value name the first slot, so there is always one.
+    for _ in 0..translation.max_height.max(1) {
+        init.push(Value::I32(0));
+    }
+    return Code {
+        instrs: translation.instrs,
+        ops,
+        fuel: translation.fuel,
+        frame_size: typ.param_types.len() + init.len(),
+        init,
+        result: translation.slots,
+    };
+}
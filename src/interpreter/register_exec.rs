@@ -0,0 +1,313 @@
+use interpreter::exec::{memory, same_type, Suspended};
+use interpreter::imports::HostContext;
+use interpreter::instance::{Function, Instance};
+use interpreter::numeric;
+use interpreter::register::{self, Code, Instr, Target};
+use interpreter::trap::Trap;
+use interpreter::value::Value;
+
+use std::rc::Rc;
+
+/// A function activation of the register backend.
+struct Frame {
+    func: u32,
+    code: Rc<Code>,
+    /// Next instruction to execute, after a call the one following it.
+    pc: usize,
+    /// Index of the first register on the value stack.
+    base: usize,
+    returns: bool,
+}
+
+/// The state of a call in progress on the register backend. The register
+/// windows of the frames overlap where a caller passes arguments.
+pub struct Execution {
+    frames: Vec<Frame>,
+    stack: Vec<Value>,
+}
+
+fn condition(value: Value) -> i32 {
+    match value {
+        Value::I32(v) => v,
+        other => panic!("expected I32 operand, found {:?}", other),
+    }
+}
+
+/// Removes the registers of a finished call starting at `at` and restores the
+/// window of the caller, with the result in its first argument register.
+fn leave(execution: &mut Execution, at: usize, result: Option<Value>) {
+    let stack = &mut execution.stack;
+    stack.truncate(at);
+    match execution.frames.last() {
+        Some(caller) => {
+            stack.resize(caller.base + caller.code.frame_size, Value::I32(0));
+            if let Some(result) = result {
+                stack[at] = result;
+            }
+        }
+        None => stack.extend(result),
+    }
+}
+
+impl<'m> Instance<'m> {
+    pub(super) fn call_registers(
+        &mut self,
+        index: u32,
+        args: &[Value],
+    ) -> Result<Option<Value>, Trap> {
+        let mut execution = Execution {
+            frames: Vec::new(),
+            stack: args.to_vec(),
+        };
+        self.enter_registers(&mut execution, index, 0)?;
+        return self.finish_registers(execution);
+    }
+
+    /// Runs an execution to its end, or keeps it in the instance if it runs
+    /// out of fuel.
+    pub(super) fn finish_registers(
+        &mut self,
+        mut execution: Execution,
+    ) -> Result<Option<Value>, Trap> {
+        match self.run_registers(&mut execution) {
+            Ok(()) => Ok(execution.stack.pop()),
+            Err(Trap::OutOfFuel) => {
+                self.suspended = Some(Suspended::Register(execution));
+                Err(Trap::OutOfFuel)
+            }
+            Err(trap) => {
+                // every frame continues after the instruction it stopped at
+                let frames = execution.frames.iter().rev();
+                self.record_backtrace(frames.map(|f| (f.func, f.code.ops[f.pc.max(1) - 1])));
+                Err(trap)
+            }
+        }
+    }
+
+    /// Calls a function with the arguments on the stack from `at` on. A host
+    /// function runs right away, a wasm function gets a new frame.
+    fn enter_registers(
+        &mut self,
+        execution: &mut Execution,
+        index: u32,
+        at: usize,
+    ) -> Result<(), Trap> {
+        let typ = self.func_type(index);
+        let params = typ.param_types.len();
+        let code = match self.functions[index as usize] {
+            Function::Host { ref func, .. } => {
+                let func = func.clone();
+                let mut ctx = HostContext {
+                    memory: self.memory.as_mut(),
+                };
+                let result = func.call(&mut ctx, &execution.stack[at..at + params])?;
+                if result.map(|v| v.typ()) != typ.return_type {
+                    return Err(Trap::Host(
+                        "host function returned a wrong type".to_string(),
+                    ));
+                }
+                leave(execution, at, result);
+                return Ok(());
+            }
+            Function::Wasm {
+                registers: Some(ref code),
+                ..
+            } => code.clone(),
+            Function::Wasm { .. } => self.translate(index)?,
+        };
+        if execution.frames.len() >= self.max_call_depth
+            || at + code.frame_size > self.max_value_stack
+        {
+            return Err(Trap::CallStackExhausted);
+        }
+        execution.stack.truncate(at + params);
+        execution.stack.extend_from_slice(&code.init);
+        execution.frames.push(Frame {
+            func: index,
+            code,
+            pc: 0,
+            base: at,
+            returns: typ.return_type.is_some(),
+        });
+        Ok(())
+    }
+
+    /// Translates the body of a wasm function and caches the result.
+    fn translate(&mut self, index: u32) -> Result<Rc<Code>, Trap> {
+        let body = self.body(index)?;
+        let code = Rc::new(register::translate(
+            self.func_type(index),
+            body,
+            self.types(),
+            &|callee| self.func_type(callee),
+            &self.fuel_costs,
+        ));
+        if let Function::Wasm {
+            ref mut registers, ..
+        } = self.functions[index as usize]
+        {
+            *registers = Some(code.clone());
+        }
+        return Ok(code);
+    }
+
+    fn run_registers(&mut self, execution: &mut Execution) -> Result<(), Trap> {
+        'frames: while let Some(frame) = execution.frames.last() {
+            let code = frame.code.clone();
+            let instrs = &code.instrs[..];
+            let mut pc = frame.pc;
+            let base = frame.base;
+            let returns = frame.returns;
+            let mut result = code.result;
+            let frames = &mut execution.frames;
+            let regs = &mut execution.stack[base..];
+            // Records where to continue the frame, after a call or when
+            // suspended.
+            macro_rules! save_pc {
+                () => {
+                    frames.last_mut().expect("a frame").pc = pc;
+                };
+            }
+            // Raises a trap, recording the instruction that raised it as where
+            // the frame continues.
+            macro_rules! trap {
+                ($trap:expr) => {{
+                    save_pc!();
+                    return Err($trap);
+                }};
+            }
+            macro_rules! check {
+                ($result:expr) => {
+                    match $result {
+                        Ok(value) => value,
+                        Err(err) => trap!(err),
+                    }
+                };
+            }
+            macro_rules! reg {
+                ($reg:expr) => {
+                    regs[$reg as usize]
+                };
+            }
+            macro_rules! jump {
+                ($target:expr) => {{
+                    let target: &Target = $target;
+                    reg!(target.dst) = reg!(target.src);
+                    pc = target.pc as usize;
+                }};
+            }
+            while pc < instrs.len() {
+                let instr = &instrs[pc];
+                if let Some(ref mut fuel) = self.fuel {
+                    let cost = code.fuel[pc];
+                    if *fuel < cost {
+                        save_pc!();
+                        return Err(Trap::OutOfFuel);
+                    }
+                    *fuel -= cost;
+                }
+                pc += 1;
+                match *instr {
+                    Instr::Unreachable => trap!(Trap::Unreachable),
+                    Instr::Br(ref target) => jump!(target),
+                    Instr::BrIf { cond, ref target } => {
+                        if condition(reg!(cond)) != 0 {
+                            jump!(target);
+                        }
+                    }
+                    Instr::BrTable { index, ref targets } => {
+                        let index = condition(reg!(index)) as u32 as usize;
+                        jump!(&targets[index.min(targets.len() - 1)]);
+                    }
+                    Instr::BrUnless { cond, pc: target } => {
+                        if condition(reg!(cond)) == 0 {
+                            pc = target as usize;
+                        }
+                    }
+                    Instr::Jump(target) => pc = target as usize,
+                    Instr::Return(src) => {
+                        result = src;
+                        break;
+                    }
+                    Instr::Call { index, args } => {
+                        save_pc!();
+                        self.enter_registers(execution, index, base + args as usize)?;
+                        continue 'frames;
+                    }
+                    Instr::CallIndirect {
+                        type_index,
+                        element,
+                        args,
+                    } => {
+                        let expected = &self.types()[type_index as usize];
+                        let element = condition(reg!(element)) as u32;
+                        let func = match self.table {
+                            Some(ref table) => check!(table.get(element)),
+                            None => trap!(Trap::UndefinedElement),
+                        };
+                        let func = check!(func.ok_or(Trap::UninitializedElement));
+                        if !same_type(expected, self.func_type(func)) {
+                            trap!(Trap::IndirectCallTypeMismatch);
+                        }
+                        save_pc!();
+                        self.enter_registers(execution, func, base + args as usize)?;
+                        continue 'frames;
+                    }
+
+                    Instr::Copy { dst, src } => reg!(dst) = reg!(src),
+                    Instr::Nop => {}
+                    Instr::Select { dst, a, b, cond } => {
+                        reg!(dst) = if condition(reg!(cond)) != 0 {
+                            reg!(a)
+                        } else {
+                            reg!(b)
+                        };
+                    }
+                    Instr::GetGlobal { dst, index } => reg!(dst) = self.globals[index as usize],
+                    Instr::SetGlobal { index, src } => self.globals[index as usize] = reg!(src),
+
+                    Instr::Load {
+                        opcode,
+                        dst,
+                        addr,
+                        offset,
+                    } => {
+                        let addr = condition(reg!(addr)) as u32;
+                        let memory = check!(memory(&mut self.memory));
+                        reg!(dst) = check!(memory.load(opcode, addr, offset));
+                    }
+                    Instr::Store {
+                        opcode,
+                        addr,
+                        value,
+                        offset,
+                    } => {
+                        let addr = condition(reg!(addr)) as u32;
+                        let memory = check!(memory(&mut self.memory));
+                        check!(memory.store(opcode, addr, offset, reg!(value)));
+                    }
+                    Instr::CurrentMemory(dst) => {
+                        let size = check!(memory(&mut self.memory)).size();
+                        reg!(dst) = Value::I32(size as i32);
+                    }
+                    Instr::GrowMemory { dst, delta } => {
+                        let delta = condition(reg!(delta)) as u32;
+                        let old = check!(self.grow_memory(delta));
+                        reg!(dst) = Value::I32(old.map_or(-1, |old| old as i32));
+                    }
+
+                    Instr::Unary { opcode, dst, a } => {
+                        reg!(dst) = check!(numeric::unary(opcode, reg!(a)));
+                    }
+                    Instr::Binary { opcode, dst, a, b } => {
+                        reg!(dst) = check!(numeric::binary(opcode, reg!(a), reg!(b)));
+                    }
+                }
+            }
+            let value = if returns { Some(reg!(result)) } else { None };
+            execution.frames.pop();
+            leave(execution, base, value);
+        }
+        Ok(())
+    }
+}
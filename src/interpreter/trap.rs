@@ -0,0 +1,64 @@
+use std::error::Error;
+use std::fmt;
+
+/// A runtime error that aborts the execution of a module.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trap {
+    Unreachable,
+    MemoryOutOfBounds,
+    /// `call_indirect` with an index outside of the table.
+    UndefinedElement,
+    UninitializedElement,
+    IndirectCallTypeMismatch,
+    IntegerDivideByZero,
+    IntegerOverflow,
+    InvalidConversionToInteger,
+    CallStackExhausted,
+    /// The body of the function with the given index failed to decode or to
+    /// validate when it was first called.
+    InvalidFunction(u32, String),
+    /// `grow_memory` failed and the resource limiter asks to trap.
+    ResourceLimitExceeded,
+    /// The instance ran out of fuel. It can be resumed after adding fuel.
+    OutOfFuel,
+    /// Raised by a host function.
+    Host(String),
+    /// The program asked to terminate with an exit status, e.g. through WASI
+    /// `proc_exit`.
+    Exit(i32),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Trap::Unreachable => write!(f, "unreachable executed"),
+            Trap::MemoryOutOfBounds => write!(f, "out of bounds memory access"),
+            Trap::UndefinedElement => write!(f, "undefined element"),
+            Trap::UninitializedElement => write!(f, "uninitialized element"),
+            Trap::IndirectCallTypeMismatch => write!(f, "indirect call type mismatch"),
+            Trap::IntegerDivideByZero => write!(f, "integer divide by zero"),
+            Trap::IntegerOverflow => write!(f, "integer overflow"),
+            Trap::InvalidConversionToInteger => write!(f, "invalid conversion to integer"),
+            Trap::CallStackExhausted => write!(f, "call stack exhausted"),
+            Trap::InvalidFunction(index, ref msg) => {
+                write!(f, "function {} is invalid: {}", index, msg)
+            }
+            Trap::ResourceLimitExceeded => write!(f, "resource limit exceeded"),
+            Trap::OutOfFuel => write!(f, "all fuel consumed"),
+            Trap::Host(ref msg) => write!(f, "{}", msg),
+            Trap::Exit(code) => write!(f, "exit with status {}", code),
+        }
+    }
+}
+
+impl Error for Trap {}
+
+/// A wasm function that was running when a call trapped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceFrame {
+    /// Index of the function in the function index space.
+    pub func: u32,
+    /// File offset of the instruction that trapped, or of the call the trap
+    /// happened in.
+    pub offset: u32,
+}
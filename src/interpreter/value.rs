@@ -0,0 +1,98 @@
+use parser::Type;
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Value {
+    /// The zero value of a type, used to initialize locals.
+    pub fn default_for(typ: Type) -> Value {
+        match typ {
+            Type::I32 => Value::I32(0),
+            Type::I64 => Value::I64(0),
+            Type::F32 => Value::F32(0.0),
+            Type::F64 => Value::F64(0.0),
+            _ => panic!("not a value type: {:?}", typ),
+        }
+    }
+
+    pub fn typ(&self) -> Type {
+        match *self {
+            Value::I32(_) => Type::I32,
+            Value::I64(_) => Type::I64,
+            Value::F32(_) => Type::F32,
+            Value::F64(_) => Type::F64,
+        }
+    }
+
+    /// Parses a value of the given type, e.g. a command line argument.
+    ///
+    /// Integers may be given in decimal or as `0x` hex; unsigned values above
+    /// the signed range wrap. Floats also accept `nan`, `inf` and `-inf`.
+    pub fn parse(typ: Type, text: &str) -> Option<Value> {
+        match typ {
+            Type::I32 => parse_int(text)
+                .filter(|&v| v >= i32::MIN as i128 && v <= u32::MAX as i128)
+                .map(|v| Value::I32(v as i32)),
+            Type::I64 => parse_int(text)
+                .filter(|&v| v >= i64::MIN as i128 && v <= u64::MAX as i128)
+                .map(|v| Value::I64(v as i64)),
+            Type::F32 => text.parse().ok().map(Value::F32),
+            Type::F64 => text.parse().ok().map(Value::F64),
+            _ => None,
+        }
+    }
+}
+
+fn parse_int(text: &str) -> Option<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let magnitude = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i128>().ok()?,
+    };
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::I32(v) => write!(f, "{}", v),
+            Value::I64(v) => write!(f, "{}", v),
+            Value::F32(v) => write!(f, "{:?}", v),
+            Value::F64(v) => write!(f, "{:?}", v),
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Value {
+        Value::I32(v)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Value {
+        Value::I64(v)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(v: f32) -> Value {
+        Value::F32(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Value {
+        Value::F64(v)
+    }
+}
@@ -0,0 +1,583 @@
+//! Encodes module documents back into the binary format.
+
+use json::Value;
+use json::module::{LoadError, FORMAT_VERSION};
+use parser::leb128::{write_signed, write_unsigned};
+use parser::opcode::{opcode_from_byte, Opcode};
+use wat::{natural_alignment, type_name};
+
+type Result<T> = ::std::result::Result<T, LoadError>;
+
+fn invalid<T>(what: &str) -> Result<T> {
+    Err(LoadError::Invalid(what.to_string()))
+}
+
+fn field<'v>(value: &'v Value, key: &str) -> Result<&'v Value> {
+    match value.get(key) {
+        Some(member) => Ok(member),
+        None => invalid(&format!("missing member '{}'", key)),
+    }
+}
+
+fn u32_field(value: &Value, key: &str) -> Result<u32> {
+    match field(value, key)?.as_u64() {
+        Some(n) if n <= u32::MAX as u64 => Ok(n as u32),
+        _ => invalid(&format!("'{}' must be a 32 bit unsigned number", key)),
+    }
+}
+
+fn str_field<'v>(value: &'v Value, key: &str) -> Result<&'v str> {
+    match field(value, key)?.as_str() {
+        Some(s) => Ok(s),
+        None => invalid(&format!("'{}' must be a string", key)),
+    }
+}
+
+fn bool_field(value: &Value, key: &str) -> Result<bool> {
+    match field(value, key)?.as_bool() {
+        Some(b) => Ok(b),
+        None => invalid(&format!("'{}' must be a boolean", key)),
+    }
+}
+
+/// The array member `key`, or `None` if it is `null`.
+fn array_field<'v>(value: &'v Value, key: &str) -> Result<Option<&'v [Value]>> {
+    match *field(value, key)? {
+        Value::Null => Ok(None),
+        Value::Array(ref items) => Ok(Some(items)),
+        _ => invalid(&format!("'{}' must be an array or null", key)),
+    }
+}
+
+fn items<'v>(value: &'v Value, key: &str) -> Result<&'v [Value]> {
+    Ok(array_field(value, key)?.unwrap_or(&[]))
+}
+
+fn u32_value(value: &Value) -> Result<u32> {
+    match value.as_u64() {
+        Some(n) if n <= u32::MAX as u64 => Ok(n as u32),
+        _ => invalid("expected a 32 bit unsigned number"),
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    write_unsigned(out, value as u64);
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_vec<F>(out: &mut Vec<u8>, items: &[Value], mut write: F) -> Result<()>
+where
+    F: FnMut(&mut Vec<u8>, &Value) -> Result<()>,
+{
+    write_u32(out, items.len() as u32);
+    for item in items {
+        write(out, item)?;
+    }
+    Ok(())
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    out.push(id);
+    write_u32(out, payload.len() as u32);
+    out.extend_from_slice(payload);
+}
+
+fn hex_bytes(text: &str) -> Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return invalid("hex string of odd length");
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 2);
+    for i in (0..text.len()).step_by(2) {
+        match text.get(i..i + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()) {
+            Some(byte) => bytes.push(byte),
+            None => return invalid(&format!("invalid hex string '{}'", text)),
+        }
+    }
+    return Ok(bytes);
+}
+
+fn value_type(name: &str) -> Result<u8> {
+    match name {
+        "i32" => Ok(0x7f),
+        "i64" => Ok(0x7e),
+        "f32" => Ok(0x7d),
+        "f64" => Ok(0x7c),
+        _ => invalid(&format!("unknown value type '{}'", name)),
+    }
+}
+
+fn write_value_type(out: &mut Vec<u8>, value: &Value) -> Result<()> {
+    match value.as_str() {
+        Some(name) => out.push(value_type(name)?),
+        None => return invalid("value types must be strings"),
+    }
+    Ok(())
+}
+
+fn write_limits(out: &mut Vec<u8>, limits: &Value) -> Result<()> {
+    let initial = u32_field(limits, "initial")?;
+    match *field(limits, "maximum")? {
+        Value::Null => {
+            out.push(0);
+            write_u32(out, initial);
+        }
+        ref maximum => {
+            out.push(1);
+            write_u32(out, initial);
+            write_u32(out, u32_value(maximum)?);
+        }
+    }
+    Ok(())
+}
+
+fn kind_byte(kind: &str) -> Result<u8> {
+    match kind {
+        "func" => Ok(0),
+        "table" => Ok(1),
+        "memory" => Ok(2),
+        "global" => Ok(3),
+        _ => invalid(&format!("unknown external kind '{}'", kind)),
+    }
+}
+
+fn opcode_by_name(name: &str) -> Result<Opcode> {
+    for byte in 0..=0xff {
+        if let Ok(opcode) = opcode_from_byte(byte) {
+            if opcode.name() == name {
+                return Ok(opcode);
+            }
+        }
+    }
+    return invalid(&format!("unknown instruction '{}'", name));
+}
+
+fn parse_number<T: ::std::str::FromStr>(text: Option<&str>, instr: &str) -> Result<T> {
+    match text.and_then(|t| t.parse().ok()) {
+        Some(n) => Ok(n),
+        None => invalid(&format!("invalid immediate in '{}'", instr)),
+    }
+}
+
+/// Bits of a float written by the text printer: a decimal number, `inf`, or
+/// `nan:0x..` with its payload, each optionally negative.
+fn float_bits(text: &str, mantissa_bits: u32, exponent_mask: u64) -> Option<u64> {
+    let (negative, magnitude) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let sign = if negative { 1u64 << (mantissa_bits + exponent_mask.count_ones()) } else { 0 };
+    let exponent = exponent_mask << mantissa_bits;
+    if magnitude == "inf" {
+        return Some(sign | exponent);
+    }
+    if let Some(payload) = magnitude.strip_prefix("nan:0x") {
+        let payload = u64::from_str_radix(payload, 16).ok()?;
+        return Some(sign | exponent | payload);
+    }
+    return None;
+}
+
+fn write_instruction(out: &mut Vec<u8>, text: &str) -> Result<()> {
+    let mut tokens = text.split_whitespace();
+    let opcode = opcode_by_name(tokens.next().unwrap_or(""))?;
+    out.push(opcode as u8);
+    let args: Vec<&str> = tokens.collect();
+    match opcode as u8 {
+        0x02..=0x04 => match args.len() {
+            0 => out.push(0x40),
+            2 if args[0] == "(result" && args[1].ends_with(')') => {
+                out.push(value_type(args[1].trim_end_matches(')'))?)
+            }
+            _ => return invalid(&format!("invalid block type in '{}'", text)),
+        },
+        0x0c..=0x0d | 0x10 | 0x20..=0x24 => {
+            write_u32(out, parse_number(args.first().cloned(), text)?);
+        }
+        0x11 => {
+            let index = args.get(1).map(|arg| arg.trim_end_matches(')'));
+            if args.first() != Some(&"(type") {
+                return invalid(&format!("expected a type use in '{}'", text));
+            }
+            write_u32(out, parse_number(index, text)?);
+            out.push(0);
+        }
+        0x0e => {
+            if args.is_empty() {
+                return invalid(&format!("missing default target in '{}'", text));
+            }
+            write_u32(out, args.len() as u32 - 1);
+            for arg in args {
+                write_u32(out, parse_number(Some(arg), text)?);
+            }
+        }
+        0x28..=0x3e => {
+            let mut flags = natural_alignment(opcode);
+            let mut offset = 0;
+            for arg in args {
+                if let Some(value) = arg.strip_prefix("offset=") {
+                    offset = parse_number(Some(value), text)?;
+                } else if let Some(value) = arg.strip_prefix("align=") {
+                    let align: u64 = parse_number(Some(value), text)?;
+                    if !align.is_power_of_two() {
+                        return invalid(&format!("alignment is not a power of two in '{}'", text));
+                    }
+                    flags = align.trailing_zeros();
+                } else {
+                    return invalid(&format!("unknown memory argument in '{}'", text));
+                }
+            }
+            write_u32(out, flags);
+            write_u32(out, offset);
+        }
+        0x3f..=0x40 => out.push(0),
+        0x41 => write_signed(out, parse_number::<i32>(args.first().cloned(), text)? as i64),
+        0x42 => write_signed(out, parse_number(args.first().cloned(), text)?),
+        0x43 => {
+            let arg = args.first().cloned().unwrap_or("");
+            let bits = match float_bits(arg, 23, 0xff) {
+                Some(bits) => bits as u32,
+                None => parse_number::<f32>(Some(arg), text)?.to_bits(),
+            };
+            out.extend_from_slice(&bits.to_le_bytes());
+        }
+        0x44 => {
+            let arg = args.first().cloned().unwrap_or("");
+            let bits = match float_bits(arg, 52, 0x7ff) {
+                Some(bits) => bits,
+                None => parse_number::<f64>(Some(arg), text)?.to_bits(),
+            };
+            out.extend_from_slice(&bits.to_le_bytes());
+        }
+        _ => {
+            if !args.is_empty() {
+                return invalid(&format!("unexpected immediate in '{}'", text));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_init_expr(out: &mut Vec<u8>, expr: &Value) -> Result<()> {
+    match expr.as_str() {
+        Some(text) => write_instruction(out, text)?,
+        None => return invalid("constant expressions must be strings"),
+    }
+    out.push(0x0b);
+    Ok(())
+}
+
+fn write_name_map(out: &mut Vec<u8>, names: &Value) -> Result<()> {
+    let names = match names.as_array() {
+        Some(names) => names,
+        None => return invalid("name maps must be arrays"),
+    };
+    write_vec(out, names, |out, naming| {
+        write_u32(out, u32_field(naming, "index")?);
+        write_str(out, str_field(naming, "name")?);
+        Ok(())
+    })
+}
+
+fn write_indirect_name_map(out: &mut Vec<u8>, names: &Value) -> Result<()> {
+    let names = match names.as_array() {
+        Some(names) => names,
+        None => return invalid("indirect name maps must be arrays"),
+    };
+    write_vec(out, names, |out, entry| {
+        write_u32(out, u32_field(entry, "index")?);
+        write_name_map(out, field(entry, "names")?)
+    })
+}
+
+fn name_section(names: &Value) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let subsections = [
+        "module", "functions", "locals", "labels", "types", "tables", "memories", "globals",
+        "elems", "datas", "fields", "tags",
+    ];
+    for (id, &key) in subsections.iter().enumerate() {
+        let value = field(names, key)?;
+        if *value == Value::Null {
+            continue;
+        }
+        let mut payload = Vec::new();
+        match key {
+            "module" => match value.as_str() {
+                Some(name) => write_str(&mut payload, name),
+                None => return invalid("the module name must be a string"),
+            },
+            "locals" | "labels" | "fields" => write_indirect_name_map(&mut payload, value)?,
+            _ => write_name_map(&mut payload, value)?,
+        }
+        write_section(&mut out, id as u8, &payload);
+    }
+    return Ok(out);
+}
+
+fn producers_section(producers: &Value) -> Result<Vec<u8>> {
+    let mut fields = Vec::new();
+    for &key in &["language", "processed-by", "sdk"] {
+        let versions = items(producers, key)?;
+        if !versions.is_empty() {
+            fields.push((key, versions));
+        }
+    }
+    let mut out = Vec::new();
+    write_u32(&mut out, fields.len() as u32);
+    for (key, versions) in fields {
+        write_str(&mut out, key);
+        write_vec(&mut out, versions, |out, version| {
+            write_str(out, str_field(version, "name")?);
+            write_str(out, str_field(version, "version")?);
+            Ok(())
+        })?;
+    }
+    return Ok(out);
+}
+
+fn target_features_section(features: &[Value]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write_vec(&mut out, features, |out, feature| {
+        match str_field(feature, "prefix")? {
+            "+" => out.push(b'+'),
+            "-" => out.push(b'-'),
+            "=" => out.push(b'='),
+            prefix => return invalid(&format!("unknown feature prefix '{}'", prefix)),
+        }
+        write_str(out, str_field(feature, "name")?);
+        Ok(())
+    })?;
+    return Ok(out);
+}
+
+/// Payloads of the custom sections in the order of `custom_section_positions`,
+/// each with the id of the section it follows. Sections whose decoded member
+/// is `null` could not be decoded and are among `custom_sections`.
+fn custom_sections(doc: &Value) -> Result<Vec<(Option<u8>, Vec<u8>)>> {
+    let mut generic = items(doc, "custom_sections")?.iter();
+    let mut sections = Vec::new();
+    for position in items(doc, "custom_section_positions")? {
+        let name = str_field(position, "name")?;
+        let after = match *field(position, "after")? {
+            Value::Null => None,
+            ref id => Some(u32_value(id)? as u8),
+        };
+        let content = match name {
+            "name" if *field(doc, "names")? != Value::Null => {
+                name_section(field(doc, "names")?)?
+            }
+            "producers" if *field(doc, "producers")? != Value::Null => {
+                producers_section(field(doc, "producers")?)?
+            }
+            "target_features" if *field(doc, "target_features")? != Value::Null => {
+                target_features_section(items(doc, "target_features")?)?
+            }
+            "sourceMappingURL" if *field(doc, "source_mapping_url")? != Value::Null => {
+                let mut out = Vec::new();
+                write_str(&mut out, str_field(doc, "source_mapping_url")?);
+                out
+            }
+            _ => match generic.next() {
+                Some(section) if str_field(section, "name")? == name => {
+                    hex_bytes(str_field(section, "bytes")?)?
+                }
+                _ => return invalid(&format!("no payload for custom section '{}'", name)),
+            },
+        };
+        let mut payload = Vec::new();
+        write_str(&mut payload, name);
+        payload.extend_from_slice(&content);
+        sections.push((after, payload));
+    }
+    return Ok(sections);
+}
+
+/// The known sections of the module as (id, payload) pairs in module order.
+fn known_sections(doc: &Value) -> Result<Vec<(u8, Vec<u8>)>> {
+    let mut sections = Vec::new();
+
+    if let Some(types) = array_field(doc, "types")? {
+        let mut out = Vec::new();
+        write_vec(&mut out, types, |out, typ| {
+            out.push(0x60);
+            write_vec(out, items(typ, "params")?, write_value_type)?;
+            write_vec(out, items(typ, "results")?, write_value_type)
+        })?;
+        sections.push((0x1, out));
+    }
+
+    if let Some(imports) = array_field(doc, "imports")? {
+        let mut out = Vec::new();
+        write_vec(&mut out, imports, |out, import| {
+            write_str(out, str_field(import, "module")?);
+            write_str(out, str_field(import, "field")?);
+            let kind = kind_byte(str_field(import, "kind")?)?;
+            out.push(kind);
+            match kind {
+                0 => write_u32(out, u32_field(import, "type")?),
+                1 => {
+                    out.push(0x70);
+                    write_limits(out, field(import, "limits")?)?;
+                }
+                2 => write_limits(out, field(import, "limits")?)?,
+                _ => {
+                    out.push(value_type(str_field(import, "type")?)?);
+                    out.push(bool_field(import, "mutable")? as u8);
+                }
+            }
+            Ok(())
+        })?;
+        sections.push((0x2, out));
+    }
+
+    let functions = array_field(doc, "functions")?;
+    if let Some(functions) = functions {
+        let mut out = Vec::new();
+        write_vec(&mut out, functions, |out, function| {
+            write_u32(out, u32_field(function, "type")?);
+            Ok(())
+        })?;
+        sections.push((0x3, out));
+    }
+
+    if let Some(tables) = array_field(doc, "tables")? {
+        let mut out = Vec::new();
+        write_vec(&mut out, tables, |out, table| {
+            if str_field(table, "element")? != type_name(::parser::Type::anyfunc) {
+                return invalid("tables must hold anyfunc elements");
+            }
+            out.push(0x70);
+            write_limits(out, field(table, "limits")?)
+        })?;
+        sections.push((0x4, out));
+    }
+
+    if let Some(memories) = array_field(doc, "memories")? {
+        let mut out = Vec::new();
+        write_vec(&mut out, memories, |out, memory| {
+            write_limits(out, field(memory, "limits")?)
+        })?;
+        sections.push((0x5, out));
+    }
+
+    if let Some(globals) = array_field(doc, "globals")? {
+        let mut out = Vec::new();
+        write_vec(&mut out, globals, |out, global| {
+            out.push(value_type(str_field(global, "type")?)?);
+            out.push(bool_field(global, "mutable")? as u8);
+            write_init_expr(out, field(global, "init")?)
+        })?;
+        sections.push((0x6, out));
+    }
+
+    if let Some(exports) = array_field(doc, "exports")? {
+        let mut out = Vec::new();
+        write_vec(&mut out, exports, |out, export| {
+            write_str(out, str_field(export, "name")?);
+            out.push(kind_byte(str_field(export, "kind")?)?);
+            write_u32(out, u32_field(export, "index")?);
+            Ok(())
+        })?;
+        sections.push((0x7, out));
+    }
+
+    if *field(doc, "start")? != Value::Null {
+        let mut out = Vec::new();
+        write_u32(&mut out, u32_field(doc, "start")?);
+        sections.push((0x8, out));
+    }
+
+    if let Some(elements) = array_field(doc, "elements")? {
+        let mut out = Vec::new();
+        write_vec(&mut out, elements, |out, segment| {
+            write_u32(out, u32_field(segment, "table")?);
+            write_init_expr(out, field(segment, "offset")?)?;
+            write_vec(out, items(segment, "functions")?, |out, index| {
+                write_u32(out, u32_value(index)?);
+                Ok(())
+            })
+        })?;
+        sections.push((0x9, out));
+    }
+
+    if *field(doc, "data_count")? != Value::Null {
+        let mut out = Vec::new();
+        write_u32(&mut out, u32_field(doc, "data_count")?);
+        sections.push((0xC, out));
+    }
+
+    if let Some(functions) = functions {
+        let mut out = Vec::new();
+        write_vec(&mut out, functions, |out, function| {
+            let mut body = Vec::new();
+            write_vec(&mut body, items(function, "locals")?, |out, local| {
+                write_u32(out, u32_field(local, "count")?);
+                out.push(value_type(str_field(local, "type")?)?);
+                Ok(())
+            })?;
+            for instr in items(function, "body")? {
+                match instr.as_str() {
+                    Some(text) => write_instruction(&mut body, text)?,
+                    None => return invalid("instructions must be strings"),
+                }
+            }
+            body.push(0x0b);
+            write_u32(out, body.len() as u32);
+            out.extend_from_slice(&body);
+            Ok(())
+        })?;
+        sections.push((0xA, out));
+    }
+
+    if let Some(data) = array_field(doc, "data")? {
+        let mut out = Vec::new();
+        write_vec(&mut out, data, |out, segment| {
+            write_u32(out, u32_field(segment, "memory")?);
+            write_init_expr(out, field(segment, "offset")?)?;
+            let bytes = hex_bytes(str_field(segment, "bytes")?)?;
+            write_u32(out, bytes.len() as u32);
+            out.extend_from_slice(&bytes);
+            Ok(())
+        })?;
+        sections.push((0xB, out));
+    }
+
+    return Ok(sections);
+}
+
+/// Builds the binary module described by a document in the format of
+/// `module_to_json`.
+pub fn encode_module(doc: &Value) -> Result<Vec<u8>> {
+    if doc.get("format").and_then(Value::as_str) != Some("wasm-module") {
+        return invalid("not a module document");
+    }
+    let version = u32_field(doc, "version")?;
+    if version != FORMAT_VERSION {
+        return invalid(&format!("unsupported format version {}", version));
+    }
+    let customs = custom_sections(doc)?;
+    let mut out = b"\0asm\x01\0\0\0".to_vec();
+    let place_customs = |out: &mut Vec<u8>, after: Option<u8>| {
+        for (_, payload) in customs.iter().filter(|c| c.0 == after) {
+            write_section(out, 0x0, payload);
+        }
+    };
+    place_customs(&mut out, None);
+    let sections = known_sections(doc)?;
+    for (id, payload) in &sections {
+        let id = *id;
+        write_section(&mut out, id, payload);
+        place_customs(&mut out, Some(id));
+    }
+    // custom sections that followed a section the document does not contain
+    for (after, payload) in &customs {
+        if after.is_some_and(|after| !sections.iter().any(|s| s.0 == after)) {
+            write_section(&mut out, 0x0, payload);
+        }
+    }
+    return Ok(out);
+}
@@ -0,0 +1,469 @@
+//! A minimal JSON reader and writer for the auxiliary files the interpreter
+//! consumes and the reports it produces.
+
+pub mod module;
+mod encode;
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    /// Numbers keep their literal text so that 64 bit integers survive.
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    /// Members in the order they appeared.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref members) => members.iter().find(|m| m.0 == key).map(|m| &m.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match *self {
+            Value::Array(ref values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Number(ref n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Number(ref n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(ref n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! number_from {
+    ($($t:ty),*) => ($(
+        impl From<$t> for Value {
+            fn from(n: $t) -> Value {
+                Value::Number(n.to_string())
+            }
+        }
+    )*)
+}
+
+number_from!(u8, u32, u64, usize, i32, i64);
+
+impl From<f64> for Value {
+    /// Non-finite numbers have no JSON representation and become `null`.
+    fn from(n: f64) -> Value {
+        if n.is_finite() {
+            Value::Number(format!("{:?}", n))
+        } else {
+            Value::Null
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Value {
+        Value::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+impl Value {
+    /// Serializes the value. With `indent`, nested arrays and objects are put
+    /// on separate lines, indented by that many spaces per level.
+    fn write(&self, out: &mut String, indent: Option<usize>, level: usize) {
+        let newline = |out: &mut String, level: usize| {
+            if let Some(width) = indent {
+                out.push('\n');
+                out.push_str(&" ".repeat(width * level));
+            }
+        };
+        match *self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+            Value::Number(ref n) => out.push_str(n),
+            Value::String(ref s) => write_string(out, s),
+            Value::Array(ref values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, level + 1);
+                    value.write(out, indent, level + 1);
+                }
+                if !values.is_empty() {
+                    newline(out, level);
+                }
+                out.push(']');
+            }
+            Value::Object(ref members) => {
+                out.push('{');
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(out, level + 1);
+                    write_string(out, key);
+                    out.push_str(if indent.is_some() { ": " } else { ":" });
+                    value.write(out, indent, level + 1);
+                }
+                if !members.is_empty() {
+                    newline(out, level);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    /// Serializes the value with two spaces of indentation per level.
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, Some(2), 0);
+        return out;
+    }
+}
+
+/// Compact serialization without any whitespace.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        self.write(&mut out, None, 0);
+        f.write_str(&out)
+    }
+}
+
+/// Byte offset in the input at which the JSON text stopped making sense.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JsonError(pub usize);
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid JSON at byte {}", self.0)
+    }
+}
+
+impl Error for JsonError {}
+
+struct Reader<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn err<T>(&self) -> Result<T, JsonError> {
+        Err(JsonError(self.pos))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.text.len() && b" \t\r\n".contains(&self.text[self.pos]) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), JsonError> {
+        if self.peek() != Some(b) {
+            return self.err();
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, JsonError> {
+        if !self.text[self.pos..].starts_with(word.as_bytes()) {
+            return self.err();
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn read_value(&mut self) -> Result<Value, JsonError> {
+        match self.peek() {
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => Ok(Value::String(self.read_string()?)),
+            Some(b'[') => self.read_array(),
+            Some(b'{') => self.read_object(),
+            Some(b'-') | Some(b'0'..=b'9') => self.read_number(),
+            _ => self.err(),
+        }
+    }
+
+    fn read_number(&mut self) -> Result<Value, JsonError> {
+        let start = self.pos;
+        while self.pos < self.text.len() && b"+-.eE0123456789".contains(&self.text[self.pos]) {
+            self.pos += 1;
+        }
+        let literal = String::from_utf8_lossy(&self.text[start..self.pos]).into_owned();
+        if literal.parse::<f64>().is_err() {
+            return Err(JsonError(start));
+        }
+        Ok(Value::Number(literal))
+    }
+
+    fn read_hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = match self.text.get(self.pos..self.pos + 4) {
+            Some(digits) => String::from_utf8_lossy(digits).into_owned(),
+            None => return self.err(),
+        };
+        let value = match u32::from_str_radix(&digits, 16) {
+            Ok(value) => value,
+            Err(_) => return self.err(),
+        };
+        self.pos += 4;
+        Ok(value)
+    }
+
+    fn read_escape(&mut self) -> Result<char, JsonError> {
+        let c = match self.text.get(self.pos) {
+            Some(&c) => c,
+            None => return self.err(),
+        };
+        self.pos += 1;
+        let decoded = match c {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let mut code = self.read_hex4()?;
+                // surrogate pair
+                if (0xd800..0xdc00).contains(&code) && self.text[self.pos..].starts_with(b"\\u") {
+                    self.pos += 2;
+                    let low = self.read_hex4()?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return self.err();
+                    }
+                    code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                }
+                match ::std::char::from_u32(code) {
+                    Some(c) => c,
+                    None => return self.err(),
+                }
+            }
+            _ => return self.err(),
+        };
+        Ok(decoded)
+    }
+
+    fn read_string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+        let mut s = String::new();
+        loop {
+            let start = self.pos;
+            while self.pos < self.text.len() && !b"\"\\".contains(&self.text[self.pos]) {
+                self.pos += 1;
+            }
+            match ::std::str::from_utf8(&self.text[start..self.pos]) {
+                Ok(chunk) => s.push_str(chunk),
+                Err(_) => return Err(JsonError(start)),
+            }
+            match self.text.get(self.pos) {
+                Some(&b'"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(&b'\\') => {
+                    self.pos += 1;
+                    s.push(self.read_escape()?);
+                }
+                _ => return self.err(),
+            }
+        }
+    }
+
+    fn read_array(&mut self) -> Result<Value, JsonError> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.read_value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return self.err(),
+            }
+        }
+    }
+
+    fn read_object(&mut self) -> Result<Value, JsonError> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.read_string()?;
+            self.expect(b':')?;
+            members.push((key, self.read_value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return self.err(),
+            }
+        }
+    }
+}
+
+/// Parses a complete JSON document.
+pub fn parse(text: &str) -> Result<Value, JsonError> {
+    let mut reader = Reader {
+        text: text.as_bytes(),
+        pos: 0,
+    };
+    let value = reader.read_value()?;
+    if reader.peek().is_some() {
+        return reader.err();
+    }
+    return Ok(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn values() {
+        let value = parse(r#" {"a": [null, true, false, -1.5e3, 18446744073709551615],
+                               "b": {}, "c": [], "a": "x"} "#)
+            .unwrap();
+        assert_eq!(
+            value,
+            Value::Object(vec![
+                (
+                    "a".to_string(),
+                    Value::Array(vec![
+                        Value::Null,
+                        Value::Bool(true),
+                        Value::Bool(false),
+                        Value::Number("-1.5e3".to_string()),
+                        Value::Number("18446744073709551615".to_string()),
+                    ])
+                ),
+                ("b".to_string(), Value::Object(vec![])),
+                ("c".to_string(), Value::Array(vec![])),
+                ("a".to_string(), string("x")),
+            ])
+        );
+        assert_eq!(value.get("a").unwrap().as_array().unwrap()[4].as_u64(), Some(!0));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(parse(r#""\"\\\/\b\f\n\r\t""#).unwrap(), string("\"\\/\u{8}\u{c}\n\r\t"));
+        assert_eq!(parse(r#""\u00e9\u20ac""#).unwrap(), string("\u{e9}\u{20ac}"));
+        assert_eq!(parse(r#""\ud83d\ude00""#).unwrap(), string("\u{1f600}"));
+        assert_eq!(parse(r#""\ud83d""#), Err(JsonError(7)));
+        assert_eq!(parse(r#""\ud83d\u0041""#), Err(JsonError(13)));
+        assert_eq!(parse(r#""\ude00""#), Err(JsonError(7)));
+    }
+
+    #[test]
+    fn error_offsets() {
+        assert_eq!(parse(""), Err(JsonError(0)));
+        assert_eq!(parse("[1, 2] x"), Err(JsonError(7)));
+        assert_eq!(parse(r#"{"a": "b"#), Err(JsonError(8)));
+        assert_eq!(parse(r#""\x""#), Err(JsonError(3)));
+        assert_eq!(parse(r#"{"a" 1}"#), Err(JsonError(5)));
+        assert_eq!(parse("[1,]"), Err(JsonError(3)));
+        assert_eq!(parse("[1.2.3]"), Err(JsonError(1)));
+        assert_eq!(parse("nul"), Err(JsonError(0)));
+    }
+}
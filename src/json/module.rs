@@ -0,0 +1,432 @@
+//! A stable JSON representation of `ParseResult`.
+//!
+//! The format is described by the JSON Schema in `SCHEMA`. In short, a
+//! document is an object with these members, in this order:
+//!
+//! - `format` (`"wasm-module"`) and `version` (`FORMAT_VERSION`)
+//! - `types`, `imports`, `functions`, `tables`, `memories`, `globals`,
+//!   `exports`, `elements`, `data`: arrays, or `null` if the module has no
+//!   such section
+//! - `start`, `data_count`: numbers or `null`
+//! - `names`, `producers`, `target_features`, `source_mapping_url`: the
+//!   decoded custom sections, or `null`
+//! - `custom_sections`: all other custom sections with hex encoded payloads,
+//!   including those of the sections above that could not be decoded
+//! - `custom_section_positions`: where each custom section appeared
+//!
+//! Value types are written as in the text format (`"i32"`), instructions as
+//! text format strings (`"i32.load offset=8"`) with indices for all
+//! references, and bytes as lowercase hex strings. Function bodies do not
+//! include the final `end`. The `linking` and `reloc.*` sections of
+//! relocatable object files, unless they are malformed and kept among
+//! `custom_sections`, and unknown name subsections are not represented.
+//!
+//! `load_module` turns a document back into a binary module for `Parser`. It
+//! describes the same module as the original, although the bytes differ if
+//! the original used padded LEB128 numbers.
+
+use json::{self, JsonError, Value};
+use parser::{ParseError, ParseResult, ResizableLimits};
+use parser::custom_section::{IndirectNaming, Naming, Namings};
+use parser::import_export_section::{ExternalKind, ExternalKindType};
+use parser::opcode::InitExpr;
+use parser::producers_section::ProducerVersion;
+use parser::target_features_section::FeaturePrefix;
+use wat::{instruction, type_name};
+
+use std::error::Error;
+use std::fmt;
+
+pub use json::encode::encode_module;
+
+/// Version of the format written by `module_to_json`. It is increased
+/// whenever a change would break existing readers.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// JSON Schema (draft-07) of the format.
+pub const SCHEMA: &str = include_str!("module.schema.json");
+
+/// Why a document could not be turned back into a module.
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    Json(JsonError),
+    /// The document does not follow the schema; the string tells where.
+    Invalid(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Json(ref err) => err.fmt(f),
+            LoadError::Invalid(ref msg) => write!(f, "invalid module document: {}", msg),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<JsonError> for LoadError {
+    fn from(err: JsonError) -> LoadError {
+        LoadError::Json(err)
+    }
+}
+
+fn object(members: Vec<(&str, Value)>) -> Value {
+    Value::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+fn optional<T, F>(items: &Option<Vec<T>>, f: F) -> Value
+where
+    F: Fn(&T) -> Value,
+{
+    match *items {
+        Some(ref items) => Value::Array(items.iter().map(f).collect()),
+        None => Value::Null,
+    }
+}
+
+fn hex(bytes: &[u8]) -> Value {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Value::String(hex.concat())
+}
+
+fn kind_name(kind: &ExternalKind) -> &'static str {
+    match *kind {
+        ExternalKind::Func => "func",
+        ExternalKind::Table => "table",
+        ExternalKind::Memory => "memory",
+        ExternalKind::Global => "global",
+    }
+}
+
+fn limits(limits: &ResizableLimits) -> Value {
+    object(vec![
+        ("initial", limits.initial.into()),
+        ("maximum", limits.maximum.into()),
+    ])
+}
+
+fn init_expr(expr: &InitExpr) -> Value {
+    instruction(&expr.0).into()
+}
+
+fn name_map(names: &[Naming]) -> Value {
+    let names = names
+        .iter()
+        .map(|naming| object(vec![("index", naming.index.into()), ("name", naming.name.into())]))
+        .collect();
+    Value::Array(names)
+}
+
+fn indirect_name_map(names: &[IndirectNaming]) -> Value {
+    let names = names
+        .iter()
+        .map(|(index, names)| object(vec![("index", (*index).into()), ("names", name_map(names))]))
+        .collect();
+    Value::Array(names)
+}
+
+fn namings(names: &Namings) -> Value {
+    let map = |names: &Option<Vec<Naming>>| names.as_ref().map_or(Value::Null, |n| name_map(n));
+    let indirect = |names: &Option<Vec<IndirectNaming>>| {
+        names.as_ref().map_or(Value::Null, |n| indirect_name_map(n))
+    };
+    object(vec![
+        ("module", names.module.into()),
+        ("functions", map(&names.functions)),
+        ("locals", indirect(&names.locales)),
+        ("labels", indirect(&names.labels)),
+        ("types", map(&names.types)),
+        ("tables", map(&names.tables)),
+        ("memories", map(&names.memories)),
+        ("globals", map(&names.globals)),
+        ("elems", map(&names.elems)),
+        ("datas", map(&names.datas)),
+        ("fields", indirect(&names.fields)),
+        ("tags", map(&names.tags)),
+    ])
+}
+
+fn producer_versions(versions: &[ProducerVersion]) -> Value {
+    let versions = versions
+        .iter()
+        .map(|v| object(vec![("name", v.name.into()), ("version", v.version.into())]))
+        .collect();
+    Value::Array(versions)
+}
+
+fn is_represented(name: &str) -> bool {
+    name != "linking" && !name.starts_with("reloc.")
+}
+
+/// Builds the JSON representation of a module. Fails if a function body
+/// cannot be decoded.
+pub fn module_to_json(module: &ParseResult) -> Result<Value, ParseError> {
+    let types = optional(&module.function_types, |typ| {
+        let results: Vec<&str> = typ.return_type.iter().map(|&t| type_name(t)).collect();
+        object(vec![
+            ("params", typ.param_types.iter().map(|&t| type_name(t)).collect::<Vec<_>>().into()),
+            ("results", results.into()),
+        ])
+    });
+
+    let imports = optional(&module.import_entires, |entry| {
+        let mut members = vec![
+            ("module", entry.module.into()),
+            ("field", entry.field.into()),
+            ("kind", kind_name(&entry.kind).into()),
+        ];
+        match entry.typ {
+            ExternalKindType::Func(typ) => members.push(("type", typ.into())),
+            ExternalKindType::Table(_, ref l) => {
+                members.push(("element", "anyfunc".into()));
+                members.push(("limits", limits(l)));
+            }
+            ExternalKindType::Memory(ref l) => members.push(("limits", limits(l))),
+            ExternalKindType::Global(typ, mutable) => {
+                members.push(("type", type_name(typ).into()));
+                members.push(("mutable", mutable.into()));
+            }
+        }
+        object(members)
+    });
+
+    let imported_functions = module
+        .import_entires
+        .iter()
+        .flat_map(|i| i.iter())
+        .filter(|entry| entry.kind == ExternalKind::Func)
+        .count() as u32;
+    let functions = match (&module.function_ids, &module.function_bodies) {
+        (&None, &None) => Value::Null,
+        (ids, bodies) => {
+            let ids = ids.as_ref().map_or(&[][..], |ids| &ids[..]);
+            let bodies = bodies.as_ref().map_or(&[][..], |bodies| &bodies[..]);
+            let mut functions = Vec::new();
+            for (i, (id, body)) in ids.iter().zip(bodies).enumerate() {
+                let body = body.body()?;
+                let locals = body
+                    .locals
+                    .iter()
+                    .map(|l| {
+                        object(vec![("count", l.count.into()), ("type", type_name(l.typ).into())])
+                    })
+                    .collect();
+                functions.push(object(vec![
+                    ("index", (imported_functions + i as u32).into()),
+                    ("type", id.0.into()),
+                    ("locals", Value::Array(locals)),
+                    ("body", body.code.iter().map(instruction).collect::<Vec<_>>().into()),
+                ]));
+            }
+            Value::Array(functions)
+        }
+    };
+
+    let tables = optional(&module.table_entries, |table| {
+        object(vec![("element", type_name(table.typ).into()), ("limits", limits(&table.limits))])
+    });
+    let memories = optional(&module.memory_types, |memory| {
+        object(vec![("limits", limits(&memory.limits))])
+    });
+    let globals = optional(&module.global_variables, |global| {
+        object(vec![
+            ("type", type_name(global.typ.content_type).into()),
+            ("mutable", global.typ.mutability.into()),
+            ("init", init_expr(&global.init)),
+        ])
+    });
+    let exports = optional(&module.export_entires, |export| {
+        object(vec![
+            ("name", export.field.into()),
+            ("kind", kind_name(&export.kind).into()),
+            ("index", export.index.into()),
+        ])
+    });
+    let elements = optional(&module.element_segments, |segment| {
+        let functions: Vec<u32> = segment.elems.iter().map(|f| f.0).collect();
+        object(vec![
+            ("table", segment.index.into()),
+            ("offset", init_expr(&segment.offset)),
+            ("functions", functions.into()),
+        ])
+    });
+    let data = optional(&module.data_entries, |segment| {
+        object(vec![
+            ("memory", segment.index.into()),
+            ("offset", init_expr(&segment.offset)),
+            ("bytes", hex(segment.data)),
+        ])
+    });
+
+    let producers = match module.producers {
+        Some(ref producers) => object(vec![
+            ("language", producer_versions(&producers.language)),
+            ("processed-by", producer_versions(&producers.processed_by)),
+            ("sdk", producer_versions(&producers.sdk)),
+        ]),
+        None => Value::Null,
+    };
+    let target_features = optional(&module.target_features, |feature| {
+        let prefix = match feature.prefix {
+            FeaturePrefix::Used => "+",
+            FeaturePrefix::Disallowed => "-",
+            FeaturePrefix::Required => "=",
+        };
+        object(vec![("prefix", prefix.into()), ("name", feature.name.into())])
+    });
+    let custom_sections = module
+        .custom_sections
+        .iter()
+        .map(|section| object(vec![("name", section.0.into()), ("bytes", hex(section.1))]))
+        .collect();
+    // malformed `linking` and `reloc.*` sections are kept as custom sections
+    let mut kept = module
+        .custom_section_errors
+        .iter()
+        .filter(|error| !is_represented(error.name))
+        .peekable();
+    let positions = module
+        .custom_section_positions
+        .iter()
+        .filter(|position| {
+            is_represented(position.name)
+                || kept.next_if(|error| error.name == position.name).is_some()
+        })
+        .map(|position| {
+            object(vec![("name", position.name.into()), ("after", position.after.into())])
+        })
+        .collect();
+
+    return Ok(object(vec![
+        ("format", "wasm-module".into()),
+        ("version", FORMAT_VERSION.into()),
+        ("types", types),
+        ("imports", imports),
+        ("functions", functions),
+        ("tables", tables),
+        ("memories", memories),
+        ("globals", globals),
+        ("exports", exports),
+        ("start", module.start_function.as_ref().map(|f| f.0).into()),
+        ("elements", elements),
+        ("data_count", module.data_count.into()),
+        ("data", data),
+        ("names", module.namings.as_ref().map_or(Value::Null, namings)),
+        ("producers", producers),
+        ("target_features", target_features),
+        ("source_mapping_url", module.source_mapping_url.into()),
+        ("custom_sections", Value::Array(custom_sections)),
+        ("custom_section_positions", Value::Array(positions)),
+    ]));
+}
+
+/// Reads a document written by `module_to_json` and returns the binary
+/// module it describes.
+pub fn load_module(text: &str) -> Result<Vec<u8>, LoadError> {
+    encode_module(&json::parse(text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+
+    use std::fs;
+
+    #[test]
+    fn examples_round_trip() {
+        for entry in fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "wasm") {
+                continue;
+            }
+            let data = fs::read(&path).unwrap();
+            let json = module_to_json(&Parser::new(&data).parse().unwrap()).unwrap();
+            let encoded = load_module(&json.to_string()).unwrap();
+            let reloaded = module_to_json(&Parser::new(&encoded).parse().unwrap()).unwrap();
+            assert_eq!(json, reloaded, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn malformed_name_section_round_trips() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x00, 0x0a, 0x04, b'n', b'a', b'm', b'e', 0x01, 0x03, 0x01, 0x00, 0x0a, // names
+        ];
+        let json = module_to_json(&Parser::new(&data).parse().unwrap()).unwrap();
+        assert_eq!(json.get("names"), Some(&Value::Null));
+        assert_eq!(load_module(&json.to_string()).unwrap(), data);
+    }
+
+    #[test]
+    fn malformed_tool_sections_round_trip() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x00, 0x10, 0x09, b'p', b'r', b'o', b'd', b'u', b'c', b'e', b'r', b's', 0x01, 0x03,
+            b'f', b'o', b'o', 0x00, // producers
+            0x00, 0x14, 0x0f, b't', b'a', b'r', b'g', b'e', b't', b'_', b'f', b'e', b'a', b't',
+            b'u', b'r', b'e', b's', 0x01, b'?', 0x01, b'x', // target features
+        ];
+        let json = module_to_json(&Parser::new(&data).parse().unwrap()).unwrap();
+        assert_eq!(json.get("producers"), Some(&Value::Null));
+        assert_eq!(json.get("target_features"), Some(&Value::Null));
+        assert_eq!(load_module(&json.to_string()).unwrap(), data);
+    }
+
+    #[test]
+    fn malformed_source_mapping_url_round_trips() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x00, 0x14, 0x10, b's', b'o', b'u', b'r', b'c', b'e', b'M', b'a', b'p', b'p', b'i',
+            b'n', b'g', b'U', b'R', b'L', 0x05, b'a', b'b', // url
+        ];
+        let json = module_to_json(&Parser::new(&data).parse().unwrap()).unwrap();
+        assert_eq!(json.get("source_mapping_url"), Some(&Value::Null));
+        assert_eq!(load_module(&json.to_string()).unwrap(), data);
+    }
+
+    #[test]
+    fn malformed_object_file_sections_round_trip() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x00, 0x09, 0x07, b'l', b'i', b'n', b'k', b'i', b'n', b'g', 0x01, // linking
+            0x00, 0x10, 0x0a, b'r', b'e', b'l', b'o', b'c', b'.', b'C', b'O', b'D', b'E', 0x03,
+            0x01, 0x40, 0x00, 0x00, // relocations
+        ];
+        let json = module_to_json(&Parser::new(&data).parse().unwrap()).unwrap();
+        assert_eq!(load_module(&json.to_string()).unwrap(), data);
+    }
+
+    #[test]
+    fn data_count_round_trips() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x0c, 0x01, 0x00, // data count
+            0x0b, 0x01, 0x00, // data
+        ];
+        let json = module_to_json(&Parser::new(&data).parse().unwrap()).unwrap();
+        assert_eq!(json.get("data_count"), Some(&Value::from(0u32)));
+        let encoded = load_module(&json.to_string()).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn special_floats_round_trip() {
+        let doc = r#"{"format": "wasm-module", "version": 1, "types": null, "imports": null,
+            "functions": null, "tables": null, "memories": null,
+            "globals": [
+                {"type": "f32", "mutable": false, "init": "f32.const -nan:0x200001"},
+                {"type": "f64", "mutable": false, "init": "f64.const -inf"},
+                {"type": "f64", "mutable": false, "init": "f64.const 5e-324"}
+            ],
+            "exports": null, "start": null, "elements": null, "data_count": null,
+            "data": null, "names": null, "producers": null, "target_features": null,
+            "source_mapping_url": null, "custom_sections": [], "custom_section_positions": []}"#;
+        let encoded = load_module(doc).unwrap();
+        let module = Parser::new(&encoded).parse().unwrap();
+        let json = module_to_json(&module).unwrap();
+        assert_eq!(json, json::parse(doc).unwrap());
+    }
+}
@@ -0,0 +1,33 @@
+#![allow(clippy::needless_return)]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static TRACE: AtomicBool = AtomicBool::new(false);
+
+/// Enables progress output of the parser and validator on stderr.
+pub fn set_trace(enabled: bool) {
+    TRACE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn trace_enabled() -> bool {
+    TRACE.load(Ordering::Relaxed)
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => (
+        if ::trace_enabled() {
+            eprintln!($($arg)*);
+        }
+    )
+}
+
+pub mod c;
+pub mod interpreter;
+pub mod json;
+pub mod objdump;
+pub mod parser;
+pub mod sourcemap;
+pub mod stats;
+pub mod validator;
+pub mod wasi;
+pub mod wat;
@@ -1,20 +1,381 @@
-mod parser;
-mod validator;
+#![allow(clippy::needless_return)]
 
-use parser::Parser;
-use validator::Validator;
+extern crate wasm_interpreter;
 
-fn main() {
-    println!("WASM PARSER\n===========");
-    let mut parser = Parser::new();
-    let res = parser.parse();
-    println!("===========\nDONE");
-    println!("===========COMPLETE PARSE RESULT:===========\n");
-    println!("{:?}", res);
-    println!("===========Validating===========");
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use wasm_interpreter::c;
+use wasm_interpreter::interpreter::{
+    Backend, Config, Imports, Instance, InstantiationError, InvokeError, Limits, Trap, Value,
+};
+use wasm_interpreter::json::module::{load_module, module_to_json};
+use wasm_interpreter::objdump;
+use wasm_interpreter::parser::{ParseResult, Parser};
+use wasm_interpreter::parser::dwarf::LineTable;
+use wasm_interpreter::parser::names::{NameKind, NameResolver};
+use wasm_interpreter::sourcemap::SourceMap;
+use wasm_interpreter::stats::Stats;
+use wasm_interpreter::validator::Validator;
+use wasm_interpreter::wasi::{VirtualFs, Wasi};
+use wasm_interpreter::wat;
+
+const EXIT_INVALID: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_TRAP: i32 = 3;
+const EXIT_LINK: i32 = 4;
+
+/// Number of frames of a trap backtrace that are printed.
+const BACKTRACE_FRAMES: usize = 20;
+
+const USAGE: &str = "usage: wasm-interpreter [-v] <command> <file> [args...]
+
+commands:
+  validate <file>                 check the module, exit status 1 if invalid
+  dump <file>                     print the parsed module structure
+  wat <file>                      print the module in the text format
+  objdump <file>                  list the sections and disassemble the functions
+  stats <file> [--json]           report what the bytes of the module are spent on
+  json <file>                     print the module as JSON
+  c <file> [prefix]               print the module as C source, with names starting
+                                  with the prefix (default: the file name)
+  from-json <file> <output>       write the module described by a JSON file
+  invoke <file> [options] <export> [args]
+                                  call an exported function and print its result
+  run <file> [options] [args]     call `_start` (or `main`) and exit with its result
+
+options of invoke and run, before the export or the program's arguments (or `--`):
+  --dir <host>[:<guest>]          make a host directory available to the program
+  --tar <archive>[:<guest>]       make the contents of a tar archive available in
+                                  memory, without writing changes back
+  --env <key>=<value>             set an environment variable of the program
+  --fuel <n>                      trap after executing instructions worth n fuel
+  --max-memory <bytes>            limit the size of the linear memory
+  --max-call-depth <n>            trap when calls nest deeper than n
+  --max-stack <n>                 trap when the value stack holds more than n values
+  --backend stack|register|jit    choose the interpreter backend (default: stack),
+                                  jit needs the `jit` feature
+
+exit status: 0 success, 1 invalid module, 2 usage or I/O error, 3 trap, 4 link error";
+
+fn fail(code: i32, msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(code);
+}
+
+fn parse(data: &[u8]) -> ParseResult<'_> {
+    let res = match Parser::new(data).parse() {
+        Ok(res) => res,
+        Err(err) => fail(EXIT_INVALID, &format!("Invalid Module: {}", err)),
+    };
+    for malformed in &res.custom_section_errors {
+        eprintln!("Warning: ignoring malformed '{}' section: {}", malformed.name, malformed.error);
+    }
+    return res;
+}
+
+fn validate(res: ParseResult<'_>) -> Validator<'_> {
     let validator = Validator::new(res);
     if !validator.validate() {
-        println!("Invalid Module!");
-        return;
+        fail(EXIT_INVALID, "Invalid Module!");
+    }
+    return validator;
+}
+
+/// The WASI context of `program` and the instance configuration, set by the
+/// options at the start of `args`, and the arguments after the options.
+fn options<'a>(program: &str, args: &'a [String]) -> (Wasi, Config, &'a [String]) {
+    let mut config = Config::default();
+    let mut wasi = Wasi::new();
+    wasi.arg(program);
+    let mut args = args.iter();
+    let rest = loop {
+        let rest = args.as_slice();
+        let arg = match args.next() {
+            Some(arg) => arg,
+            None => break rest,
+        };
+        match arg.as_str() {
+            "--dir" => {
+                let dir = args.next().unwrap_or_else(|| fail(EXIT_USAGE, USAGE));
+                let (host, guest) = dir.split_once(':').unwrap_or((dir, dir));
+                if let Err(err) = wasi.preopen_dir(host, guest) {
+                    fail(EXIT_USAGE, &format!("Cannot open directory {}: {}", host, err));
+                }
+            }
+            "--tar" => {
+                let dir = args.next().unwrap_or_else(|| fail(EXIT_USAGE, USAGE));
+                let (archive, guest) = dir.split_once(':').unwrap_or((dir, "/"));
+                match VirtualFs::open_tar(archive) {
+                    Ok(fs) => wasi.preopen(fs, guest),
+                    Err(err) => fail(EXIT_USAGE, &format!("Cannot read {}: {}", archive, err)),
+                };
+            }
+            "--fuel" => {
+                let fuel = args.next().unwrap_or_else(|| fail(EXIT_USAGE, USAGE));
+                match fuel.parse() {
+                    Ok(fuel) => config.fuel = Some(fuel),
+                    Err(_) => fail(EXIT_USAGE, &format!("Invalid fuel '{}'", fuel)),
+                }
+            }
+            "--max-memory" => {
+                let bytes = args.next().unwrap_or_else(|| fail(EXIT_USAGE, USAGE));
+                match bytes.parse() {
+                    Ok(bytes) => config.limiter = Some(Limits::new().memory_size(bytes).shared()),
+                    Err(_) => fail(EXIT_USAGE, &format!("Invalid memory size '{}'", bytes)),
+                }
+            }
+            "--max-call-depth" => {
+                let depth = args.next().unwrap_or_else(|| fail(EXIT_USAGE, USAGE));
+                match depth.parse() {
+                    Ok(depth) => config.max_call_depth = depth,
+                    Err(_) => fail(EXIT_USAGE, &format!("Invalid call depth '{}'", depth)),
+                }
+            }
+            "--max-stack" => {
+                let values = args.next().unwrap_or_else(|| fail(EXIT_USAGE, USAGE));
+                match values.parse() {
+                    Ok(values) => config.max_value_stack = values,
+                    Err(_) => fail(EXIT_USAGE, &format!("Invalid stack size '{}'", values)),
+                }
+            }
+            "--backend" => match args.next().map(String::as_str) {
+                Some("stack") => config.backend = Backend::Stack,
+                Some("register") => config.backend = Backend::Register,
+                #[cfg(feature = "jit")]
+                Some("jit") => config.backend = Backend::Jit,
+                _ => fail(EXIT_USAGE, USAGE),
+            },
+            "--env" => {
+                let var = args.next().unwrap_or_else(|| fail(EXIT_USAGE, USAGE));
+                match var.split_once('=') {
+                    Some((key, value)) => wasi.env(key, value),
+                    None => fail(EXIT_USAGE, &format!("Invalid environment variable '{}'", var)),
+                };
+            }
+            "--" => break args.as_slice(),
+            _ => break rest,
+        }
+    };
+    return (wasi, config, rest);
+}
+
+fn imports(wasi: Wasi) -> Imports {
+    let mut imports = Imports::new();
+    wasi.register(&mut imports);
+    return imports;
+}
+
+fn instantiate<'m>(
+    module: &'m ParseResult<'m>,
+    (imports, config): (Imports, Config),
+) -> Instance<'m> {
+    match Instance::with_config(module, &imports, config) {
+        Ok(instance) => instance,
+        Err(InstantiationError::Trap(Trap::Exit(code))) => process::exit(code),
+        Err(err) => fail(EXIT_LINK, &format!("Instantiation failed: {}", err)),
+    }
+}
+
+/// The source map the module at `path` references, if it can be loaded.
+fn source_map(module: &ParseResult, path: &str) -> Option<SourceMap> {
+    match SourceMap::load_for_module(module, Path::new(path))? {
+        Ok(map) => Some(map),
+        Err(err) => {
+            eprintln!("Warning: {}", err);
+            None
+        }
+    }
+}
+
+/// The DWARF line table of the module, if it has one that can be decoded.
+fn line_table<'m>(module: &ParseResult<'m>) -> Option<LineTable<'m>> {
+    match LineTable::from_module(module)? {
+        Ok(table) => Some(table),
+        Err(err) => {
+            eprintln!("Warning: cannot decode .debug_line: {}", err);
+            None
+        }
+    }
+}
+
+/// One line for each function that was running when the last call trapped,
+/// with its source position if the module at `path` has a source map or
+/// DWARF line information.
+fn backtrace(instance: &Instance, path: &str) -> String {
+    let frames = instance.backtrace();
+    if frames.is_empty() {
+        return String::new();
+    }
+    let module = instance.module();
+    let names = NameResolver::new(module);
+    let source_map = source_map(module, path);
+    let line_table = line_table(module);
+    let mut out = String::new();
+    for frame in frames.iter().take(BACKTRACE_FRAMES) {
+        let name = names.display_name(NameKind::Function, frame.func);
+        out.push_str(&format!("\n  at {} (@{:#x})", name, frame.offset));
+        if let Some(position) = source_map.as_ref().and_then(|map| map.lookup(frame.offset)) {
+            out.push_str(&format!(" {}", position));
+            continue;
+        }
+        // DWARF addresses are relative to the code section payload
+        let address = module.code_section_offset.and_then(|code| frame.offset.checked_sub(code));
+        let location = match (address, line_table.as_ref()) {
+            (Some(address), Some(table)) => table.lookup(address as u64),
+            _ => None,
+        };
+        if let Some(location) = location {
+            out.push_str(&format!(" {}", location));
+        }
+    }
+    if frames.len() > BACKTRACE_FRAMES {
+        out.push_str(&format!("\n  ... {} more", frames.len() - BACKTRACE_FRAMES));
+    }
+    return out;
+}
+
+fn report_invoke_error(instance: &Instance, path: &str, err: InvokeError) -> ! {
+    match err {
+        InvokeError::Trap(Trap::Exit(code)) => process::exit(code),
+        InvokeError::Trap(trap) => {
+            let msg = format!("Trap: {}{}", trap, backtrace(instance, path));
+            fail(EXIT_TRAP, &msg)
+        }
+        err => fail(EXIT_USAGE, &err.to_string()),
+    }
+}
+
+fn invoke(instance: &mut Instance, path: &str, name: &str, args: &[String]) {
+    let index = match instance.exported_function(name) {
+        Some(index) => index,
+        None => fail(EXIT_USAGE, &format!("No exported function '{}'", name)),
+    };
+    let params = &instance.func_type(index).param_types;
+    if params.len() != args.len() {
+        fail(EXIT_USAGE, &format!("'{}' takes {} arguments", name, params.len()));
+    }
+    let mut values = Vec::new();
+    for (&typ, arg) in params.iter().zip(args) {
+        match Value::parse(typ, arg) {
+            Some(value) => values.push(value),
+            None => fail(EXIT_USAGE, &format!("Invalid {:?} argument '{}'", typ, arg)),
+        }
+    }
+    match instance.invoke(name, &values) {
+        Ok(Some(result)) => println!("{}", result),
+        Ok(None) => {}
+        Err(err) => report_invoke_error(instance, path, err),
+    }
+}
+
+fn run(instance: &mut Instance, path: &str) -> i32 {
+    let entry = ["_start", "main"]
+        .iter()
+        .find(|name| instance.exported_function(name).is_some());
+    let entry = match entry {
+        Some(entry) => entry,
+        None => fail(EXIT_LINK, "Module exports neither '_start' nor 'main'"),
+    };
+    if !instance.func_type(instance.exported_function(entry).unwrap()).param_types.is_empty() {
+        fail(EXIT_LINK, &format!("'{}' must not take parameters", entry));
+    }
+    match instance.invoke(entry, &[]) {
+        Ok(Some(Value::I32(code))) => code,
+        Ok(_) => 0,
+        Err(err) => report_invoke_error(instance, path, err),
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "-v") {
+        wasm_interpreter::set_trace(true);
+        args.remove(0);
+    }
+    if args.len() < 2 {
+        fail(EXIT_USAGE, USAGE);
+    }
+    let data = match fs::read(&args[1]) {
+        Ok(data) => data,
+        Err(err) => fail(EXIT_USAGE, &format!("Cannot read {}: {}", args[1], err)),
+    };
+    let rest = &args[2..];
+    match args[0].as_str() {
+        "validate" => {
+            validate(parse(&data));
+            println!("Module is valid");
+        }
+        "dump" => println!("{:#?}", parse(&data)),
+        "wat" => match wat::print_module(&parse(&data)) {
+            Ok(text) => print!("{}", text),
+            Err(err) => fail(EXIT_INVALID, &format!("Invalid Module: {}", err)),
+        },
+        "objdump" => {
+            let source_map = source_map(&parse(&data), &args[1]);
+            match objdump::print_module(&data, source_map.as_ref()) {
+                Ok(text) => print!("{}", text),
+                Err(err) => fail(EXIT_INVALID, &format!("Invalid Module: {}", err)),
+            }
+        }
+        "stats" => match Stats::collect(&data) {
+            Ok(stats) if rest.first().is_some_and(|arg| arg == "--json") => {
+                println!("{}", stats.to_json().to_pretty_string())
+            }
+            Ok(stats) => print!("{}", stats),
+            Err(err) => fail(EXIT_INVALID, &format!("Invalid Module: {}", err)),
+        },
+        "json" => match module_to_json(&parse(&data)) {
+            Ok(json) => println!("{}", json.to_pretty_string()),
+            Err(err) => fail(EXIT_INVALID, &format!("Invalid Module: {}", err)),
+        },
+        "c" => {
+            let validator = validate(parse(&data));
+            let stem = Path::new(&args[1])
+                .file_stem()
+                .and_then(|stem| stem.to_str());
+            let prefix = rest
+                .first()
+                .map(String::as_str)
+                .or(stem)
+                .unwrap_or("module");
+            match c::translate_module(validator.parse_result(), prefix) {
+                Ok(source) => print!("{}", source),
+                Err(err) => fail(EXIT_INVALID, &format!("Invalid Module: {}", err)),
+            }
+        }
+        "from-json" => {
+            if rest.is_empty() {
+                fail(EXIT_USAGE, USAGE);
+            }
+            let module = match load_module(&String::from_utf8_lossy(&data)) {
+                Ok(module) => module,
+                Err(err) => fail(EXIT_INVALID, &err.to_string()),
+            };
+            if let Err(err) = fs::write(&rest[0], module) {
+                fail(EXIT_USAGE, &format!("Cannot write {}: {}", rest[0], err));
+            }
+        }
+        "invoke" => {
+            let (wasi, config, rest) = options(&args[1], rest);
+            if rest.is_empty() {
+                fail(EXIT_USAGE, USAGE);
+            }
+            let validator = validate(parse(&data));
+            let mut instance = instantiate(validator.parse_result(), (imports(wasi), config));
+            invoke(&mut instance, &args[1], &rest[0], &rest[1..]);
+        }
+        "run" => {
+            let (mut wasi, config, program_args) = options(&args[1], rest);
+            for arg in program_args {
+                wasi.arg(arg);
+            }
+            let validator = validate(parse(&data));
+            let mut instance = instantiate(validator.parse_result(), (imports(wasi), config));
+            process::exit(run(&mut instance, &args[1]));
+        }
+        _ => fail(EXIT_USAGE, USAGE),
     }
 }
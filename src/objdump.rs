@@ -0,0 +1,194 @@
+//! Lists the sections and disassembles the functions of a module, similar to
+//! `wasm-objdump -h -d`.
+
+use parser::{ParseError, Parser};
+use parser::import_export_section::ExternalKind;
+use parser::names::{NameKind, NameResolver};
+use parser::opcode::Opcode;
+use sourcemap::SourceMap;
+use wat;
+
+use std::fmt::Write;
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    hex.join(" ")
+}
+
+/// Prints the section header table followed by the disassembly of every
+/// function body. With a source map, each run of instructions from the same
+/// source position is preceded by that position.
+pub fn print_module(data: &[u8], source_map: Option<&SourceMap>) -> Result<String, ParseError> {
+    let headers = Parser::new(data).parse_section_headers()?;
+    let module = Parser::new(data).parse()?;
+    let names = NameResolver::new(&module);
+    let mut out = String::new();
+
+    out.push_str("Sections:\n");
+    writeln!(
+        out,
+        "{:>4} {:<20} {:>10} {:>10} {:>8}",
+        "id", "name", "offset", "size", "count"
+    )
+    .unwrap();
+    for header in &headers {
+        let count = header
+            .count
+            .map_or(String::new(), |count| count.to_string());
+        writeln!(
+            out,
+            "{:>4} {:<20} {:#010x} {:>10} {:>8}",
+            header.id, header.name, header.offset, header.size, count
+        )
+        .unwrap();
+    }
+
+    out.push_str("\nCode Disassembly:\n");
+    let imported = module
+        .import_entires
+        .iter()
+        .flat_map(|i| i.iter())
+        .filter(|entry| entry.kind == ExternalKind::Func)
+        .count() as u32;
+    for (i, body) in module
+        .function_bodies
+        .iter()
+        .flat_map(|b| b.iter())
+        .enumerate()
+    {
+        let index = imported + i as u32;
+        writeln!(
+            out,
+            "\nfunc[{}] <{}>:",
+            index,
+            names.display_name(NameKind::Function, index)
+        )
+        .unwrap();
+        let instructions = body.instructions()?;
+        let code_start = instructions.first().map_or(body.offset, |ins| ins.offset);
+        let locals = &body.data[..(code_start - body.offset) as usize];
+        let decls: Vec<String> = body
+            .body()?
+            .locals
+            .iter()
+            .map(|local| format!("{} x {:?}", local.count, local.typ))
+            .collect();
+        let decls = if decls.is_empty() {
+            "none".to_string()
+        } else {
+            decls.join(", ")
+        };
+        writeln!(out, " {:06x}: {:<30} | locals: {}", body.offset, hex_bytes(locals), decls)
+            .unwrap();
+        let mut depth = 0;
+        let mut position = None;
+        for ins in &instructions {
+            let original = source_map.and_then(|map| map.lookup(ins.offset));
+            if let Some(ref original) = original {
+                if position.as_ref() != Some(original) {
+                    writeln!(out, " ; {}", original).unwrap();
+                }
+            }
+            position = original;
+            if ins.op.opcode == Opcode::end || ins.op.opcode == Opcode::else_ {
+                depth = depth.max(1) - 1;
+            }
+            writeln!(
+                out,
+                " {:06x}: {:<30} | {}{}",
+                ins.offset,
+                hex_bytes(ins.bytes),
+                "  ".repeat(depth),
+                wat::instruction(&ins.op)
+            )
+            .unwrap();
+            match ins.op.opcode {
+                Opcode::block | Opcode::loop_ | Opcode::if_ | Opcode::else_ => depth += 1,
+                _ => {}
+            }
+        }
+    }
+    return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (func (export "f") (param i32) (result i32) (local i32)
+    ///  (if (result i32) (get_local 0) (then (i32.const 1)) (else (i32.const 2))))
+    /// followed by a custom section "foo".
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // types
+        0x03, 0x02, 0x01, 0x00, // functions
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // exports
+        0x0a, 0x10, 0x01, 0x0e, 0x01, 0x01, 0x7f, 0x20, 0x00, 0x04, 0x7f, 0x41, 0x01, 0x05,
+        0x41, 0x02, 0x0b, 0x0b, // code
+        0x00, 0x07, 0x03, b'f', b'o', b'o', 0x01, 0x02, 0x03, // custom
+    ];
+
+    const DISASSEMBLY: &str = concat!(
+        " 00001f: 01 01 7f                       | locals: 1 x I32\n",
+        " 000022: 20 00                          | local.get 0\n",
+        " 000024: 04 7f                          | if (result i32)\n",
+        " 000026: 41 01                          |   i32.const 1\n",
+        " 000028: 05                             | else\n",
+        " 000029: 41 02                          |   i32.const 2\n",
+        " 00002b: 0b                             | end\n",
+        " 00002c: 0b                             | end\n",
+    );
+
+    #[test]
+    fn section_headers() {
+        let out = print_module(MODULE, None).unwrap();
+        let expected = concat!(
+            "Sections:\n",
+            "  id name                     offset       size    count\n",
+            "   1 type                 0x0000000a          6        1\n",
+            "   3 function             0x00000012          2        1\n",
+            "   7 export               0x00000016          5        1\n",
+            "  10 code                 0x0000001d         16        1\n",
+            "   0 foo                  0x0000002f          7         \n",
+            "\nCode Disassembly:\n",
+        );
+        assert!(out.starts_with(expected), "{}", out);
+    }
+
+    #[test]
+    fn disassembly() {
+        let out = print_module(MODULE, None).unwrap();
+        let expected = format!("Code Disassembly:\n\nfunc[0] <f>:\n{}", DISASSEMBLY);
+        assert!(out.ends_with(&expected), "{}", out);
+    }
+
+    #[test]
+    fn source_positions() {
+        let map = SourceMap::parse(
+            r#"{"version": 3, "sources": ["a.c"], "names": [], "mappings": "kCAAA,IACA"}"#,
+        )
+        .unwrap();
+        let out = print_module(MODULE, Some(&map)).unwrap();
+        let mut expected: Vec<&str> = DISASSEMBLY.lines().collect();
+        expected.insert(3, " ; a.c:2:1");
+        expected.insert(1, " ; a.c:1:1");
+        assert!(out.ends_with(&(expected.join("\n") + "\n")), "{}", out);
+    }
+
+    #[test]
+    fn custom_sections() {
+        // a name section calling the function "pick", before the other one
+        let names = [
+            0x00, 0x0e, 0x04, b'n', b'a', b'm', b'e', 0x01, 0x07, 0x01, 0x00, 0x04, b'p', b'i',
+            b'c', b'k',
+        ];
+        let data = [MODULE, &names].concat();
+        let out = print_module(&data, None).unwrap();
+        let expected = concat!(
+            "   0 foo                  0x0000002f          7         \n",
+            "   0 name                 0x00000038         14         \n",
+            "\nCode Disassembly:\n\nfunc[0] <pick>:\n",
+        );
+        assert!(out.contains(expected), "{}", out);
+    }
+}
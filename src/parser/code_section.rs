@@ -1,46 +1,145 @@
-use parser::{Parser, Type};
+use parser::{ParseError, Parser, Type};
 use parser::opcode::{Op, Opcode};
 
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Largest number of locals a function body may declare.
+pub const MAX_LOCALS: u32 = 50_000;
+
 #[derive(Debug)]
-struct Local {
-    count: u32,
-    typ: Type,
+pub struct Local {
+    pub count: u32,
+    pub typ: Type,
 }
 
 #[derive(Debug)]
 pub struct FnBody {
-    locals: Vec<Local>,
-    code: Vec<Op>,
+    pub locals: Vec<Local>,
+    pub code: Vec<Op>,
+}
+
+/// An instruction together with where it was found.
+#[derive(Debug)]
+pub struct Instruction<'a> {
+    /// File offset of the opcode byte.
+    pub offset: u32,
+    /// The encoded instruction, including its immediates.
+    pub bytes: &'a [u8],
+    pub op: Op,
+}
+
+/// A function body as stored in the code section.
+///
+/// Only the byte range of the body is recorded while parsing; the locals and
+/// instructions are decoded the first time `body()` is called and cached.
+pub struct LazyFnBody<'a> {
+    /// File offset of the first byte after the body size field.
+    pub offset: u32,
+    /// Raw body bytes, including the local declarations and the final `end`.
+    pub data: &'a [u8],
+    decoded: OnceLock<Result<FnBody, ParseError>>,
 }
 
-impl Parser {
-    fn read_local_entry(&mut self) -> Local {
-        let count = self.read_varuint32();
-        let typ = self.read_value_type();
-        Local { count, typ }
+impl<'a> LazyFnBody<'a> {
+    fn new(offset: u32, data: &'a [u8]) -> LazyFnBody<'a> {
+        LazyFnBody {
+            offset,
+            data,
+            decoded: OnceLock::new(),
+        }
+    }
+
+    pub fn body(&self) -> Result<&FnBody, ParseError> {
+        let decoded = self.decoded
+            .get_or_init(|| Parser::new(self.data).read_fn_body_content(self.data.len() as u32));
+        match *decoded {
+            Ok(ref body) => Ok(body),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Decodes the instructions of the body again, this time keeping their
+    /// offsets and encodings. Unlike `body().code`, the final `end` is
+    /// included.
+    pub fn instructions(&self) -> Result<Vec<Instruction<'a>>, ParseError> {
+        let mut p = Parser::new(self.data);
+        p.read_vu32_times(Parser::read_local_entry)?;
+        let mut instructions = Vec::new();
+        while p.offset < self.data.len() {
+            let start = p.offset;
+            let op = p.read_op()?;
+            instructions.push(Instruction {
+                offset: self.offset + start as u32,
+                bytes: &self.data[start..p.offset],
+                op,
+            });
+        }
+        return Ok(instructions);
     }
 
-    fn read_fn_body(&mut self) -> FnBody {
-        let body_size = self.read_varuint32();
+    pub fn is_decoded(&self) -> bool {
+        self.decoded.get().is_some()
+    }
+}
+
+impl<'a> fmt::Debug for LazyFnBody<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.decoded.get() {
+            Some(Ok(body)) => body.fmt(f),
+            _ => f.debug_struct("LazyFnBody")
+                .field("offset", &self.offset)
+                .field("size", &self.data.len())
+                .finish(),
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn read_local_entry(&mut self) -> Result<Local, ParseError> {
+        let count = self.read_varuint32()?;
+        let typ = self.read_value_type()?;
+        Ok(Local { count, typ })
+    }
+
+    fn read_fn_body_content(&mut self, body_size: u32) -> Result<FnBody, ParseError> {
         let body_head_offset = self.get_current_offset();
-        let locals = self.read_vu32_times(Parser::read_local_entry);
+        let locals = self.read_vu32_times(Parser::read_local_entry)?;
+        let local_count = locals.iter().map(|local| local.count as u64).sum::<u64>();
+        if local_count > MAX_LOCALS as u64 {
+            return Err(ParseError::TooManyLocals);
+        }
         let body_head_size = self.get_read_len(body_head_offset);
-        let codelen = body_size - body_head_size - 1;
+        let codelen = body_size
+            .checked_sub(body_head_size + 1)
+            .ok_or(ParseError::MissingEnd)?;
         let mut code = Vec::<Op>::new();
         let code_offset = self.get_current_offset();
         while self.get_read_len(code_offset) < codelen {
-            code.push(self.read_op());
+            code.push(self.read_op()?);
         }
-        let end = self.read_op();
-        assert!(end.opcode == Opcode::end);
-        FnBody { locals, code }
+        let end = self.read_op()?;
+        if end.opcode != Opcode::end {
+            return Err(ParseError::MissingEnd);
+        }
+        Ok(FnBody { locals, code })
+    }
+
+    pub fn read_fn_body(&mut self) -> Result<LazyFnBody<'a>, ParseError> {
+        let body_size = self.read_varuint32()?;
+        let offset = self.get_current_offset();
+        let data = self.read_bytes(body_size)?;
+        Ok(LazyFnBody::new(offset, data))
     }
 
-    pub fn parse_code_section(&mut self, payload_len: u32) -> Vec<FnBody> {
-        println!("  # Parsing code section");
+    pub fn parse_code_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Vec<LazyFnBody<'a>>, ParseError> {
+        trace!("  # Parsing code section");
         let init_offset = self.get_current_offset();
-        let bodies = self.read_vu32_times(Parser::read_fn_body);
-        assert_eq!(self.get_read_len(init_offset), payload_len);
-        return bodies;
+        let bodies = self.read_vu32_times(Parser::read_fn_body)?;
+        self.check_read_len(0xA, init_offset, payload_len)?;
+        return Ok(bodies);
     }
 }
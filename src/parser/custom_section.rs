@@ -1,13 +1,13 @@
-use parser::Parser;
+use parser::{ParseError, Parser};
 
 #[derive(Debug)]
-struct Naming {
-    index: u32,
-    name: String,
+pub struct Naming<'a> {
+    pub index: u32,
+    pub name: &'a str,
 }
 
-impl Naming {
-    fn new(idx: u32, n: String) -> Naming {
+impl<'a> Naming<'a> {
+    fn new(idx: u32, n: &'a str) -> Naming<'a> {
         Naming {
             index: idx,
             name: n,
@@ -23,12 +23,22 @@ impl std::fmt::Debug for Naming {
 }
 */
 
-#[derive(Debug)]
-enum NameType {
+#[derive(Debug, PartialEq)]
+pub enum NameType {
     Unknown = -1,
     Module = 0,
     Function = 1,
     Local = 2,
+    // extended name section subsections
+    Label = 3,
+    Type = 4,
+    Table = 5,
+    Memory = 6,
+    Global = 7,
+    Elem = 8,
+    Data = 9,
+    Field = 10,
+    Tag = 11,
 }
 
 impl NameType {
@@ -37,91 +47,151 @@ impl NameType {
             0 => NameType::Module,
             1 => NameType::Function,
             2 => NameType::Local,
+            3 => NameType::Label,
+            4 => NameType::Type,
+            5 => NameType::Table,
+            6 => NameType::Memory,
+            7 => NameType::Global,
+            8 => NameType::Elem,
+            9 => NameType::Data,
+            10 => NameType::Field,
+            11 => NameType::Tag,
             _ => NameType::Unknown,
         }
     }
 }
 
-type LocalNaming = (u32, Vec<Naming>);
-type OtherSubSec = (NameType, Vec<u8>);
+/// Names of items that are indexed within another item, e.g. the locals of a
+/// function: the outer index and the name map of the inner items.
+pub type IndirectNaming<'a> = (u32, Vec<Naming<'a>>);
+pub type LocalNaming<'a> = IndirectNaming<'a>;
+pub type OtherSubSec<'a> = (NameType, &'a [u8]);
+
+#[derive(Debug, Default)]
+pub struct Namings<'a> {
+    pub module: Option<&'a str>,
+    pub functions: Option<Vec<Naming<'a>>>,
+    pub locales: Option<Vec<LocalNaming<'a>>>,
+    pub labels: Option<Vec<IndirectNaming<'a>>>,
+    pub types: Option<Vec<Naming<'a>>>,
+    pub tables: Option<Vec<Naming<'a>>>,
+    pub memories: Option<Vec<Naming<'a>>>,
+    pub globals: Option<Vec<Naming<'a>>>,
+    pub elems: Option<Vec<Naming<'a>>>,
+    pub datas: Option<Vec<Naming<'a>>>,
+    /// Field names per type index.
+    pub fields: Option<Vec<IndirectNaming<'a>>>,
+    pub tags: Option<Vec<Naming<'a>>>,
+    pub others: Vec<OtherSubSec<'a>>,
+}
+
+#[derive(Debug)]
+pub struct CustomSection<'a>(pub &'a str, pub &'a [u8]);
 
+/// Where a custom section appeared relative to the known sections.
 #[derive(Debug)]
-pub struct Namings {
-    module: Option<String>,
-    functions: Option<Vec<Naming>>,
-    locales: Option<Vec<LocalNaming>>,
-    others: Vec<OtherSubSec>,
+pub struct CustomSectionPosition<'a> {
+    pub name: &'a str,
+    /// Id of the last known section before it, `None` if it came first.
+    pub after: Option<u8>,
 }
 
+/// A custom section that could not be decoded. It is kept as an opaque
+/// `CustomSection`, since custom sections do not affect the module.
 #[derive(Debug)]
-pub struct CustomSection(String, Vec<u8>);
+pub struct CustomSectionError<'a> {
+    pub name: &'a str,
+    pub error: ParseError,
+}
 
-impl Parser {
-    fn read_naming(&mut self) -> Naming {
-        let index = self.read_varuint32() as u32;
-        let name = self.read_utf8_str_vu32();
-        Naming::new(index, name)
+impl<'a> Parser<'a> {
+    fn read_naming(&mut self) -> Result<Naming<'a>, ParseError> {
+        let index = self.read_varuint32()?;
+        let name = self.read_utf8_str_vu32()?;
+        Ok(Naming::new(index, name))
     }
 
-    fn read_name_map(&mut self) -> Vec<Naming> {
+    fn read_name_map(&mut self) -> Result<Vec<Naming<'a>>, ParseError> {
         self.read_vu32_times(Parser::read_naming)
     }
 
-    pub fn parse_name_custom_section(&mut self, payload_len: u32) -> Namings {
-        println!("  # Parsing name custom section");
+    fn read_indirect_naming(&mut self) -> Result<IndirectNaming<'a>, ParseError> {
+        let index = self.read_varuint32()?;
+        let name_map = self.read_name_map()?;
+        Ok((index, name_map))
+    }
+
+    fn read_indirect_name_map(&mut self) -> Result<Vec<IndirectNaming<'a>>, ParseError> {
+        self.read_vu32_times(Parser::read_indirect_naming)
+    }
+
+    pub fn parse_name_custom_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Namings<'a>, ParseError> {
+        trace!("  # Parsing name custom section");
         let init_offset = self.get_current_offset();
 
-        let mut module: Option<String> = None;
-        let mut functions: Option<Vec<Naming>> = None;
-        let mut locales: Option<Vec<LocalNaming>> = None;
-        let mut others = Vec::<OtherSubSec>::new();
+        let mut namings = Namings::default();
+        let mut last_id: Option<u8> = None;
 
         while self.get_read_len(init_offset) < payload_len {
-            let name_type = NameType::from_int(self.read_varuint7());
-            let name_payload_len = self.read_varuint32();
-            // enforce ordering and uniqueness of the sections with assertions
+            let id = self.read_varuint7()?;
+            let name_type = NameType::from_int(id);
+            let name_payload_len = self.read_varuint32()?;
+            let subsection_offset = self.get_current_offset();
+            if name_type == NameType::Unknown {
+                let name_payload = self.read_bytes(name_payload_len)?;
+                namings.others.push((name_type, name_payload));
+                continue;
+            }
+            // known subsections must be unique and in order of their ids
+            if last_id.is_some_and(|last| last >= id) {
+                return Err(ParseError::MalformedNameSubsection(id));
+            }
+            last_id = Some(id);
             match name_type {
-                NameType::Module => {
-                    assert!(module.is_none() && functions.is_none() && locales.is_none());
-                    let name = self.read_utf8_str_vu32();
-                    module = Some(name);
-                }
-                NameType::Function => {
-                    assert!(functions.is_none() && locales.is_none());
-                    let name_map = self.read_name_map();
-                    functions = Some(name_map);
-                }
-                NameType::Local => {
-                    assert!(locales.is_none());
-                    fn read_local_entry(p: &mut Parser) -> (u32, Vec<Naming>) {
-                        let index = p.read_varuint32();
-                        let local_map = p.read_name_map();
-                        (index, local_map)
-                    }
-                    let locals = self.read_vu32_times(read_local_entry);
-                    locales = Some(locals);
-                }
-                _ => {
-                    let name_payload_data = self.read_bytes(name_payload_len);
-                    let name_payload = name_payload_data;
-                    let subsection = (name_type, name_payload);
-                    others.push(subsection);
-                }
+                NameType::Module => namings.module = Some(self.read_utf8_str_vu32()?),
+                NameType::Function => namings.functions = Some(self.read_name_map()?),
+                NameType::Local => namings.locales = Some(self.read_indirect_name_map()?),
+                NameType::Label => namings.labels = Some(self.read_indirect_name_map()?),
+                NameType::Type => namings.types = Some(self.read_name_map()?),
+                NameType::Table => namings.tables = Some(self.read_name_map()?),
+                NameType::Memory => namings.memories = Some(self.read_name_map()?),
+                NameType::Global => namings.globals = Some(self.read_name_map()?),
+                NameType::Elem => namings.elems = Some(self.read_name_map()?),
+                NameType::Data => namings.datas = Some(self.read_name_map()?),
+                NameType::Field => namings.fields = Some(self.read_indirect_name_map()?),
+                NameType::Tag => namings.tags = Some(self.read_name_map()?),
+                NameType::Unknown => unreachable!(),
             }
+            if self.get_read_len(subsection_offset) != name_payload_len {
+                return Err(ParseError::MalformedNameSubsection(id));
+            }
+        }
+        if self.get_read_len(init_offset) != payload_len {
+            return Err(ParseError::MalformedCustomSection("name"));
+        }
+        return Ok(namings);
+    }
+
+    pub fn parse_source_mapping_url(&mut self, payload_len: u32) -> Result<&'a str, ParseError> {
+        trace!("  # Parsing sourceMappingURL custom section");
+        let init_offset = self.get_current_offset();
+        let url = self.read_utf8_str_vu32()?;
+        if self.get_read_len(init_offset) != payload_len {
+            return Err(ParseError::MalformedCustomSection("sourceMappingURL"));
         }
-        assert_eq!(self.get_read_len(init_offset), payload_len);
-        let result = Namings {
-            module,
-            functions,
-            locales,
-            others,
-        };
-        return result;
+        return Ok(url);
     }
 
-    pub fn parse_custom_section(&mut self, name: &str, payload_len: u32) -> CustomSection {
-        println!("  # Parsing custom section [name = '{}']", name);
-        let payload = self.read_bytes(payload_len);
-        return CustomSection(name.to_string(), payload);
+    pub fn parse_custom_section(
+        &mut self,
+        name: &'a str,
+        payload_len: u32,
+    ) -> Result<CustomSection<'a>, ParseError> {
+        trace!("  # Parsing custom section [name = '{}']", name);
+        let payload = self.read_bytes(payload_len)?;
+        return Ok(CustomSection(name, payload));
     }
 }
@@ -1,33 +1,46 @@
-use parser::Parser;
+use parser::{ParseError, Parser};
+use parser::leb128;
 use parser::opcode::InitExpr;
 
 #[derive(Debug)]
-pub struct DataEntry {
-    index: u32,
-    offset: InitExpr,
-    data: Vec<u8>,
+pub struct DataEntry<'a> {
+    pub index: u32,
+    pub offset: InitExpr,
+    pub data: &'a [u8],
 }
 
-impl Parser {
-    fn read_data_entry(&mut self) -> DataEntry {
-        let index = self.read_varuint32();
-        let offset = self.read_init_expr();
-        let size = self.read_varuint32();
-        let data = self.read_bytes(size);
-        DataEntry {
+impl<'a> Parser<'a> {
+    pub fn read_data_entry(&mut self) -> Result<DataEntry<'a>, ParseError> {
+        let index = self.read_varuint32()?;
+        let offset = self.read_init_expr()?;
+        let size = self.read_varuint32()?;
+        let data = self.read_bytes(size)?;
+        Ok(DataEntry {
             index,
             offset,
             data,
-        }
+        })
     }
 
-    pub fn parse_data_section(&mut self, payload_len: u32) -> Vec<DataEntry> {
+    pub fn parse_data_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Vec<DataEntry<'a>>, ParseError> {
         // custom name section needs to be parsed after the data section!
         //assert!(self.resData.name_section.is_none()); // TODO!
-        println!("  # Parsing data section");
+        trace!("  # Parsing data section");
         let init_offset = self.get_current_offset();
-        let entries = self.read_vu32_times(Parser::read_data_entry);
-        assert_eq!(self.get_read_len(init_offset), payload_len);
-        return entries;
+        let entries = self.read_vu32_times(Parser::read_data_entry)?;
+        self.check_read_len(0xB, init_offset, payload_len)?;
+        return Ok(entries);
+    }
+
+    pub fn parse_data_count_section(&mut self, payload_len: u32) -> Result<u32, ParseError> {
+        trace!("  # Parsing data count section");
+        let (count, len) = self.read_leb_len(leb128::read_varuint32)?;
+        if len != payload_len as usize {
+            return Err(ParseError::SectionSizeMismatch(0xC));
+        }
+        return Ok(count);
     }
 }
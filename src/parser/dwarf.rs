@@ -0,0 +1,645 @@
+//! Decoding of the DWARF line number program (`.debug_line`) that compilers
+//! emit as custom sections, to map code addresses back to source locations.
+//!
+//! Addresses in WebAssembly DWARF are offsets relative to the start of the
+//! code section payload (see `ParseResult::code_section_offset`).
+
+use parser::{ParseError, ParseResult, Parser};
+use parser::leb128;
+
+use parser::byteorder::{ByteOrder, LittleEndian};
+use std::fmt;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_SET_COLUMN: u8 = 5;
+const DW_LNS_NEGATE_STMT: u8 = 6;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+/// Address that linkers write for code that was discarded.
+const TOMBSTONE: u64 = 0xffff_ffff;
+
+fn malformed() -> ParseError {
+    ParseError::MalformedCustomSection(".debug_line")
+}
+
+/// A source file referenced by the line program.
+#[derive(Debug)]
+pub struct SourceFile<'a> {
+    pub directory: &'a str,
+    pub name: &'a str,
+}
+
+#[derive(Debug)]
+pub struct LineRow {
+    pub address: u64,
+    /// Index into `LineTable::files`.
+    pub file: usize,
+    pub line: u32,
+    pub column: u32,
+    pub is_stmt: bool,
+}
+
+/// A contiguous range of code described by consecutive rows.
+#[derive(Debug)]
+struct Sequence {
+    start: u64,
+    end: u64,
+    rows: Vec<LineRow>,
+}
+
+/// The source location of a code address.
+#[derive(Debug, PartialEq)]
+pub struct SourceLocation<'a> {
+    pub directory: &'a str,
+    pub file: &'a str,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl<'a> fmt::Display for SourceLocation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.directory.is_empty() || self.file.starts_with('/') {
+            write!(f, "{}:{}", self.file, self.line)
+        } else {
+            write!(f, "{}/{}:{}", self.directory, self.file, self.line)
+        }
+    }
+}
+
+/// The decoded line programs of all compilation units of a module.
+#[derive(Debug, Default)]
+pub struct LineTable<'a> {
+    pub files: Vec<SourceFile<'a>>,
+    sequences: Vec<Sequence>,
+}
+
+/// Sections a line program header may refer to for its strings.
+struct StrSections<'a> {
+    debug_str: &'a [u8],
+    debug_line_str: &'a [u8],
+}
+
+struct Header {
+    version: u16,
+    min_inst_length: u8,
+    default_is_stmt: bool,
+    line_base: i8,
+    line_range: u8,
+    opcode_base: u8,
+    standard_opcode_lengths: Vec<u8>,
+}
+
+struct RowState {
+    address: u64,
+    file: u64,
+    line: u64,
+    column: u64,
+    is_stmt: bool,
+}
+
+impl RowState {
+    fn new(default_is_stmt: bool) -> RowState {
+        RowState {
+            address: 0,
+            file: 1,
+            line: 1,
+            column: 0,
+            is_stmt: default_is_stmt,
+        }
+    }
+
+    /// Moves the address by `operation_advance` instructions. Addresses wrap
+    /// around like those of the target, whatever the program claims.
+    fn advance(&mut self, operation_advance: u64, min_inst_length: u8) {
+        let advance = operation_advance.wrapping_mul(min_inst_length as u64);
+        self.address = self.address.wrapping_add(advance);
+    }
+
+    fn row(&self, file_base: usize) -> LineRow {
+        LineRow {
+            address: self.address,
+            file: file_base.saturating_add(self.file as usize),
+            line: self.line as u32,
+            column: self.column as u32,
+            is_stmt: self.is_stmt,
+        }
+    }
+}
+
+fn str_at(section: &[u8], offset: u64) -> Result<&str, ParseError> {
+    let mut p = Parser::new(section);
+    if offset as usize > section.len() {
+        return Err(malformed());
+    }
+    p.offset = offset as usize;
+    p.read_cstr()
+}
+
+impl<'a> Parser<'a> {
+    fn read_cstr(&mut self) -> Result<&'a str, ParseError> {
+        let rest = &self.data[self.offset..];
+        let len = match rest.iter().position(|&b| b == 0) {
+            Some(len) => len,
+            None => return Err(ParseError::UnexpectedEof),
+        };
+        let s = self.read_utf8(len as u32)?;
+        self.read_byte()?;
+        Ok(s)
+    }
+
+    fn read_uint16(&mut self) -> Result<u16, ParseError> {
+        Ok(LittleEndian::read_u16(self.read_bytes(2)?))
+    }
+
+    fn read_uint64(&mut self) -> Result<u64, ParseError> {
+        Ok(LittleEndian::read_u64(self.read_bytes(8)?))
+    }
+
+    fn read_uleb128(&mut self) -> Result<u64, ParseError> {
+        self.read_leb(leb128::read_varuint64)
+    }
+
+    fn read_sleb128(&mut self) -> Result<i64, ParseError> {
+        self.read_leb(leb128::read_varint64)
+    }
+
+    fn read_offset(&mut self, offset_size: usize) -> Result<u64, ParseError> {
+        match offset_size {
+            8 => self.read_uint64(),
+            _ => Ok(self.read_uint32()? as u64),
+        }
+    }
+
+    /// Reads an attribute of a v5 directory or file entry; strings are
+    /// returned as `Some`, all other values as `None` plus their number.
+    fn read_line_form(
+        &mut self,
+        form: u64,
+        offset_size: usize,
+        strs: &StrSections<'a>,
+    ) -> Result<(Option<&'a str>, u64), ParseError> {
+        let value = match form {
+            DW_FORM_STRING => return Ok((Some(self.read_cstr()?), 0)),
+            DW_FORM_LINE_STRP => {
+                let offset = self.read_offset(offset_size)?;
+                return Ok((Some(str_at(strs.debug_line_str, offset)?), 0));
+            }
+            DW_FORM_STRP => {
+                let offset = self.read_offset(offset_size)?;
+                return Ok((Some(str_at(strs.debug_str, offset)?), 0));
+            }
+            DW_FORM_UDATA => self.read_uleb128()?,
+            DW_FORM_DATA1 => self.read_byte()? as u64,
+            DW_FORM_DATA2 => self.read_uint16()? as u64,
+            DW_FORM_DATA4 => self.read_uint32()? as u64,
+            DW_FORM_DATA8 => self.read_uint64()?,
+            DW_FORM_DATA16 => {
+                self.read_bytes(16)?;
+                0
+            }
+            DW_FORM_BLOCK => {
+                let len = self.read_uleb128()?;
+                self.read_bytes(len as u32)?;
+                0
+            }
+            _ => return Err(malformed()),
+        };
+        Ok((None, value))
+    }
+
+    /// Reads the v5 directory or file name table as (path, directory index).
+    fn read_entry_table(
+        &mut self,
+        offset_size: usize,
+        strs: &StrSections<'a>,
+    ) -> Result<Vec<(&'a str, u64)>, ParseError> {
+        let format_count = self.read_byte()?;
+        let mut format = Vec::new();
+        for _ in 0..format_count {
+            let content_type = self.read_uleb128()?;
+            let form = self.read_uleb128()?;
+            format.push((content_type, form));
+        }
+        let count = self.read_uleb128()?;
+        // every entry has a path, which takes at least a byte
+        if count > 0 && (format.is_empty() || count > (self.data.len() - self.offset) as u64) {
+            return Err(malformed());
+        }
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let mut entry = ("", 0);
+            for &(content_type, form) in &format {
+                let (string, value) = self.read_line_form(form, offset_size, strs)?;
+                match content_type {
+                    DW_LNCT_PATH => entry.0 = string.ok_or_else(malformed)?,
+                    DW_LNCT_DIRECTORY_INDEX => entry.1 = value,
+                    _ => {}
+                }
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn read_line_header(&mut self, offset_size: usize) -> Result<(Header, usize), ParseError> {
+        let version = self.read_uint16()?;
+        if !(2..=5).contains(&version) {
+            return Err(malformed());
+        }
+        if version >= 5 {
+            let _address_size = self.read_byte()?;
+            let _segment_selector_size = self.read_byte()?;
+        }
+        let header_length = self.read_offset(offset_size)?;
+        let program_start = self.offset.saturating_add(header_length as usize);
+        let min_inst_length = self.read_byte()?;
+        if version >= 4 {
+            let _max_ops_per_inst = self.read_byte()?;
+        }
+        let default_is_stmt = self.read_byte()? != 0;
+        let line_base = self.read_byte()? as i8;
+        let line_range = self.read_byte()?;
+        let opcode_base = self.read_byte()?;
+        if line_range == 0 {
+            return Err(malformed());
+        }
+        // opcode 0 starts the extended opcodes, so it has no length entry
+        let standard_opcodes = opcode_base.checked_sub(1).ok_or_else(malformed)?;
+        let standard_opcode_lengths = self.read_bytes(standard_opcodes as u32)?.to_vec();
+        let header = Header {
+            version,
+            min_inst_length,
+            default_is_stmt,
+            line_base,
+            line_range,
+            opcode_base,
+            standard_opcode_lengths,
+        };
+        Ok((header, program_start))
+    }
+
+    /// Reads the pre-v5 include directory and file name tables.
+    fn read_legacy_file_table(&mut self) -> Result<Vec<SourceFile<'a>>, ParseError> {
+        // directory 0 is the compilation directory, which is not listed
+        let mut directories = vec![""];
+        loop {
+            let dir = self.read_cstr()?;
+            if dir.is_empty() {
+                break;
+            }
+            directories.push(dir);
+        }
+        // file indices start at 1, keep a placeholder for 0
+        let mut files = vec![SourceFile {
+            directory: "",
+            name: "",
+        }];
+        loop {
+            let name = self.read_cstr()?;
+            if name.is_empty() {
+                break;
+            }
+            files.push(self.read_legacy_file_entry(name, &directories)?);
+        }
+        Ok(files)
+    }
+
+    fn read_legacy_file_entry(
+        &mut self,
+        name: &'a str,
+        directories: &[&'a str],
+    ) -> Result<SourceFile<'a>, ParseError> {
+        let dir_index = self.read_uleb128()?;
+        let _mtime = self.read_uleb128()?;
+        let _length = self.read_uleb128()?;
+        let directory = match directories.get(dir_index as usize) {
+            Some(dir) => dir,
+            None => return Err(malformed()),
+        };
+        Ok(SourceFile { directory, name })
+    }
+
+    /// Decodes one line number program unit ending at `unit_end` into `table`.
+    fn read_line_unit(
+        &mut self,
+        unit_end: usize,
+        offset_size: usize,
+        strs: &StrSections<'a>,
+        table: &mut LineTable<'a>,
+    ) -> Result<(), ParseError> {
+        let (header, program_start) = self.read_line_header(offset_size)?;
+        let (mut files, directories) = if header.version >= 5 {
+            let directories: Vec<&str> = self.read_entry_table(offset_size, strs)?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect();
+            let files = self.read_entry_table(offset_size, strs)?
+                .into_iter()
+                .map(|(name, dir)| SourceFile {
+                    directory: directories.get(dir as usize).cloned().unwrap_or(""),
+                    name,
+                })
+                .collect();
+            (files, directories)
+        } else {
+            (self.read_legacy_file_table()?, vec![""])
+        };
+        if program_start > unit_end {
+            return Err(malformed());
+        }
+        self.offset = program_start;
+
+        // rows refer to files of this unit, which are appended after the files
+        // of the previous units
+        let file_base = table.files.len();
+        let mut state = RowState::new(header.default_is_stmt);
+        let mut rows = Vec::new();
+        while self.offset < unit_end {
+            let opcode = self.read_byte()?;
+            if opcode >= header.opcode_base {
+                let adjusted = opcode - header.opcode_base;
+                let addr_advance = (adjusted / header.line_range) as u64;
+                state.advance(addr_advance, header.min_inst_length);
+                let line_advance = header.line_base as i64 + (adjusted % header.line_range) as i64;
+                state.line = (state.line as i64).wrapping_add(line_advance) as u64;
+                rows.push(state.row(file_base));
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let len = self.read_uleb128()? as usize;
+                    let op_end = self.offset.saturating_add(len);
+                    if len == 0 || op_end > unit_end {
+                        return Err(malformed());
+                    }
+                    match self.read_byte()? {
+                        DW_LNE_END_SEQUENCE => {
+                            table.push_sequence(rows, state.address);
+                            rows = Vec::new();
+                            state = RowState::new(header.default_is_stmt);
+                        }
+                        DW_LNE_SET_ADDRESS => {
+                            state.address = match len - 1 {
+                                4 => self.read_uint32()? as u64,
+                                8 => self.read_uint64()?,
+                                _ => return Err(malformed()),
+                            }
+                        }
+                        DW_LNE_DEFINE_FILE if header.version < 5 => {
+                            let name = self.read_cstr()?;
+                            files.push(self.read_legacy_file_entry(name, &directories)?);
+                        }
+                        _ => {}
+                    }
+                    self.offset = op_end;
+                }
+                DW_LNS_COPY => rows.push(state.row(file_base)),
+                DW_LNS_ADVANCE_PC => state.advance(self.read_uleb128()?, header.min_inst_length),
+                DW_LNS_ADVANCE_LINE => {
+                    state.line = (state.line as i64).wrapping_add(self.read_sleb128()?) as u64;
+                }
+                DW_LNS_SET_FILE => state.file = self.read_uleb128()?,
+                DW_LNS_SET_COLUMN => state.column = self.read_uleb128()?,
+                DW_LNS_NEGATE_STMT => state.is_stmt = !state.is_stmt,
+                DW_LNS_CONST_ADD_PC => {
+                    let adjusted = 255 - header.opcode_base;
+                    let addr_advance = (adjusted / header.line_range) as u64;
+                    state.advance(addr_advance, header.min_inst_length);
+                }
+                DW_LNS_FIXED_ADVANCE_PC => {
+                    state.address = state.address.wrapping_add(self.read_uint16()? as u64);
+                }
+                _ => {
+                    // skip the operands of opcodes that do not affect the rows
+                    let operands = header.standard_opcode_lengths[opcode as usize - 1];
+                    for _ in 0..operands {
+                        self.read_uleb128()?;
+                    }
+                }
+            }
+        }
+        table.files.extend(files);
+        Ok(())
+    }
+}
+
+impl<'a> LineTable<'a> {
+    /// Decodes the line programs of all units in a `.debug_line` section.
+    ///
+    /// `debug_line_str` and `debug_str` are needed for DWARF 5 units that keep
+    /// their file names in those sections; pass empty slices if they are absent.
+    pub fn parse(
+        debug_line: &'a [u8],
+        debug_line_str: &'a [u8],
+        debug_str: &'a [u8],
+    ) -> Result<LineTable<'a>, ParseError> {
+        let strs = StrSections {
+            debug_str,
+            debug_line_str,
+        };
+        let mut table = LineTable::default();
+        let mut p = Parser::new(debug_line);
+        while p.offset < debug_line.len() {
+            let (unit_length, offset_size) = match p.read_uint32()? {
+                0xffff_ffff => (p.read_uint64()?, 8),
+                len if len >= 0xffff_fff0 => return Err(malformed()),
+                len => (len as u64, 4),
+            };
+            let unit_end = p.offset.saturating_add(unit_length as usize);
+            if unit_end > debug_line.len() {
+                return Err(ParseError::UnexpectedEof);
+            }
+            p.read_line_unit(unit_end, offset_size, &strs, &mut table)?;
+            p.offset = unit_end;
+        }
+        table.sequences.sort_by_key(|seq| seq.start);
+        return Ok(table);
+    }
+
+    /// Decodes the `.debug_line` custom section of a module, if it has one.
+    pub fn from_module(module: &ParseResult<'a>) -> Option<Result<LineTable<'a>, ParseError>> {
+        let debug_line = module.custom_section(".debug_line")?;
+        let debug_line_str = module.custom_section(".debug_line_str").unwrap_or(&[]);
+        let debug_str = module.custom_section(".debug_str").unwrap_or(&[]);
+        Some(LineTable::parse(debug_line, debug_line_str, debug_str))
+    }
+
+    fn push_sequence(&mut self, rows: Vec<LineRow>, end: u64) {
+        let start = match rows.first() {
+            Some(row) => row.address,
+            None => return,
+        };
+        // sequences of code removed by the linker
+        if start == TOMBSTONE || start == u64::MAX {
+            return;
+        }
+        self.sequences.push(Sequence { start, end, rows });
+    }
+
+    /// All rows, ordered by the start address of their sequence.
+    pub fn rows(&self) -> impl Iterator<Item = &LineRow> {
+        self.sequences.iter().flat_map(|seq| seq.rows.iter())
+    }
+
+    /// The row describing the instruction at `address`.
+    pub fn find_row(&self, address: u64) -> Option<&LineRow> {
+        let index = match self.sequences.binary_search_by_key(&address, |seq| seq.start) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let seq = &self.sequences[index];
+        if address >= seq.end {
+            return None;
+        }
+        // several rows may share an address; the last one applies
+        let row = seq.rows.partition_point(|row| row.address <= address);
+        return seq.rows.get(row.checked_sub(1)?);
+    }
+
+    /// The source location of the instruction at `address`, an offset
+    /// relative to the start of the code section payload.
+    pub fn lookup(&self, address: u64) -> Option<SourceLocation<'a>> {
+        let row = self.find_row(address)?;
+        let file = self.files.get(row.file)?;
+        Some(SourceLocation {
+            directory: file.directory,
+            file: file.name,
+            line: row.line,
+            column: row.column,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A DWARF 4 unit with the file `src/a.c`, line_base -5 and line_range 14.
+    /// Opcode 13 is a standard opcode with two operands when `opcode_base`
+    /// is 14.
+    fn unit(opcode_base: u8, program: &[u8]) -> Vec<u8> {
+        let lengths = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1, 2];
+        let mut header = vec![1, 1, 1, 0xfb, 14, opcode_base];
+        header.extend_from_slice(&lengths[..(opcode_base as usize).saturating_sub(1)]);
+        header.extend_from_slice(b"src\0\0a.c\0\x01\0\0\0");
+        let mut unit = vec![4, 0];
+        unit.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        unit.extend(header);
+        unit.extend_from_slice(program);
+        let mut out = (unit.len() as u32).to_le_bytes().to_vec();
+        out.extend(unit);
+        out
+    }
+
+    fn locations(table: &LineTable, addresses: &[u64]) -> Vec<Option<(u32, u32)>> {
+        addresses
+            .iter()
+            .map(|&address| table.lookup(address).map(|l| (l.line, l.column)))
+            .collect()
+    }
+
+    #[test]
+    fn line_program() {
+        let mut data = unit(
+            14,
+            &[
+                0, 5, DW_LNE_SET_ADDRESS, 0x10, 0, 0, 0, // address 0x10
+                DW_LNS_ADVANCE_LINE, 2, // line 3
+                DW_LNS_SET_COLUMN, 4,
+                13, 0x80, 0x01, 0x05, // skipped
+                DW_LNS_COPY,
+                48, // special opcode: address 0x12, line 4
+                DW_LNS_ADVANCE_PC, 3, // address 0x15
+                0, 1, DW_LNE_END_SEQUENCE,
+                0, 5, DW_LNE_SET_ADDRESS, 0x30, 0, 0, 0,
+                DW_LNS_COPY,
+                DW_LNS_CONST_ADD_PC, // address 0x41
+                0, 1, DW_LNE_END_SEQUENCE,
+            ],
+        );
+        data.extend(unit(
+            13,
+            &[
+                0, 5, DW_LNE_SET_ADDRESS, 0x50, 0, 0, 0,
+                DW_LNS_COPY,
+                DW_LNS_ADVANCE_PC, 1,
+                0, 1, DW_LNE_END_SEQUENCE,
+            ],
+        ));
+        let table = LineTable::parse(&data, &[], &[]).unwrap();
+        assert_eq!(
+            locations(&table, &[0x0f, 0x10, 0x11, 0x12, 0x14, 0x15, 0x30, 0x40, 0x41]),
+            [
+                None,
+                Some((3, 4)),
+                Some((3, 4)),
+                Some((4, 4)),
+                Some((4, 4)),
+                None,
+                Some((1, 0)),
+                Some((1, 0)),
+                None
+            ]
+        );
+        assert_eq!(table.lookup(0x12).unwrap().to_string(), "src/a.c:4");
+        // the second unit's files follow those of the first
+        assert_eq!(table.find_row(0x50).unwrap().file, 3);
+        assert_eq!(table.lookup(0x50).unwrap().to_string(), "src/a.c:1");
+    }
+
+    #[test]
+    fn addresses_wrap() {
+        let data = unit(
+            13,
+            &[
+                0, 9, DW_LNE_SET_ADDRESS, 0xf0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                DW_LNS_FIXED_ADVANCE_PC, 0x20, 0, // address 0x10
+                DW_LNS_ADVANCE_PC, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+                DW_LNS_ADVANCE_LINE, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f,
+                DW_LNS_COPY, // address 0x0f
+                0xff, // special opcode
+                0, 1, DW_LNE_END_SEQUENCE,
+            ],
+        );
+        let table = LineTable::parse(&data, &[], &[]).unwrap();
+        let addresses: Vec<_> = table.rows().map(|row| row.address).collect();
+        assert_eq!(addresses, [0x0f, 0x20]);
+    }
+
+    #[test]
+    fn malformed_programs() {
+        let malformed = Err(ParseError::MalformedCustomSection(".debug_line"));
+        assert_eq!(LineTable::parse(&unit(0, &[]), &[], &[]).map(|_| ()), malformed);
+        let data = unit(13, &[0, 5, DW_LNE_SET_ADDRESS, 0x10]);
+        assert_eq!(LineTable::parse(&data, &[], &[]).map(|_| ()), malformed);
+        let mut data = unit(13, &[DW_LNS_COPY]);
+        data.truncate(data.len() - 1);
+        assert_eq!(LineTable::parse(&data, &[], &[]).map(|_| ()), Err(ParseError::UnexpectedEof));
+        // an extended opcode whose length runs past the end of the unit
+        let data = unit(13, &[0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        assert_eq!(LineTable::parse(&data, &[], &[]).map(|_| ()), malformed);
+    }
+}
@@ -1,30 +1,33 @@
-use parser::{FnId, Parser};
+use parser::{FnId, ParseError, Parser};
 use parser::opcode::InitExpr;
 
 #[derive(Debug)]
 pub struct ElemSegment {
-    index: u32,
-    offset: InitExpr,
-    elems: Vec<FnId>,
+    pub index: u32,
+    pub offset: InitExpr,
+    pub elems: Vec<FnId>,
 }
 
-impl Parser {
-    fn read_element(&mut self) -> ElemSegment {
-        let index = self.read_varuint32();
-        let offset = self.read_init_expr();
-        let elems = self.read_vu32_times(Parser::read_fn_id);
-        ElemSegment {
+impl<'a> Parser<'a> {
+    pub fn read_element(&mut self) -> Result<ElemSegment, ParseError> {
+        let index = self.read_varuint32()?;
+        let offset = self.read_init_expr()?;
+        let elems = self.read_vu32_times(Parser::read_fn_id)?;
+        Ok(ElemSegment {
             index,
             offset,
             elems,
-        }
+        })
     }
 
-    pub fn parse_element_section(&mut self, payload_len: u32) -> Vec<ElemSegment> {
-        println!("  # Parsing element section");
+    pub fn parse_element_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Vec<ElemSegment>, ParseError> {
+        trace!("  # Parsing element section");
         let init_offset = self.get_current_offset();
-        let entries = self.read_vu32_times(Parser::read_element);
-        assert_eq!(self.get_read_len(init_offset), payload_len);
-        return entries;
+        let entries = self.read_vu32_times(Parser::read_element)?;
+        self.check_read_len(0x9, init_offset, payload_len)?;
+        return Ok(entries);
     }
 }
@@ -1,15 +1,15 @@
-use parser::{FnId, Parser};
+use parser::{FnId, ParseError, Parser};
 
-impl Parser {
-    pub fn read_fn_id(&mut self) -> FnId {
-        FnId(self.read_varuint32())
+impl<'a> Parser<'a> {
+    pub fn read_fn_id(&mut self) -> Result<FnId, ParseError> {
+        Ok(FnId(self.read_varuint32()?))
     }
 
-    pub fn parse_function_section(&mut self, payload_len: u32) -> Vec<FnId> {
-        println!("  # Parsing function section");
+    pub fn parse_function_section(&mut self, payload_len: u32) -> Result<Vec<FnId>, ParseError> {
+        trace!("  # Parsing function section");
         let init_offset = self.get_current_offset();
-        let ids = self.read_vu32_times(Parser::read_fn_id);
-        assert_eq!(self.get_read_len(init_offset), payload_len);
-        return ids;
+        let ids = self.read_vu32_times(Parser::read_fn_id)?;
+        self.check_read_len(0x3, init_offset, payload_len)?;
+        return Ok(ids);
     }
 }
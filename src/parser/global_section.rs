@@ -1,4 +1,4 @@
-use parser::{Parser, Type};
+use parser::{ParseError, Parser, Type};
 use parser::opcode::InitExpr;
 
 #[derive(Debug)]
@@ -13,26 +13,29 @@ pub struct GlobalVariable {
     pub init: InitExpr,
 }
 
-impl Parser {
-    fn read_global_type(&mut self) -> GlobalType {
-        let content_type = self.read_value_type();
-        let mutability = self.read_varuint1();
-        GlobalType {
+impl<'a> Parser<'a> {
+    fn read_global_type(&mut self) -> Result<GlobalType, ParseError> {
+        let content_type = self.read_value_type()?;
+        let mutability = self.read_varuint1()?;
+        Ok(GlobalType {
             content_type,
             mutability,
-        }
+        })
     }
 
-    fn read_global_variable(&mut self) -> GlobalVariable {
-        let typ = self.read_global_type();
-        let init = self.read_init_expr();
-        GlobalVariable { typ, init }
+    pub fn read_global_variable(&mut self) -> Result<GlobalVariable, ParseError> {
+        let typ = self.read_global_type()?;
+        let init = self.read_init_expr()?;
+        Ok(GlobalVariable { typ, init })
     }
-    pub fn parse_global_section(&mut self, payload_len: u32) -> Vec<GlobalVariable> {
-        println!("  # Parsing global section");
+    pub fn parse_global_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Vec<GlobalVariable>, ParseError> {
+        trace!("  # Parsing global section");
         let init_offset = self.get_current_offset();
-        let globals = self.read_vu32_times(Parser::read_global_variable);
-        assert_eq!(self.get_read_len(init_offset), payload_len);
-        return globals;
+        let globals = self.read_vu32_times(Parser::read_global_variable)?;
+        self.check_read_len(0x6, init_offset, payload_len)?;
+        return Ok(globals);
     }
 }
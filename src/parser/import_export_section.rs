@@ -1,7 +1,7 @@
-use parser::{Parser, ResizableLimits, Type};
+use parser::{ParseError, Parser, ResizableLimits, Type};
 
-#[derive(Debug)]
-enum ExternalKind {
+#[derive(Debug, PartialEq)]
+pub enum ExternalKind {
     Func = 0,
     Table = 1,
     Memory = 2,
@@ -9,7 +9,7 @@ enum ExternalKind {
 }
 
 #[derive(Debug)]
-enum ExternalKindType {
+pub enum ExternalKindType {
     Func(u32),
     Table(u8, ResizableLimits),
     Memory(ResizableLimits),
@@ -17,92 +17,100 @@ enum ExternalKindType {
 }
 
 #[derive(Debug)]
-pub struct ImportEntry {
-    module: String,
-    field: String,
-    kind: ExternalKind,
-    typ: ExternalKindType,
+pub struct ImportEntry<'a> {
+    pub module: &'a str,
+    pub field: &'a str,
+    pub kind: ExternalKind,
+    pub typ: ExternalKindType,
 }
 
 #[derive(Debug)]
-pub struct ExportEntry {
-    field: String,
-    kind: ExternalKind,
-    index: u32,
+pub struct ExportEntry<'a> {
+    pub field: &'a str,
+    pub kind: ExternalKind,
+    pub index: u32,
 }
 
-impl Parser {
-    fn read_external_kind(&mut self) -> ExternalKind {
-        match self.read_byte() {
-            0 => ExternalKind::Func,
-            1 => ExternalKind::Table,
-            2 => ExternalKind::Memory,
-            3 => ExternalKind::Global,
-            _ => panic!("Unknown ExternalKind value"),
+impl<'a> Parser<'a> {
+    fn read_external_kind(&mut self) -> Result<ExternalKind, ParseError> {
+        match self.read_byte()? {
+            0 => Ok(ExternalKind::Func),
+            1 => Ok(ExternalKind::Table),
+            2 => Ok(ExternalKind::Memory),
+            3 => Ok(ExternalKind::Global),
+            kind => Err(ParseError::InvalidExternalKind(kind)),
         }
     }
-    fn read_external_kind_and_type(&mut self) -> (ExternalKind, ExternalKindType) {
-        let kind = self.read_external_kind();
+    fn read_external_kind_and_type(
+        &mut self,
+    ) -> Result<(ExternalKind, ExternalKindType), ParseError> {
+        let kind = self.read_external_kind()?;
         let typ = match kind {
-            ExternalKind::Func => self.read_ext_func_type(),
-            ExternalKind::Table => self.read_ext_table_type(),
-            ExternalKind::Memory => self.read_ext_memory_type(),
-            ExternalKind::Global => self.read_ext_global_type(),
+            ExternalKind::Func => self.read_ext_func_type()?,
+            ExternalKind::Table => self.read_ext_table_type()?,
+            ExternalKind::Memory => self.read_ext_memory_type()?,
+            ExternalKind::Global => self.read_ext_global_type()?,
         };
-        return (kind, typ);
+        return Ok((kind, typ));
     }
 
-    fn read_ext_func_type(&mut self) -> ExternalKindType {
-        ExternalKindType::Func(self.read_varuint32())
+    fn read_ext_func_type(&mut self) -> Result<ExternalKindType, ParseError> {
+        Ok(ExternalKindType::Func(self.read_varuint32()?))
     }
 
-    fn read_ext_table_type(&mut self) -> ExternalKindType {
-        let elem_type = self.read_varuint7();
-        let limits = self.read_resizable_limits();
-        ExternalKindType::Table(elem_type, limits)
+    fn read_ext_table_type(&mut self) -> Result<ExternalKindType, ParseError> {
+        let elem_type = self.read_varuint7()?;
+        let limits = self.read_resizable_limits()?;
+        Ok(ExternalKindType::Table(elem_type, limits))
     }
 
-    fn read_ext_memory_type(&mut self) -> ExternalKindType {
-        ExternalKindType::Memory(self.read_resizable_limits())
+    fn read_ext_memory_type(&mut self) -> Result<ExternalKindType, ParseError> {
+        Ok(ExternalKindType::Memory(self.read_resizable_limits()?))
     }
 
-    fn read_ext_global_type(&mut self) -> ExternalKindType {
-        let content_type = self.read_value_type();
-        let mutability = self.read_varuint1();
-        ExternalKindType::Global(content_type, mutability)
+    fn read_ext_global_type(&mut self) -> Result<ExternalKindType, ParseError> {
+        let content_type = self.read_value_type()?;
+        let mutability = self.read_varuint1()?;
+        Ok(ExternalKindType::Global(content_type, mutability))
     }
 
-    fn read_import_entry(&mut self) -> ImportEntry {
-        let module = self.read_utf8_str_vu32();
-        let field = self.read_utf8_str_vu32();
-        let (kind, typ) = self.read_external_kind_and_type();
-        ImportEntry {
+    pub fn read_import_entry(&mut self) -> Result<ImportEntry<'a>, ParseError> {
+        let module = self.read_utf8_str_vu32()?;
+        let field = self.read_utf8_str_vu32()?;
+        let (kind, typ) = self.read_external_kind_and_type()?;
+        Ok(ImportEntry {
             module,
             field,
             kind,
             typ,
-        }
+        })
     }
 
-    pub fn parse_import_section(&mut self, payload_len: u32) -> Vec<ImportEntry> {
-        println!("  # Parsing import section");
+    pub fn parse_import_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Vec<ImportEntry<'a>>, ParseError> {
+        trace!("  # Parsing import section");
         let init_offset = self.get_current_offset();
-        let entries = self.read_vu32_times(Parser::read_import_entry);
-        assert_eq!(self.get_read_len(init_offset), payload_len);
-        return entries;
+        let entries = self.read_vu32_times(Parser::read_import_entry)?;
+        self.check_read_len(0x2, init_offset, payload_len)?;
+        return Ok(entries);
     }
-    fn read_export_entry(&mut self) -> ExportEntry {
-        let field = self.read_utf8_str_vu32();
-        let kind = self.read_external_kind();
-        let index = self.read_varuint32();
-        ExportEntry { field, kind, index }
+    pub fn read_export_entry(&mut self) -> Result<ExportEntry<'a>, ParseError> {
+        let field = self.read_utf8_str_vu32()?;
+        let kind = self.read_external_kind()?;
+        let index = self.read_varuint32()?;
+        Ok(ExportEntry { field, kind, index })
     }
 
-    pub fn parse_export_section(&mut self, payload_len: u32) -> Vec<ExportEntry> {
-        println!("  # Parsing export section");
+    pub fn parse_export_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Vec<ExportEntry<'a>>, ParseError> {
+        trace!("  # Parsing export section");
         let init_offset = self.get_current_offset();
-        let entries = self.read_vu32_times(Parser::read_export_entry);
-        assert_eq!(self.get_read_len(init_offset), payload_len);
-        return entries;
+        let entries = self.read_vu32_times(Parser::read_export_entry)?;
+        self.check_read_len(0x7, init_offset, payload_len)?;
+        return Ok(entries);
     }
 }
@@ -0,0 +1,331 @@
+//! LEB128 decoding as specified for the binary format.
+//!
+//! An `N` bit value may use at most `ceil(N / 7)` bytes. In the last permitted
+//! byte, the bits beyond `N` must be zero for unsigned values and copies of
+//! the sign bit for signed values.
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LebError {
+    /// The input ended before the last byte of the number.
+    UnexpectedEof,
+    /// The encoding uses more bytes than the value's bit width allows.
+    TooLong,
+    /// The last byte has bits set that do not fit into the value's bit width.
+    Overflow,
+}
+
+impl fmt::Display for LebError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LebError::UnexpectedEof => write!(f, "unexpected end of LEB128 number"),
+            LebError::TooLong => write!(f, "LEB128 number is too long"),
+            LebError::Overflow => write!(f, "LEB128 number is out of range"),
+        }
+    }
+}
+
+impl Error for LebError {}
+
+/// A reader for one LEB128 number type, returning the value and its length.
+pub type LebReader<T> = fn(&[u8]) -> Result<(T, usize), LebError>;
+
+fn max_len(bits: u32) -> usize {
+    bits.div_ceil(7) as usize
+}
+
+/// Reads an unsigned number of `bits` width (at most 64) from the start of
+/// `data` and returns it together with the number of bytes it occupied.
+pub fn read_unsigned(data: &[u8], bits: u32) -> Result<(u64, usize), LebError> {
+    let mut res: u64 = 0;
+    for i in 0..max_len(bits) {
+        let byte = *data.get(i).ok_or(LebError::UnexpectedEof)?;
+        let shift = 7 * i as u32;
+        if i == max_len(bits) - 1 {
+            if byte & 0x80 != 0 {
+                return Err(LebError::TooLong);
+            }
+            let remaining_bits = bits - shift;
+            if remaining_bits < 7 && byte >> remaining_bits != 0 {
+                return Err(LebError::Overflow);
+            }
+        }
+        res |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((res, i + 1));
+        }
+    }
+    unreachable!()
+}
+
+/// Reads a signed number of `bits` width (at most 64) from the start of `data`
+/// and returns it together with the number of bytes it occupied.
+pub fn read_signed(data: &[u8], bits: u32) -> Result<(i64, usize), LebError> {
+    let mut res: i64 = 0;
+    for i in 0..max_len(bits) {
+        let byte = *data.get(i).ok_or(LebError::UnexpectedEof)?;
+        let shift = 7 * i as u32;
+        if i == max_len(bits) - 1 {
+            if byte & 0x80 != 0 {
+                return Err(LebError::TooLong);
+            }
+            // the sign bit and all unused bits above it must be equal
+            let remaining_bits = bits - shift;
+            let high_bits = byte >> (remaining_bits - 1);
+            if remaining_bits < 7 && high_bits != 0 && high_bits != 0x7f >> (remaining_bits - 1) {
+                return Err(LebError::Overflow);
+            }
+        }
+        res |= ((byte & 0x7f) as i64) << shift;
+        if byte & 0x80 == 0 {
+            let shift = shift + 7;
+            if shift < 64 && byte & 0x40 != 0 {
+                res |= !0i64 << shift;
+            }
+            return Ok((res, i + 1));
+        }
+    }
+    unreachable!()
+}
+
+/// Appends the shortest encoding of `value`.
+pub fn write_unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Appends the shortest encoding of `value`.
+pub fn write_signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let sign_bit_clear = byte & 0x40 == 0;
+        if (value == 0 && sign_bit_clear) || (value == -1 && !sign_bit_clear) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn read_varuint1(data: &[u8]) -> Result<(bool, usize), LebError> {
+    read_unsigned(data, 1).map(|(v, len)| (v != 0, len))
+}
+
+pub fn read_varuint7(data: &[u8]) -> Result<(u8, usize), LebError> {
+    read_unsigned(data, 7).map(|(v, len)| (v as u8, len))
+}
+
+pub fn read_varuint32(data: &[u8]) -> Result<(u32, usize), LebError> {
+    read_unsigned(data, 32).map(|(v, len)| (v as u32, len))
+}
+
+pub fn read_varuint64(data: &[u8]) -> Result<(u64, usize), LebError> {
+    read_unsigned(data, 64)
+}
+
+pub fn read_varint7(data: &[u8]) -> Result<(i8, usize), LebError> {
+    read_signed(data, 7).map(|(v, len)| (v as i8, len))
+}
+
+pub fn read_varint32(data: &[u8]) -> Result<(i32, usize), LebError> {
+    read_signed(data, 32).map(|(v, len)| (v as i32, len))
+}
+
+pub fn read_varint64(data: &[u8]) -> Result<(i64, usize), LebError> {
+    read_signed(data, 64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reference encoder: canonical encoding, optionally padded with redundant
+    /// continuation bytes up to `len` bytes.
+    fn encode_unsigned(mut value: u64, len: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            out.push(byte | 0x80);
+            if value == 0 && out.len() >= len {
+                break;
+            }
+        }
+        *out.last_mut().unwrap() &= 0x7f;
+        out
+    }
+
+    fn encode_signed(mut value: i64, len: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            out.push(byte | 0x80);
+            let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+            if done && out.len() >= len {
+                break;
+            }
+        }
+        *out.last_mut().unwrap() &= 0x7f;
+        out
+    }
+
+    /// Reference decoder on unbounded integers, without length or range limits.
+    fn reference_decode(data: &[u8], signed: bool) -> Option<(i128, usize)> {
+        let mut res: i128 = 0;
+        for (i, &byte) in data.iter().enumerate() {
+            res |= ((byte & 0x7f) as i128) << (7 * i);
+            if byte & 0x80 == 0 {
+                let shift = 7 * (i + 1);
+                if signed && byte & 0x40 != 0 {
+                    res -= 1i128 << shift;
+                }
+                return Some((res, i + 1));
+            }
+        }
+        None
+    }
+
+    fn in_range(value: i128, bits: u32, signed: bool) -> bool {
+        if signed {
+            value >= -(1i128 << (bits - 1)) && value < (1i128 << (bits - 1))
+        } else {
+            value >= 0 && value < (1i128 << bits)
+        }
+    }
+
+    /// xorshift64, so the random cases are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    fn sample_values(bits: u32) -> Vec<u64> {
+        let mask = if bits == 64 { !0 } else { (1u64 << bits) - 1 };
+        let mut values: Vec<u64> = (0..=0xffff).collect();
+        for shift in 0..64 {
+            values.push(1 << shift);
+            values.push((1 << shift) - 1);
+            values.push(!0 << shift);
+        }
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..100_000 {
+            let v = rng.next();
+            values.push(v >> (rng.next() % 64));
+        }
+        values.into_iter().map(|v| v & mask).collect()
+    }
+
+    #[test]
+    fn unsigned_roundtrip() {
+        for &bits in &[1, 7, 32, 64] {
+            for value in sample_values(bits) {
+                for len in 1..=max_len(bits) + 1 {
+                    let enc = encode_unsigned(value, len);
+                    if enc.len() > max_len(bits) {
+                        assert_eq!(read_unsigned(&enc, bits), Err(LebError::TooLong));
+                    } else {
+                        assert_eq!(read_unsigned(&enc, bits), Ok((value, enc.len())));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn signed_roundtrip() {
+        for &bits in &[7, 32, 64] {
+            for raw in sample_values(bits) {
+                // sign-extend the sample to the bit width
+                let value = ((raw << (64 - bits)) as i64) >> (64 - bits);
+                for len in 1..=max_len(bits) + 1 {
+                    let enc = encode_signed(value, len);
+                    if enc.len() > max_len(bits) {
+                        assert_eq!(read_signed(&enc, bits), Err(LebError::TooLong));
+                    } else {
+                        assert_eq!(read_signed(&enc, bits), Ok((value, enc.len())));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        for &bits in &[1, 7, 32] {
+            let enc = encode_unsigned(1 << bits, 0);
+            assert!(read_unsigned(&enc, bits).is_err());
+        }
+        for &bits in &[7, 32] {
+            let enc = encode_signed(1 << (bits - 1), 0);
+            assert!(read_signed(&enc, bits).is_err());
+            let enc = encode_signed(-(1 << (bits - 1)) - 1, 0);
+            assert!(read_signed(&enc, bits).is_err());
+        }
+    }
+
+    #[test]
+    fn negative_constants() {
+        assert_eq!(read_varint32(&[0x7f]), Ok((-1, 1)));
+        assert_eq!(read_varint32(&[0x80, 0x7f]), Ok((-128, 2)));
+        assert_eq!(read_varint32(&[0x80, 0x80, 0x80, 0x80, 0x78]), Ok((i32::MIN, 5)));
+        assert_eq!(read_varint64(&[0x40]), Ok((-64, 1)));
+        assert_eq!(read_varint7(&[0x40]), Ok((-64, 1)));
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert_eq!(read_varuint32(&[]), Err(LebError::UnexpectedEof));
+        assert_eq!(read_varuint32(&[0x80, 0x80]), Err(LebError::UnexpectedEof));
+        assert_eq!(read_varint64(&[0xff]), Err(LebError::UnexpectedEof));
+    }
+
+    /// Every input of up to three bytes whose first two bytes continue the
+    /// number, checked against the reference decoder. Shorter numbers ignore
+    /// any further bytes.
+    #[test]
+    fn exhaustive_short_inputs() {
+        let mut inputs: Vec<Vec<u8>> = Vec::new();
+        for a in 0..=255u8 {
+            inputs.push(vec![a]);
+            for b in 0..=255u8 {
+                inputs.push(vec![a, b]);
+                if a & 0x80 != 0 && b & 0x80 != 0 {
+                    for c in 0..=255u8 {
+                        inputs.push(vec![a, b, c]);
+                    }
+                }
+            }
+        }
+        for input in &inputs {
+            for &bits in &[1, 7, 14, 32, 64] {
+                let expected = reference_decode(input, false)
+                    .filter(|&(v, len)| len <= max_len(bits) && in_range(v, bits, false))
+                    .map(|(v, len)| (v as u64, len));
+                assert_eq!(read_unsigned(input, bits).ok(), expected, "{:?}", input);
+            }
+            for &bits in &[7, 14, 32, 64] {
+                let expected = reference_decode(input, true)
+                    .filter(|&(v, len)| len <= max_len(bits) && in_range(v, bits, true))
+                    .map(|(v, len)| (v as i64, len));
+                assert_eq!(read_signed(input, bits).ok(), expected, "{:?}", input);
+            }
+        }
+    }
+}
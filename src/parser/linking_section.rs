@@ -0,0 +1,511 @@
+//! The `linking` and `reloc.*` custom sections of relocatable object files, as
+//! described by the WebAssembly tool conventions.
+
+use parser::{ParseError, Parser};
+
+const WASM_SEGMENT_INFO: u8 = 5;
+const WASM_INIT_FUNCS: u8 = 6;
+const WASM_COMDAT_INFO: u8 = 7;
+const WASM_SYMBOL_TABLE: u8 = 8;
+
+pub const WASM_SYM_BINDING_WEAK: u32 = 0x1;
+pub const WASM_SYM_BINDING_LOCAL: u32 = 0x2;
+pub const WASM_SYM_VISIBILITY_HIDDEN: u32 = 0x4;
+pub const WASM_SYM_UNDEFINED: u32 = 0x10;
+pub const WASM_SYM_EXPORTED: u32 = 0x20;
+pub const WASM_SYM_EXPLICIT_NAME: u32 = 0x40;
+pub const WASM_SYM_NO_STRIP: u32 = 0x80;
+pub const WASM_SYM_TLS: u32 = 0x100;
+pub const WASM_SYM_ABSOLUTE: u32 = 0x200;
+
+#[derive(Debug)]
+pub struct SegmentInfo<'a> {
+    pub name: &'a str,
+    /// Alignment of the segment as a power of two.
+    pub alignment: u32,
+    pub flags: u32,
+}
+
+#[derive(Debug)]
+pub struct InitFunc {
+    pub priority: u32,
+    pub symbol_index: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ComdatKind {
+    Data = 0,
+    Function = 1,
+    Global = 2,
+    Event = 3,
+    Table = 4,
+    Section = 5,
+}
+
+#[derive(Debug)]
+pub struct ComdatSym {
+    pub kind: ComdatKind,
+    pub index: u32,
+}
+
+#[derive(Debug)]
+pub struct Comdat<'a> {
+    pub name: &'a str,
+    pub flags: u32,
+    pub symbols: Vec<ComdatSym>,
+}
+
+/// Location of a defined data symbol.
+#[derive(Debug)]
+pub struct DataSymbol {
+    pub segment: u32,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// The kind of a symbol and the index of the item it refers to.
+#[derive(Debug)]
+pub enum SymbolKind {
+    Function(u32),
+    /// `None` for undefined data symbols.
+    Data(Option<DataSymbol>),
+    Global(u32),
+    Section(u32),
+    Event(u32),
+    Table(u32),
+}
+
+#[derive(Debug)]
+pub struct SymbolInfo<'a> {
+    pub flags: u32,
+    /// Undefined symbols without an explicit name take the name of their import.
+    pub name: Option<&'a str>,
+    pub kind: SymbolKind,
+}
+
+impl<'a> SymbolInfo<'a> {
+    pub fn is_undefined(&self) -> bool {
+        self.flags & WASM_SYM_UNDEFINED != 0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Linking<'a> {
+    pub version: u32,
+    pub segments: Vec<SegmentInfo<'a>>,
+    pub init_funcs: Vec<InitFunc>,
+    pub comdats: Vec<Comdat<'a>>,
+    pub symbols: Vec<SymbolInfo<'a>>,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RelocType {
+    R_WASM_FUNCTION_INDEX_LEB = 0,
+    R_WASM_TABLE_INDEX_SLEB = 1,
+    R_WASM_TABLE_INDEX_I32 = 2,
+    R_WASM_MEMORY_ADDR_LEB = 3,
+    R_WASM_MEMORY_ADDR_SLEB = 4,
+    R_WASM_MEMORY_ADDR_I32 = 5,
+    R_WASM_TYPE_INDEX_LEB = 6,
+    R_WASM_GLOBAL_INDEX_LEB = 7,
+    R_WASM_FUNCTION_OFFSET_I32 = 8,
+    R_WASM_SECTION_OFFSET_I32 = 9,
+    R_WASM_EVENT_INDEX_LEB = 10,
+    R_WASM_MEMORY_ADDR_REL_SLEB = 11,
+    R_WASM_TABLE_INDEX_REL_SLEB = 12,
+    R_WASM_GLOBAL_INDEX_I32 = 13,
+    R_WASM_MEMORY_ADDR_LEB64 = 14,
+    R_WASM_MEMORY_ADDR_SLEB64 = 15,
+    R_WASM_MEMORY_ADDR_I64 = 16,
+    R_WASM_MEMORY_ADDR_REL_SLEB64 = 17,
+    R_WASM_TABLE_INDEX_SLEB64 = 18,
+    R_WASM_TABLE_INDEX_I64 = 19,
+    R_WASM_TABLE_NUMBER_LEB = 20,
+    R_WASM_MEMORY_ADDR_TLS_SLEB = 21,
+    R_WASM_FUNCTION_OFFSET_I64 = 22,
+    R_WASM_MEMORY_ADDR_LOCREL_I32 = 23,
+    R_WASM_TABLE_INDEX_REL_SLEB64 = 24,
+    R_WASM_MEMORY_ADDR_TLS_SLEB64 = 25,
+    R_WASM_FUNCTION_INDEX_I32 = 26,
+}
+
+impl RelocType {
+    fn from_byte(b: u8) -> Option<RelocType> {
+        use self::RelocType::*;
+        let types = [
+            R_WASM_FUNCTION_INDEX_LEB,
+            R_WASM_TABLE_INDEX_SLEB,
+            R_WASM_TABLE_INDEX_I32,
+            R_WASM_MEMORY_ADDR_LEB,
+            R_WASM_MEMORY_ADDR_SLEB,
+            R_WASM_MEMORY_ADDR_I32,
+            R_WASM_TYPE_INDEX_LEB,
+            R_WASM_GLOBAL_INDEX_LEB,
+            R_WASM_FUNCTION_OFFSET_I32,
+            R_WASM_SECTION_OFFSET_I32,
+            R_WASM_EVENT_INDEX_LEB,
+            R_WASM_MEMORY_ADDR_REL_SLEB,
+            R_WASM_TABLE_INDEX_REL_SLEB,
+            R_WASM_GLOBAL_INDEX_I32,
+            R_WASM_MEMORY_ADDR_LEB64,
+            R_WASM_MEMORY_ADDR_SLEB64,
+            R_WASM_MEMORY_ADDR_I64,
+            R_WASM_MEMORY_ADDR_REL_SLEB64,
+            R_WASM_TABLE_INDEX_SLEB64,
+            R_WASM_TABLE_INDEX_I64,
+            R_WASM_TABLE_NUMBER_LEB,
+            R_WASM_MEMORY_ADDR_TLS_SLEB,
+            R_WASM_FUNCTION_OFFSET_I64,
+            R_WASM_MEMORY_ADDR_LOCREL_I32,
+            R_WASM_TABLE_INDEX_REL_SLEB64,
+            R_WASM_MEMORY_ADDR_TLS_SLEB64,
+            R_WASM_FUNCTION_INDEX_I32,
+        ];
+        types.get(b as usize).cloned()
+    }
+
+    /// Whether entries of this type carry an addend.
+    pub fn has_addend(self) -> bool {
+        use self::RelocType::*;
+        matches!(
+            self,
+            R_WASM_MEMORY_ADDR_LEB
+                | R_WASM_MEMORY_ADDR_SLEB
+                | R_WASM_MEMORY_ADDR_I32
+                | R_WASM_FUNCTION_OFFSET_I32
+                | R_WASM_SECTION_OFFSET_I32
+                | R_WASM_MEMORY_ADDR_REL_SLEB
+                | R_WASM_MEMORY_ADDR_LEB64
+                | R_WASM_MEMORY_ADDR_SLEB64
+                | R_WASM_MEMORY_ADDR_I64
+                | R_WASM_MEMORY_ADDR_REL_SLEB64
+                | R_WASM_MEMORY_ADDR_TLS_SLEB
+                | R_WASM_FUNCTION_OFFSET_I64
+                | R_WASM_MEMORY_ADDR_LOCREL_I32
+                | R_WASM_MEMORY_ADDR_TLS_SLEB64
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Relocation {
+    pub typ: RelocType,
+    /// Offset of the value to rewrite, relative to the start of the section
+    /// payload.
+    pub offset: u32,
+    /// Symbol index, or type index for `R_WASM_TYPE_INDEX_LEB`.
+    pub index: u32,
+    pub addend: i64,
+}
+
+/// A `reloc.*` custom section.
+#[derive(Debug)]
+pub struct RelocSection<'a> {
+    pub name: &'a str,
+    /// Index of the section the relocations apply to.
+    pub section_index: u32,
+    pub entries: Vec<Relocation>,
+}
+
+fn malformed() -> ParseError {
+    ParseError::MalformedCustomSection("linking")
+}
+
+impl<'a> Parser<'a> {
+    fn read_segment_info(&mut self) -> Result<SegmentInfo<'a>, ParseError> {
+        let name = self.read_utf8_str_vu32()?;
+        let alignment = self.read_varuint32()?;
+        let flags = self.read_varuint32()?;
+        Ok(SegmentInfo {
+            name,
+            alignment,
+            flags,
+        })
+    }
+
+    fn read_init_func(&mut self) -> Result<InitFunc, ParseError> {
+        let priority = self.read_varuint32()?;
+        let symbol_index = self.read_varuint32()?;
+        Ok(InitFunc {
+            priority,
+            symbol_index,
+        })
+    }
+
+    fn read_comdat_sym(&mut self) -> Result<ComdatSym, ParseError> {
+        let kind = match self.read_byte()? {
+            0 => ComdatKind::Data,
+            1 => ComdatKind::Function,
+            2 => ComdatKind::Global,
+            3 => ComdatKind::Event,
+            4 => ComdatKind::Table,
+            5 => ComdatKind::Section,
+            _ => return Err(malformed()),
+        };
+        let index = self.read_varuint32()?;
+        Ok(ComdatSym { kind, index })
+    }
+
+    fn read_comdat(&mut self) -> Result<Comdat<'a>, ParseError> {
+        let name = self.read_utf8_str_vu32()?;
+        let flags = self.read_varuint32()?;
+        let symbols = self.read_vu32_times(Parser::read_comdat_sym)?;
+        Ok(Comdat {
+            name,
+            flags,
+            symbols,
+        })
+    }
+
+    fn read_symbol_info(&mut self) -> Result<SymbolInfo<'a>, ParseError> {
+        let kind = self.read_byte()?;
+        let flags = self.read_varuint32()?;
+        let defined = flags & WASM_SYM_UNDEFINED == 0;
+        let explicit_name = flags & WASM_SYM_EXPLICIT_NAME != 0;
+        let mut name = None;
+        let kind = match kind {
+            1 => {
+                name = Some(self.read_utf8_str_vu32()?);
+                let data = if defined {
+                    let segment = self.read_varuint32()?;
+                    let offset = self.read_varuint32()?;
+                    let size = self.read_varuint32()?;
+                    Some(DataSymbol {
+                        segment,
+                        offset,
+                        size,
+                    })
+                } else {
+                    None
+                };
+                SymbolKind::Data(data)
+            }
+            3 => SymbolKind::Section(self.read_varuint32()?),
+            0 | 2 | 4 | 5 => {
+                let index = self.read_varuint32()?;
+                if defined || explicit_name {
+                    name = Some(self.read_utf8_str_vu32()?);
+                }
+                match kind {
+                    0 => SymbolKind::Function(index),
+                    2 => SymbolKind::Global(index),
+                    4 => SymbolKind::Event(index),
+                    _ => SymbolKind::Table(index),
+                }
+            }
+            _ => return Err(malformed()),
+        };
+        Ok(SymbolInfo { flags, name, kind })
+    }
+
+    pub fn parse_linking_custom_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Linking<'a>, ParseError> {
+        trace!("  # Parsing linking custom section");
+        let init_offset = self.get_current_offset();
+        let mut linking = Linking {
+            version: self.read_varuint32()?,
+            ..Default::default()
+        };
+        if linking.version != 2 {
+            return Err(malformed());
+        }
+        while self.get_read_len(init_offset) < payload_len {
+            let typ = self.read_byte()?;
+            let len = self.read_varuint32()?;
+            let subsection_offset = self.get_current_offset();
+            match typ {
+                WASM_SEGMENT_INFO => {
+                    linking.segments = self.read_vu32_times(Parser::read_segment_info)?
+                }
+                WASM_INIT_FUNCS => {
+                    linking.init_funcs = self.read_vu32_times(Parser::read_init_func)?
+                }
+                WASM_COMDAT_INFO => linking.comdats = self.read_vu32_times(Parser::read_comdat)?,
+                WASM_SYMBOL_TABLE => {
+                    linking.symbols = self.read_vu32_times(Parser::read_symbol_info)?
+                }
+                _ => {
+                    // unknown subsections are skipped
+                    self.read_bytes(len)?;
+                }
+            }
+            if self.get_read_len(subsection_offset) != len {
+                return Err(malformed());
+            }
+        }
+        if self.get_read_len(init_offset) != payload_len {
+            return Err(malformed());
+        }
+        return Ok(linking);
+    }
+
+    fn read_relocation(&mut self) -> Result<Relocation, ParseError> {
+        let typ = match RelocType::from_byte(self.read_byte()?) {
+            Some(typ) => typ,
+            None => return Err(ParseError::MalformedCustomSection("reloc")),
+        };
+        let offset = self.read_varuint32()?;
+        let index = self.read_varuint32()?;
+        let addend = if typ.has_addend() {
+            self.read_varint64()?
+        } else {
+            0
+        };
+        Ok(Relocation {
+            typ,
+            offset,
+            index,
+            addend,
+        })
+    }
+
+    pub fn parse_reloc_custom_section(
+        &mut self,
+        name: &'a str,
+        payload_len: u32,
+    ) -> Result<RelocSection<'a>, ParseError> {
+        trace!("  # Parsing reloc custom section [name = '{}']", name);
+        let init_offset = self.get_current_offset();
+        let section_index = self.read_varuint32()?;
+        let entries = self.read_vu32_times(Parser::read_relocation)?;
+        if self.get_read_len(init_offset) != payload_len {
+            return Err(ParseError::MalformedCustomSection("reloc"));
+        }
+        return Ok(RelocSection {
+            name,
+            section_index,
+            entries,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linking(payload: &[u8]) -> Result<Linking<'_>, ParseError> {
+        Parser::new(payload).parse_linking_custom_section(payload.len() as u32)
+    }
+
+    /// A linking section of version 2 with the given subsections.
+    fn payload(subsections: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut payload = vec![0x02];
+        for &(typ, content) in subsections {
+            payload.extend_from_slice(&[typ, content.len() as u8]);
+            payload.extend_from_slice(content);
+        }
+        return payload;
+    }
+
+    #[test]
+    fn symbol_table() {
+        let symbols: &[u8] = &[
+            0x07, // count
+            0x00, 0x00, 0x01, 0x01, b'f', // defined function 1 "f"
+            0x00, 0x10, 0x00, // undefined function 0, named by its import
+            0x00, 0x50, 0x02, 0x01, b'g', // undefined function 2, explicitly "g"
+            0x01, 0x00, 0x01, b'd', 0x00, 0x04, 0x08, // data "d" in segment 0 at 4, 8 bytes
+            0x01, 0x10, 0x01, b'e', // undefined data "e"
+            0x03, 0x00, 0x05, // section 5
+            0x02, 0x04, 0x00, 0x02, b's', b'p', // hidden global 0 "sp"
+        ];
+        let data = payload(&[(WASM_SYMBOL_TABLE, symbols)]);
+        let linking = linking(&data).unwrap();
+        let names: Vec<_> = linking.symbols.iter().map(|s| s.name).collect();
+        assert_eq!(
+            names,
+            [Some("f"), None, Some("g"), Some("d"), Some("e"), None, Some("sp")]
+        );
+        let undefined: Vec<_> = linking.symbols.iter().map(|s| s.is_undefined()).collect();
+        assert_eq!(undefined, [false, true, true, false, true, false, false]);
+        let s = &linking.symbols;
+        assert!(matches!(s[0].kind, SymbolKind::Function(1)));
+        assert!(matches!(s[1].kind, SymbolKind::Function(0)));
+        assert!(matches!(s[2].kind, SymbolKind::Function(2)));
+        match s[3].kind {
+            SymbolKind::Data(Some(ref data)) => {
+                assert_eq!((data.segment, data.offset, data.size), (0, 4, 8));
+            }
+            ref other => panic!("expected a defined data symbol, found {:?}", other),
+        }
+        assert!(matches!(s[4].kind, SymbolKind::Data(None)));
+        assert!(matches!(s[5].kind, SymbolKind::Section(5)));
+        assert!(matches!(s[6].kind, SymbolKind::Global(0)));
+        assert_eq!(s[6].flags, WASM_SYM_VISIBILITY_HIDDEN);
+    }
+
+    #[test]
+    fn subsections() {
+        let segments: &[u8] = &[0x01, 0x05, b'.', b'd', b'a', b't', b'a', 0x02, 0x00];
+        let init_funcs: &[u8] = &[0x01, 0xff, 0xff, 0x03, 0x00];
+        let comdats: &[u8] = &[0x01, 0x01, b'c', 0x00, 0x02, 0x01, 0x00, 0x00, 0x03];
+        let unknown: &[u8] = &[0x01, 0x02, 0x03];
+        let data = payload(&[
+            (WASM_SEGMENT_INFO, segments),
+            (0x63, unknown),
+            (WASM_INIT_FUNCS, init_funcs),
+            (WASM_COMDAT_INFO, comdats),
+        ]);
+        let linking = linking(&data).unwrap();
+        assert_eq!(linking.version, 2);
+        let segment = &linking.segments[0];
+        assert_eq!((segment.name, segment.alignment, segment.flags), (".data", 2, 0));
+        let init = &linking.init_funcs[0];
+        assert_eq!((init.priority, init.symbol_index), (0xffff, 0));
+        let comdat = &linking.comdats[0];
+        assert_eq!((comdat.name, comdat.flags), ("c", 0));
+        let symbols: Vec<_> = comdat.symbols.iter().map(|s| (s.kind, s.index)).collect();
+        assert_eq!(symbols, [(ComdatKind::Function, 0), (ComdatKind::Data, 3)]);
+        assert!(linking.symbols.is_empty());
+    }
+
+    #[test]
+    fn malformed_linking() {
+        // a subsection whose contents are shorter than its length
+        let data = payload(&[(WASM_INIT_FUNCS, &[0x01, 0x00, 0x00, 0x00])]);
+        assert_eq!(linking(&data).unwrap_err(), malformed());
+        // an unsupported version and an unknown symbol kind
+        assert_eq!(linking(&[0x01]).unwrap_err(), malformed());
+        let data = payload(&[(WASM_SYMBOL_TABLE, &[0x01, 0x09, 0x00])]);
+        assert_eq!(linking(&data).unwrap_err(), malformed());
+    }
+
+    #[test]
+    fn relocations() {
+        let payload = [
+            0x03, 0x03, // section 3, 3 entries
+            0x00, 0x04, 0x01, // function index at 4, symbol 1
+            0x04, 0x0a, 0x02, 0x78, // memory address at 10, symbol 2, addend -8
+            0x05, 0x80, 0x01, 0x00, 0xac, 0x02, // memory address at 128, symbol 0, addend 300
+        ];
+        let relocs = Parser::new(&payload)
+            .parse_reloc_custom_section("reloc.CODE", payload.len() as u32)
+            .unwrap();
+        assert_eq!((relocs.name, relocs.section_index), ("reloc.CODE", 3));
+        let entries: Vec<_> = relocs
+            .entries
+            .iter()
+            .map(|r| (r.typ, r.offset, r.index, r.addend))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (RelocType::R_WASM_FUNCTION_INDEX_LEB, 4, 1, 0),
+                (RelocType::R_WASM_MEMORY_ADDR_SLEB, 10, 2, -8),
+                (RelocType::R_WASM_MEMORY_ADDR_I32, 128, 0, 300),
+            ]
+        );
+        let reloc = |payload: &[u8]| {
+            Parser::new(payload)
+                .parse_reloc_custom_section("reloc.CODE", payload.len() as u32)
+                .map(|_| ())
+        };
+        let malformed = Err(ParseError::MalformedCustomSection("reloc"));
+        // an unknown type and a trailing byte
+        assert_eq!(reloc(&[0x03, 0x01, 0x40, 0x00, 0x00]), malformed);
+        assert_eq!(reloc(&[0x03, 0x01, 0x00, 0x04, 0x01, 0x00]), malformed);
+    }
+}
@@ -1,22 +1,25 @@
-use parser::{Parser, ResizableLimits};
+use parser::{ParseError, Parser, ResizableLimits};
 
 #[derive(Debug)]
 pub struct MemoryType {
     pub limits: ResizableLimits,
 }
 
-impl Parser {
-    fn read_memory_type(&mut self) -> MemoryType {
-        MemoryType {
-            limits: self.read_resizable_limits(),
-        }
+impl<'a> Parser<'a> {
+    pub fn read_memory_type(&mut self) -> Result<MemoryType, ParseError> {
+        Ok(MemoryType {
+            limits: self.read_resizable_limits()?,
+        })
     }
 
-    pub fn parse_memory_section(&mut self, payload_len: u32) -> Vec<MemoryType> {
-        println!("  # Parsing memory section");
+    pub fn parse_memory_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Vec<MemoryType>, ParseError> {
+        trace!("  # Parsing memory section");
         let init_offset = self.get_current_offset();
-        let entries = self.read_vu32_times(Parser::read_memory_type);
-        assert_eq!(self.get_read_len(init_offset), payload_len);
-        return entries;
+        let entries = self.read_vu32_times(Parser::read_memory_type)?;
+        self.check_read_len(0x5, init_offset, payload_len)?;
+        return Ok(entries);
     }
 }
@@ -1,6 +1,8 @@
 extern crate byteorder;
 
 pub mod custom_section;
+pub mod producers_section;
+pub mod target_features_section;
 pub mod type_section;
 pub mod import_export_section;
 pub mod function_section;
@@ -11,19 +13,181 @@ pub mod start_section;
 pub mod element_section;
 pub mod code_section;
 pub mod data_section;
+pub mod linking_section;
+pub mod dwarf;
+pub mod streaming;
+pub mod leb128;
+pub mod names;
 
-mod opcode;
+pub mod opcode;
 
-use std::fs::File;
-use std::io::Read;
-use std::io::{Seek, SeekFrom};
-use std::io::BufReader;
-use std::string::String;
-use self::byteorder::{LittleEndian, ReadBytesExt};
+use std::error::Error;
+use std::fmt;
+use std::str;
+use self::byteorder::{ByteOrder, LittleEndian};
+use self::leb128::{LebError, LebReader};
 
 static MAGIC_NUM: u32 = 0x6d736100;
 static SUPPORTED_VERSION: u32 = 0x1;
 
+/// Name of the known section with the given id.
+pub fn section_name(id: u8) -> &'static str {
+    match id {
+        0x0 => "custom",
+        0x1 => "type",
+        0x2 => "import",
+        0x3 => "function",
+        0x4 => "table",
+        0x5 => "memory",
+        0x6 => "global",
+        0x7 => "export",
+        0x8 => "start",
+        0x9 => "element",
+        0xA => "code",
+        0xB => "data",
+        0xC => "datacount",
+        _ => "unknown",
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseError {
+    /// The input does not start with the `\0asm` magic number.
+    BadMagic,
+    UnsupportedVersion(u32),
+    UnexpectedEof,
+    Leb(LebError),
+    InvalidUtf8,
+    UnknownSection(u8),
+    DuplicateSection(u8),
+    /// Section `id` appeared after section `after`, which must come later.
+    SectionOutOfOrder { id: u8, after: u8 },
+    /// The contents of section `id` end before or after the section size.
+    SectionSizeMismatch(u8),
+    /// The function and code sections declare different numbers of functions.
+    FunctionCountMismatch { functions: usize, bodies: usize },
+    /// A name section subsection is out of order, duplicated or its contents
+    /// do not match its size.
+    MalformedNameSubsection(u8),
+    /// A known custom section (identified by its name) could not be decoded.
+    MalformedCustomSection(&'static str),
+    InvalidOpcode(u8),
+    /// A byte that should encode a type of the given kind does not.
+    InvalidType(&'static str, u8),
+    InvalidExternalKind(u8),
+    /// A function body or initializer expression does not end with `end`.
+    MissingEnd,
+    /// A function body declares more than `code_section::MAX_LOCALS` locals.
+    TooManyLocals,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::BadMagic => write!(f, "not a wasm module"),
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "unsupported wasm version {}", version)
+            }
+            ParseError::UnexpectedEof => write!(f, "unexpected end of module"),
+            ParseError::Leb(err) => err.fmt(f),
+            ParseError::InvalidUtf8 => write!(f, "invalid UTF-8 in name"),
+            ParseError::UnknownSection(id) => write!(f, "unknown section id {}", id),
+            ParseError::DuplicateSection(id) => {
+                write!(f, "duplicate {} section", section_name(id))
+            }
+            ParseError::SectionOutOfOrder { id, after } => write!(
+                f,
+                "{} section must come before the {} section",
+                section_name(id),
+                section_name(after)
+            ),
+            ParseError::SectionSizeMismatch(id) => {
+                write!(f, "{} section size does not match its contents", section_name(id))
+            }
+            ParseError::FunctionCountMismatch { functions, bodies } => write!(
+                f,
+                "function section declares {} functions but code section has {} bodies",
+                functions, bodies
+            ),
+            ParseError::MalformedNameSubsection(id) => {
+                write!(f, "malformed name subsection {}", id)
+            }
+            ParseError::MalformedCustomSection(name) => {
+                write!(f, "malformed {} custom section", name)
+            }
+            ParseError::InvalidOpcode(b) => write!(f, "invalid opcode 0x{:02x}", b),
+            ParseError::InvalidType(kind, b) => write!(f, "invalid {} 0x{:02x}", kind, b),
+            ParseError::InvalidExternalKind(b) => write!(f, "invalid external kind {}", b),
+            ParseError::MissingEnd => write!(f, "expression does not end with `end`"),
+            ParseError::TooManyLocals => write!(f, "too many locals"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<LebError> for ParseError {
+    fn from(err: LebError) -> ParseError {
+        ParseError::Leb(err)
+    }
+}
+
+/// Enforces that known sections appear at most once and in ascending id
+/// order. Custom sections may appear anywhere.
+#[derive(Debug, Default)]
+struct SectionOrder {
+    last: Option<u8>,
+}
+
+impl SectionOrder {
+    /// Position of a known section; the data count section goes between the
+    /// element and the code section.
+    fn rank(id: u8) -> u32 {
+        match id {
+            0xC => 0x9 * 2 + 1,
+            _ => id as u32 * 2,
+        }
+    }
+
+    fn check(&mut self, id: u8) -> Result<(), ParseError> {
+        match (id, self.last) {
+            (0x0, _) => Ok(()),
+            (0xD..=0xff, _) => Err(ParseError::UnknownSection(id)),
+            (_, Some(last)) if last == id => Err(ParseError::DuplicateSection(id)),
+            (_, Some(last)) if SectionOrder::rank(last) > SectionOrder::rank(id) => {
+                Err(ParseError::SectionOutOfOrder { id, after: last })
+            }
+            _ => {
+                self.last = Some(id);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Location of a section in the module, as reported by
+/// `Parser::parse_section_headers`.
+#[derive(Debug)]
+pub struct SectionHeader<'a> {
+    pub id: u8,
+    /// The section name for known sections, the custom section's own name
+    /// otherwise.
+    pub name: &'a str,
+    /// File offset of the payload.
+    pub offset: u32,
+    pub size: u32,
+    /// Number of entries of sections that hold a vector (the value itself for
+    /// the data count section).
+    pub count: Option<u32>,
+}
+
+fn check_function_count(functions: usize, bodies: usize) -> Result<(), ParseError> {
+    if functions != bodies {
+        return Err(ParseError::FunctionCountMismatch { functions, bodies });
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct ResizableLimits {
     pub flags: bool,
@@ -32,9 +196,9 @@ pub struct ResizableLimits {
 }
 
 #[derive(Debug)]
-pub struct FnId(u32);
+pub struct FnId(pub u32);
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Type {
     I32 = 0x7f,
@@ -62,73 +226,101 @@ impl Type {
     }
     */
 
-    fn value_type(int: u8) -> Type {
+    fn value_type(int: u8) -> Result<Type, ParseError> {
         match int {
-            0x7f => Type::I32,
-            0x7e => Type::I64,
-            0x7d => Type::F32,
-            0x7c => Type::F64,
-            _ => panic!("unknown ValueType value!"),
+            0x7f => Ok(Type::I32),
+            0x7e => Ok(Type::I64),
+            0x7d => Ok(Type::F32),
+            0x7c => Ok(Type::F64),
+            _ => Err(ParseError::InvalidType("value type", int)),
         }
     }
 
-    fn block_type(int: u8) -> Type {
+    fn block_type(int: u8) -> Result<Type, ParseError> {
         match int {
-            0x7f => Type::I32,
-            0x7e => Type::I64,
-            0x7d => Type::F32,
-            0x7c => Type::F64,
-            0x40 => Type::empty_block,
-            _ => panic!("unknown BlockType value!"),
+            0x7f => Ok(Type::I32),
+            0x7e => Ok(Type::I64),
+            0x7d => Ok(Type::F32),
+            0x7c => Ok(Type::F64),
+            0x40 => Ok(Type::empty_block),
+            _ => Err(ParseError::InvalidType("block type", int)),
         }
     }
 
-    fn elem_type(int: u8) -> Type {
+    fn elem_type(int: u8) -> Result<Type, ParseError> {
         match int {
-            0x70 => Type::anyfunc,
-            _ => panic!("unknown ElemType value!"),
+            0x70 => Ok(Type::anyfunc),
+            _ => Err(ParseError::InvalidType("element type", int)),
         }
     }
 
-    fn func_type(int: u8) -> Type {
+    fn func_type(int: u8) -> Result<Type, ParseError> {
         match int {
-            0x60 => Type::func,
-            _ => panic!("unknown FuncType value!"),
+            0x60 => Ok(Type::func),
+            _ => Err(ParseError::InvalidType("function type", int)),
         }
     }
 }
 
-use self::custom_section::{CustomSection, Namings};
+use self::custom_section::{CustomSection, CustomSectionError, CustomSectionPosition, Namings};
+use self::producers_section::Producers;
+use self::target_features_section::TargetFeature;
+use self::linking_section::{Linking, RelocSection};
 use self::type_section::FuncType;
 use self::import_export_section::{ExportEntry, ImportEntry};
 use self::table_section::TableEntry;
 use self::memory_section::MemoryType;
 use self::global_section::GlobalVariable;
 use self::element_section::ElemSegment;
-use self::code_section::FnBody;
+use self::code_section::LazyFnBody;
 use self::data_section::DataEntry;
 
 #[derive(Debug, Default)]
-pub struct ParseResult {
-    pub namings: Option<Namings>,
-    pub custom_sections: Vec<CustomSection>,
+pub struct ParseResult<'a> {
+    pub namings: Option<Namings<'a>>,
+    pub producers: Option<Producers<'a>>,
+    pub target_features: Option<Vec<TargetFeature<'a>>>,
+    /// URL of the source map from the `sourceMappingURL` custom section.
+    pub source_mapping_url: Option<&'a str>,
+    /// Symbol and segment information of relocatable object files.
+    pub linking: Option<Linking<'a>>,
+    pub relocations: Vec<RelocSection<'a>>,
+    pub custom_sections: Vec<CustomSection<'a>>,
+    /// Placement of every custom section (including the name section) in the
+    /// order they appeared in the module.
+    pub custom_section_positions: Vec<CustomSectionPosition<'a>>,
+    /// Why the custom sections among `custom_sections` that have a known
+    /// format could not be decoded.
+    pub custom_section_errors: Vec<CustomSectionError<'a>>,
     pub function_types: Option<Vec<FuncType>>,
-    pub import_entires: Option<Vec<ImportEntry>>,
+    pub import_entires: Option<Vec<ImportEntry<'a>>>,
     pub function_ids: Option<Vec<FnId>>,
     pub table_entries: Option<Vec<TableEntry>>,
     pub memory_types: Option<Vec<MemoryType>>,
     pub global_variables: Option<Vec<GlobalVariable>>,
-    pub export_entires: Option<Vec<ExportEntry>>,
+    pub export_entires: Option<Vec<ExportEntry<'a>>>,
     pub start_function: Option<FnId>,
     pub element_segments: Option<Vec<ElemSegment>>,
-    pub function_bodies: Option<Vec<FnBody>>,
-    pub data_entries: Option<Vec<DataEntry>>,
+    pub function_bodies: Option<Vec<LazyFnBody<'a>>>,
+    /// File offset of the code section payload, which DWARF addresses are
+    /// relative to.
+    pub code_section_offset: Option<u32>,
+    pub data_entries: Option<Vec<DataEntry<'a>>>,
+    pub data_count: Option<u32>,
 }
 
-impl ParseResult {
-    fn new() -> ParseResult {
+impl<'a> ParseResult<'a> {
+    fn new() -> ParseResult<'a> {
         Default::default()
     }
+
+    /// Payload of the first custom section with the given name.
+    pub fn custom_section(&self, name: &str) -> Option<&'a [u8]> {
+        self.custom_sections
+            .iter()
+            .find(|section| section.0 == name)
+            .map(|section| section.1)
+    }
 }
 
 /*
@@ -144,225 +336,619 @@ fn to_hex_string(bytes: &Vec<u8>) -> String {
 }
 */
 
-pub struct Parser {
-    file: BufReader<File>,
+/// Parses a module from an in-memory byte slice.
+///
+/// Names, custom section payloads and data segments in the `ParseResult` borrow
+/// directly from the input instead of being copied out of it.
+pub struct Parser<'a> {
+    data: &'a [u8],
+    offset: usize,
 }
 
-impl Parser {
-    pub fn new() -> Parser {
-        Parser {
-            file: BufReader::new(File::open("examples/wasm_test.wasm").unwrap()),
-        }
+impl<'a> Parser<'a> {
+    pub fn new(data: &'a [u8]) -> Parser<'a> {
+        Parser { data, offset: 0 }
     }
 
-    fn get_current_offset(&mut self) -> u32 {
-        self.file.seek(SeekFrom::Current(0)).unwrap() as u32
+    fn get_current_offset(&self) -> u32 {
+        self.offset as u32
     }
 
-    fn get_read_len(&mut self, old: u32) -> u32 {
+    fn get_read_len(&self, old: u32) -> u32 {
         self.get_current_offset() - old
     }
 
-    fn read_byte(&mut self) -> u8 {
-        self.file.read_u8().unwrap()
+    /// Fails unless exactly `payload_len` bytes of section `id` were read
+    /// since `init_offset`.
+    fn check_read_len(&self, id: u8, init_offset: u32, payload_len: u32) -> Result<(), ParseError> {
+        if self.get_read_len(init_offset) != payload_len {
+            return Err(ParseError::SectionSizeMismatch(id));
+        }
+        Ok(())
     }
 
-    fn read_bytes(&mut self, len: u32) -> Vec<u8> {
-        let mut name_bytes = vec![0u8; len as usize];
-        self.file.read_exact(&mut name_bytes).unwrap();
-        return name_bytes;
+    fn read_byte(&mut self) -> Result<u8, ParseError> {
+        let byte = *self.data.get(self.offset).ok_or(ParseError::UnexpectedEof)?;
+        self.offset += 1;
+        Ok(byte)
     }
 
-    fn read_utf8(&mut self, len: u32) -> String {
-        let name_bytes = self.read_bytes(len);
-        String::from_utf8(name_bytes).unwrap()
+    fn read_bytes(&mut self, len: u32) -> Result<&'a [u8], ParseError> {
+        if self.data.len() - self.offset < len as usize {
+            return Err(ParseError::UnexpectedEof);
+        }
+        let bytes = &self.data[self.offset..self.offset + len as usize];
+        self.offset += len as usize;
+        return Ok(bytes);
     }
 
-    fn read_utf8_str_vu32(&mut self) -> String {
-        let len = self.read_varuint32();
-        self.read_utf8(len)
+    fn read_utf8(&mut self, len: u32) -> Result<&'a str, ParseError> {
+        let name_bytes = self.read_bytes(len)?;
+        str::from_utf8(name_bytes).map_err(|_| ParseError::InvalidUtf8)
     }
 
-    fn read_uint32(&mut self) -> u32 {
-        self.file.read_u32::<LittleEndian>().unwrap()
+    fn read_utf8_str_vu32(&mut self) -> Result<&'a str, ParseError> {
+        let len = self.read_varuint32()?;
+        self.read_utf8(len)
     }
 
-    fn read_f32(&mut self) -> f32 {
-        self.file.read_f32::<LittleEndian>().unwrap()
+    fn read_uint32(&mut self) -> Result<u32, ParseError> {
+        Ok(LittleEndian::read_u32(self.read_bytes(4)?))
     }
 
-    fn read_f64(&mut self) -> f64 {
-        self.file.read_f64::<LittleEndian>().unwrap()
+    fn read_f32(&mut self) -> Result<f32, ParseError> {
+        Ok(LittleEndian::read_f32(self.read_bytes(4)?))
     }
 
-    fn read_varuint_len(&mut self, len: i32) -> (u64, u64) {
-        let mut res: u64 = 0;
-        let mut shift = 0;
-        let mut read_bytes: u64 = 0;
-        loop {
-            read_bytes += 1;
-            let byte = self.read_byte();
-            res |= (byte as u64 & 0x7f) << shift;
-            if (byte & 0x80) == 0 {
-                break;
-            }
-            shift += 7;
-        }
-        assert!(read_bytes <= (len as f32 / 7.0).ceil() as u64);
-        return (res, read_bytes);
+    fn read_f64(&mut self) -> Result<f64, ParseError> {
+        Ok(LittleEndian::read_f64(self.read_bytes(8)?))
     }
 
-    fn read_varuint(&mut self, len: i32) -> u64 {
-        self.read_varuint_len(len).0
+    /// Reads a LEB128 number with one of the readers from `leb128`.
+    fn read_leb<T>(
+        &mut self,
+        read: LebReader<T>,
+    ) -> Result<T, ParseError> {
+        self.read_leb_len(read).map(|(value, _)| value)
     }
 
-    fn read_varuint1(&mut self) -> bool {
-        self.read_varuint(7) != 0
+    /// Like `read_leb`, but also returns the length of the encoding.
+    fn read_leb_len<T>(
+        &mut self,
+        read: LebReader<T>,
+    ) -> Result<(T, usize), ParseError> {
+        let (value, len) = read(&self.data[self.offset..])?;
+        self.offset += len;
+        Ok((value, len))
     }
 
-    fn read_varuint7(&mut self) -> u8 {
-        self.read_varuint(7) as u8
+    fn read_varuint1(&mut self) -> Result<bool, ParseError> {
+        self.read_leb(leb128::read_varuint1)
     }
 
-    fn read_varuint32(&mut self) -> u32 {
-        self.read_varuint(32) as u32
+    fn read_varuint7(&mut self) -> Result<u8, ParseError> {
+        self.read_leb(leb128::read_varuint7)
     }
 
-    fn read_varint_len(&mut self, len: i32) -> (i64, u64) {
-        let mut res: i64 = 0;
-        let mut shift = 0;
-        let mut read_bytes: u64 = 0;
-        let byte: u8 = 0;
-        loop {
-            read_bytes += 1;
-            let byte = self.read_byte();
-            res |= (0x7f & byte as i64) << shift;
-            shift += 7;
-            if (byte & 0x80) == 0 {
-                break;
-            }
-        }
-        if shift < len && (byte & 0x40) != 0 {
-            res |= !0i64 << shift;
-        }
-        assert!(read_bytes <= (len as f32 / 7.0).ceil() as u64);
-        return (res, read_bytes);
+    fn read_varuint32(&mut self) -> Result<u32, ParseError> {
+        self.read_leb(leb128::read_varuint32)
     }
 
-    fn read_varint(&mut self, len: i32) -> i64 {
-        self.read_varint_len(len).0
+    fn read_varint32(&mut self) -> Result<i32, ParseError> {
+        self.read_leb(leb128::read_varint32)
     }
 
-    fn read_varint32(&mut self) -> i32 {
-        self.read_varint(32) as i32
+    fn read_varint64(&mut self) -> Result<i64, ParseError> {
+        self.read_leb(leb128::read_varint64)
     }
 
-    fn read_varint64(&mut self) -> i64 {
-        self.read_varint(64)
-    }
-
-    fn read_n_times<T>(&mut self, callback: fn(p: &mut Parser) -> T, n: u32) -> Vec<T> {
+    fn read_n_times<T>(
+        &mut self,
+        callback: fn(p: &mut Parser<'a>) -> Result<T, ParseError>,
+        n: u32,
+    ) -> Result<Vec<T>, ParseError> {
         let mut res = Vec::<T>::new();
         for _ in 0..n {
-            res.push(callback(self));
+            res.push(callback(self)?);
         }
-        return res;
+        return Ok(res);
     }
 
-    fn read_vu32_times<T>(&mut self, callback: fn(p: &mut Parser) -> T) -> Vec<T> {
-        let n = self.read_varuint32();
+    fn read_vu32_times<T>(
+        &mut self,
+        callback: fn(p: &mut Parser<'a>) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let n = self.read_varuint32()?;
         self.read_n_times(callback, n)
     }
 
     // read functions used by multiple modules:
 
-    fn read_value_type(&mut self) -> Type {
-        let ptype = self.read_varuint7();
+    fn read_value_type(&mut self) -> Result<Type, ParseError> {
+        let ptype = self.read_varuint7()?;
         Type::value_type(ptype)
     }
 
-    fn read_resizable_limits(&mut self) -> ResizableLimits {
-        let limits_flag = self.read_varuint1();
-        let limits_initial = self.read_varuint32();
+    fn read_resizable_limits(&mut self) -> Result<ResizableLimits, ParseError> {
+        let limits_flag = self.read_varuint1()?;
+        let limits_initial = self.read_varuint32()?;
         let limits_maximum = if limits_flag {
-            Some(self.read_varuint32())
+            Some(self.read_varuint32()?)
         } else {
             None
         };
-        ResizableLimits {
+        Ok(ResizableLimits {
             flags: limits_flag,
             initial: limits_initial,
             maximum: limits_maximum,
-        }
+        })
     }
 
     // ----------
 
-    fn parse_preamble(&mut self) {
-        print!("Parsing WASM header ... ");
-
-        let magic = self.read_uint32();
+    fn parse_preamble(&mut self) -> Result<(), ParseError> {
+        trace!("Parsing WASM header");
+        let magic = self.read_uint32()?;
         if magic != MAGIC_NUM {
-            panic!("Not a wasm file!");
+            return Err(ParseError::BadMagic);
         }
-        let version = self.read_uint32();
+        let version = self.read_uint32()?;
         if version != SUPPORTED_VERSION {
-            panic!("Unsupported Version!");
+            return Err(ParseError::UnsupportedVersion(version));
         }
-        println!("done");
-    }
-
-    fn parse_section(&mut self, result: &mut ParseResult) {
-        print!(" ## Parsing section ...");
-        let sec_id = self.read_varuint7();
-        let payload_len = self.read_varuint32();
-        let mut name_offset: u32 = 0;
-        let mut name: String = String::new();
+        Ok(())
+    }
+
+    fn parse_section(
+        &mut self,
+        result: &mut ParseResult<'a>,
+        order: &mut SectionOrder,
+    ) -> Result<(), ParseError> {
+        let sec_id = self.read_varuint7()?;
+        order.check(sec_id)?;
+        let payload_len = self.read_varuint32()?;
+        let end = self.offset + payload_len as usize;
+        if end > self.data.len() {
+            return Err(ParseError::UnexpectedEof);
+        }
+        let mut section = Parser {
+            data: &self.data[..end],
+            offset: self.offset,
+        };
+        section.parse_section_payload(sec_id, result, order)?;
+        if section.offset != end {
+            return Err(ParseError::SectionSizeMismatch(sec_id));
+        }
+        self.offset = end;
+        Ok(())
+    }
+
+    /// Keeps a custom section that could not be decoded as an opaque one and
+    /// skips the rest of it.
+    fn keep_malformed_custom_section(
+        &mut self,
+        name: &'a str,
+        payload: &'a [u8],
+        error: ParseError,
+        result: &mut ParseResult<'a>,
+    ) {
+        result.custom_sections.push(CustomSection(name, payload));
+        result.custom_section_errors.push(CustomSectionError { name, error });
+        self.offset = self.data.len();
+    }
+
+    /// Parses the payload of a section, which is all of the remaining data.
+    fn parse_section_payload(
+        &mut self,
+        sec_id: u8,
+        result: &mut ParseResult<'a>,
+        order: &mut SectionOrder,
+    ) -> Result<(), ParseError> {
+        let mut name: &'a str = "";
         if sec_id == 0 {
-            let (name_len, name_len_field_size) = self.read_varuint_len(32);
-            name_offset = (name_len_field_size + name_len) as u32;
-            name = self.read_utf8(name_len as u32);
-            println!("[name = '{}']", name)
+            name = self.read_utf8_str_vu32()?;
+            trace!(" ## Parsing section [name = '{}']", name);
         } else {
-            println!("[id = {}]", sec_id);
+            trace!(" ## Parsing section [id = {}]", sec_id);
         }
-        let payload_data_len = payload_len - name_offset;
+        let payload_data_len = (self.data.len() - self.offset) as u32;
+        let payload = &self.data[self.offset..];
 
         match sec_id {
             0x0 => {
+                result.custom_section_positions.push(CustomSectionPosition {
+                    name,
+                    after: order.last,
+                });
                 if name == "name" {
-                    result.namings = Some(self.parse_name_custom_section(payload_data_len));
+                    match self.parse_name_custom_section(payload_data_len) {
+                        Ok(namings) => result.namings = Some(namings),
+                        Err(err) => self.keep_malformed_custom_section(name, payload, err, result),
+                    }
+                } else if name == "producers" {
+                    match self.parse_producers_custom_section(payload_data_len) {
+                        Ok(producers) => result.producers = Some(producers),
+                        Err(err) => self.keep_malformed_custom_section(name, payload, err, result),
+                    }
+                } else if name == "target_features" {
+                    match self.parse_target_features_custom_section(payload_data_len) {
+                        Ok(features) => result.target_features = Some(features),
+                        Err(err) => self.keep_malformed_custom_section(name, payload, err, result),
+                    }
+                } else if name == "sourceMappingURL" {
+                    match self.parse_source_mapping_url(payload_data_len) {
+                        Ok(url) => result.source_mapping_url = Some(url),
+                        Err(err) => self.keep_malformed_custom_section(name, payload, err, result),
+                    }
+                } else if name == "linking" {
+                    match self.parse_linking_custom_section(payload_data_len) {
+                        Ok(linking) => result.linking = Some(linking),
+                        Err(err) => self.keep_malformed_custom_section(name, payload, err, result),
+                    }
+                } else if name.starts_with("reloc.") {
+                    match self.parse_reloc_custom_section(name, payload_data_len) {
+                        Ok(relocs) => result.relocations.push(relocs),
+                        Err(err) => self.keep_malformed_custom_section(name, payload, err, result),
+                    }
                 } else {
                     // some other custom section
                     result
                         .custom_sections
-                        .push(self.parse_custom_section(&name, payload_data_len));
+                        .push(self.parse_custom_section(name, payload_data_len)?);
                 }
             }
-            0x1 => result.function_types = Some(self.parse_type_section(payload_data_len)),
-            0x2 => result.import_entires = Some(self.parse_import_section(payload_data_len)),
-            0x3 => result.function_ids = Some(self.parse_function_section(payload_data_len)),
-            0x4 => result.table_entries = Some(self.parse_table_section(payload_data_len)),
-            0x5 => result.memory_types = Some(self.parse_memory_section(payload_data_len)),
-            0x6 => result.global_variables = Some(self.parse_global_section(payload_data_len)),
-            0x7 => result.export_entires = Some(self.parse_export_section(payload_data_len)),
-            0x8 => result.start_function = Some(self.parse_start_section(payload_data_len)),
-            0x9 => result.element_segments = Some(self.parse_element_section(payload_data_len)),
-            0xA => result.function_bodies = Some(self.parse_code_section(payload_data_len)),
-            0xB => result.data_entries = Some(self.parse_data_section(payload_data_len)),
-            _ => panic!("Unknown Section ID!"),
+            0x1 => result.function_types = Some(self.parse_type_section(payload_data_len)?),
+            0x2 => result.import_entires = Some(self.parse_import_section(payload_data_len)?),
+            0x3 => result.function_ids = Some(self.parse_function_section(payload_data_len)?),
+            0x4 => result.table_entries = Some(self.parse_table_section(payload_data_len)?),
+            0x5 => result.memory_types = Some(self.parse_memory_section(payload_data_len)?),
+            0x6 => result.global_variables = Some(self.parse_global_section(payload_data_len)?),
+            0x7 => result.export_entires = Some(self.parse_export_section(payload_data_len)?),
+            0x8 => result.start_function = Some(self.parse_start_section(payload_data_len)?),
+            0x9 => result.element_segments = Some(self.parse_element_section(payload_data_len)?),
+            0xA => {
+                result.code_section_offset = Some(self.get_current_offset());
+                result.function_bodies = Some(self.parse_code_section(payload_data_len)?);
+            }
+            0xB => result.data_entries = Some(self.parse_data_section(payload_data_len)?),
+            0xC => result.data_count = Some(self.parse_data_count_section(payload_data_len)?),
+            _ => unreachable!(),
         }
+        Ok(())
+    }
+
+    /// Reads only the section headers and entry counts of the module without
+    /// decoding any section contents.
+    pub fn parse_section_headers(&mut self) -> Result<Vec<SectionHeader<'a>>, ParseError> {
+        self.parse_preamble()?;
+        let mut order = SectionOrder::default();
+        let mut headers = Vec::new();
+        while self.offset < self.data.len() {
+            let id = self.read_varuint7()?;
+            order.check(id)?;
+            let size = self.read_varuint32()?;
+            let offset = self.get_current_offset();
+            let end = self.offset + size as usize;
+            if end > self.data.len() {
+                return Err(ParseError::UnexpectedEof);
+            }
+            let mut payload = Parser {
+                data: &self.data[..end],
+                offset: self.offset,
+            };
+            let (name, count) = match id {
+                0x0 => (payload.read_utf8_str_vu32()?, None),
+                0x8 => (section_name(id), None),
+                _ => (section_name(id), Some(payload.read_varuint32()?)),
+            };
+            headers.push(SectionHeader {
+                id,
+                name,
+                offset,
+                size,
+                count,
+            });
+            self.offset = end;
+        }
+        return Ok(headers);
     }
 
-    pub fn parse(&mut self) -> ParseResult {
-        self.parse_preamble();
+    pub fn parse(&mut self) -> Result<ParseResult<'a>, ParseError> {
+        self.parse_preamble()?;
 
         let mut result = ParseResult::new();
+        let mut order = SectionOrder::default();
+
+        while self.offset < self.data.len() {
+            self.parse_section(&mut result, &mut order)?;
+        }
+        check_function_count(
+            result.function_ids.as_ref().map_or(0, |ids| ids.len()),
+            result.function_bodies.as_ref().map_or(0, |bodies| bodies.len()),
+        )?;
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let file_len = self.file.get_ref().metadata().unwrap().len() as u32;
-        while self.get_current_offset() < file_len {
-            self.parse_section(&mut result);
+    /// A module consisting of the given sections.
+    fn module(sections: &[&[u8]]) -> Vec<u8> {
+        let mut data = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        for section in sections {
+            data.extend_from_slice(section);
         }
-        return result;
+        return data;
+    }
+
+    fn parse(sections: &[&[u8]]) -> Result<(), ParseError> {
+        Parser::new(&module(sections)).parse().map(|_| ())
+    }
+
+    /// The result of decoding a single function body of type `() -> ()`.
+    fn body(body: &[u8]) -> Result<(), ParseError> {
+        let mut code = vec![0x0a, body.len() as u8 + 2, 0x01, body.len() as u8];
+        code.extend_from_slice(body);
+        let data = module(&[
+            &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00],
+            &[0x03, 0x02, 0x01, 0x00],
+            &code,
+        ]);
+        let module = Parser::new(&data).parse()?;
+        return module.function_bodies.unwrap()[0].body().map(|_| ());
+    }
+
+    #[test]
+    fn data_count_section() {
+        let memory: &[u8] = &[0x05, 0x03, 0x01, 0x00, 0x01];
+        let elements: &[u8] = &[0x09, 0x01, 0x00];
+        let data_count: &[u8] = &[0x0c, 0x01, 0x02];
+        let code: &[u8] = &[0x0a, 0x01, 0x00];
+        let data: &[u8] = &[0x0b, 0x01, 0x00];
+        let bytes = module(&[memory, elements, data_count, code, data]);
+        let result = Parser::new(&bytes).parse().unwrap();
+        assert_eq!(result.data_count, Some(2));
+        // between the element and the code section, at most once
+        assert_eq!(
+            parse(&[data_count, elements]),
+            Err(ParseError::SectionOutOfOrder { id: 0x9, after: 0xC })
+        );
+        assert_eq!(
+            parse(&[code, data_count]),
+            Err(ParseError::SectionOutOfOrder { id: 0xC, after: 0xA })
+        );
+        assert_eq!(parse(&[data_count, data_count]), Err(ParseError::DuplicateSection(0xC)));
+        assert_eq!(parse(&[&[0x0d, 0x00]]), Err(ParseError::UnknownSection(0xD)));
+    }
+
+    #[test]
+    fn section_order() {
+        let types: &[u8] = &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+        let functions: &[u8] = &[0x03, 0x02, 0x01, 0x00];
+        let code: &[u8] = &[0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b];
+        let imports: &[u8] = &[0x02, 0x01, 0x00];
+        let exports: &[u8] = &[0x07, 0x01, 0x00];
+        let custom: &[u8] = &[0x00, 0x02, 0x01, b'a'];
+        assert_eq!(parse(&[types, functions, code]), Ok(()));
+        assert_eq!(
+            parse(&[functions, types, code]),
+            Err(ParseError::SectionOutOfOrder { id: 0x1, after: 0x3 })
+        );
+        assert_eq!(
+            parse(&[exports, imports]),
+            Err(ParseError::SectionOutOfOrder { id: 0x2, after: 0x7 })
+        );
+        // custom sections may appear anywhere, but do not separate duplicates
+        assert_eq!(parse(&[custom, types, custom, functions, custom, code, custom]), Ok(()));
+        assert_eq!(parse(&[types, types]), Err(ParseError::DuplicateSection(0x1)));
+        assert_eq!(parse(&[types, custom, types]), Err(ParseError::DuplicateSection(0x1)));
+    }
+
+    #[test]
+    fn function_count() {
+        let types: &[u8] = &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+        let functions: &[u8] = &[0x03, 0x03, 0x02, 0x00, 0x00];
+        let code: &[u8] = &[0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b];
+        assert_eq!(
+            parse(&[types, functions, code]),
+            Err(ParseError::FunctionCountMismatch { functions: 2, bodies: 1 })
+        );
+        assert_eq!(
+            parse(&[types, functions]),
+            Err(ParseError::FunctionCountMismatch { functions: 2, bodies: 0 })
+        );
+        assert_eq!(
+            parse(&[types, code]),
+            Err(ParseError::FunctionCountMismatch { functions: 0, bodies: 1 })
+        );
+    }
+
+    #[test]
+    fn custom_section_positions() {
+        let types: &[u8] = &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+        let functions: &[u8] = &[0x03, 0x02, 0x01, 0x00];
+        let code: &[u8] = &[0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b];
+        let bytes = module(&[
+            &[0x00, 0x02, 0x01, b'a'],
+            types,
+            &[0x00, 0x03, 0x01, b'b', 0xff],
+            &[0x00, 0x02, 0x01, b'c'],
+            functions,
+            code,
+            &[0x00, 0x02, 0x01, b'd'],
+        ]);
+        let result = Parser::new(&bytes).parse().unwrap();
+        let positions: Vec<_> =
+            result.custom_section_positions.iter().map(|p| (p.name, p.after)).collect();
+        assert_eq!(
+            positions,
+            [("a", None), ("b", Some(0x1)), ("c", Some(0x1)), ("d", Some(0xA))]
+        );
+    }
+
+    #[test]
+    fn malformed_name_section_is_kept() {
+        // A function names subsection whose only name runs past its end.
+        let names: &[u8] = &[
+            0x00, 0x0a, 0x04, b'n', b'a', b'm', b'e', 0x01, 0x03, 0x01, 0x00, 0x0a,
+        ];
+        let types: &[u8] = &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+        let bytes = module(&[types, names]);
+        let result = Parser::new(&bytes).parse().unwrap();
+        assert!(result.namings.is_none());
+        assert_eq!(result.function_types.unwrap().len(), 1);
+        assert_eq!(result.custom_sections.len(), 1);
+        assert_eq!(result.custom_sections[0].0, "name");
+        assert_eq!(result.custom_sections[0].1, &names[7..]);
+        assert_eq!(result.custom_section_errors.len(), 1);
+        assert_eq!(result.custom_section_errors[0].name, "name");
+        assert_eq!(result.custom_section_errors[0].error, ParseError::UnexpectedEof);
+        assert_eq!(result.custom_section_positions.len(), 1);
+        // An empty function names subsection followed by a stray byte.
+        let names: &[u8] = &[0x00, 0x09, 0x04, b'n', b'a', b'm', b'e', 0x01, 0x02, 0x00, 0x00];
+        let bytes = module(&[names]);
+        let result = Parser::new(&bytes).parse().unwrap();
+        assert!(result.namings.is_none());
+        assert_eq!(
+            result.custom_section_errors[0].error,
+            ParseError::MalformedNameSubsection(0x1)
+        );
+    }
+
+    #[test]
+    fn malformed_source_mapping_url_is_kept() {
+        // A URL that is longer than the section.
+        let url: &[u8] = &[
+            0x00, 0x14, 0x10, b's', b'o', b'u', b'r', b'c', b'e', b'M', b'a', b'p', b'p', b'i',
+            b'n', b'g', b'U', b'R', b'L', 0x05, b'a', b'b',
+        ];
+        let types: &[u8] = &[0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+        let bytes = module(&[types, url]);
+        let result = Parser::new(&bytes).parse().unwrap();
+        assert!(result.source_mapping_url.is_none());
+        assert_eq!(result.function_types.unwrap().len(), 1);
+        assert_eq!(result.custom_sections.len(), 1);
+        assert_eq!(result.custom_sections[0].0, "sourceMappingURL");
+        assert_eq!(result.custom_sections[0].1, &url[19..]);
+        assert_eq!(result.custom_section_errors[0].error, ParseError::UnexpectedEof);
+    }
+
+    #[test]
+    fn malformed_object_file_sections_are_kept() {
+        // A linking section of an unsupported version and a relocation of an
+        // unknown type.
+        let linking: &[u8] = &[0x00, 0x09, 0x07, b'l', b'i', b'n', b'k', b'i', b'n', b'g', 0x01];
+        let reloc: &[u8] = &[
+            0x00, 0x10, 0x0a, b'r', b'e', b'l', b'o', b'c', b'.', b'C', b'O', b'D', b'E', 0x03,
+            0x01, 0x40, 0x00, 0x00,
+        ];
+        let bytes = module(&[linking, reloc]);
+        let result = Parser::new(&bytes).parse().unwrap();
+        assert!(result.linking.is_none());
+        assert!(result.relocations.is_empty());
+        let kept: Vec<_> = result.custom_sections.iter().map(|s| (s.0, s.1.len())).collect();
+        assert_eq!(kept, [("linking", 1), ("reloc.CODE", 5)]);
+        let errors: Vec<_> = result.custom_section_errors.iter().map(|e| e.error).collect();
+        assert_eq!(
+            errors,
+            [
+                ParseError::MalformedCustomSection("linking"),
+                ParseError::MalformedCustomSection("reloc")
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_tool_sections_are_kept() {
+        // A producers section with an unknown field.
+        let producers: &[u8] = &[
+            0x00, 0x10, 0x09, b'p', b'r', b'o', b'd', b'u', b'c', b'e', b'r', b's', 0x01, 0x03,
+            b'f', b'o', b'o', 0x00,
+        ];
+        // A target features section with an unknown prefix.
+        let features: &[u8] = &[
+            0x00, 0x14, 0x0f, b't', b'a', b'r', b'g', b'e', b't', b'_', b'f', b'e', b'a', b't',
+            b'u', b'r', b'e', b's', 0x01, b'?', 0x01, b'x',
+        ];
+        let bytes = module(&[producers, features]);
+        let result = Parser::new(&bytes).parse().unwrap();
+        assert!(result.producers.is_none());
+        assert!(result.target_features.is_none());
+        let kept: Vec<_> = result.custom_sections.iter().map(|s| (s.0, s.1.len())).collect();
+        assert_eq!(kept, [("producers", 6), ("target_features", 4)]);
+        let errors: Vec<_> = result.custom_section_errors.iter().map(|e| e.error).collect();
+        assert_eq!(
+            errors,
+            [
+                ParseError::MalformedCustomSection("producers"),
+                ParseError::MalformedCustomSection("target_features")
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_sections() {
+        // A custom section whose name is longer than its payload.
+        assert_eq!(
+            parse(&[&[0x00, 0x02, 0x05, b'a', b'b', b'c', b'd', b'e']]),
+            Err(ParseError::UnexpectedEof)
+        );
+        // A type section with a trailing byte.
+        assert_eq!(
+            parse(&[&[0x01, 0x05, 0x01, 0x60, 0x00, 0x00, 0x00]]),
+            Err(ParseError::SectionSizeMismatch(0x1))
+        );
+        // A type section whose entry continues into the next section.
+        assert_eq!(
+            parse(&[&[0x01, 0x03, 0x01, 0x60, 0x00], &[0x00, 0x01, 0x00]]),
+            Err(ParseError::Leb(LebError::UnexpectedEof))
+        );
+        assert_eq!(
+            parse(&[&[0x0c, 0x02, 0x01, 0x00]]),
+            Err(ParseError::SectionSizeMismatch(0xC))
+        );
+        assert_eq!(
+            parse(&[&[0x08, 0x02, 0x00, 0x00]]),
+            Err(ParseError::SectionSizeMismatch(0x8))
+        );
+        assert_eq!(
+            parse(&[&[0x01, 0x04, 0x01, 0x61, 0x00, 0x00]]),
+            Err(ParseError::InvalidType("function type", 0x61))
+        );
+        assert_eq!(
+            parse(&[&[0x01, 0x05, 0x01, 0x60, 0x01, 0x6f, 0x00]]),
+            Err(ParseError::InvalidType("value type", 0x6f))
+        );
+        assert_eq!(
+            parse(&[&[0x04, 0x04, 0x01, 0x6f, 0x00, 0x00]]),
+            Err(ParseError::InvalidType("element type", 0x6f))
+        );
+        assert_eq!(
+            parse(&[&[0x07, 0x05, 0x01, 0x01, b'f', 0x04, 0x00]]),
+            Err(ParseError::InvalidExternalKind(4))
+        );
+        // A global initializer without `end`.
+        assert_eq!(
+            parse(&[&[0x06, 0x06, 0x01, 0x7f, 0x00, 0x41, 0x00, 0x01]]),
+            Err(ParseError::MissingEnd)
+        );
+    }
+
+    #[test]
+    fn malformed_bodies() {
+        assert_eq!(body(&[0x00, 0x01, 0x0b]), Ok(()));
+        assert_eq!(body(&[]), Err(ParseError::Leb(LebError::UnexpectedEof)));
+        assert_eq!(body(&[0x00]), Err(ParseError::MissingEnd));
+        assert_eq!(body(&[0x00, 0x01, 0x01]), Err(ParseError::MissingEnd));
+        assert_eq!(
+            body(&[0x00, 0x02, 0x6f, 0x0b]),
+            Err(ParseError::InvalidType("block type", 0x6f))
+        );
+        assert_eq!(
+            body(&[0x01, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f, 0x0b]),
+            Err(ParseError::TooManyLocals)
+        );
     }
 }
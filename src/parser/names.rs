@@ -0,0 +1,276 @@
+use parser::ParseResult;
+use parser::custom_section::{IndirectNaming, Naming};
+use parser::import_export_section::ExternalKind;
+
+/// The index spaces that can be named in the name section.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NameKind {
+    Function,
+    Type,
+    Table,
+    Memory,
+    Global,
+    Elem,
+    Data,
+    Tag,
+}
+
+impl NameKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            NameKind::Function => "func",
+            NameKind::Type => "type",
+            NameKind::Table => "table",
+            NameKind::Memory => "memory",
+            NameKind::Global => "global",
+            NameKind::Elem => "elem",
+            NameKind::Data => "data",
+            NameKind::Tag => "tag",
+        }
+    }
+
+    fn external_kind(self) -> Option<ExternalKind> {
+        match self {
+            NameKind::Function => Some(ExternalKind::Func),
+            NameKind::Table => Some(ExternalKind::Table),
+            NameKind::Memory => Some(ExternalKind::Memory),
+            NameKind::Global => Some(ExternalKind::Global),
+            _ => None,
+        }
+    }
+}
+
+fn find_name<'a>(map: &Option<Vec<Naming<'a>>>, index: u32) -> Option<&'a str> {
+    map.as_ref()
+        .and_then(|map| map.iter().find(|n| n.index == index))
+        .map(|n| n.name)
+}
+
+fn find_indirect_name<'a>(
+    map: &Option<Vec<IndirectNaming<'a>>>,
+    outer: u32,
+    inner: u32,
+) -> Option<&'a str> {
+    map.as_ref()
+        .and_then(|map| map.iter().find(|&&(index, _)| index == outer))
+        .and_then(|(_, names)| names.iter().find(|n| n.index == inner))
+        .map(|n| n.name)
+}
+
+/// Maps indices of a parsed module to human readable names.
+///
+/// Names come from the name section; `display_name` falls back to import and
+/// export names and finally to a `func[3]` style placeholder.
+pub struct NameResolver<'r, 'a: 'r> {
+    module: &'r ParseResult<'a>,
+}
+
+impl<'r, 'a> NameResolver<'r, 'a> {
+    pub fn new(module: &'r ParseResult<'a>) -> NameResolver<'r, 'a> {
+        NameResolver { module }
+    }
+
+    pub fn module_name(&self) -> Option<&'a str> {
+        self.module.namings.as_ref().and_then(|n| n.module)
+    }
+
+    /// The name given to `index` in the name section, if any.
+    pub fn name(&self, kind: NameKind, index: u32) -> Option<&'a str> {
+        let namings = self.module.namings.as_ref()?;
+        let map = match kind {
+            NameKind::Function => &namings.functions,
+            NameKind::Type => &namings.types,
+            NameKind::Table => &namings.tables,
+            NameKind::Memory => &namings.memories,
+            NameKind::Global => &namings.globals,
+            NameKind::Elem => &namings.elems,
+            NameKind::Data => &namings.datas,
+            NameKind::Tag => &namings.tags,
+        };
+        return find_name(map, index);
+    }
+
+    pub fn local_name(&self, function: u32, local: u32) -> Option<&'a str> {
+        let namings = self.module.namings.as_ref()?;
+        return find_indirect_name(&namings.locales, function, local);
+    }
+
+    pub fn label_name(&self, function: u32, label: u32) -> Option<&'a str> {
+        let namings = self.module.namings.as_ref()?;
+        return find_indirect_name(&namings.labels, function, label);
+    }
+
+    pub fn field_name(&self, typ: u32, field: u32) -> Option<&'a str> {
+        let namings = self.module.namings.as_ref()?;
+        return find_indirect_name(&namings.fields, typ, field);
+    }
+
+    /// Name to show for `index`: the name section entry, else the import
+    /// (`module.field`) or export name, else e.g. `func[3]`.
+    pub fn display_name(&self, kind: NameKind, index: u32) -> String {
+        if let Some(name) = self.name(kind, index) {
+            return name.to_string();
+        }
+        if let Some(ext) = kind.external_kind() {
+            if let Some(name) = self.import_name(&ext, index) {
+                return name;
+            }
+            if let Some(name) = self.export_name(&ext, index) {
+                return name.to_string();
+            }
+        }
+        return format!("{}[{}]", kind.prefix(), index);
+    }
+
+    pub fn display_local_name(&self, function: u32, local: u32) -> String {
+        match self.local_name(function, local) {
+            Some(name) => name.to_string(),
+            None => format!("local[{}]", local),
+        }
+    }
+
+    fn import_name(&self, kind: &ExternalKind, index: u32) -> Option<String> {
+        // imports come first in their index space
+        self.module
+            .import_entires
+            .as_ref()?
+            .iter()
+            .filter(|entry| entry.kind == *kind)
+            .nth(index as usize)
+            .map(|entry| format!("{}.{}", entry.module, entry.field))
+    }
+
+    fn export_name(&self, kind: &ExternalKind, index: u32) -> Option<&'a str> {
+        self.module
+            .export_entires
+            .as_ref()?
+            .iter()
+            .find(|entry| entry.kind == *kind && entry.index == index)
+            .map(|entry| entry.field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.push(s.len() as u8);
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    /// A section or subsection with the given id and contents.
+    fn section(id: u8, contents: &[u8]) -> Vec<u8> {
+        assert!(contents.len() < 0x80);
+        let mut out = vec![id, contents.len() as u8];
+        out.extend_from_slice(contents);
+        return out;
+    }
+
+    fn name_map(names: &[(u32, &str)]) -> Vec<u8> {
+        let mut out = vec![names.len() as u8];
+        for &(index, name) in names {
+            out.push(index as u8);
+            string(&mut out, name);
+        }
+        return out;
+    }
+
+    fn indirect_name_map(names: &[(u32, &[(u32, &str)])]) -> Vec<u8> {
+        let mut out = vec![names.len() as u8];
+        for &(index, map) in names {
+            out.push(index as u8);
+            out.extend(name_map(map));
+        }
+        return out;
+    }
+
+    /// Imports function 0 and global 0, defines functions 1 to 3, exports
+    /// functions 0 to 2, and names items of every kind.
+    fn module() -> Vec<u8> {
+        let mut imports = vec![0x02];
+        for &(field, desc) in &[("imp", &[0x00, 0x00][..]), ("g", &[0x03, 0x7f, 0x00][..])] {
+            string(&mut imports, "env");
+            string(&mut imports, field);
+            imports.extend_from_slice(desc);
+        }
+        let mut exports = vec![0x03];
+        for &(field, index) in &[("exported", 2), ("alsoexp", 1), ("reexp", 0)] {
+            string(&mut exports, field);
+            exports.extend_from_slice(&[0x00, index]);
+        }
+        let mut names = Vec::new();
+        string(&mut names, "name");
+        let mut module_name = Vec::new();
+        string(&mut module_name, "m");
+        let subsections: [(u8, Vec<u8>); 12] = [
+            (0, module_name),
+            (1, name_map(&[(1, "named")])),
+            (2, indirect_name_map(&[(1, &[(0, "x")])])),
+            (3, indirect_name_map(&[(1, &[(0, "l")])])),
+            (4, name_map(&[(0, "t")])),
+            (5, name_map(&[(0, "tab")])),
+            (6, name_map(&[(0, "mem")])),
+            (7, name_map(&[(0, "gl")])),
+            (8, name_map(&[(0, "e")])),
+            (9, name_map(&[(0, "d")])),
+            (10, indirect_name_map(&[(0, &[(1, "f")])])),
+            (11, name_map(&[(0, "tg")])),
+        ];
+        for &(id, ref contents) in &subsections {
+            names.extend(section(id, contents));
+        }
+        let mut data = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        data.extend(section(0x1, &[0x01, 0x60, 0x00, 0x00]));
+        data.extend(section(0x2, &imports));
+        data.extend(section(0x3, &[0x03, 0x00, 0x00, 0x00]));
+        data.extend(section(0x7, &exports));
+        let body = [0x02, 0x00, 0x0b];
+        data.extend(section(0xA, &[&[0x03][..], &body, &body, &body].concat()));
+        data.extend(section(0x0, &names));
+        return data;
+    }
+
+    #[test]
+    fn extended_name_subsections() {
+        let data = module();
+        let module = Parser::new(&data).parse().unwrap();
+        assert!(module.custom_section_errors.is_empty());
+        let names = NameResolver::new(&module);
+        assert_eq!(names.module_name(), Some("m"));
+        assert_eq!(names.local_name(1, 0), Some("x"));
+        assert_eq!(names.label_name(1, 0), Some("l"));
+        assert_eq!(names.field_name(0, 1), Some("f"));
+        assert_eq!(names.field_name(0, 0), None);
+        let kinds = [
+            (NameKind::Type, "t"),
+            (NameKind::Table, "tab"),
+            (NameKind::Memory, "mem"),
+            (NameKind::Global, "gl"),
+            (NameKind::Elem, "e"),
+            (NameKind::Data, "d"),
+            (NameKind::Tag, "tg"),
+        ];
+        for &(kind, name) in &kinds {
+            assert_eq!(names.name(kind, 0), Some(name), "{:?}", kind);
+            assert_eq!(names.name(kind, 1), None, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn display_names() {
+        let data = module();
+        let module = Parser::new(&data).parse().unwrap();
+        let names = NameResolver::new(&module);
+        let functions: Vec<_> =
+            (0..4).map(|i| names.display_name(NameKind::Function, i)).collect();
+        // the name section, then imports, then exports, then placeholders
+        assert_eq!(functions, ["env.imp", "named", "exported", "func[3]"]);
+        assert_eq!(names.display_name(NameKind::Global, 0), "gl");
+        assert_eq!(names.display_name(NameKind::Global, 1), "global[1]");
+        assert_eq!(names.display_name(NameKind::Elem, 2), "elem[2]");
+        assert_eq!(names.display_local_name(1, 0), "x");
+        assert_eq!(names.display_local_name(1, 1), "local[1]");
+    }
+}
@@ -1,16 +1,18 @@
-use parser::{Parser, Type};
+use parser::{ParseError, Parser, Type};
 
 use std::mem;
 
-fn opcode_from_byte(b: u8) -> Opcode {
+pub fn opcode_from_byte(b: u8) -> Result<Opcode, ParseError> {
     match b {
-        0...0x11 | 0x1a...0x1b | 0x20...0x24 | 0x28...0xbf => unsafe { mem::transmute(b) },
-        _ => panic!("Invalid Opcode!"),
+        0..=0x05 | 0x0b..=0x11 | 0x1a..=0x1b | 0x20..=0x24 | 0x28..=0xbf => unsafe {
+            Ok(mem::transmute::<u8, Opcode>(b))
+        },
+        _ => Err(ParseError::InvalidOpcode(b)),
     }
 }
 
 #[derive(Debug)]
-pub struct InitExpr(Op);
+pub struct InitExpr(pub Op);
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(u8)]
@@ -209,6 +211,186 @@ pub enum Opcode {
     f64_reinterpret_i64 = 0xbf,
 }
 
+impl Opcode {
+    /// The instruction name used in the text format.
+    pub fn name(self) -> &'static str {
+        match self {
+            Opcode::unreachable => "unreachable",
+            Opcode::nop => "nop",
+            Opcode::block => "block",
+            Opcode::loop_ => "loop",
+            Opcode::if_ => "if",
+            Opcode::else_ => "else",
+            Opcode::end => "end",
+            Opcode::br => "br",
+            Opcode::br_if => "br_if",
+            Opcode::br_table => "br_table",
+            Opcode::return_ => "return",
+            Opcode::call_ => "call",
+            Opcode::call_indirect => "call_indirect",
+            Opcode::drop => "drop",
+            Opcode::select => "select",
+            Opcode::get_local => "local.get",
+            Opcode::set_local => "local.set",
+            Opcode::tee_local => "local.tee",
+            Opcode::get_global => "global.get",
+            Opcode::set_global => "global.set",
+            Opcode::i32_load => "i32.load",
+            Opcode::i64_load => "i64.load",
+            Opcode::f32_load => "f32.load",
+            Opcode::f64_load => "f64.load",
+            Opcode::i32_load8_s => "i32.load8_s",
+            Opcode::i32_load8_u => "i32.load8_u",
+            Opcode::i32_load16_s => "i32.load16_s",
+            Opcode::i32_load16_u => "i32.load16_u",
+            Opcode::i64_load8_s => "i64.load8_s",
+            Opcode::i64_load8_u => "i64.load8_u",
+            Opcode::i64_load16_s => "i64.load16_s",
+            Opcode::i64_load16_u => "i64.load16_u",
+            Opcode::i64_load32_s => "i64.load32_s",
+            Opcode::i64_load32_u => "i64.load32_u",
+            Opcode::i32_store => "i32.store",
+            Opcode::i64_store => "i64.store",
+            Opcode::f32_store => "f32.store",
+            Opcode::f64_store => "f64.store",
+            Opcode::i32_store8 => "i32.store8",
+            Opcode::i32_store16 => "i32.store16",
+            Opcode::i64_store8 => "i64.store8",
+            Opcode::i64_store16 => "i64.store16",
+            Opcode::i64_store32 => "i64.store32",
+            Opcode::current_memory => "memory.size",
+            Opcode::grow_memory => "memory.grow",
+            Opcode::i32_const => "i32.const",
+            Opcode::i64_const => "i64.const",
+            Opcode::f32_const => "f32.const",
+            Opcode::f64_const => "f64.const",
+            Opcode::i32_eqz => "i32.eqz",
+            Opcode::i32_eq => "i32.eq",
+            Opcode::i32_ne => "i32.ne",
+            Opcode::i32_lt_s => "i32.lt_s",
+            Opcode::i32_lt_u => "i32.lt_u",
+            Opcode::i32_gt_s => "i32.gt_s",
+            Opcode::i32_gt_u => "i32.gt_u",
+            Opcode::i32_le_s => "i32.le_s",
+            Opcode::i32_le_u => "i32.le_u",
+            Opcode::i32_ge_s => "i32.ge_s",
+            Opcode::i32_ge_u => "i32.ge_u",
+            Opcode::i64_eqz => "i64.eqz",
+            Opcode::i64_eq => "i64.eq",
+            Opcode::i64_ne => "i64.ne",
+            Opcode::i64_lt_s => "i64.lt_s",
+            Opcode::i64_lt_u => "i64.lt_u",
+            Opcode::i64_gt_s => "i64.gt_s",
+            Opcode::i64_gt_u => "i64.gt_u",
+            Opcode::i64_le_s => "i64.le_s",
+            Opcode::i64_le_u => "i64.le_u",
+            Opcode::i64_ge_s => "i64.ge_s",
+            Opcode::i64_ge_u => "i64.ge_u",
+            Opcode::f32_eq => "f32.eq",
+            Opcode::f32_ne => "f32.ne",
+            Opcode::f32_lt => "f32.lt",
+            Opcode::f32_gt => "f32.gt",
+            Opcode::f32_le => "f32.le",
+            Opcode::f32_ge => "f32.ge",
+            Opcode::f64_eq => "f64.eq",
+            Opcode::f64_ne => "f64.ne",
+            Opcode::f64_lt => "f64.lt",
+            Opcode::f64_gt => "f64.gt",
+            Opcode::f64_le => "f64.le",
+            Opcode::f64_ge => "f64.ge",
+            Opcode::i32_clz => "i32.clz",
+            Opcode::i32_ctz => "i32.ctz",
+            Opcode::i32_popcnt => "i32.popcnt",
+            Opcode::i32_add => "i32.add",
+            Opcode::i32_sub => "i32.sub",
+            Opcode::i32_mul => "i32.mul",
+            Opcode::i32_div_s => "i32.div_s",
+            Opcode::i32_div_u => "i32.div_u",
+            Opcode::i32_rem_s => "i32.rem_s",
+            Opcode::i32_rem_u => "i32.rem_u",
+            Opcode::i32_and => "i32.and",
+            Opcode::i32_or => "i32.or",
+            Opcode::i32_xor => "i32.xor",
+            Opcode::i32_shl => "i32.shl",
+            Opcode::i32_shr_s => "i32.shr_s",
+            Opcode::i32_shr_u => "i32.shr_u",
+            Opcode::i32_rotl => "i32.rotl",
+            Opcode::i32_rotr => "i32.rotr",
+            Opcode::i64_clz => "i64.clz",
+            Opcode::i64_ctz => "i64.ctz",
+            Opcode::i64_popcnt => "i64.popcnt",
+            Opcode::i64_add => "i64.add",
+            Opcode::i64_sub => "i64.sub",
+            Opcode::i64_mul => "i64.mul",
+            Opcode::i64_div_s => "i64.div_s",
+            Opcode::i64_div_u => "i64.div_u",
+            Opcode::i64_rem_s => "i64.rem_s",
+            Opcode::i64_rem_u => "i64.rem_u",
+            Opcode::i64_and => "i64.and",
+            Opcode::i64_or => "i64.or",
+            Opcode::i64_xor => "i64.xor",
+            Opcode::i64_shl => "i64.shl",
+            Opcode::i64_shr_s => "i64.shr_s",
+            Opcode::i64_shr_u => "i64.shr_u",
+            Opcode::i64_rotl => "i64.rotl",
+            Opcode::i64_rotr => "i64.rotr",
+            Opcode::f32_abs => "f32.abs",
+            Opcode::f32_neg => "f32.neg",
+            Opcode::f32_ceil => "f32.ceil",
+            Opcode::f32_floor => "f32.floor",
+            Opcode::f32_trunc => "f32.trunc",
+            Opcode::f32_nearest => "f32.nearest",
+            Opcode::f32_sqrt => "f32.sqrt",
+            Opcode::f32_add => "f32.add",
+            Opcode::f32_sub => "f32.sub",
+            Opcode::f32_mul => "f32.mul",
+            Opcode::f32_div => "f32.div",
+            Opcode::f32_min => "f32.min",
+            Opcode::f32_max => "f32.max",
+            Opcode::f32_copysign => "f32.copysign",
+            Opcode::f64_abs => "f64.abs",
+            Opcode::f64_neg => "f64.neg",
+            Opcode::f64_ceil => "f64.ceil",
+            Opcode::f64_floor => "f64.floor",
+            Opcode::f64_trunc => "f64.trunc",
+            Opcode::f64_nearest => "f64.nearest",
+            Opcode::f64_sqrt => "f64.sqrt",
+            Opcode::f64_add => "f64.add",
+            Opcode::f64_sub => "f64.sub",
+            Opcode::f64_mul => "f64.mul",
+            Opcode::f64_div => "f64.div",
+            Opcode::f64_min => "f64.min",
+            Opcode::f64_max => "f64.max",
+            Opcode::f64_copysign => "f64.copysign",
+            Opcode::i32_wrap_i64 => "i32.wrap_i64",
+            Opcode::i32_trunc_s_f32 => "i32.trunc_f32_s",
+            Opcode::i32_trunc_u_f32 => "i32.trunc_f32_u",
+            Opcode::i32_trunc_s_f64 => "i32.trunc_f64_s",
+            Opcode::i32_trunc_u_f64 => "i32.trunc_f64_u",
+            Opcode::i64_extend_s_i32 => "i64.extend_i32_s",
+            Opcode::i64_extend_u_i32 => "i64.extend_i32_u",
+            Opcode::i64_trunc_s_f32 => "i64.trunc_f32_s",
+            Opcode::i64_trunc_u_f32 => "i64.trunc_f32_u",
+            Opcode::i64_trunc_s_f64 => "i64.trunc_f64_s",
+            Opcode::i64_trunc_u_f64 => "i64.trunc_f64_u",
+            Opcode::f32_convert_s_i32 => "f32.convert_i32_s",
+            Opcode::f32_convert_u_i32 => "f32.convert_i32_u",
+            Opcode::f32_convert_s_i64 => "f32.convert_i64_s",
+            Opcode::f32_convert_u_i64 => "f32.convert_i64_u",
+            Opcode::f32_demote_f64 => "f32.demote_f64",
+            Opcode::f64_convert_s_i32 => "f64.convert_i32_s",
+            Opcode::f64_convert_u_i32 => "f64.convert_i32_u",
+            Opcode::f64_convert_s_i64 => "f64.convert_i64_s",
+            Opcode::f64_convert_u_i64 => "f64.convert_i64_u",
+            Opcode::f64_promote_f32 => "f64.promote_f32",
+            Opcode::i32_reinterpret_f32 => "i32.reinterpret_f32",
+            Opcode::i64_reinterpret_f64 => "i64.reinterpret_f64",
+            Opcode::f32_reinterpret_i32 => "f32.reinterpret_i32",
+            Opcode::f64_reinterpret_i64 => "f64.reinterpret_i64",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Payload {
     None,
@@ -235,67 +417,74 @@ pub struct Op {
     pub payload: Payload,
 }
 
-impl Parser {
-    fn read_block_type_payload(&mut self) -> Payload {
-        Payload::BlockType(Type::block_type(self.read_varuint7()))
+impl<'a> Parser<'a> {
+    fn read_block_type_payload(&mut self) -> Result<Payload, ParseError> {
+        Ok(Payload::BlockType(Type::block_type(self.read_varuint7()?)?))
     }
-    fn read_vu32_payload(&mut self) -> Payload {
-        Payload::VU32(self.read_varuint32())
+    fn read_vu32_payload(&mut self) -> Result<Payload, ParseError> {
+        Ok(Payload::VU32(self.read_varuint32()?))
     }
-    fn read_br_table_payload(&mut self) -> Payload {
-        Payload::BrTable {
-            target_table: self.read_vu32_times(Parser::read_varuint32),
-            default_target: self.read_varuint32(),
-        }
+    fn read_call_indirect_payload(&mut self) -> Result<Payload, ParseError> {
+        let type_index = self.read_varuint32()?;
+        self.read_varuint1()?; // reserved
+        Ok(Payload::VU32(type_index))
     }
-    fn read_memory_immediate_payload(&mut self) -> Payload {
-        Payload::MemoryImmediate {
-            flags: self.read_varuint32(),
-            offset: self.read_varuint32(),
-        }
+    fn read_br_table_payload(&mut self) -> Result<Payload, ParseError> {
+        Ok(Payload::BrTable {
+            target_table: self.read_vu32_times(Parser::read_varuint32)?,
+            default_target: self.read_varuint32()?,
+        })
     }
-    fn read_reserved_payload(&mut self) -> Payload {
-        self.read_varuint1();
-        Payload::Reserved
+    fn read_memory_immediate_payload(&mut self) -> Result<Payload, ParseError> {
+        Ok(Payload::MemoryImmediate {
+            flags: self.read_varuint32()?,
+            offset: self.read_varuint32()?,
+        })
     }
-    fn read_vi32_payload(&mut self) -> Payload {
-        Payload::VI32(self.read_varint32())
+    fn read_reserved_payload(&mut self) -> Result<Payload, ParseError> {
+        self.read_varuint1()?;
+        Ok(Payload::Reserved)
     }
-    fn read_vi64_payload(&mut self) -> Payload {
-        Payload::VI64(self.read_varint64())
+    fn read_vi32_payload(&mut self) -> Result<Payload, ParseError> {
+        Ok(Payload::VI32(self.read_varint32()?))
     }
-    fn read_f32_payload(&mut self) -> Payload {
-        Payload::F32(self.read_f32())
+    fn read_vi64_payload(&mut self) -> Result<Payload, ParseError> {
+        Ok(Payload::VI64(self.read_varint64()?))
     }
-    fn read_f64_payload(&mut self) -> Payload {
-        Payload::F64(self.read_f64())
+    fn read_f32_payload(&mut self) -> Result<Payload, ParseError> {
+        Ok(Payload::F32(self.read_f32()?))
+    }
+    fn read_f64_payload(&mut self) -> Result<Payload, ParseError> {
+        Ok(Payload::F64(self.read_f64()?))
     }
 
-    fn read_payload(&mut self, oc: Opcode) -> Payload {
+    fn read_payload(&mut self, oc: Opcode) -> Result<Payload, ParseError> {
         match oc as u8 {
-            0x02...0x04 => self.read_block_type_payload(),
-            0x0c...0x0d | 0x10...0x11 | 0x20...0x24 => self.read_vu32_payload(),
+            0x02..=0x04 => self.read_block_type_payload(),
+            0x0c..=0x0d | 0x10 | 0x20..=0x24 => self.read_vu32_payload(),
+            0x11 => self.read_call_indirect_payload(),
             0x0e => self.read_br_table_payload(),
-            0x28...0x3e => self.read_memory_immediate_payload(),
-            0x3f...0x40 => self.read_reserved_payload(),
+            0x28..=0x3e => self.read_memory_immediate_payload(),
+            0x3f..=0x40 => self.read_reserved_payload(),
             0x41 => self.read_vi32_payload(),
             0x42 => self.read_vi64_payload(),
             0x43 => self.read_f32_payload(),
             0x44 => self.read_f64_payload(),
-            _ => Payload::None,
+            _ => Ok(Payload::None),
         }
     }
 
-    pub fn read_op(&mut self) -> Op {
-        let b = self.read_byte();
-        let opcode = opcode_from_byte(b);
-        let payload = self.read_payload(opcode);
-        Op { opcode, payload }
+    pub fn read_op(&mut self) -> Result<Op, ParseError> {
+        let b = self.read_byte()?;
+        let opcode = opcode_from_byte(b)?;
+        let payload = self.read_payload(opcode)?;
+        Ok(Op { opcode, payload })
     }
-    pub fn read_init_expr(&mut self) -> InitExpr {
-        let op = self.read_op();
-        let res = self.read_byte();
-        assert!(res == 0x0b);
-        InitExpr(op)
+    pub fn read_init_expr(&mut self) -> Result<InitExpr, ParseError> {
+        let op = self.read_op()?;
+        if self.read_byte()? != 0x0b {
+            return Err(ParseError::MissingEnd);
+        }
+        Ok(InitExpr(op))
     }
 }
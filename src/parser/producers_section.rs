@@ -0,0 +1,94 @@
+use parser::{ParseError, Parser};
+
+#[derive(Debug)]
+pub struct ProducerVersion<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+}
+
+/// Contents of the `producers` custom section.
+#[derive(Debug, Default)]
+pub struct Producers<'a> {
+    pub language: Vec<ProducerVersion<'a>>,
+    pub processed_by: Vec<ProducerVersion<'a>>,
+    pub sdk: Vec<ProducerVersion<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn read_producer_version(&mut self) -> Result<ProducerVersion<'a>, ParseError> {
+        let name = self.read_utf8_str_vu32()?;
+        let version = self.read_utf8_str_vu32()?;
+        Ok(ProducerVersion { name, version })
+    }
+
+    pub fn parse_producers_custom_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Producers<'a>, ParseError> {
+        trace!("  # Parsing producers custom section");
+        let init_offset = self.get_current_offset();
+        let mut producers = Producers::default();
+        let mut seen = (false, false, false);
+        let field_count = self.read_varuint32()?;
+        for _ in 0..field_count {
+            let field_name = self.read_utf8_str_vu32()?;
+            let (field, seen) = match field_name {
+                "language" => (&mut producers.language, &mut seen.0),
+                "processed-by" => (&mut producers.processed_by, &mut seen.1),
+                "sdk" => (&mut producers.sdk, &mut seen.2),
+                _ => return Err(ParseError::MalformedCustomSection("producers")),
+            };
+            // every field may appear only once
+            if *seen {
+                return Err(ParseError::MalformedCustomSection("producers"));
+            }
+            *seen = true;
+            *field = self.read_vu32_times(Parser::read_producer_version)?;
+        }
+        if self.get_read_len(init_offset) != payload_len {
+            return Err(ParseError::MalformedCustomSection("producers"));
+        }
+        return Ok(producers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.push(s.len() as u8);
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn pairs<'a>(values: &[ProducerVersion<'a>]) -> Vec<(&'a str, &'a str)> {
+        values.iter().map(|v| (v.name, v.version)).collect()
+    }
+
+    #[test]
+    fn producers() {
+        let fields: [(&str, &[(&str, &str)]); 3] = [
+            ("language", &[("C99", ""), ("Rust", "1.80")]),
+            ("processed-by", &[("clang", "18.1.0")]),
+            ("sdk", &[("wasi-sdk", "22")]),
+        ];
+        let mut payload = vec![fields.len() as u8];
+        for &(field, values) in &fields {
+            string(&mut payload, field);
+            payload.push(values.len() as u8);
+            for &(name, version) in values {
+                string(&mut payload, name);
+                string(&mut payload, version);
+            }
+        }
+        let producers = Parser::new(&payload)
+            .parse_producers_custom_section(payload.len() as u32)
+            .unwrap();
+        assert_eq!(pairs(&producers.language), fields[0].1);
+        assert_eq!(pairs(&producers.processed_by), fields[1].1);
+        assert_eq!(pairs(&producers.sdk), fields[2].1);
+        // fields are optional
+        let producers = Parser::new(&[0x00]).parse_producers_custom_section(1).unwrap();
+        assert!(producers.language.is_empty() && producers.sdk.is_empty());
+    }
+}
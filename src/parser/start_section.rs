@@ -1,11 +1,13 @@
-use parser::{FnId, Parser};
+use parser::{FnId, ParseError, Parser};
+use parser::leb128;
 
-impl Parser {
-    pub fn parse_start_section(&mut self, payload_len: u32) -> FnId {
-        println!("  # Parsing start section");
-        let (index, len) = self.read_varuint_len(32);
-        let fn_id = FnId(index as u32);
-        assert!(len == payload_len as u64);
-        return fn_id;
+impl<'a> Parser<'a> {
+    pub fn parse_start_section(&mut self, payload_len: u32) -> Result<FnId, ParseError> {
+        trace!("  # Parsing start section");
+        let (index, len) = self.read_leb_len(leb128::read_varuint32)?;
+        if len != payload_len as usize {
+            return Err(ParseError::SectionSizeMismatch(0x8));
+        }
+        return Ok(FnId(index));
     }
 }
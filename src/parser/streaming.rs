@@ -0,0 +1,540 @@
+use parser::{check_function_count, FnId, ParseError, Parser, SectionOrder, SUPPORTED_VERSION};
+use parser::custom_section::{CustomSection, CustomSectionError, Namings};
+use parser::producers_section::Producers;
+use parser::target_features_section::TargetFeature;
+use parser::linking_section::{Linking, RelocSection};
+use parser::type_section::FuncType;
+use parser::import_export_section::{ExportEntry, ImportEntry};
+use parser::table_section::TableEntry;
+use parser::memory_section::MemoryType;
+use parser::global_section::GlobalVariable;
+use parser::element_section::ElemSegment;
+use parser::code_section::LazyFnBody;
+use parser::data_section::DataEntry;
+use parser::leb128;
+
+/// Something the streaming parser found in the module.
+///
+/// Events borrow from the parser's internal buffer and are only valid for the
+/// duration of the callback they are passed to.
+#[derive(Debug)]
+pub enum Event<'b> {
+    Header { version: u32 },
+    /// A section header was read; `offset` is the file offset of its payload.
+    SectionStart { id: u8, offset: u32, size: u32 },
+    Names(Box<Namings<'b>>),
+    Producers(Producers<'b>),
+    TargetFeatures(Vec<TargetFeature<'b>>),
+    SourceMappingUrl(&'b str),
+    Linking(Linking<'b>),
+    Relocations(RelocSection<'b>),
+    Custom(CustomSection<'b>),
+    /// A custom section with a known format that could not be decoded, which
+    /// is also reported as `Custom`.
+    MalformedCustom(CustomSectionError<'b>),
+    Type(FuncType),
+    Import(ImportEntry<'b>),
+    Function(FnId),
+    Table(TableEntry),
+    Memory(MemoryType),
+    Global(GlobalVariable),
+    Export(ExportEntry<'b>),
+    Start(FnId),
+    Element(ElemSegment),
+    /// A function body together with its index in the code section. Bodies are
+    /// reported as soon as their bytes have arrived.
+    FunctionBody(u32, LazyFnBody<'b>),
+    Data(DataEntry<'b>),
+    DataCount(u32),
+    SectionEnd { id: u8 },
+    End,
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Header,
+    SectionHeader,
+    /// Waiting for the complete payload of a section that ends at `end`.
+    Section { id: u8, end: usize },
+    CodeCount { end: usize },
+    CodeBodies { remaining: u32, index: u32, end: usize },
+}
+
+impl State {
+    /// Moves the section end back after `by` bytes were dropped from the
+    /// front of the buffer.
+    fn shift(&mut self, by: usize) {
+        match *self {
+            State::Section { ref mut end, .. }
+            | State::CodeCount { ref mut end }
+            | State::CodeBodies { ref mut end, .. } => *end -= by,
+            State::Header | State::SectionHeader => {}
+        }
+    }
+}
+
+/// A push-based parser for modules that arrive in chunks.
+///
+/// Bytes are handed over with `feed()`, which reports everything that can be
+/// parsed from the data received so far. Function bodies are reported one by
+/// one as they complete; the entries of all other sections are reported once
+/// the whole section has arrived.
+pub struct StreamingParser {
+    /// Received bytes that have not been parsed yet, starting at file offset
+    /// `base`.
+    buffer: Vec<u8>,
+    base: usize,
+    pos: usize,
+    state: State,
+    order: SectionOrder,
+    function_count: u32,
+    body_count: u32,
+}
+
+/// Whether `data` starts with a complete LEB128 encoded 32 bit value.
+fn varuint32_available(data: &[u8]) -> bool {
+    data.len() >= 5 || data.iter().any(|b| b & 0x80 == 0)
+}
+
+fn emit_entries<'b, T, F>(
+    p: &mut Parser<'b>,
+    read: fn(&mut Parser<'b>) -> Result<T, ParseError>,
+    wrap: fn(T) -> Event<'b>,
+    handler: &mut F,
+) -> Result<u32, ParseError>
+where
+    F: FnMut(Event),
+{
+    let count = p.read_varuint32()?;
+    for _ in 0..count {
+        handler(wrap(read(p)?));
+    }
+    return Ok(count);
+}
+
+/// Reports a decoded custom section, or the section as an opaque one and why
+/// it could not be decoded.
+fn emit_custom<'b, T, D, F>(
+    p: &mut Parser<'b>,
+    name: &'b str,
+    decode: D,
+    wrap: fn(T) -> Event<'b>,
+    handler: &mut F,
+) where
+    D: FnOnce(&mut Parser<'b>, u32) -> Result<T, ParseError>,
+    F: FnMut(Event),
+{
+    let data = p.data;
+    let payload = &data[p.offset..];
+    match decode(p, payload.len() as u32) {
+        Ok(decoded) => handler(wrap(decoded)),
+        Err(error) => {
+            handler(Event::Custom(CustomSection(name, payload)));
+            handler(Event::MalformedCustom(CustomSectionError { name, error }));
+            p.offset = data.len();
+        }
+    }
+}
+
+/// Reports the entries of a complete section and returns how many there were.
+fn emit_section<'b, F>(
+    id: u8,
+    p: &mut Parser<'b>,
+    end: usize,
+    handler: &mut F,
+) -> Result<u32, ParseError>
+where
+    F: FnMut(Event),
+{
+    let count = match id {
+        0x0 => {
+            let name = p.read_utf8_str_vu32()?;
+            let payload_data_len = (end - p.offset) as u32;
+            if name == "name" {
+                let names = |namings| Event::Names(Box::new(namings));
+                emit_custom(p, name, Parser::parse_name_custom_section, names, handler);
+            } else if name == "producers" {
+                let decode = Parser::parse_producers_custom_section;
+                emit_custom(p, name, decode, Event::Producers, handler);
+            } else if name == "target_features" {
+                let decode = Parser::parse_target_features_custom_section;
+                emit_custom(p, name, decode, Event::TargetFeatures, handler);
+            } else if name == "sourceMappingURL" {
+                let decode = Parser::parse_source_mapping_url;
+                emit_custom(p, name, decode, Event::SourceMappingUrl, handler);
+            } else if name == "linking" {
+                let decode = Parser::parse_linking_custom_section;
+                emit_custom(p, name, decode, Event::Linking, handler);
+            } else if name.starts_with("reloc.") {
+                let decode = |p: &mut Parser<'b>, len| p.parse_reloc_custom_section(name, len);
+                emit_custom(p, name, decode, Event::Relocations, handler);
+            } else {
+                handler(Event::Custom(p.parse_custom_section(name, payload_data_len)?));
+            }
+            1
+        }
+        0x1 => emit_entries(p, Parser::read_func_type, Event::Type, handler)?,
+        0x2 => emit_entries(p, Parser::read_import_entry, Event::Import, handler)?,
+        0x3 => emit_entries(p, Parser::read_fn_id, Event::Function, handler)?,
+        0x4 => emit_entries(p, Parser::read_table_type, Event::Table, handler)?,
+        0x5 => emit_entries(p, Parser::read_memory_type, Event::Memory, handler)?,
+        0x6 => emit_entries(p, Parser::read_global_variable, Event::Global, handler)?,
+        0x7 => emit_entries(p, Parser::read_export_entry, Event::Export, handler)?,
+        0x8 => {
+            handler(Event::Start(p.read_fn_id()?));
+            1
+        }
+        0x9 => emit_entries(p, Parser::read_element, Event::Element, handler)?,
+        0xB => emit_entries(p, Parser::read_data_entry, Event::Data, handler)?,
+        0xC => {
+            handler(Event::DataCount(p.read_varuint32()?));
+            1
+        }
+        _ => unreachable!(),
+    };
+    if p.offset != end {
+        return Err(ParseError::SectionSizeMismatch(id));
+    }
+    return Ok(count);
+}
+
+impl Default for StreamingParser {
+    fn default() -> StreamingParser {
+        StreamingParser::new()
+    }
+}
+
+impl StreamingParser {
+    pub fn new() -> StreamingParser {
+        StreamingParser {
+            buffer: Vec::new(),
+            base: 0,
+            pos: 0,
+            state: State::Header,
+            order: SectionOrder::default(),
+            function_count: 0,
+            body_count: 0,
+        }
+    }
+
+    /// Appends `chunk` to the received data and passes every event that has
+    /// become available to `handler`.
+    pub fn feed<F>(&mut self, chunk: &[u8], handler: &mut F) -> Result<(), ParseError>
+    where
+        F: FnMut(Event),
+    {
+        self.buffer.drain(..self.pos);
+        self.base += self.pos;
+        self.state.shift(self.pos);
+        self.pos = 0;
+        self.buffer.extend_from_slice(chunk);
+        while self.step(handler)? {}
+        Ok(())
+    }
+
+    /// Signals that the whole module has been fed. Fails with `UnexpectedEof`
+    /// if the module ended in the middle of a section.
+    pub fn finish<F>(self, handler: &mut F) -> Result<(), ParseError>
+    where
+        F: FnMut(Event),
+    {
+        if self.state != State::SectionHeader || self.pos != self.buffer.len() {
+            return Err(ParseError::UnexpectedEof);
+        }
+        check_function_count(self.function_count as usize, self.body_count as usize)?;
+        handler(Event::End);
+        Ok(())
+    }
+
+    /// Parses the next item if all of its bytes are available. Returns false
+    /// if more data is needed.
+    fn step<F>(&mut self, handler: &mut F) -> Result<bool, ParseError>
+    where
+        F: FnMut(Event),
+    {
+        let available = &self.buffer[self.pos..];
+        let mut p = Parser {
+            data: &self.buffer,
+            offset: self.pos,
+        };
+        match self.state {
+            State::Header => {
+                if available.len() < 8 {
+                    return Ok(false);
+                }
+                p.parse_preamble()?;
+                handler(Event::Header {
+                    version: SUPPORTED_VERSION,
+                });
+                self.state = State::SectionHeader;
+            }
+            State::SectionHeader => {
+                if available.is_empty() || !varuint32_available(&available[1..]) {
+                    return Ok(false);
+                }
+                let id = p.read_varuint7()?;
+                self.order.check(id)?;
+                let size = p.read_varuint32()?;
+                let offset = (self.base + p.offset) as u32;
+                handler(Event::SectionStart { id, offset, size });
+                let end = p.offset + size as usize;
+                self.state = if id == 0xA {
+                    State::CodeCount { end }
+                } else {
+                    State::Section { id, end }
+                };
+            }
+            State::Section { id, end } => {
+                if self.buffer.len() < end {
+                    return Ok(false);
+                }
+                let mut section = Parser {
+                    data: &self.buffer[..end],
+                    offset: self.pos,
+                };
+                let count = emit_section(id, &mut section, end, handler)?;
+                if id == 0x3 {
+                    self.function_count = count;
+                }
+                handler(Event::SectionEnd { id });
+                p.offset = end;
+                self.state = State::SectionHeader;
+            }
+            State::CodeCount { end } => {
+                if !varuint32_available(available) {
+                    return Ok(false);
+                }
+                let remaining = p.read_varuint32()?;
+                if p.offset > end {
+                    return Err(ParseError::SectionSizeMismatch(0xA));
+                }
+                self.body_count = remaining;
+                self.state = State::CodeBodies {
+                    remaining,
+                    index: 0,
+                    end,
+                };
+            }
+            State::CodeBodies {
+                remaining: 0, end, ..
+            } => {
+                if p.offset != end {
+                    return Err(ParseError::SectionSizeMismatch(0xA));
+                }
+                handler(Event::SectionEnd { id: 0xA });
+                self.state = State::SectionHeader;
+            }
+            State::CodeBodies {
+                remaining,
+                index,
+                end,
+            } => {
+                if !varuint32_available(available) {
+                    return Ok(false);
+                }
+                let (body_size, size_len) = p.read_leb_len(leb128::read_varuint32)?;
+                if p.offset + body_size as usize > end {
+                    return Err(ParseError::SectionSizeMismatch(0xA));
+                }
+                if available.len() < size_len + body_size as usize {
+                    return Ok(false);
+                }
+                p.offset = self.pos;
+                let mut body = p.read_fn_body()?;
+                body.offset += self.base as u32;
+                handler(Event::FunctionBody(index, body));
+                self.state = State::CodeBodies {
+                    remaining: remaining - 1,
+                    index: index + 1,
+                    end,
+                };
+            }
+        }
+        self.pos = p.offset;
+        return Ok(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fmt, fs};
+
+    /// The entries reported while feeding `data` in chunks of `chunk` bytes.
+    fn streamed(data: &[u8], chunk: usize) -> Result<Vec<String>, ParseError> {
+        let mut events = Vec::new();
+        let mut handler = |event: Event| match event {
+            Event::Header { .. } | Event::SectionStart { .. } => {}
+            Event::SectionEnd { .. } | Event::End => {}
+            event => events.push(format!("{:?}", event)),
+        };
+        let mut parser = StreamingParser::new();
+        for chunk in data.chunks(chunk) {
+            parser.feed(chunk, &mut handler)?;
+        }
+        parser.finish(&mut handler)?;
+        events.sort();
+        return Ok(events);
+    }
+
+    /// The entries of `Parser::parse()`, formatted like the matching events.
+    fn parsed(data: &[u8]) -> Vec<String> {
+        let module = Parser::new(data).parse().unwrap();
+        let mut entries = Vec::new();
+        {
+            let mut add = |kind: &str, entry: &dyn fmt::Debug| {
+                entries.push(format!("{}({:?})", kind, entry));
+            };
+            if let Some(ref names) = module.namings {
+                add("Names", names);
+            }
+            if let Some(ref producers) = module.producers {
+                add("Producers", producers);
+            }
+            if let Some(ref features) = module.target_features {
+                add("TargetFeatures", features);
+            }
+            if let Some(ref url) = module.source_mapping_url {
+                add("SourceMappingUrl", url);
+            }
+            for section in &module.custom_sections {
+                add("Custom", section);
+            }
+            for error in &module.custom_section_errors {
+                add("MalformedCustom", error);
+            }
+            for typ in module.function_types.iter().flatten() {
+                add("Type", typ);
+            }
+            for import in module.import_entires.iter().flatten() {
+                add("Import", import);
+            }
+            for id in module.function_ids.iter().flatten() {
+                add("Function", id);
+            }
+            for table in module.table_entries.iter().flatten() {
+                add("Table", table);
+            }
+            for memory in module.memory_types.iter().flatten() {
+                add("Memory", memory);
+            }
+            for global in module.global_variables.iter().flatten() {
+                add("Global", global);
+            }
+            for export in module.export_entires.iter().flatten() {
+                add("Export", export);
+            }
+            if let Some(ref start) = module.start_function {
+                add("Start", start);
+            }
+            for segment in module.element_segments.iter().flatten() {
+                add("Element", segment);
+            }
+            for entry in module.data_entries.iter().flatten() {
+                add("Data", entry);
+            }
+            if let Some(ref count) = module.data_count {
+                add("DataCount", count);
+            }
+        }
+        for (i, body) in module.function_bodies.iter().flatten().enumerate() {
+            entries.push(format!("FunctionBody({}, {:?})", i, body));
+        }
+        entries.sort();
+        return entries;
+    }
+
+    #[test]
+    fn byte_chunks_match_parse() {
+        for entry in fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "wasm") {
+                continue;
+            }
+            let data = fs::read(&path).unwrap();
+            let expected = parsed(&data);
+            assert_eq!(streamed(&data, 1), Ok(expected.clone()), "{}", path.display());
+            assert_eq!(streamed(&data, data.len()), Ok(expected), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn malformed_name_section() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x00, 0x0a, 0x04, b'n', b'a', b'm', b'e', 0x01, 0x03, 0x01, 0x00, 0x0a, // names
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types
+        ];
+        let expected = parsed(&data);
+        assert_eq!(expected.len(), 3);
+        assert_eq!(streamed(&data, 1), Ok(expected));
+    }
+
+    #[test]
+    fn malformed_tool_sections() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x00, 0x10, 0x09, b'p', b'r', b'o', b'd', b'u', b'c', b'e', b'r', b's', 0x01, 0x03,
+            b'f', b'o', b'o', 0x00, // producers
+            0x00, 0x14, 0x0f, b't', b'a', b'r', b'g', b'e', b't', b'_', b'f', b'e', b'a', b't',
+            b'u', b'r', b'e', b's', 0x01, b'?', 0x01, b'x', // target features
+        ];
+        let expected = parsed(&data);
+        assert_eq!(expected.len(), 4);
+        assert_eq!(streamed(&data, 1), Ok(expected));
+    }
+
+    #[test]
+    fn malformed_source_mapping_url() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x00, 0x14, 0x10, b's', b'o', b'u', b'r', b'c', b'e', b'M', b'a', b'p', b'p', b'i',
+            b'n', b'g', b'U', b'R', b'L', 0x05, b'a', b'b', // url
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types
+        ];
+        let expected = parsed(&data);
+        assert_eq!(expected.len(), 3);
+        assert_eq!(streamed(&data, 1), Ok(expected));
+    }
+
+    #[test]
+    fn malformed_object_file_sections() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x00, 0x09, 0x07, b'l', b'i', b'n', b'k', b'i', b'n', b'g', 0x01, // linking
+            0x00, 0x10, 0x0a, b'r', b'e', b'l', b'o', b'c', b'.', b'C', b'O', b'D', b'E', 0x03,
+            0x01, 0x40, 0x00, 0x00, // relocations
+        ];
+        let expected = parsed(&data);
+        assert_eq!(expected.len(), 4);
+        assert_eq!(streamed(&data, 1), Ok(expected));
+    }
+
+    #[test]
+    fn data_count() {
+        let data = [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x0c, 0x01, 0x00, // data count
+            0x0b, 0x01, 0x00, // data
+        ];
+        let expected = parsed(&data);
+        assert!(expected.contains(&"DataCount(0)".to_string()));
+        assert_eq!(streamed(&data, 1), Ok(expected));
+    }
+
+    #[test]
+    fn malformed_modules_fail() {
+        let data = fs::read("examples/factorial.wasm").unwrap();
+        assert_eq!(streamed(&data[..data.len() - 1], 1), Err(ParseError::UnexpectedEof));
+        // A type section whose single entry is one byte shorter than its size.
+        let header = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        let types = [0x01, 0x05, 0x01, 0x60, 0x00, 0x00, 0x00];
+        let module = [&header[..], &types[..]].concat();
+        assert_eq!(streamed(&module, 1), Err(ParseError::SectionSizeMismatch(0x1)));
+        // A code section of one byte holding a body of two.
+        let code = [0x0a, 0x01, 0x01, 0x02, 0x00, 0x0b];
+        let module = [&header[..], &code[..]].concat();
+        assert_eq!(streamed(&module, 1), Err(ParseError::SectionSizeMismatch(0xA)));
+    }
+}
@@ -0,0 +1,70 @@
+use parser::{ParseError, Parser};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeaturePrefix {
+    /// `+`: the module uses the feature.
+    Used,
+    /// `-`: the module must not be used with the feature enabled.
+    Disallowed,
+    /// `=`: the feature is required (legacy, treated like `Used`).
+    Required,
+}
+
+/// An entry of the `target_features` custom section.
+#[derive(Debug)]
+pub struct TargetFeature<'a> {
+    pub prefix: FeaturePrefix,
+    pub name: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn read_target_feature(&mut self) -> Result<TargetFeature<'a>, ParseError> {
+        let prefix = match self.read_byte()? {
+            b'+' => FeaturePrefix::Used,
+            b'-' => FeaturePrefix::Disallowed,
+            b'=' => FeaturePrefix::Required,
+            _ => return Err(ParseError::MalformedCustomSection("target_features")),
+        };
+        let name = self.read_utf8_str_vu32()?;
+        Ok(TargetFeature { prefix, name })
+    }
+
+    pub fn parse_target_features_custom_section(
+        &mut self,
+        payload_len: u32,
+    ) -> Result<Vec<TargetFeature<'a>>, ParseError> {
+        trace!("  # Parsing target_features custom section");
+        let init_offset = self.get_current_offset();
+        let features = self.read_vu32_times(Parser::read_target_feature)?;
+        if self.get_read_len(init_offset) != payload_len {
+            return Err(ParseError::MalformedCustomSection("target_features"));
+        }
+        return Ok(features);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_features() {
+        let payload = [
+            0x03, b'+', 0x04, b'a', b't', b'o', b'm', b'-', 0x04, b's', b'i', b'm', b'd', b'=',
+            0x0f, b'm', b'u', b't', b'a', b'b', b'l', b'e', b'-', b'g', b'l', b'o', b'b', b'a',
+            b'l', b's',
+        ];
+        let features = Parser::new(&payload)
+            .parse_target_features_custom_section(payload.len() as u32)
+            .unwrap();
+        let features: Vec<_> = features.iter().map(|f| (f.prefix, f.name)).collect();
+        assert_eq!(
+            features,
+            [
+                (FeaturePrefix::Used, "atom"),
+                (FeaturePrefix::Disallowed, "simd"),
+                (FeaturePrefix::Required, "mutable-globals"),
+            ]
+        );
+    }
+}
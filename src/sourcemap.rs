@@ -0,0 +1,312 @@
+//! Source maps (version 3) as referenced by the `sourceMappingURL` custom
+//! section of Emscripten-built modules.
+//!
+//! For WebAssembly the generated column of a mapping is the file offset of an
+//! instruction in the module binary; the generated line is always 0.
+
+use json::{self, JsonError, Value};
+use parser::ParseResult;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum SourceMapError {
+    Io(io::Error),
+    Json(JsonError),
+    /// The JSON document is not a version 3 source map.
+    Malformed(&'static str),
+    /// Source maps are only loaded from local files.
+    RemoteUrl(String),
+}
+
+impl fmt::Display for SourceMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SourceMapError::Io(ref err) => write!(f, "cannot read source map: {}", err),
+            SourceMapError::Json(err) => write!(f, "cannot parse source map: {}", err),
+            SourceMapError::Malformed(what) => write!(f, "malformed source map: {}", what),
+            SourceMapError::RemoteUrl(ref url) => {
+                write!(f, "source map '{}' is not a local file", url)
+            }
+        }
+    }
+}
+
+impl Error for SourceMapError {}
+
+impl From<io::Error> for SourceMapError {
+    fn from(err: io::Error) -> SourceMapError {
+        SourceMapError::Io(err)
+    }
+}
+
+impl From<JsonError> for SourceMapError {
+    fn from(err: JsonError) -> SourceMapError {
+        SourceMapError::Json(err)
+    }
+}
+
+#[derive(Debug)]
+pub struct Mapping {
+    /// File offset in the module binary.
+    pub offset: u32,
+    /// Index into `SourceMap::sources`, `None` for unmapped code.
+    pub source: Option<u32>,
+    /// Zero based line in the original source.
+    pub line: u32,
+    /// Zero based column in the original source.
+    pub column: u32,
+    /// Index into `SourceMap::names`.
+    pub name: Option<u32>,
+}
+
+/// A position in the original sources, with one based line and column.
+#[derive(Debug, PartialEq)]
+pub struct OriginalPosition<'m> {
+    pub source: &'m str,
+    pub line: u32,
+    pub column: u32,
+    pub name: Option<&'m str>,
+}
+
+impl<'m> fmt::Display for OriginalPosition<'m> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub struct SourceMap {
+    /// Source file names with `sourceRoot` already applied.
+    pub sources: Vec<String>,
+    pub names: Vec<String>,
+    /// Mappings of the first generated line, ordered by offset.
+    pub mappings: Vec<Mapping>,
+}
+
+fn base64_value(c: u8) -> Option<i64> {
+    let value = match c {
+        b'A'..=b'Z' => c - b'A',
+        b'a'..=b'z' => c - b'a' + 26,
+        b'0'..=b'9' => c - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        _ => return None,
+    };
+    Some(value as i64)
+}
+
+/// Decodes the base64 VLQ numbers of one mapping segment.
+fn decode_vlq(segment: &[u8]) -> Result<Vec<i64>, SourceMapError> {
+    let mut values = Vec::new();
+    let mut value: i64 = 0;
+    let mut shift = 0;
+    for &c in segment {
+        let digit = base64_value(c).ok_or(SourceMapError::Malformed("invalid VLQ digit"))?;
+        if shift > 60 {
+            return Err(SourceMapError::Malformed("VLQ value too large"));
+        }
+        value |= (digit & 0x1f) << shift;
+        shift += 5;
+        if digit & 0x20 == 0 {
+            // the lowest bit holds the sign
+            let magnitude = value >> 1;
+            values.push(if value & 1 != 0 { -magnitude } else { magnitude });
+            value = 0;
+            shift = 0;
+        }
+    }
+    if shift != 0 {
+        return Err(SourceMapError::Malformed("truncated VLQ value"));
+    }
+    return Ok(values);
+}
+
+fn add_delta(base: &mut i64, delta: i64) -> Result<u32, SourceMapError> {
+    *base += delta;
+    if *base < 0 || *base > u32::MAX as i64 {
+        return Err(SourceMapError::Malformed("mapping out of range"));
+    }
+    Ok(*base as u32)
+}
+
+fn parse_mappings(mappings: &str) -> Result<Vec<Mapping>, SourceMapError> {
+    let mut result = Vec::new();
+    // all fields but the generated column are relative to the previous segment
+    // across lines
+    let (mut source, mut line, mut column, mut name) = (0, 0, 0, 0);
+    // only the first line is meaningful for a module binary, but later lines
+    // still have to be decoded to keep the relative fields right
+    for (generated_line, group) in mappings.split(';').enumerate() {
+        let mut offset = 0;
+        for segment in group.split(',').filter(|s| !s.is_empty()) {
+            let fields = decode_vlq(segment.as_bytes())?;
+            let mut mapping = Mapping {
+                offset: add_delta(&mut offset, fields[0])?,
+                source: None,
+                line: 0,
+                column: 0,
+                name: None,
+            };
+            match fields.len() {
+                1 => {}
+                4 | 5 => {
+                    mapping.source = Some(add_delta(&mut source, fields[1])?);
+                    mapping.line = add_delta(&mut line, fields[2])?;
+                    mapping.column = add_delta(&mut column, fields[3])?;
+                    if fields.len() == 5 {
+                        mapping.name = Some(add_delta(&mut name, fields[4])?);
+                    }
+                }
+                _ => return Err(SourceMapError::Malformed("invalid mapping segment")),
+            }
+            if generated_line == 0 {
+                result.push(mapping);
+            }
+        }
+    }
+    result.sort_by_key(|m| m.offset);
+    return Ok(result);
+}
+
+fn string_array(map: &Value, key: &'static str) -> Result<Vec<String>, SourceMapError> {
+    let values = match map.get(key) {
+        Some(values) => values.as_array().ok_or(SourceMapError::Malformed(key))?,
+        None => return Ok(Vec::new()),
+    };
+    values
+        .iter()
+        .map(|v| match *v {
+            Value::String(ref s) => Ok(s.clone()),
+            Value::Null => Ok(String::new()),
+            _ => Err(SourceMapError::Malformed(key)),
+        })
+        .collect()
+}
+
+impl SourceMap {
+    pub fn parse(text: &str) -> Result<SourceMap, SourceMapError> {
+        let map = json::parse(text)?;
+        if map.get("version").and_then(Value::as_u64) != Some(3) {
+            return Err(SourceMapError::Malformed("version"));
+        }
+        let root = map.get("sourceRoot").and_then(Value::as_str).unwrap_or("");
+        let sources = string_array(&map, "sources")?
+            .into_iter()
+            .map(|source| {
+                if root.is_empty() || source.starts_with('/') {
+                    source
+                } else {
+                    format!("{}/{}", root.trim_end_matches('/'), source)
+                }
+            })
+            .collect();
+        let names = string_array(&map, "names")?;
+        let mappings = match map.get("mappings").and_then(Value::as_str) {
+            Some(mappings) => parse_mappings(mappings)?,
+            None => return Err(SourceMapError::Malformed("mappings")),
+        };
+        return Ok(SourceMap {
+            sources,
+            names,
+            mappings,
+        });
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SourceMap, SourceMapError> {
+        let text = fs::read_to_string(path)?;
+        SourceMap::parse(&text)
+    }
+
+    /// Loads the source map named in the module's `sourceMappingURL` section.
+    ///
+    /// Relative URLs are resolved against the directory of `module_path`.
+    /// Returns `None` if the module does not reference a source map.
+    pub fn load_for_module(
+        module: &ParseResult,
+        module_path: &Path,
+    ) -> Option<Result<SourceMap, SourceMapError>> {
+        let url = module.source_mapping_url?;
+        Some(resolve_url(url, module_path).and_then(SourceMap::load))
+    }
+
+    /// The original position of the instruction at file offset `offset`.
+    pub fn lookup(&self, offset: u32) -> Option<OriginalPosition<'_>> {
+        let index = self.mappings.partition_point(|m| m.offset <= offset);
+        let mapping = &self.mappings[index.checked_sub(1)?];
+        let source = self.sources.get(mapping.source? as usize)?;
+        Some(OriginalPosition {
+            source,
+            line: mapping.line + 1,
+            column: mapping.column + 1,
+            name: mapping.name.and_then(|n| self.names.get(n as usize)).map(|n| &n[..]),
+        })
+    }
+}
+
+fn resolve_url(url: &str, module_path: &Path) -> Result<PathBuf, SourceMapError> {
+    let path = if let Some(path) = url.strip_prefix("file://") {
+        path
+    } else if url.contains("://") || url.starts_with("data:") {
+        return Err(SourceMapError::RemoteUrl(url.to_string()));
+    } else {
+        url
+    };
+    let dir = module_path.parent().unwrap_or_else(|| Path::new(""));
+    return Ok(dir.join(path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vlq() {
+        assert_eq!(decode_vlq(b"A").unwrap(), [0]);
+        assert_eq!(decode_vlq(b"CDegBhB").unwrap(), [1, -1, 15, 16, -16]);
+        assert_eq!(decode_vlq(b"gkxHx+B").unwrap(), [123456, -1000]);
+        assert!(decode_vlq(b"g").is_err());
+        assert!(decode_vlq(b"A*").is_err());
+        assert!(decode_vlq(b"gggggggggggggC").is_err());
+    }
+
+    #[test]
+    fn mappings() {
+        let mappings = parse_mappings("CACEC,A,CAAA;AAgBA").unwrap();
+        let fields: Vec<_> = mappings
+            .iter()
+            .map(|m| (m.offset, m.source, m.line, m.column, m.name))
+            .collect();
+        // only the first line, sorted by offset, with offsets relative to
+        // the previous segment
+        assert_eq!(
+            fields,
+            [(1, Some(0), 1, 2, Some(1)), (1, None, 0, 0, None), (2, Some(0), 1, 2, None)]
+        );
+        assert!(parse_mappings("CA").is_err());
+        assert!(parse_mappings("D").is_err());
+    }
+
+    #[test]
+    fn lookup() {
+        let map = SourceMap::parse(
+            r#"{"version": 3, "sourceRoot": "src/", "sources": ["a.c", "/abs/b.c"],
+                "names": ["main"], "mappings": "UAAAA,KACEA,KAAA,KCAA"}"#,
+        )
+        .unwrap();
+        assert_eq!(map.sources, ["src/a.c", "/abs/b.c"]);
+        assert_eq!(map.lookup(9), None);
+        let position = map.lookup(11).unwrap();
+        assert_eq!(position.to_string(), "src/a.c:1:1");
+        assert_eq!(position.name, Some("main"));
+        assert_eq!(map.lookup(15).unwrap().to_string(), "src/a.c:2:3");
+        assert_eq!(map.lookup(20).unwrap().to_string(), "src/a.c:2:3");
+        assert_eq!(map.lookup(26).unwrap().to_string(), "/abs/b.c:2:3");
+        assert!(SourceMap::parse(r#"{"version": 2, "mappings": ""}"#).is_err());
+        assert!(SourceMap::parse(r#"{"version": 3}"#).is_err());
+    }
+}
@@ -0,0 +1,343 @@
+//! Attributes the bytes of a module to its sections, functions and segments
+//! and counts how often each opcode, and each pair of adjacent opcodes, is
+//! used.
+
+use json::Value;
+use parser::{ParseError, Parser};
+use parser::import_export_section::ExternalKind;
+use parser::names::{NameKind, NameResolver};
+use parser::opcode::{opcode_from_byte, Opcode};
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct SectionSize {
+    pub id: u8,
+    pub name: String,
+    /// Payload size, without the section id and size fields.
+    pub size: u32,
+}
+
+#[derive(Debug)]
+pub struct FunctionSize {
+    /// Index in the function index space.
+    pub index: u32,
+    /// Name from the name section, the imports or exports, else e.g. `func[3]`.
+    pub name: String,
+    pub size: u32,
+    pub instructions: usize,
+}
+
+#[derive(Debug)]
+pub struct SegmentSize {
+    pub index: u32,
+    pub size: u32,
+}
+
+#[derive(Debug)]
+pub struct Stats {
+    pub file_size: u32,
+    /// Sections in the order they appear in the module.
+    pub sections: Vec<SectionSize>,
+    /// Function bodies, largest first.
+    pub functions: Vec<FunctionSize>,
+    pub data_segments: Vec<SegmentSize>,
+    /// Opcodes of all function bodies with their number of uses, most frequent
+    /// first.
+    pub opcodes: Vec<(Opcode, usize)>,
+    /// Opcodes directly followed by another in a function body, most frequent
+    /// first. These are the candidates for superinstructions.
+    pub pairs: Vec<((Opcode, Opcode), usize)>,
+}
+
+/// Number of opcode pairs the report lists.
+const REPORTED_PAIRS: usize = 20;
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    return part as f64 * 100.0 / total as f64;
+}
+
+impl Stats {
+    pub fn collect(data: &[u8]) -> Result<Stats, ParseError> {
+        let headers = Parser::new(data).parse_section_headers()?;
+        let module = Parser::new(data).parse()?;
+        let names = NameResolver::new(&module);
+
+        let sections = headers
+            .iter()
+            .map(|header| SectionSize {
+                id: header.id,
+                name: header.name.to_string(),
+                size: header.size,
+            })
+            .collect();
+
+        let imported = module
+            .import_entires
+            .iter()
+            .flat_map(|i| i.iter())
+            .filter(|entry| entry.kind == ExternalKind::Func)
+            .count() as u32;
+        let mut functions = Vec::new();
+        let mut counts = [0usize; 256];
+        let mut pair_counts: HashMap<(u8, u8), usize> = HashMap::new();
+        for (i, body) in module.function_bodies.iter().flat_map(|b| b.iter()).enumerate() {
+            let index = imported + i as u32;
+            let code = &body.body()?.code;
+            for op in code {
+                counts[op.opcode as usize] += 1;
+            }
+            for ops in code.windows(2) {
+                *pair_counts
+                    .entry((ops[0].opcode as u8, ops[1].opcode as u8))
+                    .or_insert(0) += 1;
+            }
+            functions.push(FunctionSize {
+                index,
+                name: names.display_name(NameKind::Function, index),
+                size: body.data.len() as u32,
+                instructions: code.len(),
+            });
+        }
+        functions.sort_by(|a, b| b.size.cmp(&a.size).then(a.index.cmp(&b.index)));
+
+        let data_segments = module
+            .data_entries
+            .iter()
+            .flat_map(|d| d.iter())
+            .enumerate()
+            .map(|(index, entry)| SegmentSize {
+                index: index as u32,
+                size: entry.data.len() as u32,
+            })
+            .collect();
+
+        let mut opcodes: Vec<(Opcode, usize)> = counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(byte, &count)| (opcode_from_byte(byte as u8).unwrap(), count))
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then((a.0 as u8).cmp(&(b.0 as u8))));
+
+        let mut pairs: Vec<((u8, u8), usize)> = pair_counts.into_iter().collect();
+        pairs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let pairs = pairs
+            .into_iter()
+            .map(|((first, second), count)| {
+                let first = opcode_from_byte(first).unwrap();
+                ((first, opcode_from_byte(second).unwrap()), count)
+            })
+            .collect();
+
+        return Ok(Stats {
+            file_size: data.len() as u32,
+            sections,
+            functions,
+            data_segments,
+            opcodes,
+            pairs,
+        });
+    }
+
+    pub fn total_instructions(&self) -> usize {
+        self.opcodes.iter().map(|&(_, count)| count).sum()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let sections = self
+            .sections
+            .iter()
+            .map(|s| {
+                Value::Object(vec![
+                    ("id".to_string(), s.id.into()),
+                    ("name".to_string(), s.name.clone().into()),
+                    ("custom".to_string(), (s.id == 0).into()),
+                    ("size".to_string(), s.size.into()),
+                ])
+            })
+            .collect();
+        let functions = self
+            .functions
+            .iter()
+            .map(|f| {
+                Value::Object(vec![
+                    ("index".to_string(), f.index.into()),
+                    ("name".to_string(), f.name.clone().into()),
+                    ("size".to_string(), f.size.into()),
+                    ("instructions".to_string(), f.instructions.into()),
+                ])
+            })
+            .collect();
+        let data_segments = self
+            .data_segments
+            .iter()
+            .map(|d| {
+                Value::Object(vec![
+                    ("index".to_string(), d.index.into()),
+                    ("size".to_string(), d.size.into()),
+                ])
+            })
+            .collect();
+        let opcodes = self
+            .opcodes
+            .iter()
+            .map(|&(opcode, count)| {
+                Value::Object(vec![
+                    ("opcode".to_string(), opcode.name().into()),
+                    ("count".to_string(), count.into()),
+                ])
+            })
+            .collect();
+        let pairs = self
+            .pairs
+            .iter()
+            .map(|&((first, second), count)| {
+                Value::Object(vec![
+                    ("first".to_string(), first.name().into()),
+                    ("second".to_string(), second.name().into()),
+                    ("count".to_string(), count.into()),
+                ])
+            })
+            .collect();
+        return Value::Object(vec![
+            ("file_size".to_string(), self.file_size.into()),
+            ("sections".to_string(), Value::Array(sections)),
+            ("functions".to_string(), Value::Array(functions)),
+            ("data_segments".to_string(), Value::Array(data_segments)),
+            ("instructions".to_string(), self.total_instructions().into()),
+            ("opcodes".to_string(), Value::Array(opcodes)),
+            ("pairs".to_string(), Value::Array(pairs)),
+        ]);
+    }
+}
+
+/// The human readable report.
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.file_size as u64;
+        writeln!(f, "File size: {} bytes", total)?;
+
+        writeln!(f, "\nSections:")?;
+        for section in self.sections.iter().filter(|s| s.id != 0) {
+            let share = percent(section.size as u64, total);
+            writeln!(f, "  {:<24} {:>10} {:>6.1}%", section.name, section.size, share)?;
+        }
+        let payloads: u64 = self.sections.iter().map(|s| s.size as u64).sum();
+        let overhead = total - payloads;
+        let share = percent(overhead, total);
+        writeln!(f, "  {:<24} {:>10} {:>6.1}%", "(headers)", overhead, share)?;
+
+        writeln!(f, "\nCustom sections:")?;
+        for section in self.sections.iter().filter(|s| s.id == 0) {
+            let share = percent(section.size as u64, total);
+            writeln!(f, "  {:<24} {:>10} {:>6.1}%", section.name, section.size, share)?;
+        }
+
+        writeln!(f, "\nFunctions by body size:")?;
+        for func in &self.functions {
+            let share = percent(func.size as u64, total);
+            writeln!(f, "  {:>10} {:>6.1}%  {}", func.size, share, func.name)?;
+        }
+
+        writeln!(f, "\nData segments:")?;
+        for segment in &self.data_segments {
+            let share = percent(segment.size as u64, total);
+            writeln!(f, "  {:>10} {:>6.1}%  data[{}]", segment.size, share, segment.index)?;
+        }
+
+        let instructions = self.total_instructions() as u64;
+        writeln!(f, "\nOpcodes ({} instructions):", instructions)?;
+        for &(opcode, count) in &self.opcodes {
+            let share = percent(count as u64, instructions);
+            writeln!(f, "  {:<24} {:>10} {:>6.1}%", opcode.name(), count, share)?;
+        }
+
+        let pairs: usize = self.pairs.iter().map(|&(_, count)| count).sum();
+        writeln!(f, "\nMost frequent opcode pairs ({} pairs):", pairs)?;
+        for &((first, second), count) in self.pairs.iter().take(REPORTED_PAIRS) {
+            let share = percent(count as u64, pairs as u64);
+            let pair = format!("{} {}", first.name(), second.name());
+            writeln!(f, "  {:<40} {:>10} {:>6.1}%", pair, count, share)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two functions `(i32.add (get_local 0) (i32.const 1))` and
+    /// `(i32.add (get_local 0) (get_local 0))`, a memory with one data segment
+    /// and a custom section "x".
+    const MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // types
+        0x03, 0x03, 0x02, 0x00, 0x00, // functions
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // exports
+        0x0a, 0x11, 0x02, 0x07, 0x00, 0x20, 0x00, 0x41, 0x01, 0x6a, 0x0b, 0x07, 0x00, 0x20, 0x00,
+        0x20, 0x00, 0x6a, 0x0b, // code
+        0x0b, 0x09, 0x01, 0x00, 0x41, 0x00, 0x0b, 0x03, b'a', b'b', b'c', // data
+        0x00, 0x04, 0x01, b'x', 0xff, 0xff, // custom
+    ];
+
+    #[test]
+    fn sections() {
+        let stats = Stats::collect(MODULE).unwrap();
+        assert_eq!(stats.file_size, MODULE.len() as u32);
+        let sections: Vec<(u8, &str, u32)> = stats
+            .sections
+            .iter()
+            .map(|s| (s.id, s.name.as_str(), s.size))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                (1, "type", 6),
+                (3, "function", 3),
+                (5, "memory", 3),
+                (7, "export", 5),
+                (10, "code", 17),
+                (11, "data", 9),
+                (0, "x", 4),
+            ]
+        );
+        let functions: Vec<(u32, &str, u32, usize)> = stats
+            .functions
+            .iter()
+            .map(|f| (f.index, f.name.as_str(), f.size, f.instructions))
+            .collect();
+        assert_eq!(functions, vec![(0, "f", 7, 3), (1, "func[1]", 7, 3)]);
+        assert_eq!(stats.data_segments.len(), 1);
+        assert_eq!(stats.data_segments[0].size, 3);
+    }
+
+    #[test]
+    fn opcodes() {
+        let stats = Stats::collect(MODULE).unwrap();
+        assert_eq!(stats.total_instructions(), 6);
+        assert_eq!(
+            stats.opcodes,
+            vec![
+                (Opcode::get_local, 3),
+                (Opcode::i32_add, 2),
+                (Opcode::i32_const, 1),
+            ]
+        );
+        assert_eq!(
+            stats.pairs,
+            vec![
+                ((Opcode::get_local, Opcode::get_local), 1),
+                ((Opcode::get_local, Opcode::i32_const), 1),
+                ((Opcode::get_local, Opcode::i32_add), 1),
+                ((Opcode::i32_const, Opcode::i32_add), 1),
+            ]
+        );
+    }
+}
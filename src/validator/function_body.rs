@@ -0,0 +1,438 @@
+use parser::{ParseResult, Type};
+use parser::code_section::FnBody;
+use parser::import_export_section::ExternalKindType;
+use parser::opcode::{Op, Opcode, Payload};
+use parser::type_section::FuncType;
+
+type CheckResult = Result<(), String>;
+
+/// The parts of a module's index spaces needed to type-check function bodies.
+#[derive(Debug, Default)]
+pub struct ModuleContext {
+    /// Type index of every function, imported functions first.
+    pub functions: Vec<u32>,
+    /// Content type and mutability of every global, imported globals first.
+    pub globals: Vec<(Type, bool)>,
+    pub memories: usize,
+    pub tables: usize,
+    pub imported_functions: usize,
+}
+
+impl ModuleContext {
+    pub fn new(res: &ParseResult) -> ModuleContext {
+        let mut ctx = ModuleContext::default();
+        if let Some(ref imports) = res.import_entires {
+            for entry in imports {
+                match entry.typ {
+                    ExternalKindType::Func(type_index) => ctx.functions.push(type_index),
+                    ExternalKindType::Table(..) => ctx.tables += 1,
+                    ExternalKindType::Memory(..) => ctx.memories += 1,
+                    ExternalKindType::Global(typ, mutability) => {
+                        ctx.globals.push((typ, mutability))
+                    }
+                }
+            }
+        }
+        ctx.imported_functions = ctx.functions.len();
+        if let Some(ref ids) = res.function_ids {
+            ctx.functions.extend(ids.iter().map(|id| id.0));
+        }
+        if let Some(ref globals) = res.global_variables {
+            ctx.globals.extend(
+                globals
+                    .iter()
+                    .map(|g| (g.typ.content_type, g.typ.mutability)),
+            );
+        }
+        ctx.memories += res.memory_types.as_ref().map_or(0, |m| m.len());
+        ctx.tables += res.table_entries.as_ref().map_or(0, |t| t.len());
+        return ctx;
+    }
+}
+
+/// Operand types of the numeric, comparison and conversion operators.
+fn numeric_signature(oc: Opcode) -> Option<(&'static [Type], Type)> {
+    use parser::Type::*;
+    let sig: (&'static [Type], Type) = match oc as u8 {
+        0x45 => (&[I32], I32),
+        0x46..=0x4f => (&[I32, I32], I32),
+        0x50 => (&[I64], I32),
+        0x51..=0x5a => (&[I64, I64], I32),
+        0x5b..=0x60 => (&[F32, F32], I32),
+        0x61..=0x66 => (&[F64, F64], I32),
+        0x67..=0x69 => (&[I32], I32),
+        0x6a..=0x78 => (&[I32, I32], I32),
+        0x79..=0x7b => (&[I64], I64),
+        0x7c..=0x8a => (&[I64, I64], I64),
+        0x8b..=0x91 => (&[F32], F32),
+        0x92..=0x98 => (&[F32, F32], F32),
+        0x99..=0x9f => (&[F64], F64),
+        0xa0..=0xa6 => (&[F64, F64], F64),
+        0xa7 => (&[I64], I32),
+        0xa8..=0xa9 => (&[F32], I32),
+        0xaa..=0xab => (&[F64], I32),
+        0xac..=0xad => (&[I32], I64),
+        0xae..=0xaf => (&[F32], I64),
+        0xb0..=0xb1 => (&[F64], I64),
+        0xb2..=0xb3 => (&[I32], F32),
+        0xb4..=0xb5 => (&[I64], F32),
+        0xb6 => (&[F64], F32),
+        0xb7..=0xb8 => (&[I32], F64),
+        0xb9..=0xba => (&[I64], F64),
+        0xbb => (&[F32], F64),
+        0xbc => (&[F32], I32),
+        0xbd => (&[F64], I64),
+        0xbe => (&[I32], F32),
+        0xbf => (&[I64], F64),
+        _ => return None,
+    };
+    Some(sig)
+}
+
+/// Value type and natural alignment (log2 of the access size) of loads and stores.
+fn memory_access(oc: Opcode) -> (Type, u32) {
+    match oc as u8 {
+        0x28 | 0x36 => (Type::I32, 2),
+        0x29 | 0x37 => (Type::I64, 3),
+        0x2a | 0x38 => (Type::F32, 2),
+        0x2b | 0x39 => (Type::F64, 3),
+        0x2c | 0x2d | 0x3a => (Type::I32, 0),
+        0x2e | 0x2f | 0x3b => (Type::I32, 1),
+        0x30 | 0x31 | 0x3c => (Type::I64, 0),
+        0x32 | 0x33 | 0x3d => (Type::I64, 1),
+        0x34 | 0x35 | 0x3e => (Type::I64, 2),
+        _ => unreachable!(),
+    }
+}
+
+fn block_result(payload: &Payload) -> Option<Type> {
+    match *payload {
+        Payload::BlockType(Type::empty_block) => None,
+        Payload::BlockType(typ) => Some(typ),
+        _ => None,
+    }
+}
+
+fn vu32(payload: &Payload) -> u32 {
+    match *payload {
+        Payload::VU32(v) => v,
+        _ => unreachable!(),
+    }
+}
+
+struct Frame {
+    opcode: Opcode,
+    result: Option<Type>,
+    height: usize,
+    unreachable: bool,
+}
+
+struct BodyChecker<'r> {
+    types: &'r [FuncType],
+    ctx: &'r ModuleContext,
+    // (end index, type) for each run of locals, params first
+    locals: Vec<(u64, Type)>,
+    return_type: Option<Type>,
+    operands: Vec<Option<Type>>,
+    frames: Vec<Frame>,
+}
+
+impl<'r> BodyChecker<'r> {
+    fn func_type(&self, type_index: u32) -> Result<&'r FuncType, String> {
+        self.types
+            .get(type_index as usize)
+            .ok_or_else(|| format!("unknown type {}", type_index))
+    }
+
+    fn local_type(&self, index: u32) -> Result<Type, String> {
+        self.locals
+            .iter()
+            .find(|&&(end, _)| (index as u64) < end)
+            .map(|&(_, typ)| typ)
+            .ok_or_else(|| format!("unknown local {}", index))
+    }
+
+    fn push(&mut self, typ: Option<Type>) {
+        self.operands.push(typ);
+    }
+
+    fn pop(&mut self) -> Result<Option<Type>, String> {
+        let frame = self.frames.last().unwrap();
+        if self.operands.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+            return Err("operand stack underflow".to_string());
+        }
+        Ok(self.operands.pop().unwrap())
+    }
+
+    fn pop_expect(&mut self, expected: Type) -> CheckResult {
+        match self.pop()? {
+            Some(actual) if actual != expected => Err(format!(
+                "type mismatch: expected {:?}, found {:?}",
+                expected, actual
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.operands.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn push_frame(&mut self, opcode: Opcode, result: Option<Type>) {
+        self.frames.push(Frame {
+            opcode,
+            result,
+            height: self.operands.len(),
+            unreachable: false,
+        });
+    }
+
+    fn pop_frame(&mut self) -> Result<Frame, String> {
+        if let Some(typ) = self.frames.last().unwrap().result {
+            self.pop_expect(typ)?;
+        }
+        if self.operands.len() != self.frames.last().unwrap().height {
+            return Err("values remaining on stack at end of block".to_string());
+        }
+        Ok(self.frames.pop().unwrap())
+    }
+
+    fn label_type(&self, depth: u32) -> Result<Option<Type>, String> {
+        let frame = (depth as usize)
+            .checked_add(1)
+            .and_then(|d| self.frames.len().checked_sub(d))
+            .map(|i| &self.frames[i])
+            .ok_or_else(|| format!("unknown label {}", depth))?;
+        Ok(if frame.opcode == Opcode::loop_ {
+            None
+        } else {
+            frame.result
+        })
+    }
+
+    fn check_memory(&self) -> CheckResult {
+        if self.ctx.memories == 0 {
+            return Err("memory instruction without memory".to_string());
+        }
+        Ok(())
+    }
+
+    fn check_op(&mut self, op: &Op) -> CheckResult {
+        match op.opcode {
+            Opcode::unreachable => self.set_unreachable(),
+            Opcode::nop => {}
+            Opcode::block | Opcode::loop_ => self.push_frame(op.opcode, block_result(&op.payload)),
+            Opcode::if_ => {
+                self.pop_expect(Type::I32)?;
+                self.push_frame(op.opcode, block_result(&op.payload));
+            }
+            Opcode::else_ => {
+                if self.frames.last().unwrap().opcode != Opcode::if_ {
+                    return Err("else without matching if".to_string());
+                }
+                let frame = self.pop_frame()?;
+                self.push_frame(Opcode::else_, frame.result);
+            }
+            Opcode::end => {
+                if self.frames.len() == 1 {
+                    return Err("end without matching block".to_string());
+                }
+                let frame = self.pop_frame()?;
+                if frame.opcode == Opcode::if_ && frame.result.is_some() {
+                    return Err("if with a result requires an else branch".to_string());
+                }
+                if let Some(typ) = frame.result {
+                    self.push(Some(typ));
+                }
+            }
+            Opcode::br => {
+                if let Some(typ) = self.label_type(vu32(&op.payload))? {
+                    self.pop_expect(typ)?;
+                }
+                self.set_unreachable();
+            }
+            Opcode::br_if => {
+                self.pop_expect(Type::I32)?;
+                if let Some(typ) = self.label_type(vu32(&op.payload))? {
+                    self.pop_expect(typ)?;
+                    self.push(Some(typ));
+                }
+            }
+            Opcode::br_table => {
+                self.pop_expect(Type::I32)?;
+                if let Payload::BrTable {
+                    ref target_table,
+                    default_target,
+                } = op.payload
+                {
+                    let typ = self.label_type(default_target)?;
+                    for &target in target_table {
+                        if self.label_type(target)? != typ {
+                            return Err("br_table targets have different types".to_string());
+                        }
+                    }
+                    if let Some(typ) = typ {
+                        self.pop_expect(typ)?;
+                    }
+                }
+                self.set_unreachable();
+            }
+            Opcode::return_ => {
+                if let Some(typ) = self.return_type {
+                    self.pop_expect(typ)?;
+                }
+                self.set_unreachable();
+            }
+            Opcode::call_ | Opcode::call_indirect => {
+                let type_index = if op.opcode == Opcode::call_ {
+                    let fn_index = vu32(&op.payload);
+                    *self.ctx
+                        .functions
+                        .get(fn_index as usize)
+                        .ok_or_else(|| format!("unknown function {}", fn_index))?
+                } else {
+                    if self.ctx.tables == 0 {
+                        return Err("call_indirect without table".to_string());
+                    }
+                    self.pop_expect(Type::I32)?;
+                    vu32(&op.payload)
+                };
+                let func_type = self.func_type(type_index)?;
+                for &param in func_type.param_types.iter().rev() {
+                    self.pop_expect(param)?;
+                }
+                if let Some(typ) = func_type.return_type {
+                    self.push(Some(typ));
+                }
+            }
+            Opcode::drop => {
+                self.pop()?;
+            }
+            Opcode::select => {
+                self.pop_expect(Type::I32)?;
+                let first = self.pop()?;
+                let second = self.pop()?;
+                match (first, second) {
+                    (Some(a), Some(b)) if a != b => {
+                        return Err("select operands have different types".to_string())
+                    }
+                    _ => self.push(first.or(second)),
+                }
+            }
+            Opcode::get_local => {
+                let typ = self.local_type(vu32(&op.payload))?;
+                self.push(Some(typ));
+            }
+            Opcode::set_local => {
+                let typ = self.local_type(vu32(&op.payload))?;
+                self.pop_expect(typ)?;
+            }
+            Opcode::tee_local => {
+                let typ = self.local_type(vu32(&op.payload))?;
+                self.pop_expect(typ)?;
+                self.push(Some(typ));
+            }
+            Opcode::get_global | Opcode::set_global => {
+                let index = vu32(&op.payload);
+                let (typ, mutability) = *self.ctx
+                    .globals
+                    .get(index as usize)
+                    .ok_or_else(|| format!("unknown global {}", index))?;
+                if op.opcode == Opcode::get_global {
+                    self.push(Some(typ));
+                } else if !mutability {
+                    return Err(format!("global {} is immutable", index));
+                } else {
+                    self.pop_expect(typ)?;
+                }
+            }
+            Opcode::current_memory => {
+                self.check_memory()?;
+                self.push(Some(Type::I32));
+            }
+            Opcode::grow_memory => {
+                self.check_memory()?;
+                self.pop_expect(Type::I32)?;
+                self.push(Some(Type::I32));
+            }
+            Opcode::i32_const => self.push(Some(Type::I32)),
+            Opcode::i64_const => self.push(Some(Type::I64)),
+            Opcode::f32_const => self.push(Some(Type::F32)),
+            Opcode::f64_const => self.push(Some(Type::F64)),
+            oc => match oc as u8 {
+                0x28..=0x3e => {
+                    self.check_memory()?;
+                    let (typ, natural_alignment) = memory_access(oc);
+                    if let Payload::MemoryImmediate { flags, .. } = op.payload {
+                        if flags > natural_alignment {
+                            return Err("alignment larger than natural".to_string());
+                        }
+                    }
+                    if (oc as u8) < 0x36 {
+                        self.pop_expect(Type::I32)?;
+                        self.push(Some(typ));
+                    } else {
+                        self.pop_expect(typ)?;
+                        self.pop_expect(Type::I32)?;
+                    }
+                }
+                _ => {
+                    let (params, result) = numeric_signature(oc).unwrap();
+                    for &param in params.iter().rev() {
+                        self.pop_expect(param)?;
+                    }
+                    self.push(Some(result));
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Type-checks the decoded body of the function with the given type index.
+pub fn check_function_body(
+    types: &[FuncType],
+    ctx: &ModuleContext,
+    type_index: u32,
+    body: &FnBody,
+) -> CheckResult {
+    let func_type = types
+        .get(type_index as usize)
+        .ok_or_else(|| format!("unknown type {}", type_index))?;
+    let mut locals = Vec::new();
+    let mut end = 0u64;
+    for &param in &func_type.param_types {
+        end += 1;
+        locals.push((end, param));
+    }
+    for local in &body.locals {
+        end += local.count as u64;
+        locals.push((end, local.typ));
+    }
+    if end > u32::MAX as u64 {
+        return Err("too many locals".to_string());
+    }
+    let mut checker = BodyChecker {
+        types,
+        ctx,
+        locals,
+        return_type: func_type.return_type,
+        operands: Vec::new(),
+        frames: Vec::new(),
+    };
+    checker.push_frame(Opcode::block, func_type.return_type);
+    for (i, op) in body.code.iter().enumerate() {
+        checker
+            .check_op(op)
+            .map_err(|msg| format!("instruction {} ({:?}): {}", i, op.opcode, msg))?;
+    }
+    checker
+        .pop_frame()
+        .map_err(|msg| format!("function end: {}", msg))?;
+    Ok(())
+}
@@ -1,7 +1,17 @@
+pub mod function_body;
+
 use parser::{ParseResult, ResizableLimits};
+use parser::code_section::FnBody;
 use parser::memory_section::MemoryType;
 use parser::table_section::TableEntry;
 use parser::global_section::{GlobalType, GlobalVariable};
+use parser::target_features_section::{FeaturePrefix, TargetFeature};
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use self::function_body::{check_function_body, ModuleContext};
 
 pub trait Validate {
     fn is_valid(&self) -> bool;
@@ -12,7 +22,7 @@ macro_rules! validate {
         if ! $expr.is_valid() {
             return false;
         } else {
-            println!("{:?} is valid!", $expr);
+            trace!("{:?} is valid!", $expr);
         }
     )
 }
@@ -80,20 +90,201 @@ impl Validate for GlobalVariable {
     }
 }
 
+/// Features beyond the MVP that modules may declare in `target_features`.
+pub const SUPPORTED_FEATURES: &[&str] = &["mutable-globals"];
+
+impl<'a> Validate for TargetFeature<'a> {
+    fn is_valid(&self) -> bool {
+        if self.prefix == FeaturePrefix::Disallowed || SUPPORTED_FEATURES.contains(&self.name) {
+            return true;
+        }
+        trace!("Unsupported feature: {}", self.name);
+        return false;
+    }
+}
+
 // validator struct
 
-pub struct Validator {
-    parse_result: ParseResult,
+/// Controls when function bodies are decoded and type-checked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BodyValidation {
+    /// Every body is decoded and checked by `validate()`.
+    Strict,
+    /// Like `Strict`, but the bodies are decoded and checked by the given
+    /// number of worker threads (0 picks one per available core). The outcome
+    /// is the same as with `Strict`.
+    Parallel(usize),
+    /// A body is decoded and checked the first time it is requested through
+    /// `function_body()`.
+    Lazy,
+}
+
+pub struct Validator<'a> {
+    parse_result: ParseResult<'a>,
+    mode: BodyValidation,
+    context: ModuleContext,
+    checked_bodies: Vec<OnceLock<Result<(), String>>>,
 }
 
-impl Validator {
-    pub fn new(res: ParseResult) -> Validator {
-        Validator { parse_result: res }
+impl<'a> Validator<'a> {
+    pub fn new(res: ParseResult<'a>) -> Validator<'a> {
+        Validator::with_mode(res, BodyValidation::Strict)
+    }
+    pub fn with_mode(res: ParseResult<'a>, mode: BodyValidation) -> Validator<'a> {
+        let context = ModuleContext::new(&res);
+        let body_count = res.function_bodies.as_ref().map_or(0, |b| b.len());
+        Validator {
+            parse_result: res,
+            mode,
+            context,
+            checked_bodies: (0..body_count).map(|_| OnceLock::new()).collect(),
+        }
+    }
+    pub fn parse_result(&self) -> &ParseResult<'a> {
+        &self.parse_result
     }
     pub fn validate(&self) -> bool {
+        validate!(self.parse_result.target_features);
         validate!(self.parse_result.memory_types);
         validate!(self.parse_result.table_entries);
         validate!(self.parse_result.global_variables);
+        match self.mode {
+            BodyValidation::Strict => {}
+            BodyValidation::Parallel(threads) => self.check_bodies_parallel(threads),
+            BodyValidation::Lazy => return true,
+        }
+        for index in 0..self.checked_bodies.len() {
+            if !self.is_body_valid(index) {
+                return false;
+            }
+        }
         return true;
     }
+
+    /// Returns the decoded body of the function with the given index (in the
+    /// function index space, i.e. counting imports), or `None` if it is
+    /// imported, does not exist or fails validation.
+    pub fn function_body(&self, fn_index: u32) -> Option<&FnBody> {
+        let index = (fn_index as usize).checked_sub(self.context.imported_functions)?;
+        let bodies = self.parse_result.function_bodies.as_ref()?;
+        if index < bodies.len() && self.is_body_valid(index) {
+            bodies[index].body().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Decodes and checks all bodies on worker threads, filling the caches
+    /// that `is_body_valid` reads from.
+    fn check_bodies_parallel(&self, threads: usize) {
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let next = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..threads.min(self.checked_bodies.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= self.checked_bodies.len() {
+                        break;
+                    }
+                    self.checked_bodies[index].get_or_init(|| self.check_body(index));
+                });
+            }
+        });
+    }
+
+    fn is_body_valid(&self, index: usize) -> bool {
+        match *self.checked_bodies[index].get_or_init(|| self.check_body(index)) {
+            Ok(()) => true,
+            Err(ref msg) => {
+                let fn_index = self.context.imported_functions + index;
+                trace!("Function {} is invalid: {}", fn_index, msg);
+                false
+            }
+        }
+    }
+
+    fn check_body(&self, index: usize) -> Result<(), String> {
+        let fn_index = self.context.imported_functions + index;
+        let type_index = match self.context.functions.get(fn_index) {
+            Some(&type_index) => type_index,
+            None => return Err("body has no declaration in the function section".to_string()),
+        };
+        let types = self.parse_result
+            .function_types
+            .as_ref()
+            .map_or(&[][..], |t| &t[..]);
+        let body = self.parse_result.function_bodies.as_ref().unwrap()[index]
+            .body()
+            .map_err(|err| format!("malformed body: {}", err))?;
+        check_function_body(types, &self.context, type_index, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+
+    use std::fs;
+
+    /// Whether the module validates, and which of its bodies are valid.
+    fn outcome(wasm: &[u8], mode: BodyValidation) -> (bool, Vec<bool>) {
+        let module = Parser::new(wasm).parse().expect("module parses");
+        let validator = Validator::with_mode(module, mode);
+        let valid = validator.validate();
+        let count = validator.context.imported_functions + validator.checked_bodies.len();
+        let bodies = (0..count as u32).map(|i| validator.function_body(i).is_some()).collect();
+        return (valid, bodies);
+    }
+
+    #[test]
+    fn parallel_matches_sequential() {
+        let mut modules = Vec::new();
+        for entry in fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "wasm") {
+                modules.push(fs::read(path).unwrap());
+            }
+        }
+        // three `() -> ()` functions, the second leaves an i32 on the stack
+        modules.push(vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // preamble
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types
+            0x03, 0x04, 0x03, 0x00, 0x00, 0x00, // functions
+            0x0a, 0x0c, 0x03, 0x02, 0x00, 0x0b, 0x04, 0x00, 0x41, 0x00, 0x0b, 0x02, 0x00,
+            0x0b, // code
+        ]);
+        for wasm in &modules {
+            let sequential = outcome(wasm, BodyValidation::Strict);
+            for &threads in &[0, 1, 2, 8] {
+                assert_eq!(outcome(wasm, BodyValidation::Parallel(threads)), sequential);
+            }
+        }
+        let invalid = outcome(modules.last().unwrap(), BodyValidation::Strict);
+        assert_eq!(invalid, (false, vec![true, false, true]));
+    }
+
+    #[test]
+    fn target_features() {
+        let valid = |prefix: u8, name: &str| {
+            let mut section = vec![0x0f];
+            section.extend_from_slice(b"target_features");
+            section.extend_from_slice(&[0x01, prefix, name.len() as u8]);
+            section.extend_from_slice(name.as_bytes());
+            let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00];
+            wasm.push(section.len() as u8);
+            wasm.extend(section);
+            let module = Parser::new(&wasm).parse().expect("module parses");
+            return Validator::new(module).validate();
+        };
+        assert!(valid(b'+', "mutable-globals"));
+        assert!(valid(b'=', "mutable-globals"));
+        assert!(!valid(b'+', "simd128"));
+        assert!(!valid(b'=', "simd128"));
+        // a feature the module must not be used with is not needed to run it
+        assert!(valid(b'-', "simd128"));
+    }
 }
@@ -0,0 +1,109 @@
+//! The descriptor table.
+
+use wasi::filesystem::{File, FileSystem};
+use wasi::types::{errno, filetype, Errno};
+
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+pub enum Kind {
+    Input(Box<dyn Read>),
+    Output(Box<dyn Write>),
+    File { file: Box<dyn File>, append: bool },
+    /// A directory `path` of a filesystem.
+    Dir {
+        fs: Rc<dyn FileSystem>,
+        path: PathBuf,
+    },
+}
+
+pub struct Descriptor {
+    pub kind: Kind,
+    /// Guest path of a preopened directory, reported by `fd_prestat_get`.
+    pub preopen: Option<String>,
+}
+
+impl Descriptor {
+    pub fn new(kind: Kind) -> Descriptor {
+        Descriptor {
+            kind,
+            preopen: None,
+        }
+    }
+
+    pub fn filetype(&self) -> u8 {
+        match self.kind {
+            Kind::Input(_) | Kind::Output(_) => filetype::CHARACTER_DEVICE,
+            Kind::File { .. } => filetype::REGULAR_FILE,
+            Kind::Dir { .. } => filetype::DIRECTORY,
+        }
+    }
+
+    /// The filesystem of this directory and `path` relative to it. `..` may
+    /// not leave the root of the filesystem.
+    pub fn resolve(&self, path: &str) -> Result<(Rc<dyn FileSystem>, PathBuf), Errno> {
+        let (fs, dir) = match self.kind {
+            Kind::Dir { ref fs, ref path } => (fs, path),
+            _ => return Err(errno::NOTDIR),
+        };
+        let mut resolved = dir.clone();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if !resolved.pop() {
+                        return Err(errno::NOTCAPABLE);
+                    }
+                }
+                Component::RootDir | Component::Prefix(_) => return Err(errno::NOTCAPABLE),
+            }
+        }
+        Ok((fs.clone(), resolved))
+    }
+}
+
+/// Open descriptors by number. New descriptors take the lowest free number.
+#[derive(Default)]
+pub struct FdTable {
+    entries: Vec<Option<Descriptor>>,
+}
+
+impl FdTable {
+    pub fn insert(&mut self, descriptor: Descriptor) -> u32 {
+        match self.entries.iter().position(|e| e.is_none()) {
+            Some(fd) => {
+                self.entries[fd] = Some(descriptor);
+                fd as u32
+            }
+            None => {
+                self.entries.push(Some(descriptor));
+                (self.entries.len() - 1) as u32
+            }
+        }
+    }
+
+    /// Puts `descriptor` at `fd`, replacing what was there.
+    pub fn set(&mut self, fd: u32, descriptor: Descriptor) {
+        let fd = fd as usize;
+        if self.entries.len() <= fd {
+            self.entries.resize_with(fd + 1, || None);
+        }
+        self.entries[fd] = Some(descriptor);
+    }
+
+    pub fn get(&mut self, fd: u32) -> Result<&mut Descriptor, Errno> {
+        match self.entries.get_mut(fd as usize) {
+            Some(Some(descriptor)) => Ok(descriptor),
+            _ => Err(errno::BADF),
+        }
+    }
+
+    pub fn remove(&mut self, fd: u32) -> Result<Descriptor, Errno> {
+        match self.entries.get_mut(fd as usize) {
+            Some(entry) if entry.is_some() => Ok(entry.take().unwrap()),
+            _ => Err(errno::BADF),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! The interface between the WASI functions and a filesystem.
+//!
+//! Paths passed to a [`FileSystem`] are relative to its root and contain only
+//! normal components; descriptors resolve `.` and `..` before.
+
+use wasi::types::Errno;
+
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+pub type FsResult<T> = Result<T, Errno>;
+
+/// The fields of a WASI `filestat`. Times are nanoseconds since the epoch.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub dev: u64,
+    pub ino: u64,
+    pub filetype: u8,
+    pub nlink: u64,
+    pub size: u64,
+    pub atim: u64,
+    pub mtim: u64,
+    pub ctim: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirEntry {
+    pub name: String,
+    pub filetype: u8,
+    pub ino: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OpenOptions {
+    pub read: bool,
+    pub write: bool,
+    pub create: bool,
+    /// Fail if the file exists.
+    pub exclusive: bool,
+    pub truncate: bool,
+    /// Resolve a symbolic link at the end of the path.
+    pub follow: bool,
+}
+
+/// An open regular file.
+pub trait File: Read + Write + Seek {
+    fn metadata(&self) -> FsResult<Metadata>;
+    fn set_len(&mut self, len: u64) -> FsResult<()>;
+    fn sync(&mut self) -> FsResult<()>;
+    /// Sets the access and modification times that are given.
+    fn set_times(&mut self, atim: Option<u64>, mtim: Option<u64>) -> FsResult<()>;
+}
+
+/// A tree of directories and files that preopened directories refer to.
+pub trait FileSystem {
+    fn metadata(&self, path: &Path, follow: bool) -> FsResult<Metadata>;
+    fn open(&self, path: &Path, options: &OpenOptions) -> FsResult<Box<dyn File>>;
+    /// The entries of a directory, without `.` and `..`, sorted by name.
+    fn read_dir(&self, path: &Path) -> FsResult<Vec<DirEntry>>;
+    fn create_dir(&self, path: &Path) -> FsResult<()>;
+    fn remove_dir(&self, path: &Path) -> FsResult<()>;
+    fn remove_file(&self, path: &Path) -> FsResult<()>;
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()>;
+    fn hard_link(&self, from: &Path, to: &Path) -> FsResult<()>;
+    fn read_link(&self, path: &Path) -> FsResult<String>;
+    fn set_times(&self, path: &Path, atim: Option<u64>, mtim: Option<u64>) -> FsResult<()>;
+}
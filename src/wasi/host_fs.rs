@@ -0,0 +1,237 @@
+//! A directory of the host filesystem.
+
+use wasi::filesystem::{DirEntry, File, FileSystem, FsResult, Metadata, OpenOptions};
+use wasi::types::{errno, filetype, Errno};
+
+use std::fs;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub fn io_errno(err: io::Error) -> Errno {
+    match err.kind() {
+        ErrorKind::NotFound => errno::NOENT,
+        ErrorKind::PermissionDenied => errno::ACCES,
+        ErrorKind::AlreadyExists => errno::EXIST,
+        ErrorKind::InvalidInput => errno::INVAL,
+        ErrorKind::NotADirectory => errno::NOTDIR,
+        ErrorKind::IsADirectory => errno::ISDIR,
+        ErrorKind::DirectoryNotEmpty => errno::NOTEMPTY,
+        ErrorKind::NotSeekable => errno::SPIPE,
+        ErrorKind::Unsupported => errno::NOTSUP,
+        ErrorKind::CrossesDevices => errno::XDEV,
+        _ => errno::IO,
+    }
+}
+
+pub fn nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink())
+}
+
+fn file_type(typ: fs::FileType) -> u8 {
+    if typ.is_dir() {
+        filetype::DIRECTORY
+    } else if typ.is_file() {
+        filetype::REGULAR_FILE
+    } else if typ.is_symlink() {
+        filetype::SYMBOLIC_LINK
+    } else {
+        filetype::UNKNOWN
+    }
+}
+
+#[cfg(unix)]
+fn inode(meta: &fs::Metadata) -> (u64, u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino(), meta.nlink())
+}
+
+#[cfg(not(unix))]
+fn inode(_: &fs::Metadata) -> (u64, u64, u64) {
+    (0, 0, 1)
+}
+
+fn metadata(meta: &fs::Metadata) -> Metadata {
+    let (dev, ino, nlink) = inode(meta);
+    let time = |t: io::Result<SystemTime>| t.map_or(0, nanos);
+    Metadata {
+        dev,
+        ino,
+        filetype: file_type(meta.file_type()),
+        nlink,
+        size: meta.len(),
+        atim: time(meta.accessed()),
+        mtim: time(meta.modified()),
+        ctim: time(meta.modified()),
+    }
+}
+
+fn set_times(file: &fs::File, atim: Option<u64>, mtim: Option<u64>) -> FsResult<()> {
+    let mut times = fs::FileTimes::new();
+    if let Some(atim) = atim {
+        times = times.set_accessed(UNIX_EPOCH + Duration::from_nanos(atim));
+    }
+    if let Some(mtim) = mtim {
+        times = times.set_modified(UNIX_EPOCH + Duration::from_nanos(mtim));
+    }
+    file.set_times(times).map_err(io_errno)
+}
+
+/// The files below a host directory. `..` and symbolic links may not lead
+/// out of it.
+pub struct HostFs {
+    root: PathBuf,
+}
+
+impl HostFs {
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<HostFs> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(ErrorKind::NotADirectory, "not a directory"));
+        }
+        Ok(HostFs { root })
+    }
+
+    /// The host path of `path`. With `follow` a symbolic link at the end of
+    /// the path is resolved as well.
+    fn resolve(&self, path: &Path, follow: bool) -> FsResult<PathBuf> {
+        let mut resolved = self.root.join(path);
+        if resolved != self.root {
+            let name = resolved.file_name().expect("normal component").to_owned();
+            let parent = fs::canonicalize(resolved.parent().expect("below root"))
+                .map_err(io_errno)?;
+            if !parent.starts_with(&self.root) {
+                return Err(errno::NOTCAPABLE);
+            }
+            resolved = parent.join(name);
+        }
+        if follow && is_symlink(&resolved) {
+            let target = fs::canonicalize(&resolved).map_err(io_errno)?;
+            if !target.starts_with(&self.root) {
+                return Err(errno::NOTCAPABLE);
+            }
+            resolved = target;
+        }
+        Ok(resolved)
+    }
+}
+
+impl FileSystem for HostFs {
+    fn metadata(&self, path: &Path, follow: bool) -> FsResult<Metadata> {
+        let meta = fs::symlink_metadata(self.resolve(path, follow)?).map_err(io_errno)?;
+        Ok(metadata(&meta))
+    }
+
+    fn open(&self, path: &Path, options: &OpenOptions) -> FsResult<Box<dyn File>> {
+        let path = self.resolve(path, options.follow)?;
+        if is_symlink(&path) {
+            // A link that is not to be followed.
+            return Err(errno::LOOP);
+        }
+        let file = fs::OpenOptions::new()
+            .read(options.read)
+            .write(options.write)
+            .create(options.create)
+            .create_new(options.create && options.exclusive)
+            .truncate(options.truncate)
+            .open(path)
+            .map_err(io_errno)?;
+        Ok(Box::new(HostFile(file)))
+    }
+
+    fn read_dir(&self, path: &Path) -> FsResult<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.resolve(path, true)?).map_err(io_errno)? {
+            let entry = entry.map_err(io_errno)?;
+            let meta = entry.metadata().map_err(io_errno)?;
+            entries.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                filetype: file_type(meta.file_type()),
+                ino: inode(&meta).1,
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn create_dir(&self, path: &Path) -> FsResult<()> {
+        fs::create_dir(self.resolve(path, false)?).map_err(io_errno)
+    }
+
+    fn remove_dir(&self, path: &Path) -> FsResult<()> {
+        fs::remove_dir(self.resolve(path, false)?).map_err(io_errno)
+    }
+
+    fn remove_file(&self, path: &Path) -> FsResult<()> {
+        let path = self.resolve(path, false)?;
+        if fs::symlink_metadata(&path).map_err(io_errno)?.is_dir() {
+            return Err(errno::ISDIR);
+        }
+        fs::remove_file(path).map_err(io_errno)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+        fs::rename(self.resolve(from, false)?, self.resolve(to, false)?).map_err(io_errno)
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> FsResult<()> {
+        fs::hard_link(self.resolve(from, false)?, self.resolve(to, false)?).map_err(io_errno)
+    }
+
+    fn read_link(&self, path: &Path) -> FsResult<String> {
+        let target = fs::read_link(self.resolve(path, false)?).map_err(io_errno)?;
+        Ok(target.to_string_lossy().into_owned())
+    }
+
+    fn set_times(&self, path: &Path, atim: Option<u64>, mtim: Option<u64>) -> FsResult<()> {
+        let file = fs::File::open(self.resolve(path, true)?).map_err(io_errno)?;
+        set_times(&file, atim, mtim)
+    }
+}
+
+pub struct HostFile(fs::File);
+
+impl Read for HostFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for HostFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Seek for HostFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl File for HostFile {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(metadata(&self.0.metadata().map_err(io_errno)?))
+    }
+
+    fn set_len(&mut self, len: u64) -> FsResult<()> {
+        self.0.set_len(len).map_err(io_errno)
+    }
+
+    fn sync(&mut self) -> FsResult<()> {
+        self.0.sync_all().map_err(io_errno)
+    }
+
+    fn set_times(&mut self, atim: Option<u64>, mtim: Option<u64>) -> FsResult<()> {
+        set_times(&self.0, atim, mtim)
+    }
+}
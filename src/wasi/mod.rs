@@ -0,0 +1,943 @@
+//! A host implementation of `wasi_snapshot_preview1`.
+//!
+//! Programs see the arguments and environment given to [`Wasi`], standard
+//! streams that default to those of the host process, and the preopened
+//! directories. Paths are resolved inside the preopened directory they are
+//! relative to; `..` and symbolic links may not leave it.
+
+pub mod filesystem;
+pub mod types;
+mod fd;
+mod host_fs;
+mod virtual_fs;
+
+pub use wasi::virtual_fs::VirtualFs;
+
+use interpreter::{HostFunc, Imports, Trap, Value};
+use interpreter::memory::Memory;
+use parser::Type;
+use wasi::fd::{Descriptor, FdTable, Kind};
+use wasi::filesystem::{DirEntry, File, FileSystem, Metadata, OpenOptions};
+use wasi::host_fs::{io_errno, HostFs};
+use wasi::types::{clock, errno, fdflags, filetype, oflags, rights, whence, Errno};
+use wasi::types::{DIRENT_SIZE, FDSTAT_SIZE, FILESTAT_SIZE};
+
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+pub const MODULE: &str = "wasi_snapshot_preview1";
+
+const LOOKUP_SYMLINK_FOLLOW: u32 = 1;
+
+const FSTFLAGS_ATIM: u32 = 1;
+const FSTFLAGS_ATIM_NOW: u32 = 2;
+const FSTFLAGS_MTIM: u32 = 4;
+const FSTFLAGS_MTIM_NOW: u32 = 8;
+
+const EVENTTYPE_CLOCK: u8 = 0;
+const SUBSCRIPTION_SIZE: u32 = 48;
+const EVENT_SIZE: u32 = 32;
+const SUBSCRIPTION_CLOCK_ABSTIME: u16 = 1;
+
+/// The state of a WASI program.
+pub struct Wasi {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    fds: FdTable,
+    start: Instant,
+    random: u64,
+}
+
+impl Default for Wasi {
+    fn default() -> Wasi {
+        Wasi::new()
+    }
+}
+
+impl Wasi {
+    /// A program without arguments, environment or preopened directories,
+    /// connected to the standard streams of the host.
+    pub fn new() -> Wasi {
+        let mut fds = FdTable::default();
+        fds.insert(Descriptor::new(Kind::Input(Box::new(io::stdin()))));
+        fds.insert(Descriptor::new(Kind::Output(Box::new(io::stdout()))));
+        fds.insert(Descriptor::new(Kind::Output(Box::new(io::stderr()))));
+        let seed = RandomState::new().build_hasher().finish();
+        Wasi {
+            args: Vec::new(),
+            env: Vec::new(),
+            fds,
+            start: Instant::now(),
+            random: seed | 1,
+        }
+    }
+
+    /// Appends a command line argument. The first one is the program name.
+    pub fn arg(&mut self, arg: &str) -> &mut Wasi {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Wasi {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn stdin(&mut self, input: Box<dyn Read>) -> &mut Wasi {
+        self.fds.set(0, Descriptor::new(Kind::Input(input)));
+        self
+    }
+
+    pub fn stdout(&mut self, output: Box<dyn Write>) -> &mut Wasi {
+        self.fds.set(1, Descriptor::new(Kind::Output(output)));
+        self
+    }
+
+    pub fn stderr(&mut self, output: Box<dyn Write>) -> &mut Wasi {
+        self.fds.set(2, Descriptor::new(Kind::Output(output)));
+        self
+    }
+
+    /// Makes the root of `fs` available to the program as `guest`.
+    pub fn preopen<F: FileSystem + 'static>(&mut self, fs: F, guest: &str) -> &mut Wasi {
+        let mut descriptor = Descriptor::new(Kind::Dir {
+            fs: Rc::new(fs),
+            path: PathBuf::new(),
+        });
+        descriptor.preopen = Some(guest.to_string());
+        self.fds.insert(descriptor);
+        self
+    }
+
+    /// Makes the host directory `host` available to the program as `guest`.
+    pub fn preopen_dir<P: AsRef<Path>>(&mut self, host: P, guest: &str) -> io::Result<&mut Wasi> {
+        Ok(self.preopen(HostFs::new(host)?, guest))
+    }
+
+    /// Defines the `wasi_snapshot_preview1` functions in `imports`.
+    pub fn register(self, imports: &mut Imports) {
+        let state = Rc::new(RefCell::new(self));
+        for &(name, params, func) in FUNCTIONS {
+            let state = state.clone();
+            let host = HostFunc::new(params.to_vec(), Some(Type::I32), move |ctx, args| {
+                let memory = match ctx.memory {
+                    Some(ref mut memory) => memory,
+                    None => return Err(Trap::Host(format!("{} requires a memory", name))),
+                };
+                let result = func(&mut state.borrow_mut(), memory, args);
+                Ok(Some(Value::I32(result.err().unwrap_or(errno::SUCCESS) as i32)))
+            });
+            imports.func(MODULE, name, host);
+        }
+        let exit = HostFunc::new(vec![Type::I32], None, |_, args| {
+            Err(Trap::Exit(u32_arg(args, 0) as i32))
+        });
+        imports.func(MODULE, "proc_exit", exit);
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64*, which is not suitable for cryptography.
+        self.random ^= self.random >> 12;
+        self.random ^= self.random << 25;
+        self.random ^= self.random >> 27;
+        return self.random.wrapping_mul(0x2545_f491_4f6c_dd1d);
+    }
+
+    fn now(&self, id: u32) -> Result<u64, Errno> {
+        match id {
+            clock::REALTIME => Ok(host_fs::nanos(SystemTime::now())),
+            clock::MONOTONIC | clock::PROCESS_CPUTIME | clock::THREAD_CPUTIME => {
+                Ok(self.start.elapsed().as_nanos() as u64)
+            }
+            _ => Err(errno::INVAL),
+        }
+    }
+
+    /// The filesystem of the directory descriptor `fd` and the path at `path`
+    /// resolved relative to it.
+    fn path(
+        &mut self,
+        memory: &Memory,
+        fd: u32,
+        path: u32,
+        len: u32,
+    ) -> Result<(Rc<dyn FileSystem>, PathBuf), Errno> {
+        let path = string(memory, path, len)?;
+        return self.fds.get(fd)?.resolve(&path);
+    }
+}
+
+type WasiResult = Result<(), Errno>;
+
+type WasiFn = fn(&mut Wasi, &mut Memory, &[Value]) -> WasiResult;
+
+const I32: Type = Type::I32;
+const I64: Type = Type::I64;
+
+/// Every function except `proc_exit`, which does not return.
+const FUNCTIONS: &[(&str, &[Type], WasiFn)] = &[
+    ("args_get", &[I32, I32], args_get),
+    ("args_sizes_get", &[I32, I32], args_sizes_get),
+    ("environ_get", &[I32, I32], environ_get),
+    ("environ_sizes_get", &[I32, I32], environ_sizes_get),
+    ("clock_res_get", &[I32, I32], clock_res_get),
+    ("clock_time_get", &[I32, I64, I32], clock_time_get),
+    ("fd_advise", &[I32, I64, I64, I32], fd_advise),
+    ("fd_allocate", &[I32, I64, I64], fd_allocate),
+    ("fd_close", &[I32], fd_close),
+    ("fd_datasync", &[I32], fd_sync),
+    ("fd_fdstat_get", &[I32, I32], fd_fdstat_get),
+    ("fd_fdstat_set_flags", &[I32, I32], fd_fdstat_set_flags),
+    ("fd_fdstat_set_rights", &[I32, I64, I64], fd_fdstat_set_rights),
+    ("fd_filestat_get", &[I32, I32], fd_filestat_get),
+    ("fd_filestat_set_size", &[I32, I64], fd_filestat_set_size),
+    ("fd_filestat_set_times", &[I32, I64, I64, I32], fd_filestat_set_times),
+    ("fd_pread", &[I32, I32, I32, I64, I32], fd_pread),
+    ("fd_prestat_get", &[I32, I32], fd_prestat_get),
+    ("fd_prestat_dir_name", &[I32, I32, I32], fd_prestat_dir_name),
+    ("fd_pwrite", &[I32, I32, I32, I64, I32], fd_pwrite),
+    ("fd_read", &[I32, I32, I32, I32], fd_read),
+    ("fd_readdir", &[I32, I32, I32, I64, I32], fd_readdir),
+    ("fd_renumber", &[I32, I32], fd_renumber),
+    ("fd_seek", &[I32, I64, I32, I32], fd_seek),
+    ("fd_sync", &[I32], fd_sync),
+    ("fd_tell", &[I32, I32], fd_tell),
+    ("fd_write", &[I32, I32, I32, I32], fd_write),
+    ("path_create_directory", &[I32, I32, I32], path_create_directory),
+    ("path_filestat_get", &[I32, I32, I32, I32, I32], path_filestat_get),
+    (
+        "path_filestat_set_times",
+        &[I32, I32, I32, I32, I64, I64, I32],
+        path_filestat_set_times,
+    ),
+    ("path_link", &[I32, I32, I32, I32, I32, I32, I32], path_link),
+    ("path_open", &[I32, I32, I32, I32, I32, I64, I64, I32, I32], path_open),
+    ("path_readlink", &[I32, I32, I32, I32, I32, I32], path_readlink),
+    ("path_remove_directory", &[I32, I32, I32], path_remove_directory),
+    ("path_rename", &[I32, I32, I32, I32, I32, I32], path_rename),
+    ("path_symlink", &[I32, I32, I32, I32, I32], unsupported),
+    ("path_unlink_file", &[I32, I32, I32], path_unlink_file),
+    ("poll_oneoff", &[I32, I32, I32, I32], poll_oneoff),
+    ("proc_raise", &[I32], unsupported),
+    ("random_get", &[I32, I32], random_get),
+    ("sched_yield", &[], sched_yield),
+    ("sock_accept", &[I32, I32, I32], unsupported),
+    ("sock_recv", &[I32, I32, I32, I32, I32, I32], unsupported),
+    ("sock_send", &[I32, I32, I32, I32, I32], unsupported),
+    ("sock_shutdown", &[I32, I32], unsupported),
+];
+
+fn u32_arg(args: &[Value], index: usize) -> u32 {
+    match args[index] {
+        Value::I32(v) => v as u32,
+        ref v => panic!("expected i32, got {:?}", v),
+    }
+}
+
+fn u64_arg(args: &[Value], index: usize) -> u64 {
+    match args[index] {
+        Value::I64(v) => v as u64,
+        ref v => panic!("expected i64, got {:?}", v),
+    }
+}
+
+fn fault(_: Trap) -> Errno {
+    errno::FAULT
+}
+
+fn string(memory: &Memory, ptr: u32, len: u32) -> Result<String, Errno> {
+    let bytes = memory.read(ptr, 0, len as usize).map_err(fault)?;
+    match ::std::str::from_utf8(bytes) {
+        Ok(s) => Ok(s.to_string()),
+        Err(_) => Err(errno::INVAL),
+    }
+}
+
+fn store_u8(memory: &mut Memory, ptr: u32, value: u8) -> WasiResult {
+    memory.store_u8(ptr, 0, value).map_err(fault)
+}
+
+fn store_u16(memory: &mut Memory, ptr: u32, value: u16) -> WasiResult {
+    memory.store_u16(ptr, 0, value).map_err(fault)
+}
+
+fn store_u32(memory: &mut Memory, ptr: u32, value: u32) -> WasiResult {
+    memory.store_u32(ptr, 0, value).map_err(fault)
+}
+
+fn store_u64(memory: &mut Memory, ptr: u32, value: u64) -> WasiResult {
+    memory.store_u64(ptr, 0, value).map_err(fault)
+}
+
+/// The `(buf, len)` pairs of an iovec array.
+fn iovecs(memory: &Memory, ptr: u32, count: u32) -> Result<Vec<(u32, u32)>, Errno> {
+    let mut result = Vec::new();
+    for i in 0..count {
+        let entry = ptr.wrapping_add(i * 8);
+        let buf = memory.load_u32(entry, 0).map_err(fault)?;
+        let len = memory.load_u32(entry, 4).map_err(fault)?;
+        memory.read(buf, 0, len as usize).map_err(fault)?;
+        result.push((buf, len));
+    }
+    Ok(result)
+}
+
+/// Writes NUL terminated strings to `buf` and pointers to them to `ptrs`.
+fn write_strings(memory: &mut Memory, strings: &[String], ptrs: u32, buf: u32) -> WasiResult {
+    let mut next = buf;
+    for (i, s) in strings.iter().enumerate() {
+        store_u32(memory, ptrs.wrapping_add(i as u32 * 4), next)?;
+        memory.write(next, 0, s.as_bytes()).map_err(fault)?;
+        store_u8(memory, next.wrapping_add(s.len() as u32), 0)?;
+        next = next.wrapping_add(s.len() as u32 + 1);
+    }
+    Ok(())
+}
+
+fn write_sizes(memory: &mut Memory, strings: &[String], count: u32, size: u32) -> WasiResult {
+    store_u32(memory, count, strings.len() as u32)?;
+    store_u32(memory, size, strings.iter().map(|s| s.len() as u32 + 1).sum())
+}
+
+fn environ(wasi: &Wasi) -> Vec<String> {
+    wasi.env
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect()
+}
+
+fn args_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    write_strings(memory, &wasi.args, u32_arg(args, 0), u32_arg(args, 1))
+}
+
+fn args_sizes_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    write_sizes(memory, &wasi.args, u32_arg(args, 0), u32_arg(args, 1))
+}
+
+fn environ_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    write_strings(memory, &environ(wasi), u32_arg(args, 0), u32_arg(args, 1))
+}
+
+fn environ_sizes_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    write_sizes(memory, &environ(wasi), u32_arg(args, 0), u32_arg(args, 1))
+}
+
+fn clock_res_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    wasi.now(u32_arg(args, 0))?;
+    store_u64(memory, u32_arg(args, 1), 1)
+}
+
+fn clock_time_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let time = wasi.now(u32_arg(args, 0))?;
+    store_u64(memory, u32_arg(args, 2), time)
+}
+
+fn fd_advise(wasi: &mut Wasi, _: &mut Memory, args: &[Value]) -> WasiResult {
+    wasi.fds.get(u32_arg(args, 0))?;
+    Ok(())
+}
+
+fn file(wasi: &mut Wasi, fd: u32) -> Result<&mut Box<dyn File>, Errno> {
+    match wasi.fds.get(fd)?.kind {
+        Kind::File { ref mut file, .. } => Ok(file),
+        Kind::Dir { .. } => Err(errno::ISDIR),
+        _ => Err(errno::SPIPE),
+    }
+}
+
+fn fd_allocate(wasi: &mut Wasi, _: &mut Memory, args: &[Value]) -> WasiResult {
+    let file = file(wasi, u32_arg(args, 0))?;
+    let end = u64_arg(args, 1).checked_add(u64_arg(args, 2)).ok_or(errno::INVAL)?;
+    if file.metadata()?.size < end {
+        file.set_len(end)?;
+    }
+    Ok(())
+}
+
+fn fd_close(wasi: &mut Wasi, _: &mut Memory, args: &[Value]) -> WasiResult {
+    wasi.fds.remove(u32_arg(args, 0))?;
+    Ok(())
+}
+
+fn fd_sync(wasi: &mut Wasi, _: &mut Memory, args: &[Value]) -> WasiResult {
+    match wasi.fds.get(u32_arg(args, 0))?.kind {
+        Kind::File { ref mut file, .. } => file.sync(),
+        Kind::Output(ref mut output) => output.flush().map_err(io_errno),
+        _ => Ok(()),
+    }
+}
+
+fn fd_fdstat_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let descriptor = wasi.fds.get(u32_arg(args, 0))?;
+    let flags = match descriptor.kind {
+        Kind::File { append: true, .. } => fdflags::APPEND,
+        _ => 0,
+    };
+    let ptr = u32_arg(args, 1);
+    memory.write(ptr, 0, &[0; FDSTAT_SIZE]).map_err(fault)?;
+    store_u8(memory, ptr, descriptor.filetype())?;
+    store_u16(memory, ptr + 2, flags)?;
+    store_u64(memory, ptr + 8, rights::ALL)?;
+    store_u64(memory, ptr + 16, rights::ALL)
+}
+
+fn fd_fdstat_set_flags(wasi: &mut Wasi, _: &mut Memory, args: &[Value]) -> WasiResult {
+    let flags = u32_arg(args, 1) as u16;
+    match wasi.fds.get(u32_arg(args, 0))?.kind {
+        Kind::File { ref mut append, .. } if flags & !fdflags::APPEND == 0 => {
+            *append = flags & fdflags::APPEND != 0;
+            Ok(())
+        }
+        _ if flags == 0 => Ok(()),
+        _ => Err(errno::NOTSUP),
+    }
+}
+
+fn fd_fdstat_set_rights(wasi: &mut Wasi, _: &mut Memory, args: &[Value]) -> WasiResult {
+    wasi.fds.get(u32_arg(args, 0))?;
+    Ok(())
+}
+
+fn write_filestat(memory: &mut Memory, ptr: u32, meta: &Metadata) -> WasiResult {
+    memory.write(ptr, 0, &[0; FILESTAT_SIZE]).map_err(fault)?;
+    store_u64(memory, ptr, meta.dev)?;
+    store_u64(memory, ptr + 8, meta.ino)?;
+    store_u8(memory, ptr + 16, meta.filetype)?;
+    store_u64(memory, ptr + 24, meta.nlink)?;
+    store_u64(memory, ptr + 32, meta.size)?;
+    store_u64(memory, ptr + 40, meta.atim)?;
+    store_u64(memory, ptr + 48, meta.mtim)?;
+    store_u64(memory, ptr + 56, meta.ctim)
+}
+
+fn fd_filestat_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let meta = match wasi.fds.get(u32_arg(args, 0))?.kind {
+        Kind::File { ref file, .. } => file.metadata()?,
+        Kind::Dir { ref fs, ref path } => fs.metadata(path, true)?,
+        _ => Metadata {
+            filetype: filetype::CHARACTER_DEVICE,
+            ..Metadata::default()
+        },
+    };
+    write_filestat(memory, u32_arg(args, 1), &meta)
+}
+
+fn fd_filestat_set_size(wasi: &mut Wasi, _: &mut Memory, args: &[Value]) -> WasiResult {
+    file(wasi, u32_arg(args, 0))?.set_len(u64_arg(args, 1))
+}
+
+/// The times to set from the arguments of `*_filestat_set_times`.
+fn times(atim: u64, mtim: u64, flags: u32) -> (Option<u64>, Option<u64>) {
+    let time = |nanos: u64, set: u32, now: u32| {
+        if flags & now != 0 {
+            Some(host_fs::nanos(SystemTime::now()))
+        } else if flags & set != 0 {
+            Some(nanos)
+        } else {
+            None
+        }
+    };
+    let atim = time(atim, FSTFLAGS_ATIM, FSTFLAGS_ATIM_NOW);
+    let mtim = time(mtim, FSTFLAGS_MTIM, FSTFLAGS_MTIM_NOW);
+    return (atim, mtim);
+}
+
+fn fd_filestat_set_times(wasi: &mut Wasi, _: &mut Memory, args: &[Value]) -> WasiResult {
+    let (atim, mtim) = times(u64_arg(args, 1), u64_arg(args, 2), u32_arg(args, 3));
+    match wasi.fds.get(u32_arg(args, 0))?.kind {
+        Kind::File { ref mut file, .. } => file.set_times(atim, mtim),
+        Kind::Dir { ref fs, ref path } => fs.set_times(path, atim, mtim),
+        _ => Err(errno::NOTSUP),
+    }
+}
+
+/// Reads into the iovecs and returns the number of bytes read, stopping at
+/// the first short read.
+fn read_iovecs<R: Read + ?Sized>(
+    memory: &mut Memory,
+    input: &mut R,
+    iovs: &[(u32, u32)],
+) -> Result<u32, Errno> {
+    let mut total = 0;
+    for &(buf, len) in iovs {
+        let mut data = vec![0; len as usize];
+        let n = input.read(&mut data).map_err(io_errno)?;
+        memory.write(buf, 0, &data[..n]).map_err(fault)?;
+        total += n as u32;
+        if n < len as usize {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+fn gather(memory: &Memory, iovs: &[(u32, u32)]) -> Result<Vec<u8>, Errno> {
+    let mut data = Vec::new();
+    for &(buf, len) in iovs {
+        data.extend_from_slice(memory.read(buf, 0, len as usize).map_err(fault)?);
+    }
+    Ok(data)
+}
+
+fn fd_read(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let iovs = iovecs(memory, u32_arg(args, 1), u32_arg(args, 2))?;
+    let n = match wasi.fds.get(u32_arg(args, 0))?.kind {
+        Kind::Input(ref mut input) => read_iovecs(memory, input, &iovs)?,
+        Kind::File { ref mut file, .. } => read_iovecs(memory, &mut **file, &iovs)?,
+        Kind::Dir { .. } => return Err(errno::ISDIR),
+        Kind::Output(_) => return Err(errno::BADF),
+    };
+    store_u32(memory, u32_arg(args, 3), n)
+}
+
+fn fd_pread(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let iovs = iovecs(memory, u32_arg(args, 1), u32_arg(args, 2))?;
+    let file = file(wasi, u32_arg(args, 0))?;
+    let position = file.stream_position().map_err(io_errno)?;
+    file.seek(SeekFrom::Start(u64_arg(args, 3))).map_err(io_errno)?;
+    let n = read_iovecs(memory, &mut **file, &iovs);
+    file.seek(SeekFrom::Start(position)).map_err(io_errno)?;
+    store_u32(memory, u32_arg(args, 4), n?)
+}
+
+fn fd_write(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let iovs = iovecs(memory, u32_arg(args, 1), u32_arg(args, 2))?;
+    let data = gather(memory, &iovs)?;
+    match wasi.fds.get(u32_arg(args, 0))?.kind {
+        Kind::Output(ref mut output) => {
+            output.write_all(&data).map_err(io_errno)?;
+            output.flush().map_err(io_errno)?;
+        }
+        Kind::File { ref mut file, append } => {
+            if append {
+                file.seek(SeekFrom::End(0)).map_err(io_errno)?;
+            }
+            file.write_all(&data).map_err(io_errno)?;
+        }
+        Kind::Dir { .. } => return Err(errno::ISDIR),
+        Kind::Input(_) => return Err(errno::BADF),
+    }
+    store_u32(memory, u32_arg(args, 3), data.len() as u32)
+}
+
+fn fd_pwrite(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let iovs = iovecs(memory, u32_arg(args, 1), u32_arg(args, 2))?;
+    let data = gather(memory, &iovs)?;
+    let file = file(wasi, u32_arg(args, 0))?;
+    let position = file.stream_position().map_err(io_errno)?;
+    file.seek(SeekFrom::Start(u64_arg(args, 3))).map_err(io_errno)?;
+    let written = file.write_all(&data).map_err(io_errno);
+    file.seek(SeekFrom::Start(position)).map_err(io_errno)?;
+    written?;
+    store_u32(memory, u32_arg(args, 4), data.len() as u32)
+}
+
+fn fd_prestat_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let len = match wasi.fds.get(u32_arg(args, 0))?.preopen {
+        Some(ref name) => name.len() as u32,
+        None => return Err(errno::BADF),
+    };
+    let ptr = u32_arg(args, 1);
+    store_u32(memory, ptr, 0)?;
+    store_u32(memory, ptr + 4, len)
+}
+
+fn fd_prestat_dir_name(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let name = match wasi.fds.get(u32_arg(args, 0))?.preopen {
+        Some(ref name) => name.clone(),
+        None => return Err(errno::BADF),
+    };
+    if (u32_arg(args, 2) as usize) < name.len() {
+        return Err(errno::INVAL);
+    }
+    memory.write(u32_arg(args, 1), 0, name.as_bytes()).map_err(fault)
+}
+
+fn fd_readdir(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let mut entries = match wasi.fds.get(u32_arg(args, 0))?.kind {
+        Kind::Dir { ref fs, ref path } => fs.read_dir(path)?,
+        _ => return Err(errno::NOTDIR),
+    };
+    for name in &["..", "."] {
+        entries.insert(
+            0,
+            DirEntry {
+                name: name.to_string(),
+                filetype: filetype::DIRECTORY,
+                ino: 0,
+            },
+        );
+    }
+
+    let (buf, buf_len, cookie) = (u32_arg(args, 1), u32_arg(args, 2), u64_arg(args, 3));
+    let mut out = Vec::new();
+    for (i, entry) in entries.iter().enumerate().skip(cookie as usize) {
+        let mut dirent = [0; DIRENT_SIZE];
+        dirent[0..8].copy_from_slice(&(i as u64 + 1).to_le_bytes());
+        dirent[8..16].copy_from_slice(&entry.ino.to_le_bytes());
+        dirent[16..20].copy_from_slice(&(entry.name.len() as u32).to_le_bytes());
+        dirent[20] = entry.filetype;
+        out.extend_from_slice(&dirent);
+        out.extend_from_slice(entry.name.as_bytes());
+        if out.len() >= buf_len as usize {
+            break;
+        }
+    }
+    // A full buffer tells the program to call again with a later cookie.
+    out.truncate(buf_len as usize);
+    memory.write(buf, 0, &out).map_err(fault)?;
+    store_u32(memory, u32_arg(args, 4), out.len() as u32)
+}
+
+fn fd_renumber(wasi: &mut Wasi, _: &mut Memory, args: &[Value]) -> WasiResult {
+    let (from, to) = (u32_arg(args, 0), u32_arg(args, 1));
+    wasi.fds.get(to)?;
+    let descriptor = wasi.fds.remove(from)?;
+    wasi.fds.set(to, descriptor);
+    Ok(())
+}
+
+fn fd_seek(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let offset = u64_arg(args, 1) as i64;
+    let from = match u32_arg(args, 2) as u8 {
+        whence::SET if offset >= 0 => SeekFrom::Start(offset as u64),
+        whence::CUR => SeekFrom::Current(offset),
+        whence::END => SeekFrom::End(offset),
+        _ => return Err(errno::INVAL),
+    };
+    let position = file(wasi, u32_arg(args, 0))?
+        .seek(from)
+        .map_err(io_errno)?;
+    store_u64(memory, u32_arg(args, 3), position)
+}
+
+fn fd_tell(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let position = file(wasi, u32_arg(args, 0))?
+        .stream_position()
+        .map_err(io_errno)?;
+    store_u64(memory, u32_arg(args, 1), position)
+}
+
+fn path_create_directory(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let (fs, path) = wasi.path(memory, u32_arg(args, 0), u32_arg(args, 1), u32_arg(args, 2))?;
+    fs.create_dir(&path)
+}
+
+fn path_filestat_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let follow = u32_arg(args, 1) & LOOKUP_SYMLINK_FOLLOW != 0;
+    let (fs, path) = wasi.path(memory, u32_arg(args, 0), u32_arg(args, 2), u32_arg(args, 3))?;
+    let meta = fs.metadata(&path, follow)?;
+    write_filestat(memory, u32_arg(args, 4), &meta)
+}
+
+fn path_filestat_set_times(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let (fs, path) = wasi.path(memory, u32_arg(args, 0), u32_arg(args, 2), u32_arg(args, 3))?;
+    let (atim, mtim) = times(u64_arg(args, 4), u64_arg(args, 5), u32_arg(args, 6));
+    fs.set_times(&path, atim, mtim)
+}
+
+fn path_link(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let (fs, old) = wasi.path(memory, u32_arg(args, 0), u32_arg(args, 2), u32_arg(args, 3))?;
+    let (new_fs, new) = wasi.path(memory, u32_arg(args, 4), u32_arg(args, 5), u32_arg(args, 6))?;
+    if !Rc::ptr_eq(&fs, &new_fs) {
+        return Err(errno::XDEV);
+    }
+    fs.hard_link(&old, &new)
+}
+
+fn path_open(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let follow = u32_arg(args, 1) & LOOKUP_SYMLINK_FOLLOW != 0;
+    let (fs, path) = wasi.path(memory, u32_arg(args, 0), u32_arg(args, 2), u32_arg(args, 3))?;
+    let oflags = u32_arg(args, 4) as u16;
+    let rights = u64_arg(args, 5);
+    let fdflags = u32_arg(args, 7) as u16;
+
+    let exclusive = oflags & oflags::CREAT != 0 && oflags & oflags::EXCL != 0;
+    let write = rights & rights::FD_WRITE != 0 || oflags & (oflags::CREAT | oflags::TRUNC) != 0;
+    let filetype = match fs.metadata(&path, follow) {
+        Ok(meta) => Some(meta.filetype),
+        Err(errno::NOENT) => None,
+        Err(err) => return Err(err),
+    };
+    let kind = if filetype == Some(filetype::DIRECTORY) {
+        if exclusive {
+            return Err(errno::EXIST);
+        }
+        if write {
+            return Err(errno::ISDIR);
+        }
+        Kind::Dir { fs, path }
+    } else if oflags & oflags::DIRECTORY != 0 {
+        return Err(if filetype.is_some() { errno::NOTDIR } else { errno::NOENT });
+    } else {
+        let options = OpenOptions {
+            read: rights & rights::FD_READ != 0 || !write,
+            write,
+            create: oflags & oflags::CREAT != 0,
+            exclusive,
+            truncate: oflags & oflags::TRUNC != 0,
+            follow,
+        };
+        Kind::File {
+            file: fs.open(&path, &options)?,
+            append: fdflags & fdflags::APPEND != 0,
+        }
+    };
+    let fd = wasi.fds.insert(Descriptor::new(kind));
+    store_u32(memory, u32_arg(args, 8), fd)
+}
+
+fn path_readlink(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let (fs, path) = wasi.path(memory, u32_arg(args, 0), u32_arg(args, 1), u32_arg(args, 2))?;
+    let target = fs.read_link(&path)?;
+    let len = target.len().min(u32_arg(args, 4) as usize);
+    memory.write(u32_arg(args, 3), 0, &target.as_bytes()[..len]).map_err(fault)?;
+    store_u32(memory, u32_arg(args, 5), len as u32)
+}
+
+fn path_remove_directory(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let (fs, path) = wasi.path(memory, u32_arg(args, 0), u32_arg(args, 1), u32_arg(args, 2))?;
+    fs.remove_dir(&path)
+}
+
+fn path_rename(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let (fs, old) = wasi.path(memory, u32_arg(args, 0), u32_arg(args, 1), u32_arg(args, 2))?;
+    let (new_fs, new) = wasi.path(memory, u32_arg(args, 3), u32_arg(args, 4), u32_arg(args, 5))?;
+    if !Rc::ptr_eq(&fs, &new_fs) {
+        return Err(errno::XDEV);
+    }
+    fs.rename(&old, &new)
+}
+
+fn path_unlink_file(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let (fs, path) = wasi.path(memory, u32_arg(args, 0), u32_arg(args, 1), u32_arg(args, 2))?;
+    fs.remove_file(&path)
+}
+
+/// Waits for the earliest clock subscription. Subscriptions for reading or
+/// writing a descriptor are always ready, so they are reported without
+/// waiting.
+fn poll_oneoff(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let (input, output, count) = (u32_arg(args, 0), u32_arg(args, 1), u32_arg(args, 2));
+    if count == 0 {
+        return Err(errno::INVAL);
+    }
+    let mut clocks = Vec::new();
+    let mut ready = Vec::new();
+    for i in 0..count {
+        let sub = input.wrapping_add(i * SUBSCRIPTION_SIZE);
+        let userdata = memory.load_u64(sub, 0).map_err(fault)?;
+        let tag = memory.load_u8(sub, 8).map_err(fault)?;
+        if tag == EVENTTYPE_CLOCK {
+            let id = memory.load_u32(sub, 16).map_err(fault)?;
+            let timeout = memory.load_u64(sub, 24).map_err(fault)?;
+            let flags = memory.load_u16(sub, 40).map_err(fault)?;
+            let now = wasi.now(id)?;
+            let wait = if flags & SUBSCRIPTION_CLOCK_ABSTIME != 0 {
+                timeout.saturating_sub(now)
+            } else {
+                timeout
+            };
+            clocks.push((wait, userdata));
+        } else {
+            let fd = memory.load_u32(sub, 16).map_err(fault)?;
+            let error = wasi.fds.get(fd).err().unwrap_or(errno::SUCCESS);
+            ready.push((userdata, tag, error));
+        }
+    }
+    if ready.is_empty() {
+        let wait = clocks.iter().map(|c| c.0).min().expect("count > 0");
+        thread::sleep(Duration::from_nanos(wait));
+        for &(timeout, userdata) in &clocks {
+            if timeout == wait {
+                ready.push((userdata, EVENTTYPE_CLOCK, errno::SUCCESS));
+            }
+        }
+    }
+    for (i, &(userdata, tag, error)) in ready.iter().enumerate() {
+        let event = output.wrapping_add(i as u32 * EVENT_SIZE);
+        memory.write(event, 0, &[0; EVENT_SIZE as usize]).map_err(fault)?;
+        store_u64(memory, event, userdata)?;
+        store_u16(memory, event + 8, error)?;
+        store_u8(memory, event + 10, tag)?;
+    }
+    store_u32(memory, u32_arg(args, 3), ready.len() as u32)
+}
+
+fn random_get(wasi: &mut Wasi, memory: &mut Memory, args: &[Value]) -> WasiResult {
+    let (buf, len) = (u32_arg(args, 0), u32_arg(args, 1));
+    // Checked up front, and filled in place, so that `len` does not decide
+    // how much the host allocates.
+    memory.read(buf, 0, len as usize).map_err(fault)?;
+    let mut filled = 0;
+    while filled < len {
+        let n = (len - filled).min(8);
+        let bytes = wasi.next_random().to_le_bytes();
+        memory.write(buf + filled, 0, &bytes[..n as usize]).map_err(fault)?;
+        filled += n;
+    }
+    Ok(())
+}
+
+fn sched_yield(_: &mut Wasi, _: &mut Memory, _: &[Value]) -> WasiResult {
+    thread::yield_now();
+    Ok(())
+}
+
+fn unsupported(_: &mut Wasi, _: &mut Memory, _: &[Value]) -> WasiResult {
+    Err(errno::NOTSUP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    /// An output stream the test can read back.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Calls the WASI function `name` with i32 arguments, except for the i64
+    /// parameters, and returns its errno.
+    fn call(wasi: &mut Wasi, memory: &mut Memory, name: &str, args: &[u64]) -> Errno {
+        let &(_, params, func) = FUNCTIONS.iter().find(|f| f.0 == name).expect("function");
+        let args: Vec<Value> = params
+            .iter()
+            .zip(args)
+            .map(|(&typ, &arg)| match typ {
+                Type::I64 => Value::I64(arg as i64),
+                _ => Value::I32(arg as i32),
+            })
+            .collect();
+        func(wasi, memory, &args).err().unwrap_or(errno::SUCCESS)
+    }
+
+    /// Opens `path` relative to the descriptor `dir` for reading, returning the
+    /// new descriptor or the errno.
+    fn open(wasi: &mut Wasi, memory: &mut Memory, dir: u32, path: &str) -> Result<u32, Errno> {
+        memory.write(1024, 0, path.as_bytes()).unwrap();
+        let args = [dir as u64, 1, 1024, path.len() as u64, 0, rights::FD_READ, 0, 0, 0];
+        match call(wasi, memory, "path_open", &args) {
+            errno::SUCCESS => Ok(memory.load_u32(0, 0).unwrap()),
+            err => Err(err),
+        }
+    }
+
+    #[test]
+    fn fd_write() {
+        let stdout = Shared::default();
+        let mut wasi = Wasi::new();
+        wasi.stdout(Box::new(stdout.clone()));
+        let mut memory = Memory::new(1, None);
+        memory.write(100, 0, b"hello\n").unwrap();
+        for (i, &(buf, len)) in [(100, 5), (105, 1)].iter().enumerate() {
+            memory.store_u32(i as u32 * 8, 0, buf).unwrap();
+            memory.store_u32(i as u32 * 8, 4, len).unwrap();
+        }
+        assert_eq!(call(&mut wasi, &mut memory, "fd_write", &[1, 0, 2, 200]), errno::SUCCESS);
+        assert_eq!(memory.load_u32(200, 0).unwrap(), 6);
+        assert_eq!(&stdout.0.borrow()[..], b"hello\n");
+        // an iovec past the end of memory
+        memory.store_u32(8, 0, 65535).unwrap();
+        memory.store_u32(8, 4, 2).unwrap();
+        assert_eq!(call(&mut wasi, &mut memory, "fd_write", &[1, 0, 2, 200]), errno::FAULT);
+        assert_eq!(call(&mut wasi, &mut memory, "fd_write", &[9, 0, 1, 200]), errno::BADF);
+        assert_eq!(stdout.0.borrow().len(), 6);
+    }
+
+    #[test]
+    fn args_and_environ() {
+        let mut wasi = Wasi::new();
+        wasi.arg("prog").arg("x").env("A", "b");
+        let mut memory = Memory::new(1, None);
+        assert_eq!(call(&mut wasi, &mut memory, "args_sizes_get", &[0, 4]), errno::SUCCESS);
+        assert_eq!(memory.load_u32(0, 0).unwrap(), 2);
+        assert_eq!(memory.load_u32(4, 0).unwrap(), 7);
+        assert_eq!(call(&mut wasi, &mut memory, "args_get", &[16, 32]), errno::SUCCESS);
+        assert_eq!(memory.load_u32(16, 0).unwrap(), 32);
+        assert_eq!(memory.load_u32(20, 0).unwrap(), 37);
+        assert_eq!(memory.read(32, 0, 7).unwrap(), b"prog\0x\0");
+        assert_eq!(call(&mut wasi, &mut memory, "environ_sizes_get", &[0, 4]), errno::SUCCESS);
+        assert_eq!(memory.load_u32(0, 0).unwrap(), 1);
+        assert_eq!(memory.load_u32(4, 0).unwrap(), 4);
+        assert_eq!(call(&mut wasi, &mut memory, "environ_get", &[16, 64]), errno::SUCCESS);
+        assert_eq!(memory.load_u32(16, 0).unwrap(), 64);
+        assert_eq!(memory.read(64, 0, 4).unwrap(), b"A=b\0");
+        assert_eq!(call(&mut wasi, &mut memory, "args_get", &[16, 65534]), errno::FAULT);
+    }
+
+    #[test]
+    fn virtual_fs_sandbox() {
+        let mut wasi = Wasi::new();
+        wasi.preopen(VirtualFs::from_map(vec![("dir/a.txt", "a")]).unwrap(), "/sandbox");
+        let mut memory = Memory::new(1, None);
+        assert_eq!(open(&mut wasi, &mut memory, 3, "dir/a.txt"), Ok(4));
+        assert_eq!(open(&mut wasi, &mut memory, 3, "dir/../dir/./a.txt"), Ok(5));
+        assert_eq!(open(&mut wasi, &mut memory, 3, "dir/../a.txt"), Err(errno::NOENT));
+        assert_eq!(open(&mut wasi, &mut memory, 3, ".."), Err(errno::NOTCAPABLE));
+        assert_eq!(open(&mut wasi, &mut memory, 3, "dir/../../a"), Err(errno::NOTCAPABLE));
+        assert_eq!(open(&mut wasi, &mut memory, 3, "/dir/a.txt"), Err(errno::NOTCAPABLE));
+        // relative to an opened subdirectory, `..` still may not leave the root
+        let dir = open(&mut wasi, &mut memory, 3, "dir").unwrap();
+        assert!(open(&mut wasi, &mut memory, dir, "../dir/a.txt").is_ok());
+        assert_eq!(open(&mut wasi, &mut memory, dir, "../../a"), Err(errno::NOTCAPABLE));
+    }
+
+    #[test]
+    fn host_fs_sandbox() {
+        let base = env::temp_dir().join(format!("wasm-interpreter-wasi-{}", process::id()));
+        let root = base.join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(base.join("secret"), "secret").unwrap();
+        #[cfg(unix)]
+        ::std::os::unix::fs::symlink(base.join("secret"), root.join("link")).unwrap();
+        let mut wasi = Wasi::new();
+        wasi.preopen_dir(&root, "/").unwrap();
+        let mut memory = Memory::new(1, None);
+        let secret = base.join("secret");
+        let results = [
+            open(&mut wasi, &mut memory, 3, "sub/../a.txt").is_ok(),
+            open(&mut wasi, &mut memory, 3, "../secret") == Err(errno::NOTCAPABLE),
+            open(&mut wasi, &mut memory, 3, "sub/../../secret") == Err(errno::NOTCAPABLE),
+            open(&mut wasi, &mut memory, 3, secret.to_str().unwrap()) == Err(errno::NOTCAPABLE),
+            !cfg!(unix) || open(&mut wasi, &mut memory, 3, "link") == Err(errno::NOTCAPABLE),
+        ];
+        fs::remove_dir_all(&base).unwrap();
+        assert_eq!(results, [true; 5]);
+    }
+
+    #[test]
+    fn random_get() {
+        let mut wasi = Wasi::new();
+        let mut memory = Memory::new(1, None);
+        assert_eq!(call(&mut wasi, &mut memory, "random_get", &[16, 13]), errno::SUCCESS);
+        assert_eq!(memory.load_u8(15, 0).unwrap(), 0);
+        assert_eq!(memory.load_u8(29, 0).unwrap(), 0);
+        assert!(memory.read(16, 0, 13).unwrap().iter().any(|&b| b != 0));
+        // out of bounds buffers, one of them too large to allocate
+        assert_eq!(call(&mut wasi, &mut memory, "random_get", &[65530, 10]), errno::FAULT);
+        assert_eq!(call(&mut wasi, &mut memory, "random_get", &[0, 0xffff_ffff]), errno::FAULT);
+        assert_eq!(memory.load_u64(65528, 0).unwrap(), 0);
+    }
+}
@@ -0,0 +1,73 @@
+//! Constants of the `wasi_snapshot_preview1` ABI.
+
+pub type Errno = u16;
+
+pub mod errno {
+    use super::Errno;
+
+    pub const SUCCESS: Errno = 0;
+    pub const TOOBIG: Errno = 1;
+    pub const ACCES: Errno = 2;
+    pub const BADF: Errno = 8;
+    pub const BUSY: Errno = 10;
+    pub const EXIST: Errno = 20;
+    pub const FAULT: Errno = 21;
+    pub const INVAL: Errno = 28;
+    pub const IO: Errno = 29;
+    pub const ISDIR: Errno = 31;
+    pub const LOOP: Errno = 32;
+    pub const NOENT: Errno = 44;
+    pub const NOSYS: Errno = 52;
+    pub const NOTDIR: Errno = 54;
+    pub const NOTEMPTY: Errno = 55;
+    pub const NOTSUP: Errno = 58;
+    pub const PERM: Errno = 63;
+    pub const SPIPE: Errno = 70;
+    pub const XDEV: Errno = 75;
+    pub const NOTCAPABLE: Errno = 76;
+}
+
+pub mod filetype {
+    pub const UNKNOWN: u8 = 0;
+    pub const CHARACTER_DEVICE: u8 = 2;
+    pub const DIRECTORY: u8 = 3;
+    pub const REGULAR_FILE: u8 = 4;
+    pub const SYMBOLIC_LINK: u8 = 7;
+}
+
+pub mod clock {
+    pub const REALTIME: u32 = 0;
+    pub const MONOTONIC: u32 = 1;
+    pub const PROCESS_CPUTIME: u32 = 2;
+    pub const THREAD_CPUTIME: u32 = 3;
+}
+
+pub mod whence {
+    pub const SET: u8 = 0;
+    pub const CUR: u8 = 1;
+    pub const END: u8 = 2;
+}
+
+pub mod oflags {
+    pub const CREAT: u16 = 1;
+    pub const DIRECTORY: u16 = 2;
+    pub const EXCL: u16 = 4;
+    pub const TRUNC: u16 = 8;
+}
+
+pub mod fdflags {
+    pub const APPEND: u16 = 1;
+}
+
+pub mod rights {
+    pub const FD_READ: u64 = 1 << 1;
+    pub const FD_WRITE: u64 = 1 << 6;
+    /// All rights defined by preview1. Rights are reported but not enforced
+    /// beyond deciding whether a file is opened for reading and writing.
+    pub const ALL: u64 = (1 << 29) - 1;
+}
+
+/// Size of `fdstat`, `filestat` and the fixed part of `dirent`.
+pub const FDSTAT_SIZE: usize = 24;
+pub const FILESTAT_SIZE: usize = 64;
+pub const DIRENT_SIZE: usize = 24;
@@ -0,0 +1,640 @@
+//! An in-memory filesystem for running programs without access to the disk.
+
+use wasi::filesystem::{DirEntry, File, FileSystem, FsResult, Metadata, OpenOptions};
+use wasi::host_fs::nanos;
+use wasi::types::{errno, filetype};
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+const ROOT: usize = 0;
+const BLOCK_SIZE: usize = 512;
+
+enum Content {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, usize>),
+}
+
+struct Node {
+    content: Content,
+    /// Files can not be written, entries of directories not added or removed.
+    readonly: bool,
+    nlink: u64,
+    atim: u64,
+    mtim: u64,
+    ctim: u64,
+}
+
+impl Node {
+    fn new(content: Content) -> Node {
+        let now = nanos(SystemTime::now());
+        Node {
+            content,
+            readonly: false,
+            nlink: 1,
+            atim: now,
+            mtim: now,
+            ctim: now,
+        }
+    }
+
+    fn filetype(&self) -> u8 {
+        match self.content {
+            Content::File(_) => filetype::REGULAR_FILE,
+            Content::Dir(_) => filetype::DIRECTORY,
+        }
+    }
+}
+
+/// The nodes by inode number. Removed nodes are kept, so that descriptors
+/// still open on them remain valid.
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+fn names(path: &Path) -> Vec<&str> {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Tree {
+    fn entries(&self, ino: usize) -> FsResult<&BTreeMap<String, usize>> {
+        match self.nodes[ino].content {
+            Content::Dir(ref entries) => Ok(entries),
+            Content::File(_) => Err(errno::NOTDIR),
+        }
+    }
+
+    fn entries_mut(&mut self, ino: usize) -> FsResult<&mut BTreeMap<String, usize>> {
+        if self.nodes[ino].readonly {
+            return Err(errno::ACCES);
+        }
+        self.nodes[ino].mtim = nanos(SystemTime::now());
+        match self.nodes[ino].content {
+            Content::Dir(ref mut entries) => Ok(entries),
+            Content::File(_) => Err(errno::NOTDIR),
+        }
+    }
+
+    fn lookup(&self, path: &Path) -> FsResult<usize> {
+        let mut ino = ROOT;
+        for name in names(path) {
+            ino = *self.entries(ino)?.get(name).ok_or(errno::NOENT)?;
+        }
+        Ok(ino)
+    }
+
+    /// The directory containing `path` and the last name of `path`.
+    fn parent<'p>(&self, path: &'p Path) -> FsResult<(usize, &'p str)> {
+        let mut names = names(path);
+        let name = names.pop().ok_or(errno::BUSY)?;
+        let mut ino = ROOT;
+        for name in names {
+            ino = *self.entries(ino)?.get(name).ok_or(errno::NOENT)?;
+        }
+        self.entries(ino)?;
+        Ok((ino, name))
+    }
+
+    fn add(&mut self, content: Content) -> usize {
+        self.nodes.push(Node::new(content));
+        self.nodes.len() - 1
+    }
+
+    fn is_empty_dir(&self, ino: usize) -> bool {
+        self.entries(ino).is_ok_and(|entries| entries.is_empty())
+    }
+}
+
+/// A filesystem held in memory.
+///
+/// Clones share the same files, so a clone kept by the embedder shows what a
+/// program changed after it ran. There are no symbolic links.
+#[derive(Clone)]
+pub struct VirtualFs {
+    tree: Rc<RefCell<Tree>>,
+}
+
+impl Default for VirtualFs {
+    fn default() -> VirtualFs {
+        VirtualFs::new()
+    }
+}
+
+impl VirtualFs {
+    /// An empty filesystem.
+    pub fn new() -> VirtualFs {
+        VirtualFs {
+            tree: Rc::new(RefCell::new(Tree {
+                nodes: vec![Node::new(Content::Dir(BTreeMap::new()))],
+            })),
+        }
+    }
+
+    /// A filesystem with the given files and the directories containing them.
+    pub fn from_map<I, K, V>(files: I) -> FsResult<VirtualFs>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<[u8]>,
+    {
+        let fs = VirtualFs::new();
+        for (path, data) in files {
+            fs.write_file(path.as_ref(), data.as_ref())?;
+        }
+        Ok(fs)
+    }
+
+    /// A filesystem with the directories and regular files of a tar archive.
+    /// Files without write permission for their owner are read-only; other
+    /// entries such as links are skipped.
+    pub fn from_tar(data: &[u8]) -> io::Result<VirtualFs> {
+        let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+        let fs = VirtualFs::new();
+        let mut offset = 0;
+        let mut long_name = None;
+        let mut attributes = Vec::new();
+        while offset + BLOCK_SIZE <= data.len() {
+            let header = &data[offset..offset + BLOCK_SIZE];
+            if header.iter().all(|&b| b == 0) {
+                break;
+            }
+            let size = tar_number(&header[124..136]).ok_or_else(|| invalid("invalid size"))?;
+            let start = offset + BLOCK_SIZE;
+            let end = start
+                .checked_add(size as usize)
+                .filter(|&end| end <= data.len())
+                .ok_or_else(|| invalid("truncated archive"))?;
+            let content = &data[start..end];
+            offset = start + (size as usize).div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+            let mut name = tar_string(&header[0..100]);
+            if &header[257..262] == b"ustar" {
+                let prefix = tar_string(&header[345..500]);
+                if !prefix.is_empty() {
+                    name = format!("{}/{}", prefix, name);
+                }
+            }
+            let name = long_name.take().unwrap_or(name);
+            if name.split('/').any(|c| c == "..") {
+                return Err(invalid("path leaves the archive"));
+            }
+            let mode = tar_number(&header[100..108]).unwrap_or(0o644);
+            let result = match header[156] {
+                b'0' | b'\0' | b'7' => fs.write_file(&name, content),
+                b'5' => fs.create_dir_all(&name),
+                b'L' => {
+                    long_name = Some(tar_string(content));
+                    continue;
+                }
+                b'x' => {
+                    long_name = pax_path(content);
+                    continue;
+                }
+                _ => continue,
+            };
+            result.map_err(|_| invalid("conflicting entries"))?;
+            let mtime = tar_number(&header[136..148]).unwrap_or(0);
+            attributes.push((name, mode & 0o200 == 0, mtime));
+        }
+        // Only after all entries exist, as they may be in read-only directories.
+        let mut tree = fs.tree.borrow_mut();
+        for (name, readonly, mtime) in attributes {
+            let ino = tree.lookup(Path::new(&name)).expect("created above");
+            tree.nodes[ino].readonly = readonly;
+            tree.nodes[ino].mtim = mtime.saturating_mul(1_000_000_000);
+        }
+        drop(tree);
+        Ok(fs)
+    }
+
+    pub fn open_tar<P: AsRef<Path>>(path: P) -> io::Result<VirtualFs> {
+        VirtualFs::from_tar(&fs::read(path)?)
+    }
+
+    /// Creates or replaces a file, creating the directories containing it.
+    pub fn write_file(&self, path: &str, data: &[u8]) -> FsResult<()> {
+        let path = Path::new(path);
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(&parent.to_string_lossy())?;
+        }
+        let mut tree = self.tree.borrow_mut();
+        let (dir, name) = tree.parent(path)?;
+        match tree.entries(dir)?.get(name) {
+            Some(&ino) => match tree.nodes[ino].content {
+                Content::File(ref mut content) => *content = data.to_vec(),
+                Content::Dir(_) => return Err(errno::ISDIR),
+            },
+            None => {
+                let ino = tree.add(Content::File(data.to_vec()));
+                tree.entries_mut(dir)?.insert(name.to_string(), ino);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_dir_all(&self, path: &str) -> FsResult<()> {
+        let mut tree = self.tree.borrow_mut();
+        let mut dir = ROOT;
+        for name in names(Path::new(path)) {
+            dir = match tree.entries(dir)?.get(name) {
+                Some(&ino) => ino,
+                None => {
+                    let ino = tree.add(Content::Dir(BTreeMap::new()));
+                    tree.entries_mut(dir)?.insert(name.to_string(), ino);
+                    ino
+                }
+            };
+        }
+        tree.entries(dir)?;
+        Ok(())
+    }
+
+    /// The contents of a regular file.
+    pub fn read_file(&self, path: &str) -> Option<Vec<u8>> {
+        let tree = self.tree.borrow();
+        let ino = tree.lookup(Path::new(path)).ok()?;
+        match tree.nodes[ino].content {
+            Content::File(ref data) => Some(data.clone()),
+            Content::Dir(_) => None,
+        }
+    }
+
+    pub fn is_dir(&self, path: &str) -> bool {
+        let tree = self.tree.borrow();
+        tree.lookup(Path::new(path))
+            .is_ok_and(|ino| tree.entries(ino).is_ok())
+    }
+
+    /// All regular files by their path.
+    pub fn files(&self) -> BTreeMap<String, Vec<u8>> {
+        let tree = self.tree.borrow();
+        let mut files = BTreeMap::new();
+        let mut dirs = vec![(String::new(), ROOT)];
+        while let Some((prefix, dir)) = dirs.pop() {
+            for (name, &ino) in tree.entries(dir).expect("directory") {
+                let path = format!("{}{}", prefix, name);
+                match tree.nodes[ino].content {
+                    Content::File(ref data) => {
+                        files.insert(path, data.clone());
+                    }
+                    Content::Dir(_) => dirs.push((path + "/", ino)),
+                }
+            }
+        }
+        files
+    }
+
+    pub fn set_readonly(&self, path: &str, readonly: bool) -> FsResult<()> {
+        let mut tree = self.tree.borrow_mut();
+        let ino = tree.lookup(Path::new(path))?;
+        tree.nodes[ino].readonly = readonly;
+        Ok(())
+    }
+}
+
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// An octal number, or a base-256 one if the high bit is set.
+fn tar_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        let value = field[1..].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+        return Some(value);
+    }
+    let text = tar_string(field);
+    let text = text.trim_matches(|c| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// The `path` record of a pax extended header.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    for record in text.lines() {
+        let record = record.split_once(' ')?.1;
+        if let Some(path) = record.strip_prefix("path=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+impl FileSystem for VirtualFs {
+    fn metadata(&self, path: &Path, _: bool) -> FsResult<Metadata> {
+        let tree = self.tree.borrow();
+        let ino = tree.lookup(path)?;
+        Ok(metadata(&tree, ino))
+    }
+
+    fn open(&self, path: &Path, options: &OpenOptions) -> FsResult<Box<dyn File>> {
+        let mut tree = self.tree.borrow_mut();
+        let ino = match tree.lookup(path) {
+            Ok(_) if options.create && options.exclusive => return Err(errno::EXIST),
+            Ok(ino) => ino,
+            Err(errno::NOENT) if options.create => {
+                let (dir, name) = tree.parent(path)?;
+                let ino = tree.add(Content::File(Vec::new()));
+                tree.entries_mut(dir)?.insert(name.to_string(), ino);
+                ino
+            }
+            Err(err) => return Err(err),
+        };
+        let node = &mut tree.nodes[ino];
+        if options.write && node.readonly {
+            return Err(errno::ACCES);
+        }
+        match node.content {
+            Content::File(ref mut data) if options.truncate => data.clear(),
+            Content::File(_) => {}
+            Content::Dir(_) => return Err(errno::ISDIR),
+        }
+        Ok(Box::new(VirtualFile {
+            tree: self.tree.clone(),
+            ino,
+            position: 0,
+            read: options.read,
+            write: options.write,
+        }))
+    }
+
+    fn read_dir(&self, path: &Path) -> FsResult<Vec<DirEntry>> {
+        let tree = self.tree.borrow();
+        let entries = tree.entries(tree.lookup(path)?)?;
+        Ok(entries
+            .iter()
+            .map(|(name, &ino)| DirEntry {
+                name: name.clone(),
+                filetype: tree.nodes[ino].filetype(),
+                ino: ino as u64,
+            })
+            .collect())
+    }
+
+    fn create_dir(&self, path: &Path) -> FsResult<()> {
+        let mut tree = self.tree.borrow_mut();
+        let (dir, name) = tree.parent(path)?;
+        if tree.entries(dir)?.contains_key(name) {
+            return Err(errno::EXIST);
+        }
+        let ino = tree.add(Content::Dir(BTreeMap::new()));
+        tree.entries_mut(dir)?.insert(name.to_string(), ino);
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> FsResult<()> {
+        let mut tree = self.tree.borrow_mut();
+        let (dir, name) = tree.parent(path)?;
+        let ino = *tree.entries(dir)?.get(name).ok_or(errno::NOENT)?;
+        if !tree.entries(ino)?.is_empty() {
+            return Err(errno::NOTEMPTY);
+        }
+        tree.entries_mut(dir)?.remove(name);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> FsResult<()> {
+        let mut tree = self.tree.borrow_mut();
+        let (dir, name) = tree.parent(path)?;
+        let ino = *tree.entries(dir)?.get(name).ok_or(errno::NOENT)?;
+        if tree.entries(ino).is_ok() {
+            return Err(errno::ISDIR);
+        }
+        tree.entries_mut(dir)?.remove(name);
+        tree.nodes[ino].nlink -= 1;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+        let mut tree = self.tree.borrow_mut();
+        let (from_dir, from_name) = tree.parent(from)?;
+        let (to_dir, to_name) = tree.parent(to)?;
+        let ino = *tree.entries(from_dir)?.get(from_name).ok_or(errno::NOENT)?;
+        let is_dir = tree.entries(ino).is_ok();
+        if is_dir && names(to).starts_with(&names(from)) {
+            return if names(to) == names(from) { Ok(()) } else { Err(errno::INVAL) };
+        }
+        if let Some(&existing) = tree.entries(to_dir)?.get(to_name) {
+            if existing == ino {
+                return Ok(());
+            }
+            match (is_dir, tree.entries(existing).is_ok()) {
+                (true, false) => return Err(errno::NOTDIR),
+                (false, true) => return Err(errno::ISDIR),
+                (true, true) if !tree.is_empty_dir(existing) => return Err(errno::NOTEMPTY),
+                (false, false) => tree.nodes[existing].nlink -= 1,
+                _ => {}
+            }
+        }
+        tree.entries_mut(from_dir)?;
+        tree.entries_mut(to_dir)?.insert(to_name.to_string(), ino);
+        tree.entries_mut(from_dir)?.remove(from_name);
+        Ok(())
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> FsResult<()> {
+        let mut tree = self.tree.borrow_mut();
+        let ino = tree.lookup(from)?;
+        if tree.entries(ino).is_ok() {
+            return Err(errno::PERM);
+        }
+        let (dir, name) = tree.parent(to)?;
+        if tree.entries(dir)?.contains_key(name) {
+            return Err(errno::EXIST);
+        }
+        tree.entries_mut(dir)?.insert(name.to_string(), ino);
+        tree.nodes[ino].nlink += 1;
+        Ok(())
+    }
+
+    fn read_link(&self, path: &Path) -> FsResult<String> {
+        self.tree.borrow().lookup(path)?;
+        Err(errno::INVAL)
+    }
+
+    fn set_times(&self, path: &Path, atim: Option<u64>, mtim: Option<u64>) -> FsResult<()> {
+        let mut tree = self.tree.borrow_mut();
+        let ino = tree.lookup(path)?;
+        set_times(&mut tree.nodes[ino], atim, mtim);
+        Ok(())
+    }
+}
+
+fn metadata(tree: &Tree, ino: usize) -> Metadata {
+    let node = &tree.nodes[ino];
+    let size = match node.content {
+        Content::File(ref data) => data.len() as u64,
+        Content::Dir(ref entries) => entries.len() as u64,
+    };
+    Metadata {
+        dev: 0,
+        ino: ino as u64,
+        filetype: node.filetype(),
+        nlink: node.nlink,
+        size,
+        atim: node.atim,
+        mtim: node.mtim,
+        ctim: node.ctim,
+    }
+}
+
+fn set_times(node: &mut Node, atim: Option<u64>, mtim: Option<u64>) {
+    if let Some(atim) = atim {
+        node.atim = atim;
+    }
+    if let Some(mtim) = mtim {
+        node.mtim = mtim;
+    }
+}
+
+/// An open file of a [`VirtualFs`].
+struct VirtualFile {
+    tree: Rc<RefCell<Tree>>,
+    ino: usize,
+    position: u64,
+    read: bool,
+    write: bool,
+}
+
+impl VirtualFile {
+    fn data<'t>(&self, tree: &'t mut Tree) -> &'t mut Vec<u8> {
+        match tree.nodes[self.ino].content {
+            Content::File(ref mut data) => data,
+            Content::Dir(_) => unreachable!("opened a directory"),
+        }
+    }
+}
+
+fn denied() -> io::Error {
+    io::Error::new(ErrorKind::PermissionDenied, "not opened for this access")
+}
+
+impl Read for VirtualFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.read {
+            return Err(denied());
+        }
+        let mut tree = self.tree.borrow_mut();
+        let data = self.data(&mut tree);
+        let start = (self.position as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for VirtualFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.write {
+            return Err(denied());
+        }
+        let mut tree = self.tree.borrow_mut();
+        let data = self.data(&mut tree);
+        let start = self.position as usize;
+        if data.len() < start + buf.len() {
+            data.resize(start + buf.len(), 0);
+        }
+        data[start..start + buf.len()].copy_from_slice(buf);
+        self.position += buf.len() as u64;
+        tree.nodes[self.ino].mtim = nanos(SystemTime::now());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for VirtualFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.data(&mut self.tree.borrow_mut()).len() as i64;
+        let position = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(offset) => self.position as i64 + offset,
+            SeekFrom::End(offset) => len + offset,
+        };
+        if position < 0 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "negative position"));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+impl File for VirtualFile {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(metadata(&self.tree.borrow(), self.ino))
+    }
+
+    fn set_len(&mut self, len: u64) -> FsResult<()> {
+        if !self.write {
+            return Err(errno::BADF);
+        }
+        let mut tree = self.tree.borrow_mut();
+        self.data(&mut tree).resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn set_times(&mut self, atim: Option<u64>, mtim: Option<u64>) -> FsResult<()> {
+        set_times(&mut self.tree.borrow_mut().nodes[self.ino], atim, mtim);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rename_and_inspect() {
+        let fs = VirtualFs::from_map(vec![("a/b.txt", "hello"), ("c.txt", "x")]).unwrap();
+        fs.rename(Path::new("a/b.txt"), Path::new("c.txt")).unwrap();
+        assert_eq!(fs.rename(Path::new("a"), Path::new("a/d")), Err(errno::INVAL));
+        fs.set_readonly("a", true).unwrap();
+        assert_eq!(fs.create_dir(Path::new("a/d")), Err(errno::ACCES));
+        let files: Vec<_> = fs.files().into_iter().collect();
+        assert_eq!(files, vec![("c.txt".to_string(), b"hello".to_vec())]);
+        assert!(fs.is_dir("a"));
+    }
+
+    #[test]
+    fn tar_archive() {
+        let mut tar = Vec::new();
+        for &(name, typ, data) in &[("dir/", b'5', &b""[..]), ("dir/f", b'0', &b"data"[..])] {
+            let mut header = [0u8; BLOCK_SIZE];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..107].copy_from_slice(b"0000444");
+            header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+            header[156] = typ;
+            tar.extend_from_slice(&header);
+            tar.extend_from_slice(data);
+            tar.resize(tar.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+        }
+        tar.extend_from_slice(&[0; 2 * BLOCK_SIZE]);
+        let fs = VirtualFs::from_tar(&tar).unwrap();
+        assert_eq!(fs.read_file("dir/f"), Some(b"data".to_vec()));
+        let options = OpenOptions {
+            write: true,
+            ..OpenOptions::default()
+        };
+        assert_eq!(fs.open(Path::new("dir/f"), &options).err(), Some(errno::ACCES));
+    }
+}
@@ -0,0 +1,327 @@
+//! Prints modules in the WebAssembly text format.
+
+use parser::{ParseError, ParseResult, ResizableLimits, Type};
+use parser::import_export_section::{ExternalKind, ExternalKindType};
+use parser::names::{NameKind, NameResolver};
+use parser::opcode::{InitExpr, Op, Opcode, Payload};
+use parser::type_section::FuncType;
+
+use std::fmt::Write;
+
+/// Text format name of a value or element type.
+pub fn type_name(typ: Type) -> &'static str {
+    match typ {
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::F32 => "f32",
+        Type::F64 => "f64",
+        Type::anyfunc => "anyfunc",
+        Type::func => "func",
+        Type::empty_block => "",
+    }
+}
+
+fn limits(limits: &ResizableLimits) -> String {
+    match limits.maximum {
+        Some(max) => format!("{} {}", limits.initial, max),
+        None => format!("{}", limits.initial),
+    }
+}
+
+fn signature(typ: &FuncType) -> String {
+    let mut out = String::new();
+    if !typ.param_types.is_empty() {
+        out.push_str(" (param");
+        for &param in &typ.param_types {
+            out.push(' ');
+            out.push_str(type_name(param));
+        }
+        out.push(')');
+    }
+    if let Some(result) = typ.return_type {
+        write!(out, " (result {})", type_name(result)).unwrap();
+    }
+    return out;
+}
+
+fn float32(v: f32) -> String {
+    if v.is_nan() {
+        return format!(
+            "{}nan:0x{:x}",
+            if v.is_sign_negative() { "-" } else { "" },
+            v.to_bits() & 0x7f_ffff
+        );
+    }
+    if v.is_infinite() {
+        return (if v < 0.0 { "-inf" } else { "inf" }).to_string();
+    }
+    return format!("{:?}", v);
+}
+
+fn float64(v: f64) -> String {
+    if v.is_nan() {
+        let payload = v.to_bits() & 0xf_ffff_ffff_ffff;
+        return format!(
+            "{}nan:0x{:x}",
+            if v.is_sign_negative() { "-" } else { "" },
+            payload
+        );
+    }
+    if v.is_infinite() {
+        return (if v < 0.0 { "-inf" } else { "inf" }).to_string();
+    }
+    return format!("{:?}", v);
+}
+
+/// Natural alignment (as log2 of the byte count) of a memory access.
+pub fn natural_alignment(opcode: Opcode) -> u32 {
+    match opcode {
+        Opcode::i32_load8_s
+        | Opcode::i32_load8_u
+        | Opcode::i64_load8_s
+        | Opcode::i64_load8_u
+        | Opcode::i32_store8
+        | Opcode::i64_store8 => 0,
+        Opcode::i32_load16_s
+        | Opcode::i32_load16_u
+        | Opcode::i64_load16_s
+        | Opcode::i64_load16_u
+        | Opcode::i32_store16
+        | Opcode::i64_store16 => 1,
+        Opcode::i64_load | Opcode::f64_load | Opcode::i64_store | Opcode::f64_store => 3,
+        _ => 2,
+    }
+}
+
+/// Formats a single instruction without indentation.
+pub fn instruction(op: &Op) -> String {
+    let mut out = op.opcode.name().to_string();
+    match op.payload {
+        Payload::None | Payload::Reserved => {}
+        Payload::BlockType(Type::empty_block) => {}
+        Payload::BlockType(typ) => write!(out, " (result {})", type_name(typ)).unwrap(),
+        Payload::VU32(v) if op.opcode == Opcode::call_indirect => {
+            write!(out, " (type {})", v).unwrap()
+        }
+        Payload::VU32(v) => write!(out, " {}", v).unwrap(),
+        Payload::VI32(v) => write!(out, " {}", v).unwrap(),
+        Payload::VI64(v) => write!(out, " {}", v).unwrap(),
+        Payload::F32(v) => write!(out, " {}", float32(v)).unwrap(),
+        Payload::F64(v) => write!(out, " {}", float64(v)).unwrap(),
+        Payload::BrTable {
+            ref target_table,
+            default_target,
+        } => {
+            for target in target_table {
+                write!(out, " {}", target).unwrap();
+            }
+            write!(out, " {}", default_target).unwrap();
+        }
+        Payload::MemoryImmediate { flags, offset } => {
+            if offset != 0 {
+                write!(out, " offset={}", offset).unwrap();
+            }
+            if flags != natural_alignment(op.opcode) {
+                write!(out, " align={}", 1u64 << flags.min(63)).unwrap();
+            }
+        }
+    }
+    return out;
+}
+
+fn init_expr(expr: &InitExpr) -> String {
+    format!("({})", instruction(&expr.0))
+}
+
+fn string_literal(data: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in data {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(byte as char),
+            _ => write!(out, "\\{:02x}", byte).unwrap(),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+/// ` (;name;)` if the name section names the item, otherwise nothing.
+fn name_comment(names: &NameResolver, kind: NameKind, index: u32) -> String {
+    match names.name(kind, index) {
+        Some(name) => format!(" (;{};)", name),
+        None => String::new(),
+    }
+}
+
+fn kind_name(kind: &ExternalKind) -> &'static str {
+    match *kind {
+        ExternalKind::Func => "func",
+        ExternalKind::Table => "table",
+        ExternalKind::Memory => "memory",
+        ExternalKind::Global => "global",
+    }
+}
+
+/// Prints the module in the text format. Names from the name section are
+/// emitted as comments, all references use indices.
+pub fn print_module(module: &ParseResult) -> Result<String, ParseError> {
+    let names = NameResolver::new(module);
+    let types = module.function_types.as_ref().map_or(&[][..], |t| &t[..]);
+    let mut out = String::from("(module");
+    if let Some(name) = names.module_name() {
+        write!(out, " (;{};)", name).unwrap();
+    }
+    out.push('\n');
+
+    for (index, typ) in types.iter().enumerate() {
+        writeln!(out, "  (type (;{};) (func{}))", index, signature(typ)).unwrap();
+    }
+
+    let (mut funcs, mut tables, mut memories, mut globals) = (0, 0, 0, 0);
+    for import in module.import_entires.iter().flat_map(|i| i.iter()) {
+        let desc = match import.typ {
+            ExternalKindType::Func(typ) => {
+                funcs += 1;
+                format!(
+                    "(func (;{};){} (type {}))",
+                    funcs - 1,
+                    name_comment(&names, NameKind::Function, funcs - 1),
+                    typ
+                )
+            }
+            ExternalKindType::Table(_, ref l) => {
+                tables += 1;
+                format!("(table (;{};) {} anyfunc)", tables - 1, limits(l))
+            }
+            ExternalKindType::Memory(ref l) => {
+                memories += 1;
+                format!("(memory (;{};) {})", memories - 1, limits(l))
+            }
+            ExternalKindType::Global(typ, mutable) => {
+                globals += 1;
+                let typ = if mutable {
+                    format!("(mut {})", type_name(typ))
+                } else {
+                    type_name(typ).to_string()
+                };
+                format!("(global (;{};) {})", globals - 1, typ)
+            }
+        };
+        writeln!(
+            out,
+            "  (import {} {} {})",
+            string_literal(import.module.as_bytes()),
+            string_literal(import.field.as_bytes()),
+            desc
+        )
+        .unwrap();
+    }
+
+    let fn_ids = module.function_ids.as_ref().map_or(&[][..], |f| &f[..]);
+    let bodies = module.function_bodies.as_ref().map_or(&[][..], |b| &b[..]);
+    for (i, (fn_id, body)) in fn_ids.iter().zip(bodies).enumerate() {
+        let index = funcs + i as u32;
+        let body = body.body()?;
+        let sig = types.get(fn_id.0 as usize).map_or(String::new(), signature);
+        writeln!(
+            out,
+            "  (func (;{};){} (type {}){}",
+            index,
+            name_comment(&names, NameKind::Function, index),
+            fn_id.0,
+            sig
+        )
+        .unwrap();
+        for local in &body.locals {
+            out.push_str("    (local");
+            for _ in 0..local.count {
+                write!(out, " {}", type_name(local.typ)).unwrap();
+            }
+            out.push_str(")\n");
+        }
+        let mut depth = 0;
+        for op in &body.code {
+            if op.opcode == Opcode::end || op.opcode == Opcode::else_ {
+                depth -= 1;
+            }
+            writeln!(out, "    {}{}", "  ".repeat(depth), instruction(op)).unwrap();
+            match op.opcode {
+                Opcode::block | Opcode::loop_ | Opcode::if_ | Opcode::else_ => depth += 1,
+                _ => {}
+            }
+        }
+        out.push_str("  )\n");
+    }
+
+    for table in module.table_entries.iter().flat_map(|t| t.iter()) {
+        writeln!(
+            out,
+            "  (table (;{};) {} anyfunc)",
+            tables,
+            limits(&table.limits)
+        )
+        .unwrap();
+        tables += 1;
+    }
+    for memory in module.memory_types.iter().flat_map(|m| m.iter()) {
+        writeln!(
+            out,
+            "  (memory (;{};) {})",
+            memories,
+            limits(&memory.limits)
+        )
+        .unwrap();
+        memories += 1;
+    }
+    for global in module.global_variables.iter().flat_map(|g| g.iter()) {
+        let typ = type_name(global.typ.content_type);
+        let typ = if global.typ.mutability {
+            format!("(mut {})", typ)
+        } else {
+            typ.to_string()
+        };
+        writeln!(
+            out,
+            "  (global (;{};){} {} {})",
+            globals,
+            name_comment(&names, NameKind::Global, globals),
+            typ,
+            init_expr(&global.init)
+        )
+        .unwrap();
+        globals += 1;
+    }
+    for export in module.export_entires.iter().flat_map(|e| e.iter()) {
+        writeln!(
+            out,
+            "  (export {} ({} {}))",
+            string_literal(export.field.as_bytes()),
+            kind_name(&export.kind),
+            export.index
+        )
+        .unwrap();
+    }
+    if let Some(ref start) = module.start_function {
+        writeln!(out, "  (start {})", start.0).unwrap();
+    }
+    for segment in module.element_segments.iter().flat_map(|e| e.iter()) {
+        write!(out, "  (elem {}", init_expr(&segment.offset)).unwrap();
+        for elem in &segment.elems {
+            write!(out, " {}", elem.0).unwrap();
+        }
+        out.push_str(")\n");
+    }
+    for segment in module.data_entries.iter().flat_map(|d| d.iter()) {
+        writeln!(
+            out,
+            "  (data {} {})",
+            init_expr(&segment.offset),
+            string_literal(segment.data)
+        )
+        .unwrap();
+    }
+    out.push_str(")\n");
+    return Ok(out);
+}
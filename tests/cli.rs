@@ -0,0 +1,67 @@
+//! Exit statuses of the command line interface.
+
+use std::env;
+use std::fs;
+use std::process::{self, Command, Output};
+
+fn wasm_interpreter(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_wasm-interpreter"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn status(args: &[&str]) -> Option<i32> {
+    wasm_interpreter(args).status.code()
+}
+
+fn stderr(args: &[&str]) -> String {
+    String::from_utf8_lossy(&wasm_interpreter(args).stderr).into_owned()
+}
+
+#[test]
+fn success() {
+    let output = wasm_interpreter(&["invoke", "examples/factorial.wasm", "fac", "5"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "120.0\n");
+    assert_eq!(status(&["validate", "examples/hello.wasm"]), Some(0));
+}
+
+#[test]
+fn invalid_module() {
+    let path = env::temp_dir().join(format!("wasm-interpreter-cli-{}.wasm", process::id()));
+    fs::write(&path, b"\0asm\x02\0\0\0").unwrap();
+    let path = path.to_str().unwrap();
+    let (validate, invoke) = (status(&["validate", path]), status(&["invoke", path, "f"]));
+    fs::remove_file(path).unwrap();
+    assert_eq!(validate, Some(1));
+    assert_eq!(invoke, Some(1));
+}
+
+#[test]
+fn usage_errors() {
+    assert_eq!(status(&[]), Some(2));
+    assert_eq!(status(&["frobnicate", "examples/add.wasm"]), Some(2));
+    assert_eq!(status(&["validate", "examples/missing.wasm"]), Some(2));
+    assert_eq!(status(&["invoke", "examples/factorial.wasm"]), Some(2));
+    assert_eq!(status(&["invoke", "examples/factorial.wasm", "nope"]), Some(2));
+    assert_eq!(status(&["invoke", "examples/factorial.wasm", "fac"]), Some(2));
+    assert_eq!(status(&["invoke", "examples/factorial.wasm", "--fuel", "x", "fac", "5"]), Some(2));
+    assert_eq!(status(&["run", "examples/add.wasm", "--backend", "x"]), Some(2));
+}
+
+#[test]
+fn traps() {
+    let args = ["invoke", "examples/factorial.wasm", "--fuel", "10", "fac", "20"];
+    assert_eq!(status(&args), Some(3));
+    assert!(stderr(&args).starts_with("Trap: all fuel consumed"));
+    let args = ["invoke", "examples/factorial.wasm", "--max-call-depth", "3", "--", "fac", "20"];
+    assert_eq!(status(&args), Some(3));
+    let args = ["invoke", "examples/factorial.wasm", "--backend", "register", "fac", "5"];
+    assert_eq!(status(&args), Some(0));
+}
+
+#[test]
+fn link_errors() {
+    assert_eq!(status(&["run", "examples/add.wasm"]), Some(4));
+}